@@ -0,0 +1,124 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use hoi4_province_editor::app::map::{Bundle, History};
+use hoi4_province_editor::app::map::synthetic::SyntheticMap;
+use hoi4_province_editor::config::Config;
+use hoi4_province_editor::util::files::{IntoLocation, Location};
+use hoi4_province_editor::util::task::Progress;
+
+use std::path::PathBuf;
+
+const SIZES: &[(&str, u32, u32, u32)] = &[
+  ("small", 512, 256, 400),
+  ("large", 2048, 1024, 4000)
+];
+
+fn synthetic_maps() -> impl Iterator<Item = (&'static str, SyntheticMap)> {
+  SIZES.iter().map(|&(name, width, height, provinces)| {
+    (name, SyntheticMap { connections: provinces as usize / 20, ..SyntheticMap::new(width, height, provinces) })
+  })
+}
+
+/// Creates an empty directory in the system temp dir for a map to be saved to
+fn temp_location(name: &str) -> Location {
+  let path = std::env::temp_dir().join(format!("hoi4pe_bench_{}_{}", name, std::process::id()));
+  if path.exists() {
+    std::fs::remove_dir_all(&path).expect("unable to clear temp dir");
+  };
+
+  std::fs::create_dir_all(&path).expect("unable to create temp dir");
+  path.into_location().expect("unable to create location")
+}
+
+fn remove_location(location: Location) {
+  let path: PathBuf = location.into_pathbuf();
+  let _ = std::fs::remove_dir_all(path);
+}
+
+fn bench_load_save(c: &mut Criterion) {
+  let mut group = c.benchmark_group("load_save");
+  group.sample_size(10);
+  for (name, synthetic_map) in synthetic_maps() {
+    let bundle = synthetic_map.generate();
+    let location = temp_location(name);
+    bundle.save(&location).expect("unable to save map");
+
+    group.bench_function(BenchmarkId::new("save", name), |b| {
+      b.iter(|| bundle.save(&location).expect("unable to save map"))
+    });
+
+    group.bench_function(BenchmarkId::new("load", name), |b| {
+      b.iter(|| {
+        let config = Config { preserve_ids: true, ..Config::default() };
+        Bundle::load(&location, config, &Progress::new()).expect("unable to load map")
+      })
+    });
+
+    remove_location(location);
+  };
+
+  group.finish();
+}
+
+fn bench_edits(c: &mut Criterion) {
+  let mut group = c.benchmark_group("edits");
+  for (name, synthetic_map) in synthetic_maps() {
+    let mut bundle = synthetic_map.generate();
+    let mut history = History::new(bundle.config.max_undo_states, &bundle.map);
+
+    // Alternate between two colors so that every fill changes the province
+    let pos = [synthetic_map.width / 2, synthetic_map.height / 2];
+    let colors = [bundle.map.get_color_at(pos), [0x01, 0x02, 0x03]];
+    let mut flip = false;
+    group.bench_function(BenchmarkId::new("flood_fill", name), |b| {
+      b.iter(|| {
+        flip = !flip;
+        bundle.map.flood_fill_province(pos, colors[flip as usize])
+      })
+    });
+
+    group.bench_function(BenchmarkId::new("recolor_everything", name), |b| {
+      b.iter(|| history.calculate_recolor_map(&mut bundle))
+    });
+  };
+
+  group.finish();
+}
+
+fn bench_analysis(c: &mut Criterion) {
+  let mut group = c.benchmark_group("analysis");
+  for (name, synthetic_map) in synthetic_maps() {
+    let bundle = synthetic_map.generate();
+    group.bench_function(BenchmarkId::new("analyze", name), |b| {
+      b.iter(|| bundle.generate_problems())
+    });
+
+    group.bench_function(BenchmarkId::new("calculate_coastal_provinces", name), |b| {
+      b.iter(|| bundle.map.calculate_coastal_provinces())
+    });
+  };
+
+  group.finish();
+}
+
+fn bench_textures(c: &mut Criterion) {
+  let mut group = c.benchmark_group("textures");
+  for (name, synthetic_map) in synthetic_maps() {
+    let bundle = synthetic_map.generate();
+    group.bench_function(BenchmarkId::new("color", name), |b| {
+      b.iter(|| bundle.texture_buffer_color())
+    });
+
+    group.bench_function(BenchmarkId::new("terrain", name), |b| {
+      b.iter(|| bundle.texture_buffer_terrain())
+    });
+
+    group.bench_function(BenchmarkId::new("generate", name), |b| {
+      b.iter_batched(|| synthetic_map.clone(), |synthetic_map| synthetic_map.generate(), BatchSize::LargeInput)
+    });
+  };
+
+  group.finish();
+}
+
+criterion_group!(benches, bench_load_save, bench_edits, bench_analysis, bench_textures);
+criterion_main!(benches);
@@ -17,9 +17,12 @@ use crate::error::Error;
 use crate::font;
 use crate::events::{EventHandler, KeyMods};
 use crate::util::files::{Location, IntoLocation};
+use crate::util::task::{Progress, Task};
 use self::alerts::Alerts;
-use self::canvas::{Canvas, ToolMode, ViewMode};
-use self::interface::{Interface, ButtonId, get_interface};
+use self::canvas::{Canvas, CanvasData, ToolMode, ViewMode};
+use self::interface::{Interface, ButtonId, InputFilter, InputResponse, TextInput, get_interface};
+use self::format::DefinitionKind;
+use self::map::{ContinentAssignment, Problem, SaveOperation};
 
 use std::path::{Path, PathBuf};
 use std::fmt;
@@ -36,6 +39,8 @@ pub mod colors {
   pub const WARNING: DrawColor = [0.875, 0.5, 0.0, 1.0];
   pub const NEUTRAL: DrawColor = [0.25, 0.25, 0.25, 1.0];
   pub const OVERLAY_T: DrawColor = [0.0, 0.0, 0.0, 0.5];
+  pub const PROGRESS: DrawColor = [0.25, 0.25, 0.75, 1.0];
+  pub const FLASH: DrawColor = [1.0, 0.875, 0.0, 1.0];
 
   pub const ADJ_LAND: DrawColor = [0.2, 0.6, 1.0/3.0, 1.0];
   pub const ADJ_SEA: DrawColor = [0.2, 1.0/3.0, 0.6, 1.0];
@@ -69,7 +74,21 @@ pub struct App {
   pub alerts: Alerts,
   pub glyph_cache: FontGlyphCache,
   pub interface: Option<Interface>,
-  pub painting: bool
+  pub painting: bool,
+  task: Option<AppTask>,
+  pending_open: Option<Location>,
+  new_map_prompt: Option<NewMapPrompt>,
+  /// The province ID, color or position being typed into the go to prompt
+  go_to_prompt: Option<TextInput>
+}
+
+/// A load, generate or save operation currently running on a worker thread
+#[derive(Debug)]
+enum AppTask {
+  Load(Task<CanvasData>, Location),
+  /// Generating a new map from images, the location is where the new map will be saved
+  Generate(Task<CanvasData>, Location),
+  Save(Task<SaveOperation>, Location)
 }
 
 impl EventHandler for App {
@@ -83,7 +102,11 @@ impl EventHandler for App {
       alerts: Alerts::new(5.0),
       glyph_cache,
       interface: None,
-      painting: false
+      painting: false,
+      task: None,
+      pending_open: None,
+      new_map_prompt: None,
+      go_to_prompt: None
     }
   }
 
@@ -109,6 +132,19 @@ impl EventHandler for App {
     };
 
     self.alerts.draw(ctx, interface, &mut self.glyph_cache, gl);
+    if let Some(task) = &self.task {
+      let (text, fraction) = task.status();
+      draw_message_box(ctx, interface, &text, fraction, &mut self.glyph_cache, gl);
+    } else if let Some(new_map_prompt) = &self.new_map_prompt {
+      let line_positions = draw_message_box(ctx, interface, &new_map_prompt.text(), None, &mut self.glyph_cache, gl);
+      new_map_prompt.draw_overlay(ctx, &line_positions, gl);
+    } else if let Some(go_to_prompt) = &self.go_to_prompt {
+      let text = format!("Go To Province\n{}{}\n{}", GO_TO_LABEL, go_to_prompt.text(), GO_TO_HELP);
+      let line_positions = draw_message_box(ctx, interface, &text, None, &mut self.glyph_cache, gl);
+      let [x, y] = line_positions[1];
+      go_to_prompt.draw_overlay(ctx, [x + font::get_width_metric_str(GO_TO_LABEL), y], gl);
+    };
+
     interface.draw(ctx, ictx, cursor_pos, &mut self.glyph_cache, gl);
   }
 
@@ -116,29 +152,114 @@ impl EventHandler for App {
     if !self.alerts.is_active() {
       self.alerts.tick(dt);
     };
+
+    if let Some(canvas) = &mut self.canvas {
+      canvas.tick(dt);
+    };
+
+    self.poll_task();
   }
 
   fn on_key(&mut self, key: Key, state: bool, mods: KeyMods, cursor_pos: Option<Vector2<f64>>) {
     let Some(interface) = self.interface.as_ref() else { return };
+    if self.task.is_some() {
+      // Editing is disabled while a map is being loaded or saved
+      match (state, key) {
+        (state, Key::Tab) => self.alerts.set_state(state),
+        (true, Key::Escape) => self.action_cancel_task(),
+        (true, Key::H) => if let Some(canvas) = &mut self.canvas { canvas.camera.reset() },
+        _ => ()
+      };
+
+      return;
+    };
+
+    if self.new_map_prompt.is_some() {
+      // The new map prompt takes all keyboard input while it is open
+      if state {
+        match self.new_map_prompt.as_mut().map(|new_map_prompt| new_map_prompt.on_key(key, mods)) {
+          Some(InputResponse::Submit) => self.action_confirm_new_map(),
+          Some(InputResponse::Cancel) => self.new_map_prompt = None,
+          _ => ()
+        };
+      };
+
+      return;
+    };
+
+    if self.go_to_prompt.is_some() {
+      // Likewise for the go to prompt
+      if state {
+        match self.go_to_prompt.as_mut().map(|go_to_prompt| go_to_prompt.on_key(key, mods)) {
+          Some(InputResponse::Submit) => self.action_confirm_go_to(),
+          Some(InputResponse::Cancel) => self.go_to_prompt = None,
+          _ => ()
+        };
+      };
+
+      return;
+    };
+
+    if let Some(canvas) = self.canvas.as_mut().filter(|canvas| canvas.is_editing_text()) {
+      // The inspector and the adjacency editor take all keyboard input while they are being edited
+      if state {
+        canvas.editor_on_key(key, mods, &mut self.alerts);
+      };
+
+      return;
+    };
+
     match (&mut self.canvas, state, key) {
       (_, state, Key::Tab) => self.alerts.set_state(state),
+      (_, true, Key::N) if mods.ctrl => self.action_new_map(),
       (_, true, Key::O) if mods.ctrl => self.action_open_map(mods.alt),
+      (Some(_), true, Key::G) if mods.ctrl => self.action_go_to(),
       (Some(_), true, Key::S) if mods.ctrl && mods.shift => self.action_save_map_as(mods.alt),
       (Some(_), true, Key::S) if mods.ctrl => self.action_save_map(),
       (Some(_), true, Key::R) if mods.ctrl && mods.alt => self.action_reveal_map(),
       (Some(canvas), true, Key::Z) if mods.ctrl => canvas.undo(),
       (Some(canvas), true, Key::Y) if mods.ctrl => canvas.redo(),
       (Some(canvas), true, Key::Space) => canvas.cycle_tool_brush(interface, cursor_pos, mods.shift, &mut self.alerts),
+      (Some(canvas), true, Key::Escape) if canvas.has_continent_preview() => canvas.discard_continent_preview(&mut self.alerts),
+      (Some(canvas), true, Key::Return) if canvas.has_continent_preview() => canvas.apply_continent_preview(&mut self.alerts),
       (Some(canvas), true, Key::Escape) => canvas.cancel_tool(),
       (Some(canvas), true, Key::Return) => canvas.finish_tool(),
       (Some(canvas), true, Key::C) if mods.shift => canvas.calculate_coastal_provinces(),
       (Some(canvas), true, Key::R) if mods.shift => canvas.calculate_recolor_map(),
       (Some(canvas), true, Key::P) if mods.shift => canvas.display_problems(&mut self.alerts),
+      (Some(canvas), true, Key::T) if mods.shift => canvas.suggest_straits(&mut self.alerts),
+      (Some(canvas), true, Key::Y) if canvas.has_suggestions() && mods.shift => canvas.accept_all_suggestions(&mut self.alerts),
+      (Some(canvas), true, Key::Y) if canvas.has_suggestions() => canvas.accept_suggestion(interface, cursor_pos, &mut self.alerts),
+      (Some(canvas), true, Key::N) if canvas.has_suggestions() && mods.shift => canvas.reject_all_suggestions(&mut self.alerts),
+      (Some(canvas), true, Key::N) if canvas.has_suggestions() => canvas.reject_suggestion(interface, cursor_pos, &mut self.alerts),
+      (Some(canvas), true, Key::L) if mods.shift => canvas.preview_continents(ContinentAssignment::PerLandmass, &mut self.alerts),
+      (Some(canvas), true, Key::L) if mods.alt => canvas.preview_continents(ContinentAssignment::Majority, &mut self.alerts),
+      (Some(canvas), true, Key::M) if mods.ctrl => if let Some(cursor_pos) = cursor_pos {
+        canvas.mask_hovered_province(interface, cursor_pos, mods.shift, &mut self.alerts);
+      },
+      (Some(canvas), true, Key::M) if mods.alt => canvas.tool.invert_brush_mask(),
       (Some(canvas), true, Key::M) if mods.shift => canvas.tool.cycle_brush_mask(),
+      (Some(canvas), true, Key::K) if mods.shift => canvas.set_selection_locked(!mods.alt, &mut self.alerts),
+      (Some(canvas), true, Key::K) => if let Some(cursor_pos) = cursor_pos {
+        canvas.toggle_lock_hovered_province(interface, cursor_pos, &mut self.alerts);
+      },
+      (Some(canvas), true, Key::I) => canvas.toggle_inspector(),
+      (Some(canvas), true, Key::J) => canvas.toggle_adjacency_list(),
+      (Some(canvas), true, Key::E) if canvas.has_selected_connection() => canvas.edit_connection_text(),
+      (Some(canvas), true, Key::E) => canvas.edit_inspected_province(interface, cursor_pos, &mut self.alerts),
+      (Some(canvas), true, Key::Delete) if canvas.has_selected_connection() => canvas.reset_connection_endpoints(),
       (Some(canvas), true, Key::H) => canvas.camera.reset(),
       (Some(canvas), true, Key::A) => canvas.set_tool_mode(ToolMode::PaintArea),
       (Some(canvas), true, Key::B) => canvas.set_tool_mode(ToolMode::PaintBucket),
       (Some(canvas), true, Key::L) => canvas.set_tool_mode(ToolMode::new_lasso()),
+      (Some(canvas), true, Key::R) => canvas.set_tool_mode(ToolMode::Rectangle),
+      (Some(canvas), true, Key::M) => canvas.set_tool_mode(ToolMode::Merge),
+      (Some(canvas), true, Key::X) => canvas.set_tool_mode(ToolMode::new_split()),
+      (Some(canvas), true, Key::D) if mods.ctrl => canvas.clear_selection(),
+      (Some(canvas), true, Key::S) => canvas.set_tool_mode(ToolMode::new_select()),
+      (Some(canvas), true, Key::F) => canvas.apply_brush_to_selection(&mut self.alerts),
+      (Some(canvas), true, Key::V) if mods.shift => canvas.tool.subdivide_target.toggle(),
+      (Some(canvas), true, Key::V) => canvas.set_tool_mode(ToolMode::Subdivide),
       (Some(_), true, Key::D1) => self.action_change_view_mode(ViewMode::Color),
       (Some(_), true, Key::D2) => self.action_change_view_mode(ViewMode::Kind),
       (Some(_), true, Key::D3) => self.action_change_view_mode(ViewMode::Terrain),
@@ -149,18 +270,32 @@ impl EventHandler for App {
     };
   }
 
+  fn on_text(&mut self, text: String, mods: KeyMods) {
+    // Shortcuts like Ctrl+C can produce text of their own on some platforms
+    if self.task.is_some() || mods.ctrl || mods.alt { return };
+    if let Some(new_map_prompt) = &mut self.new_map_prompt {
+      new_map_prompt.on_text(&text);
+    } else if let Some(go_to_prompt) = &mut self.go_to_prompt {
+      go_to_prompt.on_text(&text);
+    } else if let Some(canvas) = &mut self.canvas {
+      canvas.editor_on_text(&text);
+    };
+  }
+
   fn on_mouse(&mut self, button: MouseButton, state: bool, mods: KeyMods, pos: Vector2<f64>) {
     let Some(interface) = self.interface.as_mut() else { return };
+    let busy = self.task.is_some();
     match (&mut self.canvas, state, button) {
+      (_, true, MouseButton::Left) if busy => self.alerts.push(Err("Please wait for the current operation to finish")),
       (_, true, MouseButton::Left) => match interface.on_mouse_click(pos) {
         Ok(id) => self.action_interface_button(id),
         Err(true) => self.action_activate_tool(pos, mods),
         Err(false) => ()
       },
-      (Some(_), false, MouseButton::Left) => self.action_deactivate_tool(),
+      (Some(_), false, MouseButton::Left) => self.action_deactivate_tool(pos),
       (Some(canvas), true, MouseButton::Right) => canvas.camera.set_panning(true),
       (Some(canvas), false, MouseButton::Right) => canvas.camera.set_panning(false),
-      (Some(canvas), true, MouseButton::Middle) => canvas.pick_tool_brush(interface, pos, &mut self.alerts),
+      (Some(canvas), true, MouseButton::Middle) if !busy => canvas.pick_tool_brush(interface, pos, &mut self.alerts),
       _ => ()
     };
   }
@@ -171,7 +306,7 @@ impl EventHandler for App {
     if let Some(canvas) = &mut self.canvas {
       if self.painting && canvas.tool.mode == ToolMode::PaintArea && canvas.view_mode() != ViewMode::Adjacencies {
         // Mouse movement should not activate the tool for the paint bucket and lasso tools
-        canvas.activate_tool(interface, pos, mods.shift);
+        canvas.activate_tool(interface, pos, mods, &mut self.alerts);
       };
     };
   }
@@ -186,8 +321,10 @@ impl EventHandler for App {
     let Some(interface) = self.interface.as_ref() else { return };
     let Some(canvas) = &mut self.canvas else { return };
 
-    if mods.shift {
-      canvas.change_tool_radius(y);
+    if canvas.scroll_panel(cursor_pos, y) {
+      return;
+    } else if mods.shift {
+      canvas.change_tool_size(y);
     } else {
       canvas.camera.on_mouse_zoom(interface, y, cursor_pos);
     };
@@ -206,9 +343,16 @@ impl EventHandler for App {
   }
 
   fn on_close(mut self) {
+    // Any running operation must finish before the application can exit
+    if let Some(mut task) = self.task.take() {
+      task.cancel();
+      let result = task.join();
+      self.finish_task(result);
+    };
+
     if self.is_canvas_modified() {
       if msg_dialog_unsaved_changes_exit() {
-        self.action_save_map();
+        self.raw_save_map_blocking();
       };
     };
   }
@@ -226,7 +370,12 @@ impl App {
         selected_tool: Some(match &canvas.tool.mode {
           ToolMode::PaintArea => 0,
           ToolMode::PaintBucket => 1,
-          ToolMode::Lasso(_) => 2
+          ToolMode::Lasso(_) => 2,
+          ToolMode::Rectangle => 3,
+          ToolMode::Merge => 4,
+          ToolMode::Split(_) => 5,
+          ToolMode::Subdivide => 6,
+          ToolMode::Select(_) => 7
         }),
         enabled_options: canvas.enabled_options()
       },
@@ -250,7 +399,9 @@ impl App {
     use self::interface::ButtonId::*;
     match (&mut self.canvas, id) {
       (_, ToolbarFileOpenFileArchive) => self.action_open_map(true),
+      (_, ToolbarFileNewMap) => self.action_new_map(),
       (_, ToolbarFileOpenFolder) => self.action_open_map(false),
+      (_, ToolbarFileGenerateMap) => self.action_generate_map(),
       (Some(_), ToolbarFileSave) => self.action_save_map(),
       (Some(_), ToolbarFileSaveAsArchive) => self.action_save_map_as(true),
       (Some(_), ToolbarFileSaveAsFolder) => self.action_save_map_as(false),
@@ -262,8 +413,25 @@ impl App {
       (Some(canvas), ToolbarEditCoastal) => canvas.calculate_coastal_provinces(),
       (Some(canvas), ToolbarEditRecolor) => canvas.calculate_recolor_map(),
       (Some(canvas), ToolbarEditProblems) => canvas.display_problems(&mut self.alerts),
+      (Some(canvas), ToolbarEditSuggestStraits) => canvas.suggest_straits(&mut self.alerts),
+      (Some(canvas), ToolbarEditAssignContinents) => canvas.preview_continents(ContinentAssignment::PerLandmass, &mut self.alerts),
+      (Some(canvas), ToolbarEditFixContinents) => canvas.preview_continents(ContinentAssignment::Majority, &mut self.alerts),
+      (Some(canvas), ToolbarEditAcceptSuggestions) => canvas.accept_all_suggestions(&mut self.alerts),
+      (Some(canvas), ToolbarEditRejectSuggestions) => canvas.reject_all_suggestions(&mut self.alerts),
       (Some(canvas), ToolbarEditToggleLassoSnap) => canvas.toggle_lasso_snap(),
       (Some(canvas), ToolbarEditNextMaskMode) => canvas.tool.cycle_brush_mask(),
+      (Some(canvas), ToolbarEditMaskSelection) => canvas.mask_selection(&mut self.alerts),
+      (Some(canvas), ToolbarEditMaskTerrainBrush) => canvas.mask_terrain_brush(&mut self.alerts),
+      (Some(canvas), ToolbarEditMaskContinentBrush) => canvas.mask_continent_brush(&mut self.alerts),
+      (Some(canvas), ToolbarEditInvertMask) => canvas.tool.invert_brush_mask(),
+      (Some(canvas), ToolbarEditToggleSubdivideTarget) => canvas.tool.subdivide_target.toggle(),
+      (Some(canvas), ToolbarSelectApplyBrush) => canvas.apply_brush_to_selection(&mut self.alerts),
+      (Some(canvas), ToolbarSelectRecolor) => canvas.recolor_selection(&mut self.alerts),
+      (Some(canvas), ToolbarSelectLock) => canvas.set_selection_locked(true, &mut self.alerts),
+      (Some(canvas), ToolbarSelectUnlock) => canvas.set_selection_locked(false, &mut self.alerts),
+      (Some(canvas), ToolbarSelectRemoveAdjacencies) => canvas.remove_selection_connections(&mut self.alerts),
+      (Some(_), ToolbarSelectExportIds) => self.action_export_selection_ids(),
+      (Some(canvas), ToolbarSelectClear) => canvas.clear_selection(),
       (Some(_), ToolbarViewMode1) => self.action_change_view_mode(ViewMode::Color),
       (Some(_), ToolbarViewMode2) => self.action_change_view_mode(ViewMode::Kind),
       (Some(_), ToolbarViewMode3) => self.action_change_view_mode(ViewMode::Terrain),
@@ -275,11 +443,19 @@ impl App {
       (Some(canvas), ToolbarViewToggleRiverOverlay | SidebarOptionRiverOverlay) => if canvas.toggle_river_overlay() {
         self.alerts.push(Err("You must have a map with rivers.bmp to use this"));
       },
+      (Some(canvas), ToolbarViewToggleInspector) => canvas.toggle_inspector(),
+      (Some(canvas), ToolbarViewToggleAdjacencyList) => canvas.toggle_adjacency_list(),
+      (Some(_), ToolbarViewGoTo) => self.action_go_to(),
       (Some(canvas), ToolbarViewResetZoom) => canvas.camera.reset(),
       (_, ToolbarViewFontLicense) => self.handle_result_none(font::view_font_license()),
       (Some(canvas), SidebarToolPaintArea) => canvas.set_tool_mode(ToolMode::PaintArea),
       (Some(canvas), SidebarToolPaintBucket) => canvas.set_tool_mode(ToolMode::PaintBucket),
       (Some(canvas), SidebarToolLasso) => canvas.set_tool_mode(ToolMode::new_lasso()),
+      (Some(canvas), SidebarToolRectangle) => canvas.set_tool_mode(ToolMode::Rectangle),
+      (Some(canvas), SidebarToolMerge) => canvas.set_tool_mode(ToolMode::Merge),
+      (Some(canvas), SidebarToolSplit) => canvas.set_tool_mode(ToolMode::new_split()),
+      (Some(canvas), SidebarToolSubdivide) => canvas.set_tool_mode(ToolMode::Subdivide),
+      (Some(canvas), SidebarToolSelect) => canvas.set_tool_mode(ToolMode::new_select()),
       #[cfg(any(debug_assertions, feature = "debug-mode"))]
       (Some(canvas), ToolbarDebugValidatePixelCounts) => canvas.validate_pixel_counts(&mut self.alerts),
       #[cfg(any(debug_assertions, feature = "debug-mode"))]
@@ -290,20 +466,30 @@ impl App {
 
   fn action_activate_tool(&mut self, pos: Vector2<f64>, mods: KeyMods) {
     let Some(interface) = self.interface.as_ref() else { return };
+    if let Some(canvas) = &mut self.canvas {
+      // Clicks on the adjacency editor and list are not meant for the tool underneath them
+      if canvas.click_panel(interface, pos, &mut self.alerts) {
+        return;
+      };
+    };
+
     self.painting = true;
     if let Some(canvas) = &mut self.canvas {
-      if canvas.view_mode() == ViewMode::Adjacencies && canvas.tool.adjacency_brush.is_none() {
+      // Selecting and editing adjacencies does not need a brush
+      let needs_brush = !mods.ctrl && !canvas.has_selected_connection();
+      if canvas.view_mode() == ViewMode::Adjacencies && canvas.tool.adjacency_brush.is_none() && needs_brush {
         self.alerts.push(Err("No Adjacency brush selected"));
       } else {
-        canvas.activate_tool(interface, pos, mods.shift);
+        canvas.activate_tool(interface, pos, mods, &mut self.alerts);
       };
     };
   }
 
-  fn action_deactivate_tool(&mut self) {
+  fn action_deactivate_tool(&mut self, pos: Vector2<f64>) {
+    let Some(interface) = self.interface.as_ref() else { return };
     self.painting = false;
     if let Some(canvas) = &mut self.canvas {
-      canvas.deactivate_tool();
+      canvas.deactivate_tool(interface, pos);
     };
   }
 
@@ -328,10 +514,75 @@ impl App {
     };
   }
 
+  fn action_generate_map(&mut self) {
+    if self.task.is_some() {
+      return self.alerts.push(Err("Please wait for the current operation to finish"));
+    };
+
+    if self.is_canvas_modified() && msg_dialog_unsaved_changes() {
+      self.raw_save_map_blocking();
+    };
+
+    let Some(land_path) = file_dialog_open_image("Open Land Image") else { return };
+    let terrain_path = file_dialog_open_image("Open Terrain Image (Cancel to skip)");
+    if let Some(location) = file_dialog_save(false) {
+      let task = Task::spawn(format!("Generating map from {}", land_path.display()), move |progress| {
+        Canvas::generate_data(&land_path, terrain_path.as_deref(), progress)
+      });
+
+      self.painting = false;
+      self.task = Some(AppTask::Generate(task, location));
+    };
+  }
+
+  fn action_go_to(&mut self) {
+    self.go_to_prompt = Some(TextInput::new(InputFilter::Any, 32));
+  }
+
+  fn action_confirm_go_to(&mut self) {
+    let Some(interface) = self.interface.as_ref() else { return };
+    let (Some(canvas), Some(go_to_prompt)) = (&mut self.canvas, &self.go_to_prompt) else { return };
+    // The prompt stays open if nothing was found, so that the value can be corrected
+    if canvas.go_to(interface, go_to_prompt.text(), &mut self.alerts) {
+      self.go_to_prompt = None;
+    };
+  }
+
+  fn action_new_map(&mut self) {
+    if self.task.is_some() {
+      return self.alerts.push(Err("Please wait for the current operation to finish"));
+    };
+
+    if self.is_canvas_modified() && msg_dialog_unsaved_changes() {
+      self.raw_save_map_blocking();
+    };
+
+    self.new_map_prompt = Some(NewMapPrompt::default());
+  }
+
+  fn action_confirm_new_map(&mut self) {
+    let Some(new_map_prompt) = &self.new_map_prompt else { return };
+    let result = new_map_prompt.dimensions().and_then(|[width, height]| {
+      Canvas::new_blank_data(width, height, new_map_prompt.kind).map(|data| (data, width, height))
+    });
+
+    match result {
+      Ok((data, width, height)) => {
+        self.new_map_prompt = None;
+        self.painting = false;
+        self.canvas = Some(Canvas::new(data, None));
+        self.alerts.push(Ok(format!("Created a new {}x{} map", width, height)));
+      },
+      // The prompt stays open so that the values can be corrected
+      Err(err) => self.handle_result_none(Err(err))
+    };
+  }
+
   fn action_save_map(&mut self) {
-    if let Some(canvas) = &self.canvas {
-      let location = canvas.location().clone();
-      self.raw_save_map_at(location);
+    match self.canvas.as_ref().map(|canvas| canvas.location().cloned()) {
+      Some(Some(location)) => self.raw_save_map_at(location),
+      Some(None) => self.action_save_map_as(false),
+      None => ()
     };
   }
 
@@ -345,8 +596,11 @@ impl App {
 
   fn action_reveal_map(&mut self) {
     if let Some(canvas) = &self.canvas {
-      let path = canvas.location().as_path();
-      let result = reveal_in_file_browser(path);
+      let result = match canvas.location() {
+        Some(location) => reveal_in_file_browser(location.as_path()),
+        None => Err(Error::from("This map has not been saved yet"))
+      };
+
       self.handle_result_none(result);
     };
   }
@@ -367,34 +621,154 @@ impl App {
     };
   }
 
+  fn action_export_selection_ids(&mut self) {
+    if let Some(canvas) = &self.canvas {
+      if let Some(path) = file_dialog_save_txt("selection") {
+        canvas.export_selection_ids(path, &mut self.alerts);
+      };
+    };
+  }
+
   fn raw_open_map_at(&mut self, location: impl IntoLocation) {
-    let result = crate::try_block!{
-      let location = location.into_location()?;
-      let success_message = format!("Loaded map from {}", location);
-      let canvas = Canvas::load(location)?;
-      self.canvas = Some(canvas);
-      Ok(success_message)
+    let location = match location.into_location() {
+      Ok(location) => location,
+      Err(err) => return self.handle_result_none(Err(err.into()))
     };
 
-    self.handle_result(result);
+    match &self.task {
+      Some(AppTask::Save(..)) => self.pending_open = Some(location),
+      Some(AppTask::Load(..) | AppTask::Generate(..)) => self.alerts.push(Err("A map is already being loaded")),
+      None => {
+        let task = {
+          let location = location.clone();
+          Task::spawn(format!("Loading map from {}", location), move |progress| {
+            Canvas::load_data(&location, progress)
+          })
+        };
+
+        self.painting = false;
+        self.task = Some(AppTask::Load(task, location));
+      }
+    };
   }
 
   fn raw_save_map_at(&mut self, location: impl IntoLocation) {
+    if self.task.is_some() {
+      return self.alerts.push(Err("Please wait for the current operation to finish"));
+    };
+
     let result = crate::try_block!{
       let canvas = self.canvas.as_mut()
         .ok_or_else(|| Error::from("no canvas loaded"))?;
       let location = location.into_location()?;
-      let mut success_message = format!("Saved map to {}", location);
-      let save_operation = canvas.save(&location)?;
-      if save_operation.had_id_changes {
-        success_message.push_str("\nThe most recent save included modified province IDs, see 'id_changes.txt' for more info");
-        success_message.push_str("\nIf you do not need province IDs to be preserved, you may disable it in the config")
+      let save_job = canvas.prepare_save()?;
+      let task = {
+        let location = location.clone();
+        Task::spawn(format!("Saving map to {}", location), move |progress| {
+          save_job.write(&location, progress)
+        })
+      };
+
+      Ok(AppTask::Save(task, location))
+    };
+
+    match result {
+      Ok(task) => {
+        self.painting = false;
+        self.task = Some(task);
+      },
+      Err(err) => self.handle_result_none(Err(err))
+    };
+  }
+
+  /// Saves the map to its current location on this thread, for when the application is exiting
+  fn raw_save_map_blocking(&mut self) {
+    let result = crate::try_block!{
+      let canvas = self.canvas.as_mut()
+        .ok_or_else(|| Error::from("no canvas loaded"))?;
+      let location = match canvas.location() {
+        Some(location) => location.clone(),
+        None => file_dialog_save(false).ok_or_else(|| Error::from("no location was chosen to save the map to"))?
       };
 
-      Ok(success_message)
+      let save_operation = canvas.prepare_save()?.write(&location, &Progress::new())?;
+      canvas.finish_save(location);
+      Ok(save_operation)
     };
 
-    self.handle_result(result);
+    self.handle_result_none(result.map(|_| ()));
+  }
+
+  fn action_cancel_task(&mut self) {
+    if let Some(AppTask::Load(task, _) | AppTask::Generate(task, _)) = &self.task {
+      task.progress().cancel();
+    };
+  }
+
+  fn poll_task(&mut self) {
+    let result = match &mut self.task {
+      Some(task) => task.poll(),
+      None => return
+    };
+
+    if result.is_some() {
+      self.finish_task(result);
+    };
+  }
+
+  fn finish_task(&mut self, result: Option<TaskResult>) {
+    let Some(task) = self.task.take() else { return };
+    let Some(result) = result else { return };
+    match (task, result) {
+      (AppTask::Load(_, location), TaskResult::Load(result)) => {
+        let result = result.map(|data| {
+          let success_message = format!("Loaded map from {}", location);
+          self.canvas = Some(Canvas::new(*data, Some(location)));
+          success_message
+        });
+
+        match result {
+          Err(Error::Cancelled) => self.alerts.push(Ok("Cancelled loading map")),
+          result => self.handle_result(result)
+        };
+      },
+      (AppTask::Generate(_, location), TaskResult::Load(result)) => {
+        let result = result.map(|data| {
+          let success_message = format!("Generated a new map, it will be saved to {}", location);
+          let mut canvas = Canvas::new(*data, Some(location));
+          canvas.modified = true;
+          self.canvas = Some(canvas);
+          success_message
+        });
+
+        match result {
+          Err(Error::Cancelled) => self.alerts.push(Ok("Cancelled generating map")),
+          result => self.handle_result(result)
+        };
+      },
+      (AppTask::Save(_, location), TaskResult::Save(result)) => {
+        let result = result.map(|save_operation| {
+          let mut success_message = format!("Saved map to {}", location);
+          if save_operation.had_id_changes {
+            success_message.push_str("\nThe most recent save included modified province IDs, see 'id_changes.txt' for more info");
+            success_message.push_str("\nIf you do not need province IDs to be preserved, you may disable it in the config")
+          };
+
+          if let Some(canvas) = &mut self.canvas {
+            canvas.finish_save(location);
+          };
+
+          success_message
+        });
+
+        let succeeded = result.is_ok();
+        self.handle_result(result);
+        if let Some(location) = self.pending_open.take().filter(|_| succeeded) {
+          self.raw_open_map_at(location);
+        };
+      },
+      (_, _) => unreachable!()
+    };
   }
 
   fn handle_result_none(&mut self, result: Result<(), Error>) {
@@ -419,10 +793,193 @@ impl fmt::Debug for App {
       .field("glyph_cache", &format_args!("..."))
       .field("interface", &self.interface)
       .field("painting", &self.painting)
+      .field("task", &self.task)
+      .field("pending_open", &self.pending_open)
+      .field("new_map_prompt", &self.new_map_prompt)
+      .field("go_to_prompt", &self.go_to_prompt)
       .finish()
   }
 }
 
+impl AppTask {
+  fn poll(&mut self) -> Option<TaskResult> {
+    match self {
+      AppTask::Load(task, _) | AppTask::Generate(task, _) => task.poll().map(|result| TaskResult::Load(result.map(Box::new))),
+      AppTask::Save(task, _) => task.poll().map(TaskResult::Save)
+    }
+  }
+
+  fn join(&mut self) -> Option<TaskResult> {
+    match self {
+      AppTask::Load(task, _) | AppTask::Generate(task, _) => task.join().map(|result| TaskResult::Load(result.map(Box::new))),
+      AppTask::Save(task, _) => task.join().map(TaskResult::Save)
+    }
+  }
+
+  fn cancel(&self) {
+    if let AppTask::Load(task, _) | AppTask::Generate(task, _) = self {
+      task.progress().cancel();
+    };
+  }
+
+  fn status(&self) -> (String, Option<f32>) {
+    match self {
+      AppTask::Load(task, _) | AppTask::Generate(task, _) => {
+        let text = format!("{}\nPress Escape to cancel", task.status_text());
+        (text, task.progress().stage().1)
+      },
+      AppTask::Save(task, _) => {
+        (task.status_text(), task.progress().stage().1)
+      }
+    }
+  }
+}
+
+enum TaskResult {
+  Load(Result<Box<CanvasData>, Error>),
+  Save(Result<SaveOperation, Error>)
+}
+
+/// Draws a box of text in the middle of the screen, with a progress bar if a fraction is given.
+/// Returns where each line of text starts on its baseline.
+fn draw_message_box(
+  ctx: Context,
+  interface: &Interface,
+  text: &str,
+  fraction: Option<f32>,
+  glyph_cache: &mut FontGlyphCache,
+  gl: &mut GlGraphics
+) -> Vec<Vector2<f64>> {
+  use graphics::Transformed;
+  use self::interface::PADDING;
+
+  const BAR_HEIGHT: f64 = 6.0;
+
+  let v_metrics = font::get_v_metrics();
+  let line_height = (v_metrics.ascent - v_metrics.descent).round();
+  let lines = text.lines().count();
+  let text_width = text.lines()
+    .map(font::get_width_metric_str)
+    .fold(0.0, f64::max);
+  let width = (text_width + PADDING[0] * 4.0).round();
+  let height = (line_height * lines as f64 + PADDING[1] * 4.0 + BAR_HEIGHT).round();
+  let [center_x, center_y] = interface.get_window_center();
+  let [x, y] = [(center_x - width / 2.0).round(), (center_y - height / 2.0).round()];
+  graphics::rectangle(colors::OVERLAY_T, [x, y, width, height], ctx.transform, gl);
+
+  let mut line_positions = Vec::with_capacity(lines);
+  for (i, line) in text.lines().enumerate() {
+    let pos = [x + PADDING[0] * 2.0, y + PADDING[1] * 2.0 + v_metrics.ascent + line_height * i as f64];
+    let transform = ctx.transform.trans_pos(pos);
+    graphics::text(colors::WHITE, font::FONT_SIZE, line, glyph_cache, transform, gl)
+      .expect("unable to draw text");
+    line_positions.push(pos);
+  };
+
+  if let Some(fraction) = fraction {
+    let bar_width = width - PADDING[0] * 4.0;
+    let bar_y = y + height - PADDING[1] * 2.0 - BAR_HEIGHT;
+    graphics::rectangle(colors::NEUTRAL, [x + PADDING[0] * 2.0, bar_y, bar_width, BAR_HEIGHT], ctx.transform, gl);
+    graphics::rectangle(colors::PROGRESS, [x + PADDING[0] * 2.0, bar_y, bar_width * fraction as f64, BAR_HEIGHT], ctx.transform, gl);
+  };
+
+  line_positions
+}
+
+const GO_TO_LABEL: &str = "Find: ";
+const GO_TO_HELP: &str = "A province ID like 8123, a color like #ff8000 or 255 128 0, or a position like 1024, 512\nEnter to go, Escape to cancel";
+
+/// The size and kind of a new map, as they are being typed in
+#[derive(Debug, Clone)]
+struct NewMapPrompt {
+  /// The width and height, as typed
+  fields: [TextInput; 2],
+  /// Which line of the prompt is selected, the width, the height or the type
+  selected: usize,
+  kind: DefinitionKind
+}
+
+impl NewMapPrompt {
+  const LABELS: [&'static str; 2] = ["Width: ", "Height: "];
+
+  fn on_key(&mut self, key: Key, mods: KeyMods) -> InputResponse {
+    let response = match self.fields.get_mut(self.selected) {
+      Some(field) => field.on_key(key, mods),
+      None => match key {
+        Key::Return | Key::NumPadEnter => InputResponse::Submit,
+        Key::Escape => InputResponse::Cancel,
+        key => InputResponse::Ignored(key)
+      }
+    };
+
+    match response {
+      InputResponse::Ignored(Key::Tab | Key::Down) => self.selected = (self.selected + 1) % 3,
+      InputResponse::Ignored(Key::Up) => self.selected = (self.selected + 2) % 3,
+      InputResponse::Ignored(Key::Right) => self.kind = match self.kind {
+        DefinitionKind::Land => DefinitionKind::Sea,
+        DefinitionKind::Sea => DefinitionKind::Lake,
+        DefinitionKind::Lake => DefinitionKind::Land
+      },
+      InputResponse::Ignored(Key::Left) => self.kind = match self.kind {
+        DefinitionKind::Land => DefinitionKind::Lake,
+        DefinitionKind::Sea => DefinitionKind::Land,
+        DefinitionKind::Lake => DefinitionKind::Sea
+      },
+      _ => ()
+    };
+
+    response
+  }
+
+  fn on_text(&mut self, text: &str) {
+    if let Some(field) = self.fields.get_mut(self.selected) {
+      field.on_text(text);
+    };
+  }
+
+  /// The width and height of the new map, which must be multiples of 64 like HOI4 requires
+  fn dimensions(&self) -> Result<Vector2<u32>, Error> {
+    let [width, height] = self.fields.each_ref().map(|field| field.parse::<u32>().unwrap_or(0));
+    if width == 0 || width % 64 != 0 {
+      Err(Problem::InvalidWidth.to_string().into())
+    } else if height == 0 || height % 64 != 0 {
+      Err(Problem::InvalidHeight.to_string().into())
+    } else {
+      Ok([width, height])
+    }
+  }
+
+  fn text(&self) -> String {
+    let kind = self.kind.to_str().to_uppercase();
+    let kind = if self.selected == 2 { format!("< {} >", kind) } else { kind };
+    format!(
+      "New Map\n{}{}\n{}{}\nType: {}\nTab to switch fields, Left/Right to change type\nEnter to create, Escape to cancel",
+      Self::LABELS[0], self.fields[0].text(), Self::LABELS[1], self.fields[1].text(), kind
+    )
+  }
+
+  /// Draws the caret of the selected field, given where each line of `text` was drawn
+  fn draw_overlay(&self, ctx: Context, line_positions: &[Vector2<f64>], gl: &mut GlGraphics) {
+    if let Some(field) = self.fields.get(self.selected) {
+      let [x, y] = line_positions[self.selected + 1];
+      field.draw_overlay(ctx, [x + font::get_width_metric_str(Self::LABELS[self.selected]), y], gl);
+    };
+  }
+}
+
+impl Default for NewMapPrompt {
+  fn default() -> NewMapPrompt {
+    NewMapPrompt {
+      fields: [
+        TextInput::new(InputFilter::Digits, 5).with_text("5632"),
+        TextInput::new(InputFilter::Digits, 5).with_text("2048")
+      ],
+      selected: 0,
+      kind: DefinitionKind::Sea
+    }
+  }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct InterfaceDrawContext {
   pub view_mode: Option<ViewMode>,
@@ -442,6 +999,26 @@ fn file_dialog_save_bmp(filename: &str) -> Option<PathBuf> {
     .save_file()
 }
 
+fn file_dialog_save_txt(filename: &str) -> Option<PathBuf> {
+  let root = env::current_dir()
+    .unwrap_or_else(|_| PathBuf::from("./"));
+  FileDialog::new()
+    .set_directory(&root)
+    .set_file_name(format!("{}.txt", filename))
+    .add_filter("Text", &["txt"])
+    .save_file()
+}
+
+fn file_dialog_open_image(title: &str) -> Option<PathBuf> {
+  let root = env::current_dir()
+    .unwrap_or_else(|_| PathBuf::from("./"));
+  FileDialog::new()
+    .set_title(title)
+    .set_directory(&root)
+    .add_filter("Image", &["bmp", "png"])
+    .pick_file()
+}
+
 fn file_dialog_save(archive: bool) -> Option<Location> {
   let root = env::current_dir()
     .unwrap_or_else(|_| PathBuf::from("./"));
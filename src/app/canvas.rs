@@ -1,11 +1,18 @@
-use ahash::AHashSet;
+mod adjacency_editor;
+mod adjacency_list;
+mod go_to;
+mod inspector;
+
+use ahash::{AHashMap, AHashSet};
+use defy::Contextualize;
 use graphics::Transformed;
 use graphics::types::Color as DrawColor;
 use graphics::context::Context;
 use graphics::ellipse::Ellipse;
-use image::RgbImage;
+use image::{DynamicImage, RgbImage};
 use itertools::Itertools;
 use opengl_graphics::{Filter, GlGraphics, Texture, TextureSettings};
+use piston::input::Key;
 use uord::UOrd2 as UOrd;
 use vecmath::{Matrix2x3, Vector2};
 
@@ -15,16 +22,32 @@ use super::map::*;
 use super::interface::Interface;
 use super::format::DefinitionKind;
 use crate::config::Config;
+use crate::events::KeyMods;
 use crate::font::{self, FONT_SIZE};
 use crate::util::stringify_color;
 use crate::util::files::Location;
+use crate::util::task::Progress;
 use crate::error::Error;
+use self::adjacency_editor::{AdjacencyEditor, EditorClick};
+use self::adjacency_list::{AdjacencyList, ListClick};
+use self::go_to::GoToTarget;
+use self::inspector::Inspector;
 
 use std::path::Path;
-use std::io::BufWriter;
+use std::io::{BufWriter, Write};
 use std::fmt;
 
 const ZOOM_SENSITIVITY: f64 = 0.125;
+/// How long a province is flashed for after going to it, in seconds
+const FLASH_DURATION: f32 = 2.0;
+/// How many times per second a flashing province blinks
+const FLASH_RATE: f32 = 4.0;
+/// The radius of the handles at the start and stop of the selected adjacency, in screen pixels
+const HANDLE_RADIUS: f64 = 5.0;
+/// How close to an adjacency line a click must be to select it, in screen pixels
+const ADJACENCY_PICK_RANGE: f64 = 8.0;
+/// The length in screen pixels of each dash, and of each gap between them, of a suggested strait
+const DASH_LENGTH: f64 = 6.0;
 
 pub struct Canvas {
   bundle: Bundle,
@@ -34,28 +57,69 @@ pub struct Canvas {
   view_mode: ViewMode,
   problems: Vec<Problem>,
   unknown_terrains: Option<AHashSet<String>>,
-  location: Location,
+  /// Where the map was loaded from or last saved to, a new map has no location until it is first saved
+  location: Option<Location>,
   show_province_ids: bool,
   show_province_boundaries: bool,
   show_river_overlay: bool,
+  inspector: Inspector,
+  adjacency_editor: AdjacencyEditor,
+  adjacency_list: AdjacencyList,
+  /// Straits suggested by `suggest_straits` that have not yet been accepted or rejected
+  suggestions: Vec<StraitSuggestion>,
+  /// Continents previewed by `preview_continents` that have not yet been applied or discarded
+  continent_preview: Option<AHashMap<Color, u16>>,
+  /// The province that was last gone to, and how much longer its outline is flashed for
+  flash: Option<(Color, f32)>,
   pub tool: ToolSettings,
   pub modified: bool,
   pub camera: Camera
 }
 
 impl Canvas {
-  pub fn load(location: Location) -> Result<Canvas, Error> {
-    let bundle = Bundle::load(&location, Config::load()?)?;
-    let history = History::new(bundle.config.max_undo_states, &bundle.map);
-    let texture_settings = TextureSettings::new().mag(Filter::Nearest);
-    let texture = Texture::from_image(&bundle.texture_buffer_color(), &texture_settings);
+  /// Loads and analyzes a map, this does not touch any graphics state and may be done on a worker thread
+  pub fn load_data(location: &Location, progress: &Progress) -> Result<CanvasData, Error> {
+    let bundle = Bundle::load(location, Config::load()?, progress)?;
+    progress.check()?;
+    progress.set_stage("Analyzing map");
     // The test map is very small with large ocean provinces, the 'too large box' errors go nuts
     let problems = if cfg!(any(debug_assertions, feature = "debug-mode")) { Vec::new() } else { bundle.generate_problems() };
     let unknown_terrains = bundle.search_unknown_terrains();
+    Ok(CanvasData { bundle, problems, unknown_terrains })
+  }
+
+  /// Generates a new map from a land image and an optional terrain image, like `load_data` this may be done on a worker thread
+  pub fn generate_data(land_path: &Path, terrain_path: Option<&Path>, progress: &Progress) -> Result<CanvasData, Error> {
+    progress.set_stage("Reading images");
+    let land_image = image::open(land_path)?.into_rgb8();
+    let terrain_image = terrain_path.map(image::open).transpose()?.map(DynamicImage::into_rgb8);
+    progress.check()?;
+    let bundle = Bundle::generate(&land_image, terrain_image.as_ref(), Config::load()?, progress)?;
+    progress.check()?;
+    progress.set_stage("Analyzing map");
+    let problems = bundle.generate_problems();
+    let unknown_terrains = bundle.search_unknown_terrains();
+    Ok(CanvasData { bundle, problems, unknown_terrains })
+  }
+
+  /// Creates a new map consisting of a single province, this does not touch any graphics state
+  pub fn new_blank_data(width: u32, height: u32, kind: DefinitionKind) -> Result<CanvasData, Error> {
+    let bundle = Bundle::new_blank(width, height, kind, Config::load()?)?;
+    // A single province covering the whole map is only ever a starting point, there is no point in listing problems with it
+    let problems = Vec::new();
+    let unknown_terrains = bundle.search_unknown_terrains();
+    Ok(CanvasData { bundle, problems, unknown_terrains })
+  }
+
+  pub fn new(data: CanvasData, location: Option<Location>) -> Canvas {
+    let CanvasData { bundle, problems, unknown_terrains } = data;
+    let history = History::new(bundle.config.max_undo_states, &bundle.map);
+    let texture_settings = TextureSettings::new().mag(Filter::Nearest);
+    let texture = Texture::from_image(&bundle.texture_buffer_color(), &texture_settings);
     let show_province_ids = bundle.config.preserve_ids;
     let camera = Camera::new(&texture);
 
-    Ok(Canvas {
+    Canvas {
       bundle,
       history,
       texture,
@@ -68,25 +132,34 @@ impl Canvas {
       show_province_ids,
       show_province_boundaries: false,
       show_river_overlay: false,
+      inspector: Inspector::default(),
+      adjacency_editor: AdjacencyEditor::default(),
+      adjacency_list: AdjacencyList::default(),
+      suggestions: Vec::new(),
+      continent_preview: None,
+      flash: None,
       modified: false,
       camera
-    })
+    }
   }
 
-  pub fn save(&mut self, location: &Location) -> Result<SaveOperation, Error> {
+  /// Prepares the map to be saved, the returned job should then be written to the target location
+  pub fn prepare_save(&mut self) -> Result<SaveJob, Error> {
     if self.bundle.config.generate_coastal_on_save {
       self.history.calculate_coastal_provinces(&mut self.bundle);
     };
 
-    let save_operation = self.bundle.save(location)?;
-    self.location = location.clone();
-    self.modified = false;
+    self.bundle.prepare_save()
+  }
 
-    Ok(save_operation)
+  /// Called once a save job prepared by `prepare_save` has been successfully written
+  pub fn finish_save(&mut self, location: Location) {
+    self.location = Some(location);
+    self.modified = false;
   }
 
-  pub fn location(&self) -> &Location {
-    &self.location
+  pub fn location(&self) -> Option<&Location> {
+    self.location.as_ref()
   }
 
   pub fn view_mode(&self) -> ViewMode {
@@ -94,7 +167,7 @@ impl Canvas {
   }
 
   pub fn set_location(&mut self, location: Location) {
-    self.location = location;
+    self.location = Some(location);
   }
 
   pub fn config(&self) -> &Config {
@@ -119,10 +192,25 @@ impl Canvas {
       graphics::image(texture_overlay, transform, gl);
     };
 
-    if self.camera.scale_factor() > 1.0 && self.show_province_boundaries {
+    let boundaries_visible = self.camera.scale_factor() > 1.0 && self.show_province_boundaries;
+    let selection_visible = !self.tool.selection.is_empty();
+    let flash_visible = self.flash.filter(|&(_, remaining)| (remaining * FLASH_RATE) as u32 % 2 == 1);
+    if boundaries_visible || selection_visible || flash_visible.is_some() || self.view_mode == ViewMode::Adjacencies {
+      self.bundle.map.update_boundaries();
+    };
+
+    if boundaries_visible {
       self.draw_boundaries(ctx, interface, gl);
     };
 
+    if selection_visible {
+      self.draw_outline(ctx, interface, self.tool.selection.iter().copied(), colors::WHITE, 1.5, gl);
+    };
+
+    if let Some((which, _)) = flash_visible {
+      self.draw_outline(ctx, interface, std::iter::once(which), colors::FLASH, 2.5, gl);
+    };
+
     if self.view_mode == ViewMode::Adjacencies {
       self.draw_adjacencies(ctx, interface, cursor_pos, gl);
     } else if self.camera.scale_factor() > 1.0 && self.show_province_ids {
@@ -133,6 +221,21 @@ impl Canvas {
 
     self.draw_tool(ctx, interface, cursor_pos, gl);
 
+    if self.inspector.visible {
+      if let Some(which) = self.inspected_province(interface, cursor_pos) {
+        self.inspector.draw(ctx, interface, &self.bundle, which, glyph_cache, gl);
+      };
+    };
+
+    if let Some(rel) = self.selected_connection() {
+      self.adjacency_editor.draw(ctx, interface, &self.bundle.map, rel, glyph_cache, gl);
+    };
+
+    if self.adjacency_list.visible {
+      let selected = self.selected_connection();
+      self.adjacency_list.draw(ctx, interface, &self.bundle.map, selected, glyph_cache, gl);
+    };
+
     let camera_info = self.camera_info(interface, cursor_pos);
     let pos = [PADDING[0] + interface.get_sidebar_width() as f64, interface.get_window_size()[1] - PADDING[1] * 1.25];
     let transform = ctx.transform.trans_pos(pos);
@@ -194,42 +297,96 @@ impl Canvas {
       };
     };
 
+    // Draw suggested straits as dashed lines, with the one under the cursor highlighted along with its through province
+    let hovered = cursor_pos.and_then(|cursor_pos| self.hovered_suggestion(interface, cursor_pos));
+    for (i, suggestion) in self.suggestions.iter().enumerate() {
+      if !is_suggestion_valid(&self.bundle.map, suggestion) {
+        continue;
+      };
+
+      let [start, stop] = suggestion.positions().map(|pos| self.camera.compute_position(interface, pos));
+      let color = if hovered == Some(i) { colors::WHITE } else { ConnectionKind::Strait.draw_color() };
+      draw_dashed_line(ctx, color, 2.0, start, stop, gl);
+    };
+
+    if let Some(suggestion) = hovered.map(|i| self.suggestions[i]) {
+      self.draw_outline(ctx, interface, std::iter::once(suggestion.through), colors::WHITE, 1.5, gl);
+    };
+
+    // Draw the selected adjacency on top of the others, with its through province outlined and handles at either end
+    if let Some(rel) = self.selected_connection() {
+      let connection_data = self.bundle.map.get_connection(rel);
+      if let Some(through) = connection_data.through {
+        let through = self.bundle.map.get_province_by_id(through).color;
+        self.draw_outline(ctx, interface, std::iter::once(through), colors::WHITE, 1.5, gl);
+      };
+
+      let endpoints = self.connection_endpoints(interface, rel, cursor_pos);
+      graphics::line_from_to(colors::WHITE, 3.0, endpoints[0], endpoints[1], ctx.transform, gl);
+      for endpoint in endpoints {
+        let ellipse = Ellipse::new(colors::WHITE).resolution(8);
+        let r = HANDLE_RADIUS;
+        ellipse.draw_from_to([r, r], [-r, -r], &Default::default(), ctx.transform.trans_pos(endpoint), gl);
+      };
+    };
+
     // Draw impassible adjacencies as black boundaries
-    for (boundary, is_special) in self.bundle.map.iter_boundaries() {
-      if is_special {
-        let rel = boundary.map(|pos| self.bundle.map.get_color_at(pos));
-        if self.bundle.map.get_connection(rel).kind == ConnectionKind::Impassable {
-          let [b1, b2] = boundary_to_line(boundary).into_array();
-          let b1 = self.camera.compute_position(interface, [b1[0] as f64, b1[1] as f64]);
-          let b2 = self.camera.compute_position(interface, [b2[0] as f64, b2[1] as f64]);
-          if self.camera.within_viewport(interface, b1) || self.camera.within_viewport(interface, b2) {
-            graphics::line_from_to(colors::ADJ_IMPASSABLE, 2.0, b1, b2, ctx.transform, gl);
-          };
-        };
+    let visible_extents = self.camera.visible_extents(interface);
+    for polyline in self.bundle.map.iter_boundaries_within(visible_extents) {
+      let is_impassable = self.bundle.map.has_connection(polyline.rel) &&
+        self.bundle.map.get_connection(polyline.rel).kind == ConnectionKind::Impassable;
+      if is_impassable {
+        self.draw_polyline(ctx, interface, colors::ADJ_IMPASSABLE, 2.0, &polyline.points, gl);
       };
     };
   }
 
   fn draw_boundaries(&self, ctx: Context, interface: &Interface, gl: &mut GlGraphics) {
-    for (boundary, _is_special) in self.bundle.map.iter_boundaries() {
-      let [b1, b2] = boundary_to_line(boundary).into_array();
-      let b1 = self.camera.compute_position(interface, [b1[0] as f64, b1[1] as f64]);
-      let b2 = self.camera.compute_position(interface, [b2[0] as f64, b2[1] as f64]);
-      if self.camera.within_viewport(interface, b1) || self.camera.within_viewport(interface, b2) {
-        let color = match self.view_mode {
-          ViewMode::Color | ViewMode::Adjacencies => {
-            drawable_color(boundary_color(&self.bundle.map, boundary))
-          },
-          ViewMode::Kind | ViewMode::Terrain => colors::BLACK,
-          ViewMode::Continent => colors::WHITE,
-          ViewMode::Coastal => colors::NEUTRAL
-        };
+    let visible_extents = self.camera.visible_extents(interface);
+    for polyline in self.bundle.map.iter_boundaries_within(visible_extents) {
+      let color = match self.view_mode {
+        ViewMode::Color | ViewMode::Adjacencies => {
+          drawable_color(boundary_color(&self.bundle.map, polyline.rel))
+        },
+        ViewMode::Kind | ViewMode::Terrain => colors::BLACK,
+        ViewMode::Continent => colors::WHITE,
+        ViewMode::Coastal => colors::NEUTRAL
+      };
+
+      self.draw_polyline(ctx, interface, color, 1.0, &polyline.points, gl);
+    };
+  }
 
-        graphics::line_from_to(color, 1.0, b1, b2, ctx.transform, gl);
+  /// Outlines a group of provinces, like the selection, by drawing every border between a province inside and outside of it
+  fn draw_outline(
+    &self,
+    ctx: Context,
+    interface: &Interface,
+    provinces: impl Iterator<Item = Color>,
+    color: DrawColor,
+    radius: f64,
+    gl: &mut GlGraphics
+  ) {
+    let provinces = provinces
+      .filter_map(|color| self.bundle.map.get_province_id(color))
+      .collect::<AHashSet<ProvinceId>>();
+    let visible_extents = self.camera.visible_extents(interface);
+    for polyline in self.bundle.map.iter_boundaries_within(visible_extents) {
+      let [a, b] = polyline.rel.into_array();
+      if provinces.contains(&a) != provinces.contains(&b) {
+        self.draw_polyline(ctx, interface, color, radius, &polyline.points, gl);
       };
     };
   }
 
+  fn draw_polyline(&self, ctx: Context, interface: &Interface, color: DrawColor, radius: f64, points: &[Vector2<u32>], gl: &mut GlGraphics) {
+    for pair in points.windows(2) {
+      let b1 = self.camera.compute_position(interface, [pair[0][0] as f64, pair[0][1] as f64]);
+      let b2 = self.camera.compute_position(interface, [pair[1][0] as f64, pair[1][1] as f64]);
+      graphics::line_from_to(color, radius, b1, b2, ctx.transform, gl);
+    };
+  }
+
   fn draw_problems(&self, ctx: Context, interface: &Interface, gl: &mut GlGraphics) {
     let extras = self.bundle.config.extra_warnings.enabled;
     for problem in self.problems.iter() {
@@ -238,7 +395,7 @@ impl Canvas {
   }
 
   fn draw_tool(&self, ctx: Context, interface: &Interface, cursor_pos: Option<Vector2<f64>>, gl: &mut GlGraphics) {
-    let color = if self.tool.color_brush.is_some() { colors::WHITE } else { colors::WHITE_T };
+    let color = if self.has_brush() { colors::WHITE } else { colors::WHITE_T };
     match (self.view_mode, &self.tool.mode, cursor_pos) {
       (ViewMode::Color, ToolMode::PaintArea, Some(cursor_pos)) => {
         let ellipse = Ellipse::new_border(color, 0.5).resolution(16);
@@ -246,33 +403,73 @@ impl Canvas {
         let transform = ctx.transform.trans_pos(cursor_pos);
         ellipse.draw_from_to([r, r], [-r, -r], &Default::default(), transform, gl);
       },
-      (ViewMode::Color, ToolMode::Lasso(lasso), cursor_pos) => {
-        let can_finish = cursor_pos
-          .map(|cursor_pos| lasso.can_finish(interface, &self.camera, cursor_pos))
-          .unwrap_or(false);
-        let points = lasso.iter()
-          .map(|pos| self.camera.compute_position(interface, pos))
-          .collect::<Vec<Vector2<f64>>>();
-        let first_point = points.first().cloned();
-        let last_point = if can_finish { first_point } else { cursor_pos };
-
-        if let (true, Some(first_point)) = (can_finish, first_point) {
-          let ellipse = Ellipse::new(color).resolution(6);
-          let transform = ctx.transform.trans_pos(first_point);
-          ellipse.draw_from_to([5.0, 5.0], [-5.0, -5.0], &Default::default(), transform, gl);
+      (ViewMode::Color, ToolMode::Merge, Some(cursor_pos)) => {
+        if let Some(id) = self.tool.merge_target.and_then(|target| self.bundle.map.get_province_id(target)) {
+          let pos = self.bundle.map.get_province_by_id(id).center_of_mass();
+          let pos = self.camera.compute_position(interface, pos);
+          let ellipse = Ellipse::new(colors::WHITE).resolution(6);
+          ellipse.draw_from_to([5.0, 5.0], [-5.0, -5.0], &Default::default(), ctx.transform.trans_pos(pos), gl);
+          graphics::line_from_to(colors::WHITE, 1.0, pos, cursor_pos, ctx.transform, gl);
         };
-
-        let lines = points.into_iter()
-          .chain(last_point.into_iter())
+      },
+      (ViewMode::Color | ViewMode::Kind | ViewMode::Terrain | ViewMode::Continent, ToolMode::Lasso(lasso), cursor_pos) => {
+        self.draw_lasso(ctx, interface, lasso, color, cursor_pos, gl);
+      },
+      (ViewMode::Color | ViewMode::Kind | ViewMode::Terrain | ViewMode::Continent, ToolMode::Rectangle, Some(cursor_pos)) => {
+        self.draw_rectangle(ctx, interface, color, cursor_pos, gl);
+      },
+      (_, ToolMode::Select(lasso), cursor_pos) => {
+        self.draw_lasso(ctx, interface, lasso, colors::WHITE, cursor_pos, gl);
+        if let Some(cursor_pos) = cursor_pos {
+          self.draw_rectangle(ctx, interface, colors::WHITE, cursor_pos, gl);
+        };
+      },
+      (ViewMode::Color, ToolMode::Split(line), cursor_pos) => {
+        let lines = line.iter()
+          .map(|pos| self.camera.compute_position(interface, pos))
+          .chain(cursor_pos.filter(|_| !line.0.is_empty()))
           .tuple_windows::<(_, _)>();
         for (pos1, pos2) in lines {
-          graphics::line_from_to(color, 0.5, pos1, pos2, ctx.transform, gl);
+          graphics::line_from_to(colors::WHITE, 0.5, pos1, pos2, ctx.transform, gl);
         };
       },
       _ => ()
     };
   }
 
+  fn draw_lasso(&self, ctx: Context, interface: &Interface, lasso: &Lasso, color: DrawColor, cursor_pos: Option<Vector2<f64>>, gl: &mut GlGraphics) {
+    let can_finish = cursor_pos
+      .map(|cursor_pos| lasso.can_finish(interface, &self.camera, cursor_pos))
+      .unwrap_or(false);
+    let points = lasso.iter()
+      .map(|pos| self.camera.compute_position(interface, pos))
+      .collect::<Vec<Vector2<f64>>>();
+    let first_point = points.first().cloned();
+    let last_point = if can_finish { first_point } else { cursor_pos };
+
+    if let (true, Some(first_point)) = (can_finish, first_point) {
+      let ellipse = Ellipse::new(color).resolution(6);
+      let transform = ctx.transform.trans_pos(first_point);
+      ellipse.draw_from_to([5.0, 5.0], [-5.0, -5.0], &Default::default(), transform, gl);
+    };
+
+    let lines = points.into_iter()
+      .chain(last_point.into_iter())
+      .tuple_windows::<(_, _)>();
+    for (pos1, pos2) in lines {
+      graphics::line_from_to(color, 0.5, pos1, pos2, ctx.transform, gl);
+    };
+  }
+
+  /// Draws the rectangle being dragged out from the drag anchor, if there is one
+  fn draw_rectangle(&self, ctx: Context, interface: &Interface, color: DrawColor, cursor_pos: Vector2<f64>, gl: &mut GlGraphics) {
+    if let Some(anchor) = self.tool.drag_anchor {
+      let anchor = self.camera.compute_position(interface, anchor);
+      let rectangle = graphics::Rectangle::new_border(color, 0.5);
+      rectangle.draw_from_to(anchor, cursor_pos, &Default::default(), ctx.transform, gl);
+    };
+  }
+
   pub fn toggle_province_ids(&mut self) {
     self.show_province_ids = !self.show_province_ids;
   }
@@ -341,8 +538,9 @@ impl Canvas {
 
   pub fn undo(&mut self) {
     if let Some(commit) = self.history.undo(&mut self.bundle.map) {
-      self.bundle.map.recalculate_all_boundaries();
       self.problems.clear();
+      self.continent_preview = None;
+      self.prune_selection();
       if self.bundle.config.change_view_mode_on_undo {
         self.view_mode = commit.view_mode;
       };
@@ -352,8 +550,9 @@ impl Canvas {
 
   pub fn redo(&mut self) {
     if let Some(commit) = self.history.redo(&mut self.bundle.map) {
-      self.bundle.map.recalculate_all_boundaries();
       self.problems.clear();
+      self.continent_preview = None;
+      self.prune_selection();
       if self.bundle.config.change_view_mode_on_undo {
         self.view_mode = commit.view_mode;
       };
@@ -362,12 +561,14 @@ impl Canvas {
   }
 
   pub fn calculate_coastal_provinces(&mut self) {
+    self.continent_preview = None;
     self.history.calculate_coastal_provinces(&mut self.bundle);
     self.view_mode = ViewMode::Coastal;
     self.refresh();
   }
 
   pub fn calculate_recolor_map(&mut self) {
+    self.continent_preview = None;
     self.history.calculate_recolor_map(&mut self.bundle);
     self.view_mode = ViewMode::Color;
     self.tool.color_brush = None;
@@ -389,16 +590,19 @@ impl Canvas {
     if let (ViewMode::Terrain, Some(unknown_terrains)) = (view_mode, self.unknown_terrains()) {
       alerts.push(Err(unknown_terrains));
     } else if view_mode != self.view_mode {
-      if let ViewMode::Color | ViewMode::Adjacencies = self.view_mode {
+      if let ViewMode::Color | ViewMode::Kind | ViewMode::Terrain | ViewMode::Continent | ViewMode::Adjacencies = self.view_mode {
         self.cancel_tool();
       };
 
       self.view_mode = view_mode;
+      self.continent_preview = None;
       self.refresh();
     };
   }
 
   pub fn set_tool_mode(&mut self, mode: ToolMode) {
+    self.tool.merge_target = None;
+    self.tool.drag_anchor = None;
     self.tool.mode = mode;
   }
 
@@ -432,9 +636,9 @@ impl Canvas {
       },
       ViewMode::Continent => {
         let continent = self.tool.continent_brush;
-        let continent = cycle_continents(continent, backwards);
+        let continent = cycle_continents(continent, self.bundle.map.defined_continents(), backwards);
         self.tool.continent_brush = Some(continent);
-        alerts.push(Ok(format!("Brush set to continent {}", continent)));
+        alerts.push(Ok(format!("Brush set to continent {}", self.continent_brush_label(continent))));
       },
       ViewMode::Coastal => (),
       ViewMode::Adjacencies => {
@@ -468,7 +672,7 @@ impl Canvas {
         ViewMode::Continent => {
           let continent = province_data.continent;
           self.tool.continent_brush = Some(continent);
-          alerts.push(Ok(format!("Picked continent {}", continent)));
+          alerts.push(Ok(format!("Picked continent {}", self.bundle.map.continent_label(continent))));
         },
         ViewMode::Coastal => (),
         ViewMode::Adjacencies => ()
@@ -476,46 +680,90 @@ impl Canvas {
     };
   }
 
-  pub fn change_tool_radius(&mut self, d: f64) {
+  /// Changes the brush radius, or the number of provinces to subdivide into
+  pub fn change_tool_size(&mut self, d: f64) {
     const LIMIT: f64 = std::f64::consts::SQRT_2 / 2.0;
-    if let (ViewMode::Color, ToolMode::PaintArea) = (self.view_mode, &self.tool.mode) {
-      let r = self.tool.radius;
-      let d = d * (1.0 + 0.025 * r);
-      self.tool.radius = (r + d).max(LIMIT);
+    match (self.view_mode, &self.tool.mode) {
+      (ViewMode::Color, ToolMode::PaintArea) => {
+        let r = self.tool.radius;
+        let d = d * (1.0 + 0.025 * r);
+        self.tool.radius = (r + d).max(LIMIT);
+      },
+      (ViewMode::Color, ToolMode::Subdivide) => {
+        self.tool.subdivide_target.change(d);
+      },
+      _ => ()
     };
   }
 
   /// Activates the tool, ie, performs a left-click action
-  pub fn activate_tool(&mut self, interface: &Interface, cursor_pos: Vector2<f64>, modifier: bool) {
+  pub fn activate_tool(&mut self, interface: &Interface, cursor_pos: Vector2<f64>, mods: KeyMods, alerts: &mut Alerts) {
     match self.view_mode {
       ViewMode::Color => match self.tool.mode {
         ToolMode::PaintArea => self.tool_paint_brush(interface, cursor_pos),
-        ToolMode::PaintBucket => self.tool_paint_bucket(interface, cursor_pos, modifier),
-        ToolMode::Lasso(_) => self.tool_lasso_add_point(interface, cursor_pos)
+        ToolMode::PaintBucket => self.tool_paint_bucket(interface, cursor_pos, mods.shift),
+        ToolMode::Lasso(_) => self.tool_lasso_add_point(interface, cursor_pos),
+        ToolMode::Rectangle => self.tool_rectangle_activate(interface, cursor_pos),
+        ToolMode::Merge => self.tool_merge_activate(interface, cursor_pos),
+        ToolMode::Split(_) => self.tool_split_add_point(interface, cursor_pos),
+        ToolMode::Subdivide => self.tool_subdivide_activate(interface, cursor_pos),
+        ToolMode::Select(_) => self.tool_select_activate(interface, cursor_pos, mods)
+      },
+      ViewMode::Kind | ViewMode::Terrain | ViewMode::Continent => match self.tool.mode {
+        ToolMode::Lasso(_) => self.tool_lasso_add_point(interface, cursor_pos),
+        ToolMode::Rectangle => self.tool_rectangle_activate(interface, cursor_pos),
+        ToolMode::Select(_) => self.tool_select_activate(interface, cursor_pos, mods),
+        _ => self.tool_paint_brush(interface, cursor_pos)
       },
-      ViewMode::Adjacencies => self.tool_connect_activate(interface, cursor_pos),
-      _ => self.tool_paint_brush(interface, cursor_pos)
+      ViewMode::Adjacencies => self.tool_connect_activate(interface, cursor_pos, mods, alerts),
+      ViewMode::Coastal => ()
     };
   }
 
   /// Deactivates the tool, ie, performs a release-left-click action
-  pub fn deactivate_tool(&mut self) {
-    if let ToolMode::PaintArea = self.tool.mode {
-      self.tool_paint_end();
+  pub fn deactivate_tool(&mut self, interface: &Interface, cursor_pos: Vector2<f64>) {
+    if let Some(index) = self.tool.endpoint_drag.take() {
+      return self.tool_connect_drop_endpoint(interface, index, cursor_pos);
+    };
+
+    match self.tool.mode {
+      ToolMode::PaintArea => self.tool_paint_end(),
+      ToolMode::Rectangle => if let Some(anchor) = self.tool.drag_anchor.take() {
+        self.tool_rectangle_finish(interface, anchor, cursor_pos);
+      },
+      ToolMode::Select(_) => if let Some(anchor) = self.tool.drag_anchor.take() {
+        self.tool_select_finish(interface, anchor, cursor_pos);
+      },
+      _ => ()
     };
   }
 
   pub fn cancel_tool(&mut self) {
     self.tool.adjacency_selection = None;
-    if let ToolMode::Lasso(lasso) = &mut self.tool.mode {
+    self.tool.connection_selection = None;
+    self.tool.endpoint_drag = None;
+    self.tool.merge_target = None;
+    self.tool.drag_anchor = None;
+    if let ToolMode::Lasso(lasso) | ToolMode::Split(lasso) | ToolMode::Select(lasso) = &mut self.tool.mode {
       lasso.drain();
     };
   }
 
   pub fn finish_tool(&mut self) {
-    if let ToolMode::Lasso(lasso) = &mut self.tool.mode {
-      let lasso = lasso.drain();
-      self.tool_lasso_finish(lasso);
+    match &mut self.tool.mode {
+      ToolMode::Lasso(lasso) => {
+        let lasso = lasso.drain();
+        self.tool_lasso_finish(lasso);
+      },
+      ToolMode::Split(line) => {
+        let line = line.drain();
+        self.tool_split_finish(line);
+      },
+      ToolMode::Select(lasso) => {
+        let lasso = lasso.drain();
+        self.tool_select_lasso_finish(lasso);
+      },
+      _ => ()
     };
   }
 
@@ -537,10 +785,62 @@ impl Canvas {
     };
   }
 
+  /// Paints the pixels inside of the lasso in color mode, or every province inside of the lasso in the other map modes
   fn tool_lasso_finish(&mut self, lasso: Vec<Vector2<f64>>) {
-    if let (Some(color), ViewMode::Color) = (self.tool.color_brush, self.view_mode) {
-      if lasso.len() > 2 {
-        if let Some(extents) = self.history.paint_pixel_lasso(&mut self.bundle, lasso, color, self.tool.brush_mask) {
+    if lasso.len() > 2 {
+      match (self.view_mode, self.tool.color_brush) {
+        (ViewMode::Color, Some(color)) => {
+          if let Some(extents) = self.history.paint_pixel_lasso(&mut self.bundle, lasso, color, self.tool.brush_mask.as_ref()) {
+            self.problems.clear();
+            self.modified = true;
+            self.refresh_selective(extents);
+          };
+        },
+        (ViewMode::Kind | ViewMode::Terrain | ViewMode::Continent, _) => {
+          let mut provinces = self.bundle.map.provinces_within(lasso, self.bundle.config.shape_coverage);
+          self.paint_provinces(&mut provinces);
+        },
+        _ => ()
+      };
+    };
+  }
+
+  fn tool_rectangle_activate(&mut self, interface: &Interface, cursor_pos: Vector2<f64>) {
+    self.tool.drag_anchor = Some(self.snap_point(self.camera.relative_position(interface, cursor_pos)));
+  }
+
+  /// A rectangle is painted exactly like a lasso with four points
+  fn tool_rectangle_finish(&mut self, interface: &Interface, anchor: Vector2<f64>, cursor_pos: Vector2<f64>) {
+    let corner = self.snap_point(self.camera.relative_position(interface, cursor_pos));
+    let rectangle = vec![anchor, [corner[0], anchor[1]], corner, [anchor[0], corner[1]]];
+    self.tool_lasso_finish(rectangle);
+  }
+
+  fn snap_point(&self, point: Vector2<f64>) -> Vector2<f64> {
+    if self.tool.lasso_snap {
+      [point[0].round(), point[1].round()]
+    } else {
+      point
+    }
+  }
+
+  fn tool_split_add_point(&mut self, interface: &Interface, cursor_pos: Vector2<f64>) {
+    if let ToolMode::Split(line) = &mut self.tool.mode {
+      let point = self.camera.relative_position(interface, cursor_pos);
+      let point = if self.tool.lasso_snap {
+        [point[0].round(), point[1].round()]
+      } else {
+        point
+      };
+
+      line.push(point);
+    };
+  }
+
+  fn tool_split_finish(&mut self, line: Vec<Vector2<f64>>) {
+    if let ViewMode::Color = self.view_mode {
+      if line.len() > 1 {
+        if let Some(extents) = self.history.split_province(&mut self.bundle, line) {
           self.problems.clear();
           self.modified = true;
           self.refresh_selective(extents);
@@ -549,11 +849,391 @@ impl Canvas {
     };
   }
 
+  fn tool_subdivide_activate(&mut self, interface: &Interface, cursor_pos: Vector2<f64>) {
+    if let Some(pos) = self.camera.relative_position_int(interface, cursor_pos) {
+      if let Some(extents) = self.history.subdivide_province(&mut self.bundle, pos, self.tool.subdivide_target) {
+        self.problems.clear();
+        self.modified = true;
+        self.refresh_selective(extents);
+      };
+    };
+  }
+
+  /// Clicking starts either a click or a rectangle selection, which is completed when the mouse is released,
+  /// while holding control places the points of a lasso selection instead
+  fn tool_select_activate(&mut self, interface: &Interface, cursor_pos: Vector2<f64>, mods: KeyMods) {
+    if let ToolMode::Select(lasso) = &mut self.tool.mode {
+      self.tool.select_additive = mods.shift;
+      if mods.ctrl || !lasso.0.is_empty() {
+        if lasso.can_finish(interface, &self.camera, cursor_pos) {
+          let lasso = lasso.drain();
+          self.tool_select_lasso_finish(lasso);
+        } else {
+          lasso.push(self.camera.relative_position(interface, cursor_pos));
+        };
+      } else {
+        self.tool.drag_anchor = Some(self.camera.relative_position(interface, cursor_pos));
+      };
+    };
+  }
+
+  fn tool_select_finish(&mut self, interface: &Interface, anchor: Vector2<f64>, cursor_pos: Vector2<f64>) {
+    let distance = vecmath::vec2_len(vecmath::vec2_sub(self.camera.compute_position(interface, anchor), cursor_pos));
+    if distance < 4.0 {
+      // The mouse barely moved, so this was a click on a single province
+      if let Some(pos) = self.camera.relative_position_int(interface, cursor_pos) {
+        let which = self.bundle.map.get_color_at(pos);
+        if !self.tool.select_additive {
+          self.tool.selection.clear();
+          self.tool.selection.insert(which);
+        } else if !self.tool.selection.remove(&which) {
+          self.tool.selection.insert(which);
+        };
+      };
+    } else {
+      let corner = self.camera.relative_position(interface, cursor_pos);
+      self.tool_select_lasso_finish(vec![anchor, [corner[0], anchor[1]], corner, [anchor[0], corner[1]]]);
+    };
+  }
+
+  /// Selects every province inside of the lasso, adding to the selection if shift was held
+  fn tool_select_lasso_finish(&mut self, lasso: Vec<Vector2<f64>>) {
+    if lasso.len() > 2 {
+      let provinces = self.bundle.map.provinces_within(lasso, self.bundle.config.shape_coverage);
+      if !self.tool.select_additive {
+        self.tool.selection.clear();
+      };
+
+      self.tool.selection.extend(provinces);
+    };
+  }
+
+  /// Masks the brush to the province under the cursor, or adds that province to the provinces already masked to
+  pub fn mask_hovered_province(&mut self, interface: &Interface, cursor_pos: Vector2<f64>, add: bool, alerts: &mut Alerts) {
+    let Some(pos) = self.camera.relative_position_int(interface, cursor_pos) else { return };
+    let which = self.bundle.map.get_id_at(pos);
+    match &mut self.tool.brush_mask {
+      Some(BrushMask::Provinces(provinces)) if add => {
+        provinces.insert(which);
+      },
+      brush_mask => {
+        *brush_mask = Some(BrushMask::Provinces(AHashSet::from_iter([which])));
+      }
+    };
+
+    alerts.push(Ok(format!("Masked brush to province {}", stringify_color(self.bundle.map.get_color_at(pos)))));
+  }
+
+  pub fn mask_selection(&mut self, alerts: &mut Alerts) {
+    if self.tool.selection.is_empty() {
+      alerts.push(Err("No provinces are selected"));
+    } else {
+      let provinces = self.tool.selection.iter()
+        .filter_map(|&color| self.bundle.map.get_province_id(color))
+        .collect();
+      self.tool.brush_mask = Some(BrushMask::Provinces(provinces));
+      alerts.push(Ok(format!("Masked brush to {} selected provinces", self.tool.selection.len())));
+    };
+  }
+
+  pub fn mask_terrain_brush(&mut self, alerts: &mut Alerts) {
+    if let Some(terrain) = &self.tool.terrain_brush {
+      alerts.push(Ok(format!("Masked brush to terrain {}", terrain.to_uppercase())));
+      self.tool.brush_mask = Some(BrushMask::Terrain(terrain.clone()));
+    } else {
+      alerts.push(Err("No terrain brush selected"));
+    };
+  }
+
+  pub fn mask_continent_brush(&mut self, alerts: &mut Alerts) {
+    if let Some(continent) = self.tool.continent_brush {
+      self.tool.brush_mask = Some(BrushMask::Continent(continent));
+      alerts.push(Ok(format!("Masked brush to continent {}", self.bundle.map.continent_label(continent))));
+    } else {
+      alerts.push(Err("No continent brush selected"));
+    };
+  }
+
+  /// Removes provinces that no longer exist from the selection and the brush mask, and stops editing them in the inspector,
+  /// likewise the selected adjacency is deselected if it no longer exists
+  fn prune_selection(&mut self) {
+    let map = &self.bundle.map;
+    self.tool.selection.retain(|&color| map.get_province_id(color).is_some());
+    if let Some(provinces) = self.tool.brush_mask.as_mut().and_then(BrushMask::provinces_mut) {
+      provinces.retain(|&id| map.has_province(id));
+    };
+
+    if self.inspector.editing().is_some_and(|which| map.get_province_id(which).is_none()) {
+      self.inspector.stop_editing();
+    };
+
+    self.suggestions.retain(|suggestion| is_suggestion_valid(map, suggestion));
+
+    if self.tool.connection_selection.is_some() && self.selected_connection().is_none() {
+      self.tool.connection_selection = None;
+      self.tool.endpoint_drag = None;
+      self.adjacency_editor.stop_editing();
+    };
+  }
+
+  pub fn toggle_inspector(&mut self) {
+    self.inspector.visible = !self.inspector.visible;
+    if !self.inspector.visible {
+      self.inspector.stop_editing();
+    };
+  }
+
+  /// The province shown in the inspector, either the one being edited, the only selected province, or the hovered province
+  fn inspected_province(&self, interface: &Interface, cursor_pos: Option<Vector2<f64>>) -> Option<Color> {
+    let selected = self.tool.selection.iter()
+      .filter(|&&color| self.bundle.map.get_province_id(color).is_some())
+      .exactly_one().ok().copied();
+    let hovered = || {
+      let pos = self.camera.relative_position_int(interface, cursor_pos?)?;
+      Some(self.bundle.map.get_color_at(pos))
+    };
+
+    self.inspector.editing().or(selected).or_else(hovered)
+  }
+
+  /// Starts editing the inspected province in the inspector
+  pub fn edit_inspected_province(&mut self, interface: &Interface, cursor_pos: Option<Vector2<f64>>, alerts: &mut Alerts) {
+    let Some(which) = self.inspected_province(interface, cursor_pos) else { return };
+    let province_data = self.bundle.map.get_province(which);
+    if province_data.locked {
+      alerts.push(Err("This province is locked"));
+    } else {
+      self.inspector.start_editing(which, province_data);
+    };
+  }
+
+  /// Whether text is being typed into the inspector, the adjacency editor or the adjacency list
+  pub fn is_editing_text(&self) -> bool {
+    self.inspector.editing().is_some() || self.adjacency_editor.editing() || self.adjacency_list.editing()
+  }
+
+  /// Passes typed text to whichever of the inspector, the adjacency editor or the adjacency list is being edited
+  pub fn editor_on_text(&mut self, text: &str) {
+    self.inspector.on_text(text);
+    self.adjacency_editor.on_text(text);
+    self.adjacency_list.on_text(text);
+  }
+
+  /// Passes a key press to the inspector, the adjacency editor or the adjacency list while one of them is being edited,
+  /// applying any edit it confirms
+  pub fn editor_on_key(&mut self, key: Key, mods: KeyMods, alerts: &mut Alerts) {
+    if self.adjacency_list.editing() {
+      return self.adjacency_list.on_key(key, mods);
+    };
+
+    if self.adjacency_editor.editing() {
+      let Some(rel) = self.selected_connection() else {
+        return self.adjacency_editor.stop_editing();
+      };
+
+      match self.adjacency_editor.on_key(key, mods, self.bundle.map.get_connection(rel)) {
+        Some(Ok(edit)) => if self.edit_selected_connection(edit) {
+          alerts.push(Ok("Edited adjacency"));
+        } else {
+          alerts.push(Err("Nothing was changed, the value may already be set"));
+        },
+        Some(Err(message)) => alerts.push(Err(message)),
+        None => ()
+      };
+
+      return;
+    };
+
+    let Some(which) = self.inspector.editing() else { return };
+    match self.inspector.on_key(key, mods, &self.bundle) {
+      Some(Ok(edit)) => match self.history.edit_province(&mut self.bundle, which, edit) {
+        Some((extents, color)) => {
+          if self.tool.selection.remove(&which) {
+            self.tool.selection.insert(color);
+          };
+
+          self.modified = true;
+          self.inspector.follow_color(&self.bundle, color);
+          self.refresh_selective(extents);
+          alerts.push(Ok("Edited province"));
+        },
+        None => alerts.push(Err("Nothing was changed, the value may already be set or not valid for this province"))
+      },
+      Some(Err(message)) => alerts.push(Err(message)),
+      None => ()
+    };
+  }
+
+  /// Centers the camera on a province found by its ID, its color or a position inside of it, zooming to fit it and flashing its outline.
+  /// Returns false if no such province could be found.
+  pub fn go_to(&mut self, interface: &Interface, text: &str, alerts: &mut Alerts) -> bool {
+    let map = &self.bundle.map;
+    let result = text.parse::<GoToTarget>().and_then(|target| match target {
+      GoToTarget::Id(id) => map.iter_province_data()
+        .find(|(_, province_data)| province_data.preserved_id == Some(id))
+        .map(|(_, province_data)| province_data.color)
+        .ok_or_else(|| format!("No province has the ID {}, IDs are only known while preserve_ids is enabled", id)),
+      GoToTarget::Color(color) => map.get_province_id(color)
+        .map(|_| color)
+        .ok_or_else(|| format!("No province has the color {}", stringify_color(color))),
+      GoToTarget::Position([x, y]) if x < map.width() && y < map.height() => Ok(map.get_color_at([x, y])),
+      GoToTarget::Position([x, y]) => Err(format!("The position {}, {} is outside of the map", x, y))
+    });
+
+    match result {
+      Ok(which) => {
+        let province_data = map.get_province(which);
+        let center_of_mass = vecmath::vec2_add([0.5, 0.5], province_data.center_of_mass());
+        self.camera.focus(interface, center_of_mass, map.get_color_extents(which));
+        self.flash = Some((which, FLASH_DURATION));
+        true
+      },
+      Err(message) => {
+        alerts.push(Err(message));
+        false
+      }
+    }
+  }
+
+  pub fn tick(&mut self, dt: f32) {
+    if let Some((_, remaining)) = &mut self.flash {
+      *remaining -= dt;
+      if *remaining <= 0.0 {
+        self.flash = None;
+      };
+    };
+  }
+
+  pub fn clear_selection(&mut self) {
+    self.tool.selection.clear();
+  }
+
+  /// Applies the type, terrain or continent brush of the current view mode to every selected province at once
+  pub fn apply_brush_to_selection(&mut self, alerts: &mut Alerts) {
+    if self.tool.selection.is_empty() {
+      alerts.push(Err("No provinces are selected"));
+    } else if !matches!(self.view_mode, ViewMode::Kind | ViewMode::Terrain | ViewMode::Continent) {
+      alerts.push(Err("Brushes can only be applied to a selection in the type, terrain or continent map modes"));
+    } else if !self.has_brush() {
+      alerts.push(Err("No brush selected"));
+    } else {
+      let mut selection = std::mem::take(&mut self.tool.selection);
+      self.paint_provinces(&mut selection);
+      self.tool.selection = selection;
+    };
+  }
+
+  /// Applies the type, terrain or continent brush of the current view mode to all of the given provinces at once,
+  /// provinces that are repainted are replaced in the given set
+  fn paint_provinces(&mut self, provinces: &mut AHashSet<Color>) {
+    let extents = match self.view_mode {
+      ViewMode::Kind => self.tool.kind_brush
+        .and_then(|kind| self.history.paint_provinces_kind(&mut self.bundle, provinces, kind)),
+      ViewMode::Terrain => self.tool.terrain_brush.clone()
+        .and_then(|terrain| self.history.paint_provinces_terrain(&mut self.bundle, provinces, terrain)),
+      ViewMode::Continent => self.tool.continent_brush
+        .and_then(|continent| self.history.paint_provinces_continent(&mut self.bundle, provinces, continent)),
+      _ => None
+    };
+
+    if let Some(extents) = extents {
+      self.modified = true;
+      self.refresh_selective(extents);
+    };
+  }
+
+  /// Whether there is a brush to paint with in the current view mode
+  fn has_brush(&self) -> bool {
+    match self.view_mode {
+      ViewMode::Color => self.tool.color_brush.is_some(),
+      ViewMode::Kind => self.tool.kind_brush.is_some(),
+      ViewMode::Terrain => self.tool.terrain_brush.is_some(),
+      ViewMode::Continent => self.tool.continent_brush.is_some(),
+      ViewMode::Coastal => false,
+      ViewMode::Adjacencies => self.tool.adjacency_brush.is_some()
+    }
+  }
+
+  pub fn recolor_selection(&mut self, alerts: &mut Alerts) {
+    if self.tool.selection.is_empty() {
+      return alerts.push(Err("No provinces are selected"));
+    };
+
+    if let Some(extents) = self.history.recolor_selection(&mut self.bundle, &mut self.tool.selection) {
+      self.modified = true;
+      self.refresh_selective(extents);
+    };
+  }
+
+  /// Locks the province under the cursor, or unlocks it if it is already locked
+  pub fn toggle_lock_hovered_province(&mut self, interface: &Interface, cursor_pos: Vector2<f64>, alerts: &mut Alerts) {
+    let Some(pos) = self.camera.relative_position_int(interface, cursor_pos) else { return };
+    let which = self.bundle.map.get_color_at(pos);
+    let locked = !self.bundle.map.is_locked(which);
+    let provinces = AHashSet::from_iter([which]);
+    if let Some(extents) = self.history.set_provinces_locked(&mut self.bundle, &provinces, locked, self.view_mode) {
+      self.modified = true;
+      self.refresh_selective(extents);
+      let verb = if locked { "Locked" } else { "Unlocked" };
+      alerts.push(Ok(format!("{} province {}", verb, stringify_color(which))));
+    };
+  }
+
+  pub fn set_selection_locked(&mut self, locked: bool, alerts: &mut Alerts) {
+    if self.tool.selection.is_empty() {
+      return alerts.push(Err("No provinces are selected"));
+    };
+
+    if let Some(extents) = self.history.set_provinces_locked(&mut self.bundle, &self.tool.selection, locked, self.view_mode) {
+      self.modified = true;
+      self.refresh_selective(extents);
+    };
+
+    let verb = if locked { "Locked" } else { "Unlocked" };
+    alerts.push(Ok(format!("{} {} selected provinces", verb, self.tool.selection.len())));
+  }
+
+  pub fn remove_selection_connections(&mut self, alerts: &mut Alerts) {
+    if self.tool.selection.is_empty() {
+      alerts.push(Err("No provinces are selected"));
+    } else if self.history.remove_selection_connections(&mut self.bundle, &self.tool.selection) {
+      self.modified = true;
+      alerts.push(Ok("Removed all adjacencies of the selected provinces"));
+    } else {
+      alerts.push(Err("The selected provinces have no adjacencies"));
+    };
+  }
+
+  /// Writes the IDs of the selected provinces to a text file, separated by spaces as they would be in a state file
+  pub fn export_selection_ids<P: AsRef<Path>>(&self, path: P, alerts: &mut Alerts) {
+    let mut ids = Vec::with_capacity(self.tool.selection.len());
+    let mut missing = 0;
+    for &color in self.tool.selection.iter() {
+      if let Some(id) = self.bundle.map.get_province_id(color) {
+        match self.bundle.map.get_province_by_id(id).preserved_id {
+          Some(preserved_id) => ids.push(preserved_id),
+          None => missing += 1
+        };
+      };
+    };
+
+    ids.sort_unstable();
+    let path = path.as_ref();
+    match export_text(path, &format!("{}\n", ids.iter().join(" "))) {
+      Ok(()) => alerts.push(Ok(format!("Exported {} province IDs to {}", ids.len(), path.display()))),
+      Err(err) => alerts.push(Err(format!("Error: {}", err)))
+    };
+
+    if missing > 0 {
+      alerts.push(Err(format!("{} selected provinces do not have an ID until the map is saved", missing)));
+    };
+  }
+
   fn tool_paint_brush(&mut self, interface: &Interface, cursor_pos: Vector2<f64>) {
     if let Some(pos) = self.camera.relative_position_int(interface, cursor_pos) {
       if let (Some(color), ViewMode::Color) = (self.tool.color_brush, self.view_mode) {
         let pos = self.camera.relative_position(interface, cursor_pos);
-        if let Some(extents) = self.history.paint_pixel_area(&mut self.bundle, pos, self.tool.radius, color, self.tool.brush_mask, self.tool.id) {
+        if let Some(extents) = self.history.paint_pixel_area(&mut self.bundle, pos, self.tool.radius, color, self.tool.brush_mask.as_ref(), self.tool.id) {
           self.problems.clear();
           self.modified = true;
           self.refresh_selective(extents);
@@ -587,7 +1267,7 @@ impl Canvas {
         let result = if fill_all {
           self.history.paint_entire_province(&mut self.bundle, pos, fill_color)
         } else {
-          self.history.paint_pixel_bucket(&mut self.bundle, pos, fill_color, self.tool.brush_mask)
+          self.history.paint_pixel_bucket(&mut self.bundle, pos, fill_color, self.tool.brush_mask.as_ref())
         };
 
         if let Some(extents) = result {
@@ -599,7 +1279,67 @@ impl Canvas {
     };
   }
 
-  fn tool_connect_activate(&mut self, interface: &Interface, cursor_pos: Vector2<f64>) {
+  /// The first click picks the province to merge into, every click after that merges another province into it
+  fn tool_merge_activate(&mut self, interface: &Interface, cursor_pos: Vector2<f64>) {
+    if let Some(pos) = self.camera.relative_position_int(interface, cursor_pos) {
+      let target = self.tool.merge_target
+        .filter(|&target| self.bundle.map.get_province_id(target).is_some());
+      if let Some(target) = target {
+        if let Some(extents) = self.history.merge_provinces(&mut self.bundle, target, pos) {
+          self.problems.clear();
+          self.modified = true;
+          self.refresh_selective(extents);
+        };
+      } else {
+        self.tool.merge_target = Some(self.bundle.map.get_color_at(pos));
+      };
+    };
+  }
+
+  /// Clicking with control held selects the nearest adjacency to edit it, while one is selected clicking its handles
+  /// moves its start and stop, and clicking a province makes it the through province,
+  /// or with shift or alt held, a required province or the icon of the adjacency's rule
+  fn tool_connect_activate(&mut self, interface: &Interface, cursor_pos: Vector2<f64>, mods: KeyMods, alerts: &mut Alerts) {
+    if mods.ctrl {
+      return self.tool_connect_select(interface, cursor_pos);
+    };
+
+    if let Some(rel) = self.selected_connection() {
+      let endpoints = self.connection_endpoints(interface, rel, None);
+      let handle = endpoints.iter()
+        .position(|&endpoint| vecmath::vec2_len(vecmath::vec2_sub(endpoint, cursor_pos)) <= HANDLE_RADIUS * 1.5);
+      if let Some(index) = handle {
+        self.tool.endpoint_drag = Some(index);
+      } else if let Some(pos) = self.camera.relative_position_int(interface, cursor_pos).filter(|_| mods.shift || mods.alt) {
+        let which = self.bundle.map.get_color_at(pos);
+        let edit = if mods.shift {
+          AdjacencyRuleEdit::RequiredProvince(which)
+        } else {
+          let rule = self.bundle.map.get_adjacency_rule(&self.bundle.map.get_connection(rel).rule_name);
+          let icon = rule.and_then(|rule| rule.icon);
+          if icon.is_some() && icon == self.bundle.map.get_province_id(which) {
+            AdjacencyRuleEdit::Icon(None)
+          } else {
+            AdjacencyRuleEdit::Icon(Some(which))
+          }
+        };
+
+        self.edit_selected_rule(edit, alerts);
+      } else if let Some(pos) = self.camera.relative_position_int(interface, cursor_pos) {
+        let which = self.bundle.map.get_color_at(pos);
+        let through = self.bundle.map.get_connection(rel).through;
+        let edit = if through == self.bundle.map.get_province_id(which) {
+          ConnectionEdit::Through(None)
+        } else {
+          ConnectionEdit::Through(Some(which))
+        };
+
+        self.edit_selected_connection(edit);
+      };
+
+      return;
+    };
+
     if let Some(pos) = self.camera.relative_position_int(interface, cursor_pos) {
       let which = self.bundle.map.get_color_at(pos);
       if let Some(kind) = self.tool.adjacency_brush {
@@ -612,6 +1352,252 @@ impl Canvas {
     };
   }
 
+  fn tool_connect_select(&mut self, interface: &Interface, cursor_pos: Vector2<f64>) {
+    let pos = self.camera.relative_position(interface, cursor_pos);
+    let range = ADJACENCY_PICK_RANGE / self.camera.scale_factor();
+    let map = &self.bundle.map;
+    self.tool.adjacency_selection = None;
+    self.tool.connection_selection = map.get_rel_nearest(pos)
+      .filter(|&(_, distance)| distance < range)
+      .map(|(rel, _)| rel.map(|id| map.get_province_by_id(id).color));
+    self.adjacency_editor.stop_editing();
+  }
+
+  /// Moves the start or the stop of the selected adjacency to where the cursor was released,
+  /// the other end is fixed in place where it was drawn if it was not already
+  fn tool_connect_drop_endpoint(&mut self, interface: &Interface, index: usize, cursor_pos: Vector2<f64>) {
+    let Some(rel) = self.selected_connection() else { return };
+    let [width, height] = self.bundle.map.dimensions();
+    let to_pixel = |[x, y]: Vector2<f64>| [(x.max(0.0) as u32).min(width - 1), (y.max(0.0) as u32).min(height - 1)];
+    let (start, stop) = self.bundle.map.get_connection_positions(rel);
+    let mut endpoints = [to_pixel(start), to_pixel(stop)];
+    endpoints[index] = to_pixel(self.camera.relative_position(interface, cursor_pos));
+    self.edit_selected_connection(ConnectionEdit::Endpoints(Some(endpoints)));
+  }
+
+  /// Where the ends of the given adjacency are drawn in screen space, following the cursor if one of them is being dragged
+  fn connection_endpoints(&self, interface: &Interface, rel: UOrd<ProvinceId>, cursor_pos: Option<Vector2<f64>>) -> [Vector2<f64>; 2] {
+    let (start, stop) = self.bundle.map.get_connection_positions(rel);
+    let mut endpoints = [start, stop].map(|pos| self.camera.compute_position(interface, pos));
+    if let (Some(index), Some(cursor_pos)) = (self.tool.endpoint_drag, cursor_pos) {
+      endpoints[index] = cursor_pos;
+    };
+
+    endpoints
+  }
+
+  /// The adjacency selected for editing, if there is one and it still exists
+  fn selected_connection(&self) -> Option<UOrd<ProvinceId>> {
+    let map = &self.bundle.map;
+    let [a, b] = self.tool.connection_selection?.into_array().map(|color| map.get_province_id(color));
+    Option::zip(a, b)
+      .map(|(a, b)| UOrd::new([a, b]))
+      .filter(|&rel| self.view_mode == ViewMode::Adjacencies && map.has_connection(rel))
+  }
+
+  fn edit_selected_connection(&mut self, edit: ConnectionEdit) -> bool {
+    let Some(rel) = self.tool.connection_selection else { return false };
+    let edited = self.history.edit_connection(&mut self.bundle, rel, edit);
+    if edited {
+      self.modified = true;
+    };
+
+    edited
+  }
+
+  /// Edits the adjacency rule that the selected adjacency refers to
+  fn edit_selected_rule(&mut self, edit: AdjacencyRuleEdit, alerts: &mut Alerts) {
+    let Some(rel) = self.selected_connection() else { return };
+    let rule_name = self.bundle.map.get_connection(rel).rule_name.clone();
+    if rule_name.is_empty() {
+      alerts.push(Err("The selected adjacency has no rule name"));
+    } else if self.history.edit_adjacency_rule(&mut self.bundle, &rule_name, edit) {
+      self.problems.clear();
+      self.modified = true;
+    } else if self.bundle.map.get_adjacency_rule(&rule_name).is_none() {
+      alerts.push(Err(format!("There is no rule named {:?}, click its line in the adjacency editor to create it", rule_name)));
+    };
+  }
+
+  /// Selects an adjacency in the adjacencies view mode, framing it with the camera
+  fn select_connection(&mut self, interface: &Interface, rel: UOrd<Color>, alerts: &mut Alerts) {
+    self.set_view_mode(alerts, ViewMode::Adjacencies);
+    self.tool.connection_selection = Some(rel);
+    self.adjacency_editor.stop_editing();
+    if let Some(rel) = self.selected_connection() {
+      let (start, stop) = self.bundle.map.get_connection_positions(rel);
+      let center = vecmath::vec2_scale(vecmath::vec2_add(start, stop), 0.5);
+      self.camera.focus(interface, center, Extents::from_points(&[start, stop]));
+    };
+  }
+
+  pub fn toggle_adjacency_list(&mut self) {
+    self.adjacency_list.visible = !self.adjacency_list.visible;
+  }
+
+  /// Passes a click to the adjacency editor or the adjacency list, returning false if the click was on neither
+  pub fn click_panel(&mut self, interface: &Interface, cursor_pos: Vector2<f64>, alerts: &mut Alerts) -> bool {
+    if let Some(rel) = self.selected_connection() {
+      match self.adjacency_editor.on_click(cursor_pos, &self.bundle.map, rel) {
+        EditorClick::Outside => (),
+        EditorClick::Handled => return true,
+        EditorClick::EditRule(edit) => {
+          self.edit_selected_rule(edit, alerts);
+          return true;
+        }
+      };
+    };
+
+    match self.adjacency_list.on_click(cursor_pos) {
+      ListClick::Outside => false,
+      ListClick::Handled => true,
+      ListClick::Select(rel) => {
+        self.select_connection(interface, rel, alerts);
+        true
+      }
+    }
+  }
+
+  /// Scrolls the adjacency list, returning false if the cursor was not over it
+  pub fn scroll_panel(&mut self, cursor_pos: Vector2<f64>, dy: f64) -> bool {
+    self.adjacency_list.on_scroll(cursor_pos, dy)
+  }
+
+  /// Resets the start and stop of the selected adjacency, so that it goes between the centers of its provinces again
+  pub fn reset_connection_endpoints(&mut self) {
+    self.edit_selected_connection(ConnectionEdit::Endpoints(None));
+  }
+
+  /// Looks for narrow water between land provinces that could be crossed by a strait, showing them in the adjacencies view mode
+  pub fn suggest_straits(&mut self, alerts: &mut Alerts) {
+    self.suggestions = self.bundle.map.suggest_straits(self.bundle.config.strait_suggestion_distance);
+    if self.suggestions.is_empty() {
+      alerts.push(Ok("No straits to suggest"));
+    } else {
+      self.set_view_mode(alerts, ViewMode::Adjacencies);
+      alerts.push(Ok(format!("Suggested {} straits, press Y to accept or N to reject the one under the cursor", self.suggestions.len())));
+    };
+  }
+
+  /// The index of the suggested strait closest to the cursor, if it is close enough to be picked
+  fn hovered_suggestion(&self, interface: &Interface, cursor_pos: Vector2<f64>) -> Option<usize> {
+    if self.view_mode != ViewMode::Adjacencies {
+      return None;
+    };
+
+    self.suggestions.iter()
+      .enumerate()
+      .filter(|(_, suggestion)| is_suggestion_valid(&self.bundle.map, suggestion))
+      .map(|(i, suggestion)| {
+        let [start, stop] = suggestion.positions().map(|pos| self.camera.compute_position(interface, pos));
+        (i, distance_to_segment(cursor_pos, start, stop))
+      })
+      .filter(|&(_, distance)| distance < ADJACENCY_PICK_RANGE)
+      .min_by(|(_, d1), (_, d2)| f64::total_cmp(d1, d2))
+      .map(|(i, _)| i)
+  }
+
+  /// Accepts the suggested strait under the cursor, adding it as an adjacency
+  pub fn accept_suggestion(&mut self, interface: &Interface, cursor_pos: Option<Vector2<f64>>, alerts: &mut Alerts) {
+    match cursor_pos.and_then(|cursor_pos| self.hovered_suggestion(interface, cursor_pos)) {
+      Some(i) => {
+        let suggestion = self.suggestions.remove(i);
+        if self.history.add_suggested_straits(&mut self.bundle, &[suggestion]) > 0 {
+          self.modified = true;
+        };
+      },
+      None => alerts.push(Err("There is no suggested strait under the cursor"))
+    };
+  }
+
+  /// Rejects the suggested strait under the cursor
+  pub fn reject_suggestion(&mut self, interface: &Interface, cursor_pos: Option<Vector2<f64>>, alerts: &mut Alerts) {
+    match cursor_pos.and_then(|cursor_pos| self.hovered_suggestion(interface, cursor_pos)) {
+      Some(i) => {
+        self.suggestions.remove(i);
+      },
+      None => alerts.push(Err("There is no suggested strait under the cursor"))
+    };
+  }
+
+  pub fn accept_all_suggestions(&mut self, alerts: &mut Alerts) {
+    if self.suggestions.is_empty() {
+      alerts.push(Err("There are no suggested straits"));
+    } else {
+      let suggestions = std::mem::take(&mut self.suggestions);
+      let added = self.history.add_suggested_straits(&mut self.bundle, &suggestions);
+      if added > 0 {
+        self.modified = true;
+      };
+
+      alerts.push(Ok(format!("Added {} suggested straits", added)));
+    };
+  }
+
+  pub fn reject_all_suggestions(&mut self, alerts: &mut Alerts) {
+    if self.suggestions.is_empty() {
+      alerts.push(Err("There are no suggested straits"));
+    } else {
+      alerts.push(Ok(format!("Rejected {} suggested straits", self.suggestions.len())));
+      self.suggestions.clear();
+    };
+  }
+
+  pub fn has_suggestions(&self) -> bool {
+    self.view_mode == ViewMode::Adjacencies && !self.suggestions.is_empty()
+  }
+
+  /// Works out continents for land provinces from their land masses, showing the result in the continent view mode until it is applied or discarded
+  pub fn preview_continents(&mut self, assignment: ContinentAssignment, alerts: &mut Alerts) {
+    let changes = self.bundle.map.assign_continents(self.bundle.config.landmasses_join_straits, assignment);
+    self.set_view_mode(alerts, ViewMode::Continent);
+    if changes.is_empty() {
+      self.continent_preview = None;
+      alerts.push(Ok("Every province already has the continent of its land mass"));
+    } else {
+      alerts.push(Ok(format!("Previewing {} continent changes, press Enter to apply or Escape to discard them", changes.len())));
+      self.continent_preview = Some(changes.into_iter().collect());
+    };
+
+    self.refresh();
+  }
+
+  pub fn apply_continent_preview(&mut self, alerts: &mut Alerts) {
+    if let Some(preview) = self.continent_preview.take() {
+      let mut continents = preview.into_iter().collect::<Vec<(Color, u16)>>();
+      continents.sort();
+      let changed = self.history.assign_continents(&mut self.bundle, &continents);
+      if changed > 0 {
+        self.modified = true;
+      };
+
+      alerts.push(Ok(format!("Changed the continent of {} provinces", changed)));
+      self.refresh();
+    };
+  }
+
+  pub fn discard_continent_preview(&mut self, alerts: &mut Alerts) {
+    if self.continent_preview.take().is_some() {
+      alerts.push(Ok("Discarded the continent preview"));
+      self.refresh();
+    };
+  }
+
+  pub fn has_continent_preview(&self) -> bool {
+    self.view_mode == ViewMode::Continent && self.continent_preview.is_some()
+  }
+
+  pub fn has_selected_connection(&self) -> bool {
+    self.selected_connection().is_some()
+  }
+
+  /// Starts editing the rule name and comment of the selected adjacency
+  pub fn edit_connection_text(&mut self) {
+    if let Some(rel) = self.selected_connection() {
+      self.adjacency_editor.start_editing(self.bundle.map.get_connection(rel));
+    };
+  }
+
   pub fn validate_pixel_counts(&self, alerts: &mut Alerts) {
     if self.bundle.map.validate_pixel_counts() {
       alerts.push(Ok("Validation successful"));
@@ -634,7 +1620,10 @@ impl Canvas {
       ViewMode::Color => self.bundle.texture_buffer_color(),
       ViewMode::Kind => self.bundle.texture_buffer_kind(),
       ViewMode::Terrain => self.bundle.texture_buffer_terrain(),
-      ViewMode::Continent => self.bundle.texture_buffer_continent(),
+      ViewMode::Continent => match &self.continent_preview {
+        Some(preview) => self.bundle.texture_buffer_continent_preview(preview),
+        None => self.bundle.texture_buffer_continent()
+      },
       ViewMode::Coastal => self.bundle.texture_buffer_coastal(),
       ViewMode::Adjacencies => self.bundle.texture_buffer_color()
     };
@@ -643,6 +1632,11 @@ impl Canvas {
   }
 
   fn refresh_selective(&mut self, extents: Extents) {
+    // Editing the map leaves a continent preview out of date, so it is discarded and the whole map redrawn
+    if self.continent_preview.take().is_some() {
+      return self.refresh();
+    };
+
     use opengl_graphics::{UpdateTexture, Format};
     let (offset, size) = extents.to_offset_size();
     let buffer = match self.view_mode {
@@ -658,8 +1652,29 @@ impl Canvas {
       .expect("unable to update texture");
   }
 
+  /// Labels continents past the last defined continent as new, since painting them creates a continent
+  fn continent_brush_label(&self, continent: u16) -> String {
+    if continent > self.bundle.map.defined_continents() {
+      format!("{} (NEW)", continent)
+    } else {
+      self.bundle.map.continent_label(continent)
+    }
+  }
+
   fn brush_info(&self) -> String {
     match self.view_mode {
+      ViewMode::Color if self.tool.mode == ToolMode::Merge => match self.tool.merge_target {
+        Some(color) => format!("Merge into {}", stringify_color(color)),
+        None => "Merge (No Target)".to_owned()
+      },
+      ViewMode::Color if matches!(self.tool.mode, ToolMode::Split(_)) => "Split".to_owned(),
+      ViewMode::Color if matches!(self.tool.mode, ToolMode::Select(_)) => {
+        format!("Select ({} Provinces)", self.tool.selection.len())
+      },
+      ViewMode::Color if self.tool.mode == ToolMode::Subdivide => match self.tool.subdivide_target {
+        SubdivideTarget::Count(count) => format!("Subdivide into {} provinces", count),
+        SubdivideTarget::PixelSize(size) => format!("Subdivide into provinces of {} pixels", size)
+      },
       ViewMode::Color => match self.tool.color_brush {
         Some(color) => format!("Color {}", stringify_color(color)),
         None => "Color (No Brush)".to_owned()
@@ -673,7 +1688,7 @@ impl Canvas {
         None => "Terrain (No Brush)".to_owned()
       },
       ViewMode::Continent => match self.tool.continent_brush {
-        Some(continent) => format!("Continent {}", continent),
+        Some(continent) => format!("Continent {}", self.continent_brush_label(continent)),
         None => "Continent (No Brush)".to_owned()
       },
       ViewMode::Coastal => "Coastal".to_owned(),
@@ -686,8 +1701,8 @@ impl Canvas {
 
   fn brush_mask_info(&self) -> String {
     if self.view_mode == ViewMode::Color {
-      match self.tool.brush_mask {
-        Some(brush_mask) => format!("Mask {}", brush_mask.to_str().to_uppercase()),
+      match &self.tool.brush_mask {
+        Some(brush_mask) => format!("Mask {}", brush_mask.describe(&self.bundle.map).to_uppercase()),
         None => "No Mask".to_owned()
       }
     } else {
@@ -706,6 +1721,14 @@ impl Canvas {
   }
 }
 
+/// The result of loading a map, before any graphics state has been created for it
+#[derive(Debug)]
+pub struct CanvasData {
+  bundle: Bundle,
+  problems: Vec<Problem>,
+  unknown_terrains: Option<AHashSet<String>>
+}
+
 impl fmt::Debug for Canvas {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     f.debug_struct("Canvas")
@@ -733,6 +1756,20 @@ pub struct ToolSettings {
   pub continent_brush: Option<u16>,
   pub adjacency_brush: Option<ConnectionKind>,
   pub adjacency_selection: Option<Color>,
+  /// The adjacency selected for editing in the adjacencies view mode
+  pub connection_selection: Option<UOrd<Color>>,
+  /// Which end of the selected adjacency is being dragged, the start or the stop
+  pub endpoint_drag: Option<usize>,
+  /// The province that the merge tool merges other provinces into
+  pub merge_target: Option<Color>,
+  /// How many provinces the subdivide tool divides a province into
+  pub subdivide_target: SubdivideTarget,
+  /// The provinces selected with the select tool, which bulk operations are applied to
+  pub selection: AHashSet<Color>,
+  /// Where the rectangle tool, or a click or rectangle selection started, in map space
+  pub drag_anchor: Option<Vector2<f64>>,
+  /// Whether the current selection action adds to the selection instead of replacing it
+  pub select_additive: bool,
   pub brush_mask: Option<BrushMask>,
   pub lasso_snap: bool,
   pub radius: f64,
@@ -745,9 +1782,13 @@ impl ToolSettings {
     self.brush_mask = match self.brush_mask {
       None => Some(BrushMask::LandLakes),
       Some(BrushMask::LandLakes) => Some(BrushMask::Sea),
-      Some(BrushMask::Sea) => None
+      Some(_) => None
     }
   }
+
+  pub fn invert_brush_mask(&mut self) {
+    self.brush_mask = self.brush_mask.take().map(BrushMask::invert);
+  }
 }
 
 impl Default for ToolSettings {
@@ -759,6 +1800,13 @@ impl Default for ToolSettings {
       continent_brush: None,
       adjacency_brush: None,
       adjacency_selection: None,
+      connection_selection: None,
+      endpoint_drag: None,
+      merge_target: None,
+      subdivide_target: SubdivideTarget::default(),
+      selection: AHashSet::default(),
+      drag_anchor: None,
+      select_additive: false,
       brush_mask: None,
       lasso_snap: false,
       radius: 8.0,
@@ -772,13 +1820,28 @@ impl Default for ToolSettings {
 pub enum ToolMode {
   PaintArea,
   PaintBucket,
-  Lasso(Lasso)
+  Lasso(Lasso),
+  Rectangle,
+  Merge,
+  /// Holds the cut line, which is drawn the same way as a lasso but is not closed
+  Split(Lasso),
+  Subdivide,
+  /// Holds the points of a lasso selection, if one is being drawn
+  Select(Lasso)
 }
 
 impl ToolMode {
   pub fn new_lasso() -> Self {
     ToolMode::Lasso(Lasso(Vec::new()))
   }
+
+  pub fn new_split() -> Self {
+    ToolMode::Split(Lasso(Vec::new()))
+  }
+
+  pub fn new_select() -> Self {
+    ToolMode::Select(Lasso(Vec::new()))
+  }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -813,28 +1876,107 @@ impl Default for ToolMode {
   }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// Either a number of provinces, or the size in pixels that each province should roughly have
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SubdivideTarget {
+  Count(usize),
+  PixelSize(u64)
+}
+
+impl SubdivideTarget {
+  /// The number of provinces that a province with the given pixel count should be divided into
+  pub fn count(self, pixel_count: u64) -> usize {
+    match self {
+      SubdivideTarget::Count(count) => count,
+      SubdivideTarget::PixelSize(size) => pixel_count.div_ceil(size) as usize
+    }
+  }
+
+  pub fn toggle(&mut self) {
+    *self = match self {
+      SubdivideTarget::Count(_) => SubdivideTarget::PixelSize(1000),
+      SubdivideTarget::PixelSize(_) => SubdivideTarget::Count(4)
+    };
+  }
+
+  fn change(&mut self, d: f64) {
+    match self {
+      SubdivideTarget::Count(count) => {
+        *count = (*count as f64 + d.signum()).max(2.0) as usize;
+      },
+      SubdivideTarget::PixelSize(size) => {
+        let step = (*size as f64 * 0.1).max(1.0);
+        *size = (*size as f64 + d.signum() * step).round().max(1.0) as u64;
+      }
+    };
+  }
+}
+
+impl Default for SubdivideTarget {
+  fn default() -> SubdivideTarget {
+    SubdivideTarget::Count(4)
+  }
+}
+
+/// Limits which pixels painting may change, based on the province they currently belong to
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum BrushMask {
   LandLakes,
-  Sea
+  Sea,
+  Terrain(String),
+  Continent(u16),
+  /// Only the given provinces, usually a single picked province or the selection,
+  /// kept by handle so that recoloring a province does not drop it from the mask
+  Provinces(AHashSet<ProvinceId>),
+  /// Everything that the inner mask does not include
+  Inverse(Box<BrushMask>)
 }
 
 impl BrushMask {
   #[inline]
-  pub fn includes(&self, kind: impl Into<ProvinceKind>) -> bool {
-    match (self, kind.into()) {
+  pub fn includes(&self, id: ProvinceId, province_data: &ProvinceData) -> bool {
+    match (self, province_data.kind) {
       (BrushMask::LandLakes, ProvinceKind::Land) => true,
       (BrushMask::LandLakes, ProvinceKind::Lake) => true,
       (BrushMask::Sea, ProvinceKind::Sea) => true,
-      (_, ProvinceKind::Unknown) => true,
-      _ => false
+      (BrushMask::LandLakes | BrushMask::Sea, ProvinceKind::Unknown) => true,
+      (BrushMask::LandLakes | BrushMask::Sea, _) => false,
+      (BrushMask::Terrain(terrain), _) => province_data.terrain == *terrain,
+      (BrushMask::Continent(continent), _) => province_data.continent == *continent,
+      (BrushMask::Provinces(provinces), _) => provinces.contains(&id),
+      (BrushMask::Inverse(mask), _) => !mask.includes(id, province_data)
     }
   }
 
-  fn to_str(self) -> &'static str {
+  /// Inverts the mask, inverting an inverse mask gives back the original mask
+  pub fn invert(self) -> BrushMask {
     match self {
-      BrushMask::LandLakes => "land + lakes",
-      BrushMask::Sea => "sea"
+      BrushMask::Inverse(mask) => *mask,
+      mask => BrushMask::Inverse(Box::new(mask))
+    }
+  }
+
+  /// The provinces this mask is limited to, if it is limited to provinces
+  pub fn provinces_mut(&mut self) -> Option<&mut AHashSet<ProvinceId>> {
+    match self {
+      BrushMask::Provinces(provinces) => Some(provinces),
+      BrushMask::Inverse(mask) => mask.provinces_mut(),
+      _ => None
+    }
+  }
+
+  /// Describes the mask for the status bar, a continent is described by its name and a single masked province by its color
+  pub fn describe(&self, map: &Map) -> String {
+    match self {
+      BrushMask::LandLakes => "land + lakes".to_owned(),
+      BrushMask::Sea => "sea".to_owned(),
+      BrushMask::Terrain(terrain) => format!("terrain {}", terrain),
+      BrushMask::Continent(continent) => format!("continent {}", map.continent_label(*continent)),
+      BrushMask::Provinces(provinces) => match provinces.iter().exactly_one() {
+        Ok(&id) if map.has_province(id) => format!("province {}", stringify_color(map.get_province_by_id(id).color)),
+        _ => format!("{} provinces", provinces.len())
+      },
+      BrushMask::Inverse(mask) => format!("not {}", mask.describe(map))
     }
   }
 }
@@ -929,6 +2071,24 @@ impl Camera {
       .trans_pos(vecmath::vec2_scale(self.texture_size, -0.5));
   }
 
+  /// Centers the camera on a point, zooming so that the given extents fill most of the window
+  pub fn focus(&mut self, interface: &Interface, center: Vector2<f64>, extents: Extents) {
+    const FILL: f64 = 0.5;
+    const MAX_SCALE: f64 = 32.0;
+
+    let [window_width, window_height] = interface.get_window_size();
+    let available_width = window_width - interface.get_sidebar_width() as f64;
+    let available_height = window_height - interface.get_toolbar_height() as f64;
+    let width = (extents.upper[0] - extents.lower[0] + 1) as f64;
+    let height = (extents.upper[1] - extents.lower[1] + 1) as f64;
+    let scale = f64::min(available_width / width, available_height / height) * FILL;
+    let scale = scale.min(MAX_SCALE);
+    // The display matrix is applied after the window center is added, see `display_matrix`
+    let window_center = interface.get_window_center();
+    let offset = vecmath::vec2_sub(window_center, vecmath::vec2_scale(vecmath::vec2_add(center, window_center), scale));
+    self.display_matrix = [[scale, 0.0, offset[0]], [0.0, scale, offset[1]]];
+  }
+
   pub fn set_panning(&mut self, panning: bool) {
     self.panning = panning;
   }
@@ -969,6 +2129,15 @@ impl Camera {
     0.0 <= pos[1] && pos[1] < self.texture_size[1]
   }
 
+  /// Returns the extents of the area of the map that is currently visible
+  pub(super) fn visible_extents(&self, interface: &Interface) -> Extents {
+    let [width, height] = interface.get_window_size();
+    let corners = [[0.0, 0.0], [width, height]]
+      .map(|pos| self.relative_position(interface, pos))
+      .map(|[x, y]| [x.clamp(0.0, self.texture_size[0] - 1.0), y.clamp(0.0, self.texture_size[1] - 1.0)]);
+    Extents::from_points(&corners)
+  }
+
   #[inline]
   pub(super) fn within_viewport(&self, interface: &Interface, pos: Vector2<f64>) -> bool {
     0.0 <= pos[0] && pos[0] < interface.get_window_size()[0] as f64 &&
@@ -976,16 +2145,131 @@ impl Camera {
   }
 }
 
+fn export_text(path: &Path, text: &str) -> Result<(), Error> {
+  let mut file = crate::util::files::create_file(path)?;
+  file.write_all(text.as_bytes()).context("failed to write file")?;
+  Ok(())
+}
+
 fn export_image_buffer<P: AsRef<Path>>(path: P, image: RgbImage) -> Result<(), Error> {
   let file = crate::util::files::create_file(path.as_ref())?;
   super::map::write_rgb_bmp_image(BufWriter::new(file), &image)
 }
 
+/// Whether a suggested strait still joins two existing provinces that are not yet adjacent
+fn is_suggestion_valid(map: &Map, suggestion: &StraitSuggestion) -> bool {
+  let [a, b] = suggestion.rel.into_array().map(|color| map.get_province_id(color));
+  match (a, b, map.get_province_id(suggestion.through)) {
+    (Some(a), Some(b), Some(_)) => !map.has_connection(UOrd::new([a, b])),
+    _ => false
+  }
+}
+
+fn distance_to_segment(pos: Vector2<f64>, a: Vector2<f64>, b: Vector2<f64>) -> f64 {
+  let ab = vecmath::vec2_sub(b, a);
+  let length_squared = vecmath::vec2_square_len(ab);
+  let t = if length_squared > 0.0 {
+    (vecmath::vec2_dot(vecmath::vec2_sub(pos, a), ab) / length_squared).clamp(0.0, 1.0)
+  } else {
+    0.0
+  };
+
+  let closest = vecmath::vec2_add(a, vecmath::vec2_scale(ab, t));
+  vecmath::vec2_len(vecmath::vec2_sub(pos, closest))
+}
+
+fn draw_dashed_line(ctx: Context, color: DrawColor, radius: f64, a: Vector2<f64>, b: Vector2<f64>, gl: &mut GlGraphics) {
+  let ab = vecmath::vec2_sub(b, a);
+  let length = vecmath::vec2_len(ab);
+  let dashes = (length / (DASH_LENGTH * 2.0)).ceil() as usize;
+  for i in 0..dashes {
+    let t1 = (i as f64 * DASH_LENGTH * 2.0) / length;
+    let t2 = ((i as f64 * DASH_LENGTH * 2.0 + DASH_LENGTH) / length).min(1.0);
+    let start = vecmath::vec2_add(a, vecmath::vec2_scale(ab, t1));
+    let stop = vecmath::vec2_add(a, vecmath::vec2_scale(ab, t2));
+    graphics::line_from_to(color, radius, start, stop, ctx.transform, gl);
+  };
+}
+
 #[inline]
 fn drawable_color(color: Color) -> DrawColor {
   [color[0] as f32 / 255.0, color[1] as f32 / 255.0, color[2] as f32 / 255.0, 1.0]
 }
 
+/// Which corner of the window a panel is drawn in, panels are kept clear of the toolbar and the sidebar
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PanelAnchor {
+  TopRight,
+  BottomRight,
+  BottomLeft
+}
+
+/// Where a panel and each of its lines were drawn, so that clicks can be matched to them
+#[derive(Debug, Clone, Default)]
+struct PanelLayout {
+  bounds: [f64; 4],
+  /// Where each line starts on its baseline
+  line_positions: Vec<Vector2<f64>>
+}
+
+impl PanelLayout {
+  fn contains(&self, [x, y]: Vector2<f64>) -> bool {
+    let [bx, by, width, height] = self.bounds;
+    bx <= x && x < bx + width && by <= y && y < by + height
+  }
+
+  /// The index of the line under the given position, if any
+  fn line_at(&self, pos: Vector2<f64>) -> Option<usize> {
+    let v_metrics = font::get_v_metrics();
+    self.line_positions.iter()
+      .position(|&[_, baseline]| baseline - v_metrics.ascent <= pos[1] && pos[1] < baseline - v_metrics.descent)
+      .filter(|_| self.contains(pos))
+  }
+}
+
+/// Draws lines of text in a box in one of the corners of the window
+fn draw_panel(
+  ctx: Context,
+  interface: &Interface,
+  anchor: PanelAnchor,
+  lines: &[(String, DrawColor)],
+  glyph_cache: &mut FontGlyphCache,
+  gl: &mut GlGraphics
+) -> PanelLayout {
+  use super::interface::PADDING;
+
+  let v_metrics = font::get_v_metrics();
+  let line_height = (v_metrics.ascent - v_metrics.descent).round();
+  let text_width = lines.iter()
+    .map(|(line, _)| font::get_width_metric_str(line))
+    .fold(0.0, f64::max);
+  let width = (text_width + PADDING[0] * 4.0).round();
+  let height = (line_height * lines.len() as f64 + PADDING[1] * 4.0).round();
+  let [window_width, window_height] = interface.get_window_size();
+  let x = match anchor {
+    PanelAnchor::TopRight | PanelAnchor::BottomRight => window_width - width - PADDING[0],
+    PanelAnchor::BottomLeft => interface.get_sidebar_width() as f64 + PADDING[0]
+  };
+  let y = match anchor {
+    PanelAnchor::TopRight => interface.get_toolbar_height() as f64 + PADDING[1],
+    PanelAnchor::BottomRight => window_height - height - PADDING[1],
+    // Leaves room for the camera info at the bottom of the window
+    PanelAnchor::BottomLeft => window_height - height - line_height - PADDING[1] * 2.0
+  };
+  graphics::rectangle(colors::OVERLAY_T, [x, y, width, height], ctx.transform, gl);
+
+  let mut line_positions = Vec::with_capacity(lines.len());
+  for (i, (line, color)) in lines.iter().enumerate() {
+    let pos = [x + PADDING[0] * 2.0, y + PADDING[1] * 2.0 + v_metrics.ascent + line_height * i as f64];
+    let transform = ctx.transform.trans_pos(pos);
+    graphics::text(*color, FONT_SIZE, line, glyph_cache, transform, gl)
+      .expect("unable to draw text");
+    line_positions.push(pos);
+  };
+
+  PanelLayout { bounds: [x, y, width, height], line_positions }
+}
+
 fn cycle_kinds<P>(kind: Option<P>, backwards: bool) -> DefinitionKind
 where P: Into<ProvinceKind> {
   match kind.map(P::into) {
@@ -996,15 +2280,17 @@ where P: Into<ProvinceKind> {
   }
 }
 
-fn cycle_continents(continent: Option<u16>, backwards: bool) -> u16 {
-  const MAX_CONTINENTS: u16 = 32;
-  continent.map_or(0, |continent| {
-    if backwards {
-      (continent + MAX_CONTINENTS - 1) % MAX_CONTINENTS
+/// Cycles through continents 1 through `defined`, followed by a new continent after the last one
+fn cycle_continents(continent: Option<u16>, defined: u16, backwards: bool) -> u16 {
+  let count = defined.saturating_add(1);
+  match continent {
+    Some(continent) if (1..=count).contains(&continent) => if backwards {
+      if continent == 1 { count } else { continent - 1 }
     } else {
-      (continent + 1) % MAX_CONTINENTS
-    }
-  })
+      continent % count + 1
+    },
+    _ => if backwards { count } else { 1 }
+  }
 }
 
 fn cycle_connection(connection_kind: Option<ConnectionKind>, backwards: bool) -> ConnectionKind {
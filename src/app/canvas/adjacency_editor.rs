@@ -0,0 +1,289 @@
+//! The adjacency editor, a panel describing the adjacency selected in the adjacencies view mode,
+//! with its rule name and comment editable in place, along with the adjacency rule it refers to
+use graphics::context::Context;
+use opengl_graphics::GlGraphics;
+use piston::input::Key;
+use uord::UOrd2 as UOrd;
+use vecmath::Vector2;
+
+use super::{draw_panel, PanelAnchor, PanelLayout};
+use super::inspector::province_label;
+use crate::app::{colors, FontGlyphCache};
+use crate::app::format::{RuleRelation, RuleUnit};
+use crate::app::interface::{InputFilter, InputResponse, Interface, TextInput};
+use crate::app::map::{AdjacencyRuleData, AdjacencyRuleEdit, ConnectionData, ConnectionEdit, Map, ProvinceId};
+use crate::events::KeyMods;
+use crate::font;
+
+/// The longest rule name or comment that may be typed
+const MAX_TEXT_LEN: usize = 64;
+/// The line naming the adjacency's rule, followed by the rule's permissions for each relation
+const RULE_LINE: usize = 7;
+const PERMISSION_SEPARATOR: &str = "  ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AdjacencyField {
+  RuleName,
+  Comment
+}
+
+impl AdjacencyField {
+  fn name(self) -> &'static str {
+    match self {
+      AdjacencyField::RuleName => "Rule Name",
+      AdjacencyField::Comment => "Comment"
+    }
+  }
+
+  fn other(self) -> AdjacencyField {
+    match self {
+      AdjacencyField::RuleName => AdjacencyField::Comment,
+      AdjacencyField::Comment => AdjacencyField::RuleName
+    }
+  }
+
+  fn value(self, connection_data: &ConnectionData) -> &str {
+    match self {
+      AdjacencyField::RuleName => &connection_data.rule_name,
+      AdjacencyField::Comment => &connection_data.comment
+    }
+  }
+
+  fn input(self, connection_data: &ConnectionData) -> TextInput {
+    TextInput::new(InputFilter::Any, MAX_TEXT_LEN).with_text(self.value(connection_data))
+  }
+
+  fn edit(self, text: &str) -> ConnectionEdit {
+    match self {
+      AdjacencyField::RuleName => ConnectionEdit::RuleName(text.to_owned()),
+      AdjacencyField::Comment => ConnectionEdit::Comment(text.to_owned())
+    }
+  }
+
+  /// The line of the panel this field is shown on
+  fn line(self) -> usize {
+    match self {
+      AdjacencyField::RuleName => 5,
+      AdjacencyField::Comment => 6
+    }
+  }
+}
+
+/// What a click on the adjacency editor did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditorClick {
+  /// The click was not on the editor, and should be passed on to the tool
+  Outside,
+  Handled,
+  /// Part of the adjacency's rule was clicked, it should be edited
+  EditRule(AdjacencyRuleEdit)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AdjacencyEditor {
+  edit: Option<AdjacencyEdit>,
+  /// Where the editor was last drawn
+  layout: PanelLayout
+}
+
+/// A text field of the selected adjacency that is currently being edited
+#[derive(Debug, Clone)]
+struct AdjacencyEdit {
+  field: AdjacencyField,
+  input: TextInput
+}
+
+impl AdjacencyEditor {
+  pub fn editing(&self) -> bool {
+    self.edit.is_some()
+  }
+
+  pub fn start_editing(&mut self, connection_data: &ConnectionData) {
+    let field = AdjacencyField::RuleName;
+    self.edit = Some(AdjacencyEdit { field, input: field.input(connection_data) });
+  }
+
+  pub fn stop_editing(&mut self) {
+    self.edit = None;
+  }
+
+  /// Handles a key press while editing, returning an edit once one has been confirmed with `Enter`
+  pub fn on_key(&mut self, key: Key, mods: KeyMods, connection_data: &ConnectionData) -> Option<Result<ConnectionEdit, String>> {
+    let edit = self.edit.as_mut()?;
+    match edit.input.on_key(key, mods) {
+      InputResponse::Submit => return Some(parse_text(edit.input.text()).map(|text| edit.field.edit(text))),
+      InputResponse::Cancel => self.edit = None,
+      InputResponse::Ignored(Key::Up | Key::Down | Key::Tab) => {
+        edit.field = edit.field.other();
+        edit.input = edit.field.input(connection_data);
+      },
+      InputResponse::Ignored(_) | InputResponse::Handled => ()
+    };
+
+    None
+  }
+
+  /// Handles typed text while editing
+  pub fn on_text(&mut self, text: &str) {
+    if let Some(edit) = &mut self.edit {
+      edit.input.on_text(text);
+    };
+  }
+
+  /// Handles a click on the editor, clicking the rule line creates a missing rule,
+  /// and clicking a permission toggles it
+  pub fn on_click(&mut self, cursor_pos: Vector2<f64>, map: &Map, rel: UOrd<ProvinceId>) -> EditorClick {
+    if !self.layout.contains(cursor_pos) {
+      return EditorClick::Outside;
+    };
+
+    let connection_data = map.get_connection(rel);
+    if connection_data.rule_name.is_empty() || self.edit.is_some() {
+      return EditorClick::Handled;
+    };
+
+    let rule = map.get_adjacency_rule(&connection_data.rule_name);
+    match (self.layout.line_at(cursor_pos), rule) {
+      (Some(RULE_LINE), None) => EditorClick::EditRule(AdjacencyRuleEdit::Create),
+      (Some(line), Some(rule)) if line > RULE_LINE && line <= RULE_LINE + RuleRelation::ALL.len() => {
+        let relation = RuleRelation::ALL[line - RULE_LINE - 1];
+        let x = cursor_pos[0] - self.layout.line_positions[line][0];
+        let mut end = font::get_width_metric_str(&permission_prefix(relation));
+        for (unit, text) in permission_texts(rule, relation) {
+          end += font::get_width_metric_str(&text);
+          if x < end {
+            return EditorClick::EditRule(AdjacencyRuleEdit::Permission(relation, unit));
+          };
+
+          end += font::get_width_metric_str(PERMISSION_SEPARATOR);
+        };
+
+        EditorClick::Handled
+      },
+      _ => EditorClick::Handled
+    }
+  }
+
+  fn lines(&self, map: &Map, rel: UOrd<ProvinceId>) -> Vec<String> {
+    let connection_data = map.get_connection(rel);
+    let [a, b] = rel.into_array().map(|id| province_label(map.get_province_by_id(id)));
+    let through = connection_data.through
+      .map_or_else(|| "none".to_owned(), |through| province_label(map.get_province_by_id(through)));
+    let point = |point: Option<[u32; 2]>| point
+      .filter(|_| connection_data.start.is_some() && connection_data.stop.is_some())
+      .map_or_else(|| "province center".to_owned(), |[x, y]| format!("{}, {} px", x, y));
+
+    let mut lines = vec![
+      format!("Adjacency {} - {}", a, b),
+      format!("Type: {}", connection_data.kind.to_str().to_uppercase()),
+      format!("Through: {}", through),
+      format!("Start: {}", point(connection_data.start)),
+      format!("Stop: {}", point(connection_data.stop))
+    ];
+
+    for field in [AdjacencyField::RuleName, AdjacencyField::Comment] {
+      lines.push(match &self.edit {
+        Some(edit) if edit.field == field => format!("{}{}", edit_prefix(field), edit.input.text()),
+        _ => format!("{}: {}", field.name(), field.value(connection_data))
+      });
+    };
+
+    let rule = map.get_adjacency_rule(&connection_data.rule_name);
+    if !connection_data.rule_name.is_empty() {
+      lines.extend(rule_lines(map, &connection_data.rule_name, rule));
+    };
+
+    lines.push(String::new());
+    if self.edit.is_some() {
+      lines.push("Up/Down to choose a field, Enter to apply, Escape to stop".to_owned());
+    } else {
+      lines.push("Click a province to make it the through province, or click it again to clear it".to_owned());
+      lines.push("Drag the handles to move the start and stop, Delete resets them".to_owned());
+      lines.push("E to edit the rule name and comment, Escape to deselect".to_owned());
+      if rule.is_some() {
+        lines.push("Click a permission to toggle it, Shift-click a province to toggle it as required".to_owned());
+        lines.push("Alt-click a province to make it the rule's icon, or click it again to clear it".to_owned());
+      };
+    };
+
+    lines
+  }
+
+  /// Draws the editor for the given adjacency in the bottom right corner of the window
+  pub fn draw(
+    &mut self,
+    ctx: Context,
+    interface: &Interface,
+    map: &Map,
+    rel: UOrd<ProvinceId>,
+    glyph_cache: &mut FontGlyphCache,
+    gl: &mut GlGraphics
+  ) {
+    let edit_line = self.edit.as_ref().map(|edit| (edit.field.line(), parse_text(edit.input.text()).is_ok()));
+    let rule_name = &map.get_connection(rel).rule_name;
+    let unknown_rule = !rule_name.is_empty() && map.get_adjacency_rule(rule_name).is_none();
+    let lines = self.lines(map, rel).into_iter()
+      .enumerate()
+      .map(|(i, line)| match edit_line {
+        Some((edit_index, false)) if edit_index == i => (line, colors::PROBLEM),
+        _ if unknown_rule && i == RULE_LINE => (line, colors::WARNING),
+        _ => (line, colors::WHITE)
+      })
+      .collect::<Vec<_>>();
+    self.layout = draw_panel(ctx, interface, PanelAnchor::BottomRight, &lines, glyph_cache, gl);
+
+    if let Some(edit) = &self.edit {
+      let [x, y] = self.layout.line_positions[edit.field.line()];
+      let prefix_width = font::get_width_metric_str(&edit_prefix(edit.field));
+      edit.input.draw_overlay(ctx, [x + prefix_width, y], gl);
+    };
+  }
+}
+
+/// Describes the adjacency rule with the given name, or offers to create it if there is none
+fn rule_lines(map: &Map, rule_name: &str, rule: Option<&AdjacencyRuleData>) -> Vec<String> {
+  let Some(rule) = rule else {
+    return vec![format!("Rule {:?} does not exist, click here to create it", rule_name)];
+  };
+
+  let label = |id: ProvinceId| province_label(map.get_province_by_id(id));
+  let required = match rule.required_provinces.as_slice() {
+    [] => "none".to_owned(),
+    required => required.iter().map(|&id| label(id)).collect::<Vec<String>>().join(", ")
+  };
+
+  let mut lines = vec![format!("Rule {}:", rule.name)];
+  for relation in RuleRelation::ALL {
+    let texts = permission_texts(rule, relation).map(|(_, text)| text);
+    lines.push(format!("{}{}", permission_prefix(relation), texts.join(PERMISSION_SEPARATOR)));
+  };
+
+  lines.push(format!("  Required: {}", required));
+  lines.push(format!("  Icon: {}", rule.icon.map_or_else(|| "none".to_owned(), label)));
+  lines
+}
+
+fn permission_prefix(relation: RuleRelation) -> String {
+  format!("  {}: ", relation.to_str())
+}
+
+/// The text shown for each unit's permission in a relation, such as `army no`
+fn permission_texts(rule: &AdjacencyRuleData, relation: RuleRelation) -> [(RuleUnit, String); 4] {
+  let permissions = rule.permissions(relation);
+  RuleUnit::ALL.map(|unit| (unit, format!("{} {}", unit.to_str(), if permissions.get(unit) { "yes" } else { "no" })))
+}
+
+/// The text in front of the value of a field that is being edited
+fn edit_prefix(field: AdjacencyField) -> String {
+  format!("> {}: ", field.name())
+}
+
+/// Rule names and comments are written to `adjacencies.csv`, and HOI4 does not understand quoted fields,
+/// so they may not contain anything that would need quoting
+fn parse_text(text: &str) -> Result<&str, String> {
+  if text.contains([';', '"']) {
+    Err("Adjacency text may not contain ; or \"".to_owned())
+  } else {
+    Ok(text.trim())
+  }
+}
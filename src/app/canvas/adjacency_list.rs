@@ -0,0 +1,289 @@
+//! The adjacency list, a panel listing every adjacency on the map, which can be filtered and sorted
+use graphics::context::Context;
+use opengl_graphics::GlGraphics;
+use piston::input::Key;
+use uord::UOrd2 as UOrd;
+use vecmath::Vector2;
+
+use super::{draw_panel, PanelAnchor, PanelLayout};
+use super::inspector::province_label;
+use crate::app::{colors, FontGlyphCache};
+use crate::app::interface::{InputFilter, InputResponse, Interface, TextInput};
+use crate::app::map::{Color, ConnectionData, ConnectionKind, Map, ProvinceData, ProvinceId};
+use crate::events::KeyMods;
+use crate::font;
+
+/// How many adjacencies are listed at once, the rest can be scrolled to
+const PAGE_ROWS: usize = 16;
+/// The lines above the first adjacency, the title, the filters, the sort order and the column headers
+const HEADER_LINES: usize = 6;
+const PROVINCE_FILTER_PREFIX: &str = "Province: ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum AdjacencySort {
+  #[default]
+  From,
+  To,
+  Kind,
+  Through,
+  RuleName
+}
+
+impl AdjacencySort {
+  fn name(self) -> &'static str {
+    match self {
+      AdjacencySort::From => "FROM",
+      AdjacencySort::To => "TO",
+      AdjacencySort::Kind => "TYPE",
+      AdjacencySort::Through => "THROUGH",
+      AdjacencySort::RuleName => "RULE NAME"
+    }
+  }
+
+  fn cycle(self) -> AdjacencySort {
+    match self {
+      AdjacencySort::From => AdjacencySort::To,
+      AdjacencySort::To => AdjacencySort::Kind,
+      AdjacencySort::Kind => AdjacencySort::Through,
+      AdjacencySort::Through => AdjacencySort::RuleName,
+      AdjacencySort::RuleName => AdjacencySort::From
+    }
+  }
+}
+
+/// What a click on the adjacency list did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListClick {
+  /// The click was not on the list, and should be passed on to the tool
+  Outside,
+  Handled,
+  /// An adjacency was clicked, it should be selected
+  Select(UOrd<Color>)
+}
+
+#[derive(Debug, Clone)]
+pub struct AdjacencyList {
+  pub visible: bool,
+  kind_filter: Option<ConnectionKind>,
+  /// Only adjacencies with this province ID at either end or as their through province are listed
+  province_filter: TextInput,
+  province_filter_editing: bool,
+  /// Only straits and canals without a through province are listed
+  missing_through: bool,
+  sort: AdjacencySort,
+  scroll: usize,
+  /// The adjacencies that were listed when the panel was last drawn, and where it was drawn
+  listed: Vec<UOrd<Color>>,
+  layout: PanelLayout
+}
+
+/// A single adjacency, as it is listed
+struct Row<'a> {
+  from: &'a ProvinceData,
+  to: &'a ProvinceData,
+  through: Option<&'a ProvinceData>,
+  connection_data: &'a ConnectionData
+}
+
+impl<'a> Row<'a> {
+  fn new(map: &'a Map, rel: UOrd<ProvinceId>, connection_data: &'a ConnectionData) -> Self {
+    // Adjacencies loaded from `adjacencies.csv` keep the order their provinces were listed in
+    let [a, b] = rel.into_array();
+    let [from, to] = match connection_data.source {
+      Some(source) if source.from == b => [b, a],
+      _ => [a, b]
+    };
+
+    Row {
+      from: map.get_province_by_id(from),
+      to: map.get_province_by_id(to),
+      through: connection_data.through.map(|through| map.get_province_by_id(through)),
+      connection_data
+    }
+  }
+
+  fn text(&self) -> String {
+    let through = self.through.map_or_else(|| "-".to_owned(), province_label);
+    let mut text = format!(
+      "{:>8} {:>8} {:<11} {:>8}",
+      province_label(self.from),
+      province_label(self.to),
+      self.connection_data.kind.to_str().to_uppercase(),
+      through
+    );
+
+    for extra in [&self.connection_data.rule_name, &self.connection_data.comment] {
+      if !extra.is_empty() {
+        text.push_str(&format!(" {}", extra));
+      };
+    };
+
+    text
+  }
+}
+
+impl AdjacencyList {
+  pub fn editing(&self) -> bool {
+    self.province_filter_editing
+  }
+
+  /// Handles a key press while the province filter is being typed into, the filter applies as it is typed
+  pub fn on_key(&mut self, key: Key, mods: KeyMods) {
+    match self.province_filter.on_key(key, mods) {
+      InputResponse::Submit => self.province_filter_editing = false,
+      InputResponse::Cancel => {
+        self.province_filter.set_text("");
+        self.province_filter_editing = false;
+      },
+      InputResponse::Ignored(_) | InputResponse::Handled => ()
+    };
+
+    self.scroll = 0;
+  }
+
+  pub fn on_text(&mut self, text: &str) {
+    if self.province_filter_editing {
+      self.province_filter.on_text(text);
+      self.scroll = 0;
+    };
+  }
+
+  /// Scrolls the list if the cursor is over it, returning whether it was
+  pub fn on_scroll(&mut self, cursor_pos: Vector2<f64>, dy: f64) -> bool {
+    let over = self.visible && self.layout.contains(cursor_pos);
+    if over {
+      self.scroll = if dy > 0.0 { self.scroll.saturating_sub(1) } else { self.scroll + 1 };
+    };
+
+    over
+  }
+
+  pub fn on_click(&mut self, cursor_pos: Vector2<f64>) -> ListClick {
+    if !self.visible || !self.layout.contains(cursor_pos) {
+      return ListClick::Outside;
+    };
+
+    self.province_filter_editing = false;
+    match self.layout.line_at(cursor_pos) {
+      Some(1) => {
+        self.kind_filter = match self.kind_filter {
+          None => Some(ConnectionKind::Strait),
+          Some(ConnectionKind::Strait) => Some(ConnectionKind::Canal),
+          Some(ConnectionKind::Canal) => Some(ConnectionKind::Impassable),
+          Some(ConnectionKind::Impassable) => None
+        };
+        self.scroll = 0;
+      },
+      Some(2) => self.province_filter_editing = true,
+      Some(3) => {
+        self.missing_through = !self.missing_through;
+        self.scroll = 0;
+      },
+      Some(4) => self.sort = self.sort.cycle(),
+      Some(line) if line >= HEADER_LINES => {
+        if let Some(&rel) = self.listed.get(line - HEADER_LINES) {
+          return ListClick::Select(rel);
+        };
+      },
+      _ => ()
+    };
+
+    ListClick::Handled
+  }
+
+  fn includes(&self, row: &Row) -> bool {
+    let kind = self.kind_filter.is_none_or(|kind| row.connection_data.kind == kind);
+    let missing_through = !self.missing_through || (row.through.is_none() && row.connection_data.kind != ConnectionKind::Impassable);
+    let province = match self.province_filter.parse::<u32>() {
+      Some(id) => [Some(row.from), Some(row.to), row.through].into_iter()
+        .any(|province_data| province_data.is_some_and(|province_data| province_data.preserved_id == Some(id))),
+      None => true
+    };
+
+    kind && missing_through && province
+  }
+
+  /// Draws the list in the bottom left corner of the window, remembering which adjacencies were listed where
+  pub fn draw(
+    &mut self,
+    ctx: Context,
+    interface: &Interface,
+    map: &Map,
+    selected: Option<UOrd<ProvinceId>>,
+    glyph_cache: &mut FontGlyphCache,
+    gl: &mut GlGraphics
+  ) {
+    let mut rows = map.iter_connection_data()
+      .map(|(rel, connection_data)| (rel, Row::new(map, rel, connection_data)))
+      .filter(|(_, row)| self.includes(row))
+      .collect::<Vec<_>>();
+    match self.sort {
+      AdjacencySort::From => rows.sort_by_cached_key(|(_, row)| (province_key(row.from), province_key(row.to))),
+      AdjacencySort::To => rows.sort_by_cached_key(|(_, row)| (province_key(row.to), province_key(row.from))),
+      AdjacencySort::Kind => rows.sort_by_cached_key(|(_, row)| (row.connection_data.kind.to_str(), province_key(row.from))),
+      AdjacencySort::Through => rows.sort_by_cached_key(|(_, row)| (row.through.map(province_key), province_key(row.from))),
+      AdjacencySort::RuleName => rows.sort_by_cached_key(|(_, row)| (row.connection_data.rule_name.clone(), province_key(row.from)))
+    };
+
+    self.scroll = self.scroll.min(rows.len().saturating_sub(PAGE_ROWS));
+    let page = rows.iter().skip(self.scroll).take(PAGE_ROWS);
+    self.listed = page.clone().map(|(rel, _)| rel.map(|id| map.get_province_by_id(id).color)).collect();
+
+    let kind_filter = self.kind_filter.map_or_else(|| "ALL".to_owned(), |kind| kind.to_str().to_uppercase());
+    let yes_no = |value: bool| if value { "YES" } else { "NO" };
+    let province_filter = match (self.province_filter.text(), self.province_filter_editing) {
+      ("", false) => "ANY",
+      (text, _) => text
+    };
+
+    let white = |line: String| (line, colors::WHITE);
+    let mut lines = vec![
+      white(format!("Adjacency List: {} of {}", rows.len(), map.connections_count())),
+      white(format!("Type: {}", kind_filter)),
+      white(format!("{}{}", PROVINCE_FILTER_PREFIX, province_filter)),
+      white(format!("Missing through only: {}", yes_no(self.missing_through))),
+      white(format!("Sort by: {}", self.sort.name())),
+      white(format!("{:>8} {:>8} {:<11} {:>8} {}", "FROM", "TO", "TYPE", "THROUGH", "RULE NAME/COMMENT"))
+    ];
+
+    for (rel, row) in page {
+      let color = if Some(*rel) == selected { colors::FLASH } else { colors::WHITE };
+      lines.push((row.text(), color));
+    };
+
+    if rows.len() > PAGE_ROWS {
+      let last = (self.scroll + PAGE_ROWS).min(rows.len());
+      lines.push(white(format!("Showing {}-{}, scroll to see more", self.scroll + 1, last)));
+    };
+
+    lines.push(white("Click a filter to change it, or an adjacency to select it".to_owned()));
+
+    self.layout = draw_panel(ctx, interface, PanelAnchor::BottomLeft, &lines, glyph_cache, gl);
+    if self.province_filter_editing {
+      let [x, y] = self.layout.line_positions[2];
+      let prefix_width = font::get_width_metric_str(PROVINCE_FILTER_PREFIX);
+      self.province_filter.draw_overlay(ctx, [x + prefix_width, y], gl);
+    };
+  }
+}
+
+impl Default for AdjacencyList {
+  fn default() -> AdjacencyList {
+    AdjacencyList {
+      visible: false,
+      kind_filter: None,
+      province_filter: TextInput::new(InputFilter::Digits, 8),
+      province_filter_editing: false,
+      missing_through: false,
+      sort: AdjacencySort::default(),
+      scroll: 0,
+      listed: Vec::new(),
+      layout: PanelLayout::default()
+    }
+  }
+}
+
+/// Provinces with IDs are sorted by their ID, before any provinces without one
+fn province_key(province_data: &ProvinceData) -> (bool, Option<u32>, Color) {
+  (province_data.preserved_id.is_none(), province_data.preserved_id, province_data.color)
+}
@@ -0,0 +1,62 @@
+//! Parsing what the go to prompt was asked to find, a province ID, a province color or a position on the map
+use vecmath::Vector2;
+
+use super::inspector::parse_hex_color;
+use crate::app::map::Color;
+
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GoToTarget {
+  /// A province ID, as it appears in `definition.csv`
+  Id(u32),
+  Color(Color),
+  Position(Vector2<u32>)
+}
+
+impl FromStr for GoToTarget {
+  type Err = String;
+
+  /// Accepts `8123` as an ID, `#ff8000`, `ff8000` or `255 128 0` as a color, and `1024, 512` as a position
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let s = s.trim();
+    let target = if let Some(hex) = s.strip_prefix('#') {
+      parse_hex_color(hex).map(GoToTarget::Color)
+    } else {
+      let parts = s.split(|ch: char| ch == ',' || ch.is_whitespace())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>();
+      match parts.as_slice() {
+        [id] if id.bytes().all(|b| b.is_ascii_digit()) => id.parse::<u32>().ok().map(GoToTarget::Id),
+        [hex] => parse_hex_color(hex).map(GoToTarget::Color),
+        [x, y] => Option::zip(x.parse::<u32>().ok(), y.parse::<u32>().ok())
+          .map(|(x, y)| GoToTarget::Position([x, y])),
+        [r, g, b] => match [r, g, b].map(|channel| channel.parse::<u8>().ok()) {
+          [Some(r), Some(g), Some(b)] => Some(GoToTarget::Color([r, g, b])),
+          _ => None
+        },
+        _ => None
+      }
+    };
+
+    target.ok_or_else(|| format!("Could not understand {:?}, expected a province ID, a color or a position", s))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_go_to_target() {
+    assert_eq!("8123".parse(), Ok(GoToTarget::Id(8123)));
+    assert_eq!(" #ff8000 ".parse(), Ok(GoToTarget::Color([0xff, 0x80, 0x00])));
+    assert_eq!("ff8000".parse(), Ok(GoToTarget::Color([0xff, 0x80, 0x00])));
+    assert_eq!("255 128 0".parse(), Ok(GoToTarget::Color([255, 128, 0])));
+    assert_eq!("255, 128, 0".parse(), Ok(GoToTarget::Color([255, 128, 0])));
+    assert_eq!("1024, 512".parse(), Ok(GoToTarget::Position([1024, 512])));
+    assert!("256 128 0".parse::<GoToTarget>().is_err());
+    assert!("#123".parse::<GoToTarget>().is_err());
+    assert!("".parse::<GoToTarget>().is_err());
+  }
+}
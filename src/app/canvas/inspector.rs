@@ -0,0 +1,316 @@
+//! The province inspector, a panel listing everything about a single province, with fields that can be edited in place
+use graphics::context::Context;
+use opengl_graphics::GlGraphics;
+use piston::input::Key;
+
+use super::{cycle_kinds, draw_panel, PanelAnchor};
+use crate::app::{colors, FontGlyphCache};
+use crate::app::interface::{InputFilter, InputResponse, Interface, TextInput};
+use crate::app::map::{Bundle, Color, ProvinceData, ProvinceEdit, ProvinceKind};
+use crate::config::Config;
+use crate::events::KeyMods;
+use crate::font;
+use crate::util::stringify_color;
+
+/// The most adjacencies that are listed before the rest are summarized
+const MAX_LISTED_CONNECTIONS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InspectorField {
+  Color,
+  Kind,
+  Terrain,
+  Continent,
+  Coastal
+}
+
+impl InspectorField {
+  const ALL: [InspectorField; 5] = [
+    InspectorField::Color,
+    InspectorField::Kind,
+    InspectorField::Terrain,
+    InspectorField::Continent,
+    InspectorField::Coastal
+  ];
+
+  fn name(self) -> &'static str {
+    match self {
+      InspectorField::Color => "Color",
+      InspectorField::Kind => "Type",
+      InspectorField::Terrain => "Terrain",
+      InspectorField::Continent => "Continent",
+      InspectorField::Coastal => "Coastal"
+    }
+  }
+
+  fn cycle(self, backwards: bool) -> InspectorField {
+    let len = InspectorField::ALL.len();
+    let index = InspectorField::ALL.iter().position(|&field| field == self).expect("infallible");
+    let index = if backwards { (index + len - 1) % len } else { (index + 1) % len };
+    InspectorField::ALL[index]
+  }
+
+  /// The value of this field as it is typed into the inspector
+  fn value(self, province_data: &ProvinceData) -> String {
+    match self {
+      InspectorField::Color => hex_color(province_data.color),
+      InspectorField::Kind => province_data.kind.to_str().to_owned(),
+      InspectorField::Terrain => province_data.terrain.clone(),
+      InspectorField::Continent => province_data.continent.to_string(),
+      InspectorField::Coastal => yes_no(province_data.coastal).to_owned()
+    }
+  }
+
+  /// A text input for this field, filled in with its current value
+  fn input(self, province_data: &ProvinceData) -> TextInput {
+    let input = match self {
+      InspectorField::Color => TextInput::new(InputFilter::HexDigits, 6),
+      InspectorField::Continent => TextInput::new(InputFilter::Digits, 5),
+      InspectorField::Kind | InspectorField::Terrain | InspectorField::Coastal => TextInput::new(InputFilter::Any, 32)
+    };
+
+    input.with_text(self.value(province_data))
+  }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Inspector {
+  pub visible: bool,
+  edit: Option<InspectorEdit>
+}
+
+/// A field of a province that is currently being edited
+#[derive(Debug, Clone)]
+struct InspectorEdit {
+  which: Color,
+  field: InspectorField,
+  input: TextInput
+}
+
+impl Inspector {
+  /// The province currently being edited, if any
+  pub fn editing(&self) -> Option<Color> {
+    self.edit.as_ref().map(|edit| edit.which)
+  }
+
+  pub fn start_editing(&mut self, which: Color, province_data: &ProvinceData) {
+    let field = InspectorField::Color;
+    self.visible = true;
+    self.edit = Some(InspectorEdit { which, field, input: field.input(province_data) });
+  }
+
+  pub fn stop_editing(&mut self) {
+    self.edit = None;
+  }
+
+  /// Keeps editing the same province after one of its edits has given it a new color
+  pub fn follow_color(&mut self, bundle: &Bundle, color: Color) {
+    if let Some(edit) = &mut self.edit {
+      edit.which = color;
+      edit.input = edit.field.input(bundle.map.get_province(color));
+    };
+  }
+
+  /// Handles a key press while editing, returning an edit once one has been confirmed with `Enter`
+  pub fn on_key(&mut self, key: Key, mods: KeyMods, bundle: &Bundle) -> Option<Result<ProvinceEdit, String>> {
+    let edit = self.edit.as_mut()?;
+    let province_data = bundle.map.get_province(edit.which);
+    let cycles = matches!(edit.field, InspectorField::Kind | InspectorField::Terrain | InspectorField::Coastal);
+    if cycles && matches!(key, Key::Left | Key::Right) && !mods.shift {
+      let backwards = key == Key::Left;
+      let text = edit.input.text();
+      let text = match edit.field {
+        InspectorField::Kind => cycle_kinds(text.parse::<ProvinceKind>().ok(), backwards).to_str().to_owned(),
+        InspectorField::Terrain => bundle.config.cycle_terrains(Some(text), backwards),
+        _ => yes_no(Some(text != "yes")).to_owned()
+      };
+
+      edit.input.set_text(text);
+      return None;
+    };
+
+    match edit.input.on_key(key, mods) {
+      InputResponse::Submit => return Some(parse_edit(edit.field, edit.input.text(), &bundle.config)),
+      InputResponse::Cancel => self.edit = None,
+      InputResponse::Ignored(key @ (Key::Up | Key::Down | Key::Tab)) => {
+        edit.field = edit.field.cycle(key == Key::Up || (key == Key::Tab && mods.shift));
+        edit.input = edit.field.input(province_data);
+      },
+      InputResponse::Ignored(_) | InputResponse::Handled => ()
+    };
+
+    None
+  }
+
+  /// Handles typed text while editing
+  pub fn on_text(&mut self, text: &str) {
+    if let Some(edit) = &mut self.edit {
+      edit.input.on_text(text);
+    };
+  }
+
+  /// Every line of text shown in the inspector for the given province
+  fn lines(&self, bundle: &Bundle, which: Color) -> Vec<String> {
+    let map = &bundle.map;
+    let province_data = map.get_province(which);
+    let edit = self.edit.as_ref().filter(|edit| edit.which == which);
+    let mut lines = Vec::new();
+
+    let locked = if province_data.locked { " (Locked)" } else { "" };
+    lines.push(format!("Province {}{}", province_label(province_data), locked));
+    for field in InspectorField::ALL {
+      lines.push(match edit {
+        Some(edit) if edit.field == field => format!("{}{}", edit_prefix(field), edit.input.text()),
+        _ if field == InspectorField::Continent => format!("{}: {}", field.name(), map.continent_label(province_data.continent)),
+        _ => format!("{}: {}", field.name(), field.value(province_data).to_uppercase())
+      });
+    };
+
+    let [x, y] = province_data.center_of_mass();
+    lines.push(format!("Pixels: {}", province_data.pixel_count));
+    lines.push(format!("Center: {:.0}, {:.0} px", x, y));
+    if !province_data.merged_ids.is_empty() {
+      let merged_ids = province_data.merged_ids.iter().map(u32::to_string).collect::<Vec<String>>();
+      lines.push(format!("Merged IDs: {}", merged_ids.join(" ")));
+    };
+
+    if let Some(split_from) = province_data.split_from {
+      lines.push(format!("Split from: #{}", split_from));
+    };
+
+    let id = map.get_province_id(which).expect("province not found with color");
+    let connections = map.iter_connection_data()
+      .filter(|(rel, _)| rel.contains(&id))
+      .collect::<Vec<_>>();
+    lines.push(format!("Adjacencies: {}", connections.len()));
+    for (rel, connection_data) in connections.iter().take(MAX_LISTED_CONNECTIONS) {
+      let [a, b] = rel.into_array();
+      let other = if a == id { b } else { a };
+      let mut line = format!("- {} {}", province_label(map.get_province_by_id(other)), connection_data.kind.to_str().to_uppercase());
+      if let Some(through) = connection_data.through {
+        line.push_str(&format!(" through {}", province_label(map.get_province_by_id(through))));
+      };
+
+      lines.push(line);
+    };
+
+    if connections.len() > MAX_LISTED_CONNECTIONS {
+      lines.push(format!("- and {} more", connections.len() - MAX_LISTED_CONNECTIONS));
+    };
+
+    lines.push(String::new());
+    lines.push(match edit {
+      Some(edit) if matches!(edit.field, InspectorField::Kind | InspectorField::Terrain | InspectorField::Coastal) => {
+        "Type a value or Left/Right to change, Enter to apply, Escape to stop".to_owned()
+      },
+      Some(_) => "Type a value, Enter to apply, Escape to stop".to_owned(),
+      None => "E to edit this province".to_owned()
+    });
+
+    lines
+  }
+
+  /// Draws the inspector for the given province in the top right corner of the window
+  pub fn draw(
+    &self,
+    ctx: Context,
+    interface: &Interface,
+    bundle: &Bundle,
+    which: Color,
+    glyph_cache: &mut FontGlyphCache,
+    gl: &mut GlGraphics
+  ) {
+    // The line being edited is drawn in red while its value would not be accepted
+    let edit = self.edit.as_ref().filter(|edit| edit.which == which);
+    let edit_line = edit.map(|edit| {
+      let index = InspectorField::ALL.iter().position(|&field| field == edit.field).expect("infallible");
+      let valid = parse_edit(edit.field, edit.input.text(), &bundle.config).is_ok();
+      (index + 1, valid)
+    });
+
+    let lines = self.lines(bundle, which).into_iter()
+      .enumerate()
+      .map(|(i, line)| match edit_line {
+        Some((edit_index, false)) if edit_index == i => (line, colors::PROBLEM),
+        _ => (line, colors::WHITE)
+      })
+      .collect::<Vec<_>>();
+    let layout = draw_panel(ctx, interface, PanelAnchor::TopRight, &lines, glyph_cache, gl);
+
+    if let (Some(edit), Some((edit_index, _))) = (edit, edit_line) {
+      let [x, y] = layout.line_positions[edit_index];
+      let prefix_width = font::get_width_metric_str(&edit_prefix(edit.field));
+      edit.input.draw_overlay(ctx, [x + prefix_width, y], gl);
+    };
+  }
+}
+
+/// The text in front of the value of a field that is being edited
+fn edit_prefix(field: InspectorField) -> String {
+  format!("> {}: ", field.name())
+}
+
+fn parse_edit(field: InspectorField, text: &str, config: &Config) -> Result<ProvinceEdit, String> {
+  match field {
+    InspectorField::Color => parse_hex_color(text)
+      .map(ProvinceEdit::Color)
+      .ok_or_else(|| "Colors must be 6 hexadecimal digits".to_owned()),
+    InspectorField::Kind => text.parse::<ProvinceKind>()
+      .map(ProvinceEdit::Kind)
+      .map_err(|_| format!("Invalid type {}", text.to_uppercase())),
+    InspectorField::Terrain if config.terrains.contains_key(text) => Ok(ProvinceEdit::Terrain(text.to_owned())),
+    InspectorField::Terrain => Err(format!("Unknown terrain {}", text.to_uppercase())),
+    InspectorField::Continent => text.parse::<u16>()
+      .map(ProvinceEdit::Continent)
+      .map_err(|_| format!("Invalid continent {}", text)),
+    InspectorField::Coastal => match text {
+      "yes" => Ok(ProvinceEdit::Coastal(true)),
+      "no" => Ok(ProvinceEdit::Coastal(false)),
+      _ => Err("Coastal must be YES or NO".to_owned())
+    }
+  }
+}
+
+/// Provinces are labelled by their ID, or by their color if they do not have one yet
+pub(super) fn province_label(province_data: &ProvinceData) -> String {
+  match province_data.preserved_id {
+    Some(preserved_id) => format!("#{}", preserved_id),
+    None => stringify_color(province_data.color)
+  }
+}
+
+fn yes_no(value: Option<bool>) -> &'static str {
+  match value {
+    Some(true) => "yes",
+    Some(false) => "no",
+    None => "unknown"
+  }
+}
+
+fn hex_color(color: Color) -> String {
+  format!("{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+pub(super) fn parse_hex_color(text: &str) -> Option<Color> {
+  if text.len() != 6 || !text.bytes().all(|b| b.is_ascii_hexdigit()) {
+    return None;
+  };
+
+  let channel = |i: usize| u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).ok();
+  Some([channel(0)?, channel(1)?, channel(2)?])
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_hex_color() {
+    let color = [0x0a, 0xbc, 0xff];
+    assert_eq!(hex_color(color), "0abcff");
+    assert_eq!(parse_hex_color(&hex_color(color)), Some(color));
+    assert_eq!(parse_hex_color("0abcf"), None);
+    assert_eq!(parse_hex_color("0abcfg"), None);
+    assert_eq!(parse_hex_color("ééé"), None);
+  }
+}
@@ -2,13 +2,19 @@
 #[macro_use]
 mod csv;
 mod adjacency;
+mod adjacency_rule;
+mod continent;
 mod definition;
+mod script;
 
 use thiserror::Error;
 
 pub use self::csv::{ParseCsv, CsvError};
 pub use self::adjacency::*;
+pub use self::adjacency_rule::*;
+pub use self::continent::read_continent_names;
 pub use self::definition::*;
+pub use self::script::{ScriptEntry, ScriptValue};
 
 use std::num::ParseIntError;
 use std::str::ParseBoolError;
@@ -19,6 +25,14 @@ pub enum ParseError {
   InvalidAdjacencyKind,
   #[error("expected one of \"land\", \"sea\", or \"lake\"")]
   InvalidDefinitionKind,
+  #[error("unexpected token on line {0}")]
+  UnexpectedToken(usize),
+  #[error("unexpected end of file")]
+  UnexpectedEof,
+  #[error("unterminated string starting on line {0}")]
+  UnterminatedString(usize),
+  #[error("expected a value for {0:?}")]
+  MissingField(&'static str),
   #[error("{0}")]
   ParseIntError(#[from] ParseIntError),
   #[error("{0}")]
@@ -0,0 +1,302 @@
+//! Reading and writing `adjacency_rules.txt`, which controls who may pass through named adjacencies
+use super::ParseError;
+use super::script::{parse_script, write_script, ScriptEntry, ScriptValue};
+
+/// Each of the relations a country passing through an adjacency may have with the controller of its required provinces
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RuleRelation {
+  Contested,
+  Enemy,
+  Friend,
+  Neutral
+}
+
+impl RuleRelation {
+  pub const ALL: [RuleRelation; 4] = [RuleRelation::Contested, RuleRelation::Enemy, RuleRelation::Friend, RuleRelation::Neutral];
+
+  pub fn to_str(self) -> &'static str {
+    match self {
+      RuleRelation::Contested => "contested",
+      RuleRelation::Enemy => "enemy",
+      RuleRelation::Friend => "friend",
+      RuleRelation::Neutral => "neutral"
+    }
+  }
+}
+
+/// Each of the kinds of movement an adjacency rule may allow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RuleUnit {
+  Army,
+  Navy,
+  Submarine,
+  Trade
+}
+
+impl RuleUnit {
+  pub const ALL: [RuleUnit; 4] = [RuleUnit::Army, RuleUnit::Navy, RuleUnit::Submarine, RuleUnit::Trade];
+
+  pub fn to_str(self) -> &'static str {
+    match self {
+      RuleUnit::Army => "army",
+      RuleUnit::Navy => "navy",
+      RuleUnit::Submarine => "submarine",
+      RuleUnit::Trade => "trade"
+    }
+  }
+}
+
+/// Which kinds of movement are allowed for a single relation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RulePermissions {
+  pub army: bool,
+  pub navy: bool,
+  pub submarine: bool,
+  pub trade: bool
+}
+
+impl RulePermissions {
+  pub const ALLOW_ALL: Self = RulePermissions { army: true, navy: true, submarine: true, trade: true };
+
+  pub fn get(&self, unit: RuleUnit) -> bool {
+    match unit {
+      RuleUnit::Army => self.army,
+      RuleUnit::Navy => self.navy,
+      RuleUnit::Submarine => self.submarine,
+      RuleUnit::Trade => self.trade
+    }
+  }
+
+  pub fn get_mut(&mut self, unit: RuleUnit) -> &mut bool {
+    match unit {
+      RuleUnit::Army => &mut self.army,
+      RuleUnit::Navy => &mut self.navy,
+      RuleUnit::Submarine => &mut self.submarine,
+      RuleUnit::Trade => &mut self.trade
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdjacencyRule {
+  /// The name adjacencies refer to this rule by, in their `adjacency_rule_name` column
+  pub name: String,
+  /// Permissions for each relation, in the order of `RuleRelation::ALL`
+  pub permissions: [RulePermissions; 4],
+  /// The provinces whose controller decides who may pass
+  pub required_provinces: Vec<u32>,
+  /// The province the rule's icon is shown at, optional
+  pub icon: Option<u32>,
+  /// Anything else in the rule, such as `is_disabled` or `offset`, which is written back as it was read
+  pub extra: Vec<ScriptEntry>
+}
+
+impl AdjacencyRule {
+  /// A new rule that lets friends through and stops everyone else
+  pub fn new(name: String) -> Self {
+    let mut permissions = [RulePermissions::default(); 4];
+    permissions[RuleRelation::Friend as usize] = RulePermissions::ALLOW_ALL;
+    AdjacencyRule { name, permissions, required_provinces: Vec::new(), icon: None, extra: Vec::new() }
+  }
+
+  pub fn permissions(&self, relation: RuleRelation) -> &RulePermissions {
+    &self.permissions[relation as usize]
+  }
+
+  pub fn permissions_mut(&mut self, relation: RuleRelation) -> &mut RulePermissions {
+    &mut self.permissions[relation as usize]
+  }
+
+  /// Reads every `adjacency_rule` block from the contents of `adjacency_rules.txt`, anything else in the file is ignored
+  pub fn read_rules(text: &str) -> Result<Vec<AdjacencyRule>, ParseError> {
+    parse_script(text)?.into_iter()
+      .filter(|entry| entry.key.as_deref() == Some("adjacency_rule"))
+      .filter_map(|entry| match entry.value {
+        ScriptValue::Block(block) => Some(block),
+        ScriptValue::Scalar(_) => None
+      })
+      .map(AdjacencyRule::from_entries)
+      .collect()
+  }
+
+  pub fn write_rules(rules: &[AdjacencyRule]) -> String {
+    let entries = rules.iter()
+      .map(AdjacencyRule::to_script_entry)
+      .collect::<Vec<ScriptEntry>>();
+    write_script(&entries)
+  }
+
+  /// Writes the rules in place of the `adjacency_rule` blocks of the contents of `adjacency_rules.txt`,
+  /// keeping comments and anything else in the file where they were.
+  /// Each rule takes the place of the block with its name, rules without one follow the last block.
+  pub fn rewrite_rules(text: &str, rules: &[AdjacencyRule]) -> Result<String, ParseError> {
+    let mut entries = parse_script(text)?;
+    let mut remaining = rules.iter().collect::<Vec<&AdjacencyRule>>();
+    let mut last_rule = None;
+    let mut i = 0;
+    while i < entries.len() {
+      if entries[i].key.as_deref() != Some("adjacency_rule") {
+        i += 1;
+        continue;
+      };
+
+      let name = entries[i].value.as_block()
+        .and_then(|block| block.iter().find(|entry| entry.key.as_deref() == Some("name")))
+        .and_then(|entry| entry.value.as_scalar())
+        .map(|name| name.trim_matches('"'));
+      match remaining.iter().position(|rule| Some(rule.name.as_str()) == name) {
+        Some(position) => {
+          entries[i] = remaining.remove(position).to_script_entry();
+          last_rule = Some(i);
+          i += 1;
+        },
+        None => {
+          entries.remove(i);
+        }
+      };
+    };
+
+    let rest = entries.split_off(last_rule.map_or(entries.len(), |last_rule| last_rule + 1));
+    entries.extend(remaining.into_iter().map(AdjacencyRule::to_script_entry));
+    entries.extend(rest);
+    Ok(write_script(&entries))
+  }
+
+  fn from_entries(entries: Vec<ScriptEntry>) -> Result<Self, ParseError> {
+    let mut name = None;
+    let mut permissions = [RulePermissions::default(); 4];
+    let mut required_provinces = Vec::new();
+    let mut icon = None;
+    let mut extra = Vec::new();
+    for entry in entries {
+      let relation = RuleRelation::ALL.into_iter()
+        .find(|relation| entry.key.as_deref() == Some(relation.to_str()));
+      match (entry.key.as_deref(), relation, &entry.value) {
+        (Some("name"), _, ScriptValue::Scalar(scalar)) => name = Some(scalar.trim_matches('"').to_owned()),
+        (Some("required_provinces"), _, ScriptValue::Block(block)) => {
+          for item in block.iter().filter(|item| !item.is_comment()) {
+            let id = item.value.as_scalar().ok_or(ParseError::MissingField("required_provinces"))?;
+            required_provinces.push(id.parse::<u32>()?);
+          };
+        },
+        (Some("icon"), _, ScriptValue::Scalar(scalar)) => icon = Some(scalar.parse::<u32>()?),
+        (_, Some(relation), ScriptValue::Block(block)) => {
+          for item in block {
+            let unit = RuleUnit::ALL.into_iter()
+              .find(|unit| item.key.as_deref() == Some(unit.to_str()));
+            if let (Some(unit), Some(value)) = (unit, item.value.as_scalar()) {
+              *permissions[relation as usize].get_mut(unit) = parse_yes_no(value)?;
+            };
+          };
+        },
+        _ => extra.push(entry)
+      };
+    };
+
+    let name = name.ok_or(ParseError::MissingField("name"))?;
+    Ok(AdjacencyRule { name, permissions, required_provinces, icon, extra })
+  }
+
+  fn to_script_entry(&self) -> ScriptEntry {
+    ScriptEntry::new("adjacency_rule", ScriptValue::Block(self.to_entries()))
+  }
+
+  fn to_entries(&self) -> Vec<ScriptEntry> {
+    let mut entries = vec![ScriptEntry::new("name", ScriptValue::scalar(format!("\"{}\"", self.name)))];
+    for relation in RuleRelation::ALL {
+      let permissions = self.permissions(relation);
+      let block = RuleUnit::ALL.into_iter()
+        .map(|unit| ScriptEntry::new(unit.to_str(), ScriptValue::scalar(stringify_yes_no(permissions.get(unit)))))
+        .collect();
+      entries.push(ScriptEntry::new(relation.to_str(), ScriptValue::Block(block)));
+    };
+
+    let required_provinces = self.required_provinces.iter()
+      .map(|id| ScriptEntry::item(id.to_string()))
+      .collect();
+    entries.push(ScriptEntry::new("required_provinces", ScriptValue::Block(required_provinces)));
+    if let Some(icon) = self.icon {
+      entries.push(ScriptEntry::new("icon", ScriptValue::scalar(icon.to_string())));
+    };
+
+    entries.extend(self.extra.iter().cloned());
+    entries
+  }
+}
+
+fn parse_yes_no(s: &str) -> Result<bool, ParseError> {
+  match s {
+    "yes" => Ok(true),
+    "no" => Ok(false),
+    s => Ok(s.parse::<bool>()?)
+  }
+}
+
+fn stringify_yes_no(value: bool) -> &'static str {
+  if value { "yes" } else { "no" }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_adjacency_rules() {
+    const SAMPLE: &str = "adjacency_rule =\n\
+      {\n\
+      \tname = \"SOME_STRAIT\"\n\
+      \n\
+      \tcontested =\n\
+      \t{\n\
+      \t\tarmy = no\n\
+      \t\tnavy = no\n\
+      \t\tsubmarine = no\n\
+      \t\ttrade = no\n\
+      \t}\n\
+      \tfriend = { army = yes navy = yes submarine = yes trade = yes }\n\
+      \tneutral = { army = no navy = no submarine = no trade = yes }\n\
+      \n\
+      \trequired_provinces = { 3002 9795 }\n\
+      \tis_disabled = { tooltip = \"Closed\" }\n\
+      \ticon = 3002\n\
+      \toffset = { -3 0 -6 }\n\
+      }\n";
+    let rules = AdjacencyRule::read_rules(SAMPLE).unwrap();
+    assert_eq!(rules.len(), 1);
+    let rule = &rules[0];
+    assert_eq!(rule.name, "SOME_STRAIT");
+    assert_eq!(*rule.permissions(RuleRelation::Friend), RulePermissions::ALLOW_ALL);
+    assert_eq!(*rule.permissions(RuleRelation::Enemy), RulePermissions::default());
+    assert!(rule.permissions(RuleRelation::Neutral).trade);
+    assert_eq!(rule.required_provinces, [3002, 9795]);
+    assert_eq!(rule.icon, Some(3002));
+    assert_eq!(rule.extra.len(), 2);
+
+    let written = AdjacencyRule::write_rules(&rules);
+    assert_eq!(AdjacencyRule::read_rules(&written).unwrap(), rules);
+
+    assert_eq!(AdjacencyRule::read_rules("adjacency_rule = { icon = 1 }"), Err(ParseError::MissingField("name")));
+  }
+
+  #[test]
+  fn test_rewrite_rules() {
+    const SAMPLE: &str = "# first\n\
+      adjacency_rule = { name = \"FIRST\" }\n\
+      # second\n\
+      adjacency_rule = { name = \"SECOND\" }\n\
+      other = yes\n";
+    let mut rules = AdjacencyRule::read_rules(SAMPLE).unwrap();
+    // The first rule is removed, the second is edited in place, and the new rule follows it
+    rules.remove(0);
+    rules[0].icon = Some(1);
+    rules.push(AdjacencyRule::new("THIRD".to_owned()));
+
+    let entries = parse_script(&AdjacencyRule::rewrite_rules(SAMPLE, &rules).unwrap()).unwrap();
+    assert_eq!(entries.len(), 5);
+    assert_eq!(entries[0], ScriptEntry::comment("# first"));
+    assert_eq!(entries[1], ScriptEntry::comment("# second"));
+    assert_eq!(entries[2], rules[0].to_script_entry());
+    assert_eq!(entries[3], rules[1].to_script_entry());
+    assert_eq!(entries[4], ScriptEntry::new("other", ScriptValue::scalar("yes")));
+  }
+}
@@ -0,0 +1,39 @@
+//! Reading `continent.txt`, which names the continents that `definition.csv` refers to by number
+use super::ParseError;
+use super::script::{parse_script, ScriptValue};
+
+/// Reads the names listed in the `continents` block, the first name belongs to continent 1
+pub fn read_continent_names(text: &str) -> Result<Vec<String>, ParseError> {
+  let entries = parse_script(text)?;
+  let continents = entries.iter()
+    .find(|entry| entry.key.as_deref() == Some("continents"))
+    .and_then(|entry| entry.value.as_block())
+    .ok_or(ParseError::MissingField("continents"))?;
+  continents.iter()
+    .filter(|entry| !entry.is_comment())
+    .map(|entry| match (&entry.key, &entry.value) {
+      (None, ScriptValue::Scalar(name)) => Ok(name.trim_matches('"').to_owned()),
+      _ => Err(ParseError::MissingField("continents"))
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_continent_names() {
+    const SAMPLE: &str = "continents = {\n\
+      \teurope\n\
+      \tnorth_america # the americas are split in two\n\
+      \t\"south_america\"\n\
+      }\n";
+    let names = read_continent_names(SAMPLE).unwrap();
+    assert_eq!(names, ["europe", "north_america", "south_america"]);
+
+    assert_eq!(read_continent_names("continents = { }").unwrap(), Vec::<String>::new());
+    assert_eq!(read_continent_names("europe"), Err(ParseError::MissingField("continents")));
+    assert_eq!(read_continent_names("continents = { europe = yes }"), Err(ParseError::MissingField("continents")));
+  }
+}
@@ -13,7 +13,6 @@ fn wrap_reader<R: Read>(inner: R) -> Reader<R> {
   ReaderBuilder::new()
     .delimiter(b';')
     .terminator(csv::Terminator::CRLF)
-    .comment(Some(b'#'))
     .quote(b'\"')
     .double_quote(false)
     .trim(csv::Trim::All)
@@ -41,13 +40,33 @@ pub trait ParseCsv: Sized {
 
   fn deserialize_record(record: csv::StringRecord) -> Result<Self, CsvError>;
 
+  /// Receives the comment lines found directly above this record, they are discarded by default
+  fn attach_comments(&mut self, _comments: Vec<String>) {}
+
+  /// The comment lines that should be written directly above this record
+  fn comments(&self) -> &[String] {
+    &[]
+  }
+
   fn read_records<R: Read>(reader: R) -> Result<Vec<Self>, CsvError> {
-    let reader = wrap_reader(reader);
+    Self::read_records_commented(reader).map(|(entries, _)| entries)
+  }
+
+  /// Reads every record, along with any comment lines that were found after the last record
+  fn read_records_commented<R: Read>(mut reader: R) -> Result<(Vec<Self>, Vec<String>), CsvError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data).map_err(csv::Error::from)?;
+    let (data, mut comments) = strip_comments(data);
+
+    let reader = wrap_reader(data.as_slice());
     let mut records = reader.into_records()
       .collect::<Result<VecDeque<_>, _>>()?;
+    // Comments above a header are kept with the first record instead
+    let mut leading_comments = Vec::new();
 
     if let Some(defined_header_record) = Self::HEADER_RECORD {
       if let Some(header_record) = records.pop_front() {
+        leading_comments = comments.pop_front().unwrap_or_default();
         let all_equal = header_record.iter()
           .zip(defined_header_record.into_iter().copied())
           .all(|(field, defined_field)| str::eq_ignore_ascii_case(field, defined_field));
@@ -58,8 +77,12 @@ pub trait ParseCsv: Sized {
       };
     };
 
+    let mut trailing_comments = comments.pop_back().unwrap_or_default();
     if let Some(defined_footer_record) = Self::FOOTER_RECORD {
       if let Some(footer_record) = records.pop_back() {
+        let mut footer_comments = comments.pop_back().unwrap_or_default();
+        footer_comments.append(&mut trailing_comments);
+        trailing_comments = footer_comments;
         let all_equal = footer_record.iter()
           .zip(defined_footer_record.into_iter().copied())
           .all(|(field, defined_field)| str::eq_ignore_ascii_case(field, defined_field));
@@ -70,16 +93,32 @@ pub trait ParseCsv: Sized {
       };
     };
 
-    let entries = records.into_iter()
-      .map(Self::deserialize_record)
-      .collect::<Result<Vec<Self>, _>>()?;
+    if let Some(first_comments) = comments.front_mut() {
+      leading_comments.append(first_comments);
+      *first_comments = leading_comments;
+    } else {
+      trailing_comments.splice(0..0, leading_comments);
+    };
 
-    Ok(entries)
+    let entries = records.into_iter().zip(comments)
+      .map(|(record, comments)| {
+        let mut entry = Self::deserialize_record(record)?;
+        entry.attach_comments(comments);
+        Ok(entry)
+      })
+      .collect::<Result<Vec<Self>, CsvError>>()?;
+
+    Ok((entries, trailing_comments))
   }
 
   fn serialize_record(&self) -> csv::StringRecord;
 
   fn write_records<W: Write>(entries: &[Self], writer: W) -> Result<(), CsvError> {
+    Self::write_records_commented(entries, &[], writer)
+  }
+
+  /// Writes every record, followed by the given comment lines
+  fn write_records_commented<W: Write>(entries: &[Self], trailing_comments: &[String], writer: W) -> Result<(), CsvError> {
     let mut writer = wrap_writer(writer);
 
     if let Some(header) = Self::HEADER_RECORD {
@@ -87,6 +126,10 @@ pub trait ParseCsv: Sized {
     };
 
     for entry in entries {
+      if !entry.comments().is_empty() {
+        writer = write_comments(writer, entry.comments())?;
+      };
+
       writer.write_record(entry.serialize_record().iter())?;
     };
 
@@ -94,10 +137,48 @@ pub trait ParseCsv: Sized {
       writer.write_record(footer)?;
     };
 
+    if !trailing_comments.is_empty() {
+      write_comments(writer, trailing_comments)?;
+    };
+
     Ok(())
   }
 }
 
+/// Empties out every comment line, returning the comments above each remaining non-empty line,
+/// with one extra group at the end for the comments after the last line. Line breaks are kept
+/// so that the line numbers given in errors stay accurate.
+fn strip_comments(data: Vec<u8>) -> (Vec<u8>, VecDeque<Vec<String>>) {
+  let mut out = Vec::with_capacity(data.len());
+  let mut groups = VecDeque::from([Vec::new()]);
+  for line in data.split_inclusive(|&b| b == b'\n') {
+    let content = line.strip_suffix(b"\n").unwrap_or(line);
+    let content = content.strip_suffix(b"\r").unwrap_or(content);
+    if content.trim_ascii_start().starts_with(b"#") {
+      groups.back_mut().expect("infallible").push(String::from_utf8_lossy(content).into_owned());
+      out.extend_from_slice(&line[content.len()..]);
+    } else {
+      if !content.is_empty() {
+        groups.push_back(Vec::new());
+      };
+
+      out.extend_from_slice(line);
+    };
+  };
+
+  (out, groups)
+}
+
+/// Writes comment lines directly to the underlying writer, bypassing any quoting
+fn write_comments<W: Write>(writer: Writer<W>, comments: &[String]) -> Result<Writer<W>, CsvError> {
+  let mut inner = writer.into_inner().map_err(|err| csv::Error::from(err.into_error()))?;
+  for comment in comments {
+    write!(inner, "{comment}\r\n").map_err(csv::Error::from)?;
+  };
+
+  Ok(wrap_writer(inner))
+}
+
 #[derive(Error, Debug)]
 pub enum CsvError {
   #[error(transparent)]
@@ -24,7 +24,11 @@ pub struct Definition {
   /// Province terrain, what type of 'biome' this province is; supports custom biomes
   pub terrain: String,
   /// Province continent ID
-  pub continent: u16
+  pub continent: u16,
+  /// Any fields after the continent ID, which are not used by HOI4PE
+  pub extra: Vec<String>,
+  /// Comment lines found directly above this row
+  pub comments: Vec<String>
 }
 
 impl ParseCsv for Definition {
@@ -46,12 +50,22 @@ impl ParseCsv for Definition {
     Ok(Definition {
       id, rgb: [r, g, b], kind, coastal,
       terrain: terrain.to_lowercase(),
-      continent
+      continent,
+      extra: line.iter().skip(8).map(str::to_owned).collect(),
+      comments: Vec::new()
     })
   }
 
+  fn attach_comments(&mut self, comments: Vec<String>) {
+    self.comments = comments;
+  }
+
+  fn comments(&self) -> &[String] {
+    &self.comments
+  }
+
   fn serialize_record(&self) -> csv::StringRecord {
-    csv::StringRecord::from(vec![
+    let mut record = csv::StringRecord::from(vec![
       self.id.to_string(),
       self.rgb[0].to_string(),
       self.rgb[1].to_string(),
@@ -60,7 +74,10 @@ impl ParseCsv for Definition {
       self.coastal.to_string(),
       self.terrain.clone(),
       self.continent.to_string()
-    ])
+    ]);
+
+    record.extend(self.extra.iter());
+    record
   }
 }
 
@@ -157,4 +174,23 @@ mod tests {
     let definition = Definition::read_records(SAMPLE.as_bytes()).unwrap();
     assert_eq!(definition.len(), 13375);
   }
+
+  #[test]
+  fn test_definition_extras() {
+    const SAMPLE: &str = "0;0;0;0;land;false;unknown;0\r\n\
+      # lakes\r\n\
+      1;230;81;119;lake;false;lakes;7;extra;\r\n\
+      2;0;0;55;land;false;forest;1\r\n\
+      \t# end of file\r\n";
+    let (definitions, comments) = Definition::read_records_commented(SAMPLE.as_bytes()).unwrap();
+    assert_eq!(definitions.len(), 2);
+    assert_eq!(definitions[0].comments, ["# lakes"]);
+    assert_eq!(definitions[0].extra, ["extra", ""]);
+    assert_eq!(definitions[1].extra, Vec::<String>::new());
+    assert_eq!(comments, ["\t# end of file"]);
+
+    let mut out = Vec::new();
+    Definition::write_records_commented(&definitions, &comments, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), SAMPLE);
+  }
 }
@@ -0,0 +1,252 @@
+//! A minimal reader and writer for Clausewitz script files, such as `adjacency_rules.txt`
+use super::ParseError;
+
+use std::fmt::Write;
+
+/// A single `key = value` pair within a script, a bare value inside of a list such as `{ 1 2 3 }`, or a comment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptEntry {
+  pub key: Option<String>,
+  /// The operator between the key and the value, usually `=` but triggers may also use comparisons like `>`
+  pub op: String,
+  pub value: ScriptValue
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptValue {
+  /// A single word or number, quoted strings keep their quotes
+  Scalar(String),
+  Block(Vec<ScriptEntry>),
+  /// A `#` comment along with its `#`, which is written back on a line of its own
+  Comment(String)
+}
+
+impl ScriptEntry {
+  pub fn new(key: impl Into<String>, value: ScriptValue) -> Self {
+    ScriptEntry { key: Some(key.into()), op: "=".to_owned(), value }
+  }
+
+  pub fn item(value: impl Into<String>) -> Self {
+    ScriptEntry { key: None, op: String::new(), value: ScriptValue::Scalar(value.into()) }
+  }
+
+  pub fn comment(text: impl Into<String>) -> Self {
+    ScriptEntry { key: None, op: String::new(), value: ScriptValue::Comment(text.into()) }
+  }
+
+  pub fn is_comment(&self) -> bool {
+    matches!(self.value, ScriptValue::Comment(_))
+  }
+}
+
+impl ScriptValue {
+  pub fn scalar(value: impl Into<String>) -> Self {
+    ScriptValue::Scalar(value.into())
+  }
+
+  pub fn as_scalar(&self) -> Option<&str> {
+    match self {
+      ScriptValue::Scalar(scalar) => Some(scalar),
+      ScriptValue::Block(_) | ScriptValue::Comment(_) => None
+    }
+  }
+
+  pub fn as_block(&self) -> Option<&[ScriptEntry]> {
+    match self {
+      ScriptValue::Block(entries) => Some(entries),
+      ScriptValue::Scalar(_) | ScriptValue::Comment(_) => None
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token<'a> {
+  Word(&'a str),
+  Op(&'a str),
+  Open,
+  Close,
+  Comment(&'a str)
+}
+
+/// Splits script text into tokens, each along with the line it was found on
+fn tokenize(text: &str) -> Result<Vec<(Token<'_>, usize)>, ParseError> {
+  let mut tokens = Vec::new();
+  let mut line = 1;
+  let mut rest = text;
+  while let Some(ch) = rest.chars().next() {
+    let len = match ch {
+      '\n' => {
+        line += 1;
+        1
+      },
+      ch if ch.is_whitespace() => ch.len_utf8(),
+      '#' => {
+        let len = rest.find('\n').unwrap_or(rest.len());
+        tokens.push((Token::Comment(rest[..len].trim_end()), line));
+        len
+      },
+      '{' => {
+        tokens.push((Token::Open, line));
+        1
+      },
+      '}' => {
+        tokens.push((Token::Close, line));
+        1
+      },
+      '=' | '<' | '>' | '!' | '?' => {
+        let len = if rest[1..].starts_with('=') { 2 } else { 1 };
+        tokens.push((Token::Op(&rest[..len]), line));
+        len
+      },
+      '"' => {
+        let len = rest[1..].find('"').ok_or(ParseError::UnterminatedString(line))? + 2;
+        tokens.push((Token::Word(&rest[..len]), line));
+        line += rest[..len].matches('\n').count();
+        len
+      },
+      _ => {
+        let len = rest.find(|ch: char| ch.is_whitespace() || "#{}=<>!?\"".contains(ch)).unwrap_or(rest.len());
+        tokens.push((Token::Word(&rest[..len]), line));
+        len
+      }
+    };
+
+    rest = &rest[len..];
+  };
+
+  Ok(tokens)
+}
+
+/// Parses the entries of a script file
+pub fn parse_script(text: &str) -> Result<Vec<ScriptEntry>, ParseError> {
+  let tokens = tokenize(text.trim_start_matches('\u{feff}'))?;
+  let mut tokens = tokens.into_iter().peekable();
+  let entries = parse_entries(&mut tokens)?;
+  match tokens.next() {
+    Some((_, line)) => Err(ParseError::UnexpectedToken(line)),
+    None => Ok(entries)
+  }
+}
+
+fn parse_entries<'a, I>(tokens: &mut std::iter::Peekable<I>) -> Result<Vec<ScriptEntry>, ParseError>
+where I: Iterator<Item = (Token<'a>, usize)> {
+  let mut entries = Vec::new();
+  loop {
+    let key = match tokens.peek() {
+      None | Some((Token::Close, _)) => return Ok(entries),
+      Some(&(Token::Op(_), line)) => return Err(ParseError::UnexpectedToken(line)),
+      Some((Token::Open, _)) => {
+        tokens.next();
+        let value = parse_block(tokens)?;
+        entries.push(ScriptEntry { key: None, op: String::new(), value });
+        continue;
+      },
+      Some(&(Token::Comment(comment), _)) => {
+        tokens.next();
+        entries.push(ScriptEntry::comment(comment));
+        continue;
+      },
+      Some(&(Token::Word(word), _)) => {
+        tokens.next();
+        word
+      }
+    };
+
+    match tokens.peek() {
+      Some(&(Token::Op(op), _)) => {
+        tokens.next();
+        // A comment between an operator and its value has nowhere to be kept, so it is dropped
+        let value = match tokens.find(|(token, _)| !matches!(token, Token::Comment(_))) {
+          Some((Token::Word(word), _)) => ScriptValue::scalar(word),
+          Some((Token::Open, _)) => parse_block(tokens)?,
+          Some((_, line)) => return Err(ParseError::UnexpectedToken(line)),
+          None => return Err(ParseError::UnexpectedEof)
+        };
+
+        entries.push(ScriptEntry { key: Some(key.to_owned()), op: op.to_owned(), value });
+      },
+      _ => entries.push(ScriptEntry::item(key))
+    };
+  }
+}
+
+/// Parses the inside of a block, after its opening brace has been consumed
+fn parse_block<'a, I>(tokens: &mut std::iter::Peekable<I>) -> Result<ScriptValue, ParseError>
+where I: Iterator<Item = (Token<'a>, usize)> {
+  let entries = parse_entries(tokens)?;
+  match tokens.next() {
+    Some((Token::Close, _)) => Ok(ScriptValue::Block(entries)),
+    Some((_, line)) => Err(ParseError::UnexpectedToken(line)),
+    None => Err(ParseError::UnexpectedEof)
+  }
+}
+
+/// Writes entries as script text, indented with tabs, with blocks of bare values kept on a single line
+pub fn write_script(entries: &[ScriptEntry]) -> String {
+  let mut out = String::new();
+  write_entries(&mut out, entries, 0);
+  out
+}
+
+fn write_entries(out: &mut String, entries: &[ScriptEntry], depth: usize) {
+  for entry in entries {
+    let indent = "\t".repeat(depth);
+    match &entry.key {
+      Some(key) => write!(out, "{}{} {} ", indent, key, entry.op).expect("infallible"),
+      None => out.push_str(&indent)
+    };
+
+    match &entry.value {
+      ScriptValue::Scalar(scalar) | ScriptValue::Comment(scalar) => out.push_str(scalar),
+      ScriptValue::Block(block) if block.iter().all(|entry| entry.key.is_none() && entry.value.as_scalar().is_some()) => {
+        let items = block.iter().filter_map(|entry| entry.value.as_scalar()).collect::<Vec<&str>>();
+        if items.is_empty() {
+          out.push_str("{ }");
+        } else {
+          write!(out, "{{ {} }}", items.join(" ")).expect("infallible");
+        };
+      },
+      ScriptValue::Block(block) => {
+        out.push_str("{\n");
+        write_entries(out, block, depth + 1);
+        write!(out, "{}}}", indent).expect("infallible");
+      }
+    };
+
+    out.push('\n');
+  };
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_script() {
+    const SAMPLE: &str = "# a comment\n\
+      rule = {\n\
+      \tname = \"SOME NAME\" # trailing comment\n\
+      \tlist = { 1 2 3 }\n\
+      \tcheck = {\n\
+      \t\tnum_of_factories >= 5\n\
+      \t}\n\
+      }\n";
+    let entries = parse_script(SAMPLE).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0], ScriptEntry::comment("# a comment"));
+    let rule = entries[1].value.as_block().unwrap();
+    assert_eq!(rule[0], ScriptEntry::new("name", ScriptValue::scalar("\"SOME NAME\"")));
+    assert_eq!(rule[1], ScriptEntry::comment("# trailing comment"));
+    assert_eq!(rule[2].value.as_block().unwrap().len(), 3);
+    assert_eq!(rule[3].value.as_block().unwrap()[0].op, ">=");
+
+    // Comments are written back on lines of their own
+    let written = write_script(&entries);
+    assert!(written.starts_with("# a comment\nrule = {\n\tname = \"SOME NAME\"\n\t# trailing comment\n"));
+    assert_eq!(parse_script(&written).unwrap(), entries);
+
+    assert_eq!(parse_script("a = {"), Err(ParseError::UnexpectedEof));
+    assert_eq!(parse_script("a = { }\n}"), Err(ParseError::UnexpectedToken(2)));
+    assert_eq!(parse_script("a = \"b"), Err(ParseError::UnterminatedString(1)));
+  }
+}
@@ -1,4 +1,6 @@
 //! Code regarding buttons and interactive elements on the screen
+mod text_input;
+
 use graphics::{Transformed, Viewport};
 use graphics::context::Context;
 use graphics::types::Color as DrawColor;
@@ -13,6 +15,8 @@ use super::canvas::ViewMode;
 use super::colors;
 use super::{FontGlyphCache, InterfaceDrawContext};
 
+pub use self::text_input::{InputFilter, InputResponse, TextInput};
+
 use std::sync::Arc;
 use std::fmt;
 
@@ -215,12 +219,16 @@ impl Interface {
 
     for (i, sidebar_button) in self.sidebar_tool_buttons.iter().enumerate() {
       let selected_tool = match (ictx.view_mode, i) {
-        // color map mode has the primary 3 tools (paint area, paint bucket, lasso) available
+        // color map mode has all of the tools (paint area, paint bucket, lasso, rectangle, merge, split, subdivide, select) available
         (Some(ViewMode::Color), _) => ictx.selected_tool,
         // coastal and adjacencies are read-only and have no tools
         (Some(ViewMode::Coastal | ViewMode::Adjacencies), _) => continue,
-        // kind, terrain, and continent only have the first tool (paint area) available
-        (Some(ViewMode::Kind | ViewMode::Terrain | ViewMode::Continent), 0) => Some(0),
+        // kind, terrain, and continent only have the paint area, lasso, rectangle and select tools available,
+        // any other tool acts like the paint area tool
+        (Some(ViewMode::Kind | ViewMode::Terrain | ViewMode::Continent), 0 | 2 | 3 | 7) => match ictx.selected_tool {
+          Some(tool @ (2 | 3 | 7)) => Some(tool),
+          _ => Some(0)
+        },
         (_, _) => continue
       };
 
@@ -296,6 +304,20 @@ impl ButtonElement {
         Some("Paint Bucket: Fill the hovered province with the current brush"),
       (SidebarToolLasso, ViewMode::Color) =>
         Some("Lasso: Draw a custom selection and then apply the current brush"),
+      (SidebarToolLasso, ViewMode::Kind | ViewMode::Terrain | ViewMode::Continent) =>
+        Some("Lasso: Draw a custom selection and then apply the current brush to every province inside of it"),
+      (SidebarToolRectangle, ViewMode::Color) =>
+        Some("Rectangle: Drag to paint a rectangle with the current brush"),
+      (SidebarToolRectangle, ViewMode::Kind | ViewMode::Terrain | ViewMode::Continent) =>
+        Some("Rectangle: Drag to apply the current brush to every province inside of a rectangle"),
+      (SidebarToolMerge, ViewMode::Color) =>
+        Some("Merge: Click a province to merge into, then click the provinces to merge into it"),
+      (SidebarToolSplit, ViewMode::Color) =>
+        Some("Split: Draw a line across a province and press enter to split it along the line"),
+      (SidebarToolSubdivide, ViewMode::Color) =>
+        Some("Subdivide: Click a province to divide it into smaller provinces of roughly equal size"),
+      (SidebarToolSelect, ViewMode::Color | ViewMode::Kind | ViewMode::Terrain | ViewMode::Continent) =>
+        Some("Select: Click or drag a rectangle to select provinces, shift adds to the selection, ctrl draws a lasso"),
       (SidebarOptionProvinceIds, ..) =>
         Some("Toggle Province IDs: Show or hide province IDs on the map"),
       (SidebarOptionProvinceBoundaries, ..) =>
@@ -571,8 +593,10 @@ fn draw_tooltip(
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ButtonId {
+  ToolbarFileNewMap,
   ToolbarFileOpenFileArchive,
   ToolbarFileOpenFolder,
+  ToolbarFileGenerateMap,
   ToolbarFileSave,
   ToolbarFileSaveAsArchive,
   ToolbarFileSaveAsFolder,
@@ -584,8 +608,25 @@ pub enum ButtonId {
   ToolbarEditCoastal,
   ToolbarEditRecolor,
   ToolbarEditProblems,
+  ToolbarEditSuggestStraits,
+  ToolbarEditAcceptSuggestions,
+  ToolbarEditRejectSuggestions,
+  ToolbarEditAssignContinents,
+  ToolbarEditFixContinents,
   ToolbarEditToggleLassoSnap,
   ToolbarEditNextMaskMode,
+  ToolbarEditMaskSelection,
+  ToolbarEditMaskTerrainBrush,
+  ToolbarEditMaskContinentBrush,
+  ToolbarEditInvertMask,
+  ToolbarEditToggleSubdivideTarget,
+  ToolbarSelectApplyBrush,
+  ToolbarSelectRecolor,
+  ToolbarSelectLock,
+  ToolbarSelectUnlock,
+  ToolbarSelectRemoveAdjacencies,
+  ToolbarSelectExportIds,
+  ToolbarSelectClear,
   ToolbarViewMode1,
   ToolbarViewMode2,
   ToolbarViewMode3,
@@ -595,6 +636,9 @@ pub enum ButtonId {
   ToolbarViewToggleProvinceIds,
   ToolbarViewToggleProvinceBoundaries,
   ToolbarViewToggleRiverOverlay,
+  ToolbarViewToggleInspector,
+  ToolbarViewToggleAdjacencyList,
+  ToolbarViewGoTo,
   ToolbarViewResetZoom,
   ToolbarViewFontLicense,
   #[cfg(any(debug_assertions, feature = "debug-mode"))]
@@ -604,6 +648,11 @@ pub enum ButtonId {
   SidebarToolPaintArea,
   SidebarToolPaintBucket,
   SidebarToolLasso,
+  SidebarToolRectangle,
+  SidebarToolMerge,
+  SidebarToolSplit,
+  SidebarToolSubdivide,
+  SidebarToolSelect,
   SidebarOptionProvinceIds,
   SidebarOptionProvinceBoundaries,
   SidebarOptionRiverOverlay
@@ -621,8 +670,10 @@ enum SidebarPrimitiveKind {
 const TOOLBAR_DROPDOWN_WIDTH: u32 = 320;
 const TOOLBAR_PRIMITIVE: ToolbarPrimitive<'static> = &[
   ("File", &[
+    ("New Map...", "Ctrl+N", ButtonId::ToolbarFileNewMap),
     ("Open File or Archive...", "Ctrl+Alt+O", ButtonId::ToolbarFileOpenFileArchive),
     ("Open Folder...", "Ctrl+O", ButtonId::ToolbarFileOpenFolder),
+    ("New Map from Land Image...", "", ButtonId::ToolbarFileGenerateMap),
     ("Save", "Ctrl+S", ButtonId::ToolbarFileSave),
     ("Save As Archive...", "Ctrl+Shift+Alt+S", ButtonId::ToolbarFileSaveAsArchive),
     ("Save As...", "Ctrl+Shift+S", ButtonId::ToolbarFileSaveAsFolder),
@@ -636,8 +687,27 @@ const TOOLBAR_PRIMITIVE: ToolbarPrimitive<'static> = &[
     ("Re-calculate Coastal Provinces", "Shift+C", ButtonId::ToolbarEditCoastal),
     ("Re-color Provinces", "Shift+R", ButtonId::ToolbarEditRecolor),
     ("Calculate Map Errors/Warnings", "Shift+P", ButtonId::ToolbarEditProblems),
+    ("Suggest Straits", "Shift+T", ButtonId::ToolbarEditSuggestStraits),
+    ("Accept All Suggested Straits", "Shift+Y", ButtonId::ToolbarEditAcceptSuggestions),
+    ("Reject All Suggested Straits", "Shift+N", ButtonId::ToolbarEditRejectSuggestions),
+    ("Assign Continents by Land Mass", "Shift+L", ButtonId::ToolbarEditAssignContinents),
+    ("Fix Continents by Land Mass", "Alt+L", ButtonId::ToolbarEditFixContinents),
     ("Toggle Lasso Pixel Snap", "", ButtonId::ToolbarEditToggleLassoSnap),
-    ("Next Brush Mask Mode", "Shift+M", ButtonId::ToolbarEditNextMaskMode)
+    ("Next Brush Mask Mode", "Shift+M", ButtonId::ToolbarEditNextMaskMode),
+    ("Mask to Selected Provinces", "", ButtonId::ToolbarEditMaskSelection),
+    ("Mask to Terrain Brush", "", ButtonId::ToolbarEditMaskTerrainBrush),
+    ("Mask to Continent Brush", "", ButtonId::ToolbarEditMaskContinentBrush),
+    ("Invert Brush Mask", "Alt+M", ButtonId::ToolbarEditInvertMask),
+    ("Toggle Subdivide by Count/Size", "Shift+V", ButtonId::ToolbarEditToggleSubdivideTarget)
+  ]),
+  ("Selection", &[
+    ("Apply Brush to Selection", "F", ButtonId::ToolbarSelectApplyBrush),
+    ("Re-color Selection", "", ButtonId::ToolbarSelectRecolor),
+    ("Lock Selection", "Shift+K", ButtonId::ToolbarSelectLock),
+    ("Unlock Selection", "Shift+Alt+K", ButtonId::ToolbarSelectUnlock),
+    ("Remove Adjacencies of Selection", "", ButtonId::ToolbarSelectRemoveAdjacencies),
+    ("Export Selected Province IDs...", "", ButtonId::ToolbarSelectExportIds),
+    ("Clear Selection", "Ctrl+D", ButtonId::ToolbarSelectClear)
   ]),
   ("View", &[
     ("Color/Province Map View Mode", "1", ButtonId::ToolbarViewMode1),
@@ -649,6 +719,9 @@ const TOOLBAR_PRIMITIVE: ToolbarPrimitive<'static> = &[
     ("Toggle Province IDs", "", ButtonId::ToolbarViewToggleProvinceIds),
     ("Toggle Province Boundaries", "", ButtonId::ToolbarViewToggleProvinceBoundaries),
     ("Toggle Rivers Overlay", "", ButtonId::ToolbarViewToggleRiverOverlay),
+    ("Toggle Province Inspector", "I", ButtonId::ToolbarViewToggleInspector),
+    ("Toggle Adjacency List", "J", ButtonId::ToolbarViewToggleAdjacencyList),
+    ("Go To Province", "Ctrl+G", ButtonId::ToolbarViewGoTo),
     ("Reset Zoom", "H", ButtonId::ToolbarViewResetZoom),
     ("View Inconsolata Open Font License", "", ButtonId::ToolbarViewFontLicense)
   ]),
@@ -663,6 +736,11 @@ const SIDEBAR_PRIMITIVE: SidebarPrimitive<'static> = &[
   ([00, 00, 24, 24], ButtonId::SidebarToolPaintArea, SidebarPrimitiveKind::Tool),
   ([24, 00, 24, 24], ButtonId::SidebarToolPaintBucket, SidebarPrimitiveKind::Tool),
   ([48, 00, 24, 24], ButtonId::SidebarToolLasso, SidebarPrimitiveKind::Tool),
+  ([24, 72, 24, 24], ButtonId::SidebarToolRectangle, SidebarPrimitiveKind::Tool),
+  ([00, 48, 24, 24], ButtonId::SidebarToolMerge, SidebarPrimitiveKind::Tool),
+  ([24, 48, 24, 24], ButtonId::SidebarToolSplit, SidebarPrimitiveKind::Tool),
+  ([48, 48, 24, 24], ButtonId::SidebarToolSubdivide, SidebarPrimitiveKind::Tool),
+  ([00, 72, 24, 24], ButtonId::SidebarToolSelect, SidebarPrimitiveKind::Tool),
   ([00, 24, 24, 24], ButtonId::SidebarOptionProvinceIds, SidebarPrimitiveKind::Option),
   ([24, 24, 24, 24], ButtonId::SidebarOptionProvinceBoundaries, SidebarPrimitiveKind::Option),
   ([48, 24, 24, 24], ButtonId::SidebarOptionRiverOverlay, SidebarPrimitiveKind::Option)
@@ -0,0 +1,254 @@
+//! A single line text input, with a caret, a selection and access to the system clipboard
+use graphics::context::Context;
+use opengl_graphics::GlGraphics;
+use piston::input::Key;
+use vecmath::Vector2;
+
+use crate::app::colors;
+use crate::events::KeyMods;
+use crate::font;
+
+/// Which characters a text input accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFilter {
+  Any,
+  Digits,
+  HexDigits
+}
+
+impl InputFilter {
+  fn accepts(self, ch: char) -> bool {
+    match self {
+      InputFilter::Any => !ch.is_control(),
+      InputFilter::Digits => ch.is_ascii_digit(),
+      InputFilter::HexDigits => ch.is_ascii_hexdigit()
+    }
+  }
+}
+
+/// What the owner of a text input should do after a key press has been handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputResponse {
+  /// The key was used by the text input
+  Handled,
+  /// The key has no meaning to the text input, the owner may use it instead
+  Ignored(Key),
+  Submit,
+  Cancel
+}
+
+#[derive(Debug, Clone)]
+pub struct TextInput {
+  text: String,
+  /// The position of the caret, in characters
+  caret: usize,
+  /// The other end of the selection, the selection is empty if this is the same as the caret
+  anchor: usize,
+  filter: InputFilter,
+  max_len: usize
+}
+
+impl TextInput {
+  pub fn new(filter: InputFilter, max_len: usize) -> Self {
+    TextInput {
+      text: String::new(),
+      caret: 0,
+      anchor: 0,
+      filter,
+      max_len
+    }
+  }
+
+  pub fn with_text(mut self, text: impl Into<String>) -> Self {
+    self.set_text(text);
+    self
+  }
+
+  pub fn text(&self) -> &str {
+    &self.text
+  }
+
+  /// Replaces the contents of the input, selecting all of it so that typing will replace it
+  pub fn set_text(&mut self, text: impl Into<String>) {
+    self.text = text.into();
+    self.anchor = 0;
+    self.caret = self.len();
+  }
+
+  pub fn parse<T: std::str::FromStr>(&self) -> Option<T> {
+    self.text.parse::<T>().ok()
+  }
+
+  fn len(&self) -> usize {
+    self.text.chars().count()
+  }
+
+  fn byte_index(&self, index: usize) -> usize {
+    self.text.char_indices().nth(index).map_or(self.text.len(), |(i, _)| i)
+  }
+
+  fn selection(&self) -> (usize, usize) {
+    (self.caret.min(self.anchor), self.caret.max(self.anchor))
+  }
+
+  fn selected_text(&self) -> &str {
+    let (start, end) = self.selection();
+    &self.text[self.byte_index(start)..self.byte_index(end)]
+  }
+
+  fn delete_selection(&mut self) -> bool {
+    let (start, end) = self.selection();
+    if start != end {
+      let range = self.byte_index(start)..self.byte_index(end);
+      self.text.replace_range(range, "");
+      self.caret = start;
+      self.anchor = start;
+      true
+    } else {
+      false
+    }
+  }
+
+  fn move_caret(&mut self, caret: usize, select: bool) {
+    self.caret = caret.min(self.len());
+    if !select {
+      self.anchor = self.caret;
+    };
+  }
+
+  /// Inserts typed or pasted text at the caret, replacing the selection.
+  /// Characters the input does not accept are dropped, as is anything past the maximum length.
+  pub fn on_text(&mut self, text: &str) {
+    self.delete_selection();
+    let room = self.max_len.saturating_sub(self.len());
+    let inserted = text.chars()
+      .filter(|&ch| self.filter.accepts(ch))
+      .take(room)
+      .collect::<String>();
+    let index = self.byte_index(self.caret);
+    self.text.insert_str(index, &inserted);
+    self.move_caret(self.caret + inserted.chars().count(), false);
+  }
+
+  pub fn on_key(&mut self, key: Key, mods: KeyMods) -> InputResponse {
+    match key {
+      Key::Return | Key::NumPadEnter => return InputResponse::Submit,
+      Key::Escape => return InputResponse::Cancel,
+      Key::Left if self.caret != self.anchor && !mods.shift => {
+        let (start, _) = self.selection();
+        self.move_caret(start, false);
+      },
+      Key::Right if self.caret != self.anchor && !mods.shift => {
+        let (_, end) = self.selection();
+        self.move_caret(end, false);
+      },
+      Key::Left => self.move_caret(self.caret.saturating_sub(1), mods.shift),
+      Key::Right => self.move_caret(self.caret + 1, mods.shift),
+      Key::Home => self.move_caret(0, mods.shift),
+      Key::End => self.move_caret(self.len(), mods.shift),
+      Key::Backspace => if !self.delete_selection() && self.caret > 0 {
+        self.move_caret(self.caret - 1, true);
+        self.delete_selection();
+      },
+      Key::Delete => if !self.delete_selection() && self.caret < self.len() {
+        self.move_caret(self.caret + 1, true);
+        self.delete_selection();
+      },
+      Key::A if mods.ctrl => {
+        self.anchor = 0;
+        self.caret = self.len();
+      },
+      Key::C if mods.ctrl => set_clipboard(self.selected_text()),
+      Key::X if mods.ctrl => {
+        set_clipboard(self.selected_text());
+        self.delete_selection();
+      },
+      Key::V if mods.ctrl => if let Some(text) = get_clipboard() {
+        self.on_text(&text);
+      },
+      // Printable keys arrive as text instead, see `on_text`
+      key if mods.ctrl || mods.alt || !is_printable_key(key) => return InputResponse::Ignored(key),
+      _ => ()
+    };
+
+    InputResponse::Handled
+  }
+
+  /// Draws the selection and the caret over text that has already been drawn, `pos` should be where that text starts on its baseline
+  pub fn draw_overlay(&self, ctx: Context, pos: Vector2<f64>, gl: &mut GlGraphics) {
+    let v_metrics = font::get_v_metrics();
+    let [x, y] = pos;
+    let [top, bottom] = [y - v_metrics.ascent, y - v_metrics.descent];
+    let offset = |index: usize| x + font::get_width_metric_str(&self.text[..self.byte_index(index)]);
+
+    let (start, end) = self.selection();
+    if start != end {
+      let [start, end] = [offset(start), offset(end)];
+      graphics::rectangle(colors::WHITE_T, [start, top, end - start, bottom - top], ctx.transform, gl);
+    };
+
+    let caret = offset(self.caret).round();
+    graphics::line_from_to(colors::WHITE, 0.5, [caret, top], [caret, bottom], ctx.transform, gl);
+  }
+}
+
+/// Whether a key would normally produce text, those keys are handled by `TextInput::on_text`
+fn is_printable_key(key: Key) -> bool {
+  let code = key.code();
+  (0x20..0x7f).contains(&code)
+}
+
+fn get_clipboard() -> Option<String> {
+  arboard::Clipboard::new().and_then(|mut clipboard| clipboard.get_text()).ok()
+}
+
+fn set_clipboard(text: &str) {
+  if !text.is_empty() {
+    let _ = arboard::Clipboard::new().and_then(|mut clipboard| clipboard.set_text(text));
+  };
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const NO_MODS: KeyMods = KeyMods { shift: false, ctrl: false, alt: false };
+  const SHIFT: KeyMods = KeyMods { shift: true, ctrl: false, alt: false };
+
+  #[test]
+  fn test_typing_and_selection() {
+    let mut input = TextInput::new(InputFilter::Any, 16);
+    input.on_text("héllo");
+    assert_eq!((input.text(), input.caret), ("héllo", 5));
+
+    // Selecting backwards over two characters and typing replaces them
+    input.on_key(Key::Left, SHIFT);
+    input.on_key(Key::Left, SHIFT);
+    assert_eq!(input.selected_text(), "lo");
+    input.on_text("p!");
+    assert_eq!(input.text(), "hélp!");
+
+    input.on_key(Key::Home, NO_MODS);
+    input.on_key(Key::Delete, NO_MODS);
+    input.on_key(Key::End, NO_MODS);
+    input.on_key(Key::Backspace, NO_MODS);
+    assert_eq!(input.text(), "élp");
+    assert_eq!(input.on_key(Key::Return, NO_MODS), InputResponse::Submit);
+    assert_eq!(input.on_key(Key::Up, NO_MODS), InputResponse::Ignored(Key::Up));
+  }
+
+  #[test]
+  fn test_filter_and_max_len() {
+    let mut input = TextInput::new(InputFilter::HexDigits, 6).with_text("ab");
+    // Setting the text selects all of it, so typing replaces it
+    input.on_text("12x34z56789");
+    assert_eq!(input.text(), "123456");
+    input.on_text("7");
+    assert_eq!(input.text(), "123456");
+    assert_eq!(input.parse::<u32>(), Some(123456));
+
+    let mut input = TextInput::new(InputFilter::Digits, 5);
+    input.on_text("-12a");
+    assert_eq!(input.text(), "12");
+  }
+}
@@ -1,13 +1,20 @@
 //! An abstract representation of a map's data
+mod boundaries;
 mod history;
 mod bridge;
+#[cfg(test)]
+mod fixtures;
+mod landmasses;
 mod problems;
+mod provgen;
+mod provinces;
+mod straits;
+pub mod synthetic;
 
 use ahash::{AHashMap, AHashSet};
 use graphics::types::Color as DrawColor;
 use image::{Rgb, RgbImage, Rgba, RgbaImage};
 use rand::Rng;
-use rayon::iter::ParallelIterator;
 use serde::{Serialize, Deserialize};
 use uord::UOrd2 as UOrd;
 use vecmath::Vector2;
@@ -15,14 +22,21 @@ use vecmath::Vector2;
 use crate::config::Config;
 use crate::util::XYIter;
 use crate::util::files::Location;
+use crate::util::task::Progress;
 use crate::app::colors;
 use crate::app::format::*;
 use crate::error::Error;
 
-pub use self::bridge::{write_rgb_bmp_image, read_rgb_bmp_image, SaveOperation};
-pub use self::history::History;
+pub use self::boundaries::{Boundaries, Polyline};
+pub use self::bridge::{write_rgb_bmp_image, read_rgb_bmp_image, SaveJob, SaveOperation};
+pub use self::history::{AdjacencyRuleEdit, ConnectionEdit, History, ProvinceEdit};
+pub use self::landmasses::ContinentAssignment;
 pub use self::problems::Problem;
+pub use self::provinces::{IndexBuffer, ProvinceId, ProvinceTable};
+pub use self::straits::StraitSuggestion;
 
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
 use std::convert::TryFrom;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -38,12 +52,41 @@ pub struct Bundle {
 }
 
 impl Bundle {
-  pub fn load(location: &Location, config: Config) -> Result<Self, Error> {
-    self::bridge::load_bundle(location, config)
+  pub fn load(location: &Location, config: Config, progress: &Progress) -> Result<Self, Error> {
+    self::bridge::load_bundle(location, config, progress)
+  }
+
+  /// Generates a new map from a land image and optionally a terrain image, see `provgen::generate_bundle`
+  pub fn generate(land_image: &RgbImage, terrain_image: Option<&RgbImage>, config: Config, progress: &Progress) -> Result<Self, Error> {
+    self::provgen::generate_bundle(land_image, terrain_image, config, progress)
+  }
+
+  /// Creates a new map of the given size, consisting of a single province of the given kind
+  pub fn new_blank(width: u32, height: u32, kind: DefinitionKind, config: Config) -> Result<Self, Error> {
+    let province_kind = ProvinceKind::from(kind);
+    let definition = Definition {
+      id: 1,
+      rgb: random_color_pure(&ProvinceTable::default(), province_kind),
+      kind,
+      coastal: false,
+      terrain: province_kind.default_terrain(),
+      continent: province_kind.correct_continent_id(0),
+      extra: Vec::new(),
+      comments: Vec::new()
+    };
+
+    let province_image = RgbImage::from_pixel(width, height, Rgb(definition.rgb));
+    let tables = self::bridge::MapTables { definitions: vec![definition], ..Default::default() };
+    self::bridge::construct_map_data(province_image, tables, config, &Progress::new())
   }
 
   pub fn save(&self, location: &Location) -> Result<SaveOperation, Error> {
-    self::bridge::save_bundle(location, self)
+    self.prepare_save()?.write(location, &Progress::new())
+  }
+
+  /// Collects everything needed to save this bundle, the returned job may be written from another thread
+  pub fn prepare_save(&self) -> Result<SaveJob, Error> {
+    self::bridge::prepare_save_bundle(self)
   }
 
   pub fn generate_problems(&self) -> Vec<Problem> {
@@ -51,41 +94,40 @@ impl Bundle {
   }
 
   pub fn image_buffer_mapgen_land(&self) -> Option<RgbImage> {
-    self.map.gen_image_buffer(|which| {
-      self.map.get_province(which).kind.color_mapgen()
+    self.map.gen_image_buffer(|province_data| {
+      province_data.kind.color_mapgen()
     })
   }
 
   pub fn image_buffer_mapgen_terrain(&self) -> Option<RgbImage> {
-    self.map.gen_image_buffer(|which| {
-      let terrain = &self.map.get_province(which).terrain;
-      self.config.terrain_color(terrain)
+    self.map.gen_image_buffer(|province_data| {
+      self.config.terrain_color(&province_data.terrain)
     })
   }
 
   pub fn texture_buffer_color(&self) -> RgbaImage {
-    self.map.gen_texture_buffer(|which| which)
+    self.map.gen_texture_buffer(|province_data| province_data.color)
   }
 
   pub fn texture_buffer_selective_color(&self, extents: Extents) -> RgbaImage {
-    self.map.gen_texture_buffer_selective(extents, |which| which)
+    self.map.gen_texture_buffer_selective(extents, |province_data| province_data.color)
   }
 
   pub fn texture_buffer_kind(&self) -> RgbaImage {
-    self.map.gen_texture_buffer(|which| {
-      self.map.get_province(which).kind.color()
+    self.map.gen_texture_buffer(|province_data| {
+      province_data.kind.color()
     })
   }
 
   pub fn texture_buffer_selective_kind(&self, extents: Extents) -> RgbaImage {
-    self.map.gen_texture_buffer_selective(extents, |which| {
-      self.map.get_province(which).kind.color()
+    self.map.gen_texture_buffer_selective(extents, |province_data| {
+      province_data.kind.color()
     })
   }
 
   pub fn texture_buffer_terrain(&self) -> RgbaImage {
-    self.map.gen_texture_buffer(|which| {
-      let terrain = &self.map.get_province(which).terrain;
+    self.map.gen_texture_buffer(|province_data| {
+      let terrain = &province_data.terrain;
       match self.config.terrain_color(terrain) {
         None => panic!("unknown terrain type, color not found in config: {}", terrain),
         Some(color) => color
@@ -94,8 +136,8 @@ impl Bundle {
   }
 
   pub fn texture_buffer_selective_terrain(&self, extents: Extents) -> RgbaImage {
-    self.map.gen_texture_buffer_selective(extents, |which| {
-      let terrain = &self.map.get_province(which).terrain;
+    self.map.gen_texture_buffer_selective(extents, |province_data| {
+      let terrain = &province_data.terrain;
       match self.config.terrain_color(terrain) {
         None => panic!("unknown terrain type, color not found in config: {}", terrain),
         Some(color) => color
@@ -103,37 +145,36 @@ impl Bundle {
     })
   }
 
-  // The 4096 continent cap is due to the way `random::sequence_color` works,
-  // pre-generating the colors for each ID is much more efficient and avoids the
-  // overhead of locking mechanisms of a generate-as-you-go type setup.
-  // Besides, a map with 4096 continents sounds a bit absurd.
-
   pub fn texture_buffer_continent(&self) -> RgbaImage {
-    self.map.gen_texture_buffer(|which| {
-      let continent = self.map.get_province(which).continent;
-      crate::util::random::sequence_color(continent as usize)
-        .expect("only a maximum of 4096 provinces are supported")
+    self.map.gen_texture_buffer(|province_data| {
+      crate::util::random::sequence_color(province_data.continent as usize)
     })
   }
 
   pub fn texture_buffer_selective_continent(&self, extents: Extents) -> RgbaImage {
-    self.map.gen_texture_buffer_selective(extents, |which| {
-      let continent = self.map.get_province(which).continent;
+    self.map.gen_texture_buffer_selective(extents, |province_data| {
+      crate::util::random::sequence_color(province_data.continent as usize)
+    })
+  }
+
+  /// Shows the continents provinces would have if a continent assignment were applied, instead of the ones they have
+  pub fn texture_buffer_continent_preview(&self, preview: &AHashMap<Color, u16>) -> RgbaImage {
+    self.map.gen_texture_buffer(|province_data| {
+      let continent = preview.get(&province_data.color).copied().unwrap_or(province_data.continent);
       crate::util::random::sequence_color(continent as usize)
-        .expect("only a maximum of 4096 provinces are supported")
     })
   }
 
   pub fn texture_buffer_coastal(&self) -> RgbaImage {
-    self.map.gen_texture_buffer(|which| {
-      let ProvinceData { coastal, kind, .. } = *self.map.get_province(which);
+    self.map.gen_texture_buffer(|province_data| {
+      let ProvinceData { coastal, kind, .. } = *province_data;
       kind.color_coastal(coastal)
     })
   }
 
   pub fn texture_buffer_selective_coastal(&self, extents: Extents) -> RgbaImage {
-    self.map.gen_texture_buffer_selective(extents, |which| {
-      let ProvinceData { coastal, kind, .. } = *self.map.get_province(which);
+    self.map.gen_texture_buffer_selective(extents, |province_data| {
+      let ProvinceData { coastal, kind, .. } = *province_data;
       kind.color_coastal(coastal)
     })
   }
@@ -141,7 +182,7 @@ impl Bundle {
   /// Search for terrains that are not included in the config
   pub fn search_unknown_terrains(&self) -> Option<AHashSet<String>> {
     let mut unknown_terrains = AHashSet::default();
-    for province_data in self.map.base.province_data_map.values() {
+    for (_, province_data) in self.map.base.provinces.iter() {
       if !self.config.terrains.contains_key(&province_data.terrain) {
         unknown_terrains.insert(province_data.terrain.clone());
       };
@@ -155,7 +196,7 @@ impl Bundle {
   }
 
   pub fn random_color_pure(&self, kind: ProvinceKind) -> Color {
-    random_color_pure(&*self.map.base.province_data_map, kind)
+    random_color_pure(&*self.map.base.provinces, kind)
   }
 }
 
@@ -163,18 +204,21 @@ impl Bundle {
 
 #[derive(Clone)]
 pub struct MapBase {
-  color_buffer: Arc<RgbImage>,
-  province_data_map: Arc<AHashMap<Color, Arc<ProvinceData>>>,
-  connection_data_map: Arc<AHashMap<UOrd<Color>, Arc<ConnectionData>>>,
+  index_buffer: Arc<IndexBuffer>,
+  provinces: Arc<ProvinceTable>,
+  connection_data_map: Arc<AHashMap<UOrd<ProvinceId>, Arc<ConnectionData>>>,
+  /// The rules from `adjacency_rules.txt`, in the order they were loaded or created in
+  adjacency_rules: Arc<Vec<AdjacencyRuleData>>,
   rivers_overlay: Option<Arc<RgbaImage>>
 }
 
 impl std::fmt::Debug for MapBase {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     f.debug_struct("MapBase")
-      .field("color_buffer", &format_args!("{:p}", self.color_buffer))
-      .field("province_data_map", &format_args!("{:p}", self.province_data_map))
+      .field("index_buffer", &format_args!("{:p}", self.index_buffer))
+      .field("provinces", &format_args!("{:p}", self.provinces))
       .field("connection_data_map", &format_args!("{:p}", self.connection_data_map))
+      .field("adjacency_rules", &format_args!("{:p}", self.adjacency_rules))
       .finish()
   }
 }
@@ -182,26 +226,33 @@ impl std::fmt::Debug for MapBase {
 #[derive(Debug)]
 pub struct Map {
   base: MapBase,
-  boundaries: AHashMap<UOrd<Vector2<u32>>, bool>,
-  preserved_unsupported_adjacencies: Vec<Adjacency>,
-  preserved_id_count: Option<u32>
+  boundaries: Boundaries,
+  /// Adjacencies of kinds the editor does not support, along with the row they were loaded from
+  preserved_unsupported_adjacencies: Vec<(usize, Adjacency)>,
+  /// Comment lines found after the last row of `definition.csv`
+  preserved_definition_comments: Vec<String>,
+  preserved_id_count: Option<u32>,
+  /// Continent names from `continent.txt`, the first name belongs to continent 1
+  continent_names: Vec<String>,
+  /// The text of `adjacency_rules.txt` as it was loaded, written back as it was if none of its rules change
+  preserved_adjacency_rules: Option<String>
 }
 
 impl Map {
   pub fn dimensions(&self) -> Vector2<u32> {
-    [self.width(), self.height()]
+    self.base.index_buffer.dimensions()
   }
 
   pub fn width(&self) -> u32 {
-    self.base.color_buffer.width()
+    self.base.index_buffer.width()
   }
 
   pub fn height(&self) -> u32 {
-    self.base.color_buffer.height()
+    self.base.index_buffer.height()
   }
 
   pub fn provinces_count(&self) -> usize {
-    self.base.province_data_map.len()
+    self.base.provinces.len()
   }
 
   pub fn connections_count(&self) -> usize {
@@ -210,90 +261,163 @@ impl Map {
 
   /// Generates a texture buffer, a buffer to be consumed by the canvas to display the map
   pub fn gen_texture_buffer<F>(&self, f: F) -> RgbaImage
-  where F: Fn(Color) -> Color + Send + Sync {
+  where F: Fn(&ProvinceData) -> Color {
+    let palette = self.base.provinces.palette(|province_data| (p4(f(province_data)), province_data.locked));
     let [width, height] = self.dimensions();
     RgbaImage::from_par_fn(width, height, |x, y| {
-      Rgba(p4(f(self.get_color_at([x, y]))))
+      let (color, locked) = palette[self.get_id_at([x, y]).index()];
+      Rgba(if locked { hatch([x, y], color) } else { color })
     })
   }
 
   /// Generates a fragment of a texture buffer, based on a bounding box
   pub fn gen_texture_buffer_selective<F>(&self, extents: Extents, f: F) -> RgbaImage
-  where F: Fn(Color) -> Color {
+  where F: Fn(&ProvinceData) -> Color {
     let (offset, [width, height]) = extents.to_offset_size();
     RgbaImage::from_fn(width, height, |x, y| {
       let pos = vecmath::vec2_add(offset, [x, y]);
-      Rgba(p4(f(self.get_color_at(pos))))
+      let province_data = self.get_province_at(pos);
+      let color = p4(f(province_data));
+      Rgba(if province_data.locked { hatch(pos, color) } else { color })
     })
   }
 
   /// Generates an image buffer, a 24 bit RGB image to be exported and used outside of the program
   pub fn gen_image_buffer<F>(&self, f: F) -> Option<RgbImage>
-  where F: Fn(Color) -> Option<Color> + Send + Sync {
+  where F: Fn(&ProvinceData) -> Option<Color> {
+    let palette = self.base.provinces.palette(|province_data| f(province_data));
+    if self.base.provinces.iter().any(|(id, _)| palette[id.index()].is_none()) {
+      return None;
+    };
+
     let [width, height] = self.dimensions();
-    let mut buffer = RgbImage::new(width, height);
-    buffer.par_enumerate_pixels_mut().try_for_each(|(x, y, pixel)| {
-      if let Some(color) = f(self.get_color_at([x, y])) {
-        *pixel = Rgb(color);
-        Some(())
-      } else {
-        None
-      }
-    })?;
+    Some(RgbImage::from_par_fn(width, height, |x, y| {
+      Rgb(palette[self.get_id_at([x, y]).index()].expect("infallible"))
+    }))
+  }
 
-    Some(buffer)
+  /// Generates the contents of `provinces.bmp`
+  pub fn gen_color_buffer(&self) -> RgbImage {
+    self.gen_image_buffer(|province_data| Some(province_data.color))
+      .expect("infallible")
   }
 
   pub fn extract(&self, extents: Extents) -> RgbImage {
-    use image::GenericImageView;
-    let ([x, y], [width, height]) = extents.to_offset_size();
-    self.base.color_buffer.view(x, y, width, height).to_image()
+    let (offset, [width, height]) = extents.to_offset_size();
+    RgbImage::from_fn(width, height, |x, y| {
+      Rgb(self.get_color_at(vecmath::vec2_add(offset, [x, y])))
+    })
   }
 
-  /// Sets the color of a single pixel in `color_buffer` and manages the
-  /// pixel counts of all relevant provinces, returning Some(Color) if the
+  /// Sets the province of a single pixel in `index_buffer` and manages the
+  /// pixel counts of all relevant provinces, returning Some(ProvinceId) if the
   /// province whos pixel was replaced no longer has any pixels left
-  fn put_pixel_raw(&mut self, pos: Vector2<u32>, color: Color) -> Option<Color> {
-    let pixel = Arc::make_mut(&mut self.base.color_buffer).get_pixel_mut(pos[0], pos[1]);
-    let Rgb(previous_color) = std::mem::replace(pixel, Rgb(color));
+  fn put_pixel_raw(&mut self, pos: Vector2<u32>, id: ProvinceId) -> Option<ProvinceId> {
+    let previous_id = Arc::make_mut(&mut self.base.index_buffer).replace(pos, id);
 
-    let entry = Arc::make_mut(&mut self.base.province_data_map).entry(color).or_default();
-    let entry = Arc::make_mut(entry);
-    entry.add_pixel(pos);
+    self.get_province_by_id_mut(id).add_pixel(pos);
 
-    let previous_province = self.get_province_mut(previous_color);
+    let previous_province = self.get_province_by_id_mut(previous_id);
     previous_province.sub_pixel(pos);
 
     if previous_province.pixel_count == 0 {
-      Some(previous_color)
+      Some(previous_id)
     } else {
       None
     }
   }
 
-  /// Sets the color of a single pixel in `color_buffer`, checks included
+  /// Sets the color of a single pixel, checks included
   fn put_pixel(&mut self, pos: Vector2<u32>, color: Color) {
-    self.recalculate_boundaries_at(pos);
-    if let Some(erased_color) = self.put_pixel_raw(pos, color) {
-      self.erase_province_data(erased_color);
+    let id = self.get_or_insert_province(color);
+    self.put_pixel_id(pos, id);
+  }
+
+  fn put_pixel_id(&mut self, pos: Vector2<u32>, id: ProvinceId) {
+    self.boundaries.mark_dirty_at(pos);
+    if let Some(erased_id) = self.put_pixel_raw(pos, id) {
+      self.erase_province_data(erased_id);
     };
   }
 
-  /// Sets the color of multiple pixels in `color_buffer`, checks included
+  /// Sets the color of multiple pixels, checks included
   fn put_many_pixels(&mut self, color: Color, pixels: &[Vector2<u32>]) {
+    let id = self.get_or_insert_province(color);
     for &pos in pixels {
-      self.put_pixel(pos, color);
-      self.recalculate_boundaries_at(pos);
+      self.put_pixel_id(pos, id);
+    };
+  }
+
+  /// Returns the province with the given color, creating a new province with no pixels if it does not exist
+  fn get_or_insert_province(&mut self, color: Color) -> ProvinceId {
+    match self.base.provinces.id_of(color) {
+      Some(id) => id,
+      None => Arc::make_mut(&mut self.base.provinces)
+        .insert(ProvinceData { color, ..ProvinceData::default() })
+    }
+  }
+
+  fn erase_province_data(&mut self, id: ProvinceId) {
+    Arc::make_mut(&mut self.base.provinces).remove(id);
+    self.remove_related_connections(id);
+    self.repoint_adjacency_rules(id, None);
+  }
+
+  /// Replaces the given province with another in all connections, dropping any connections
+  /// that would connect a province to itself or that already exist for the replacement
+  fn repoint_connections(&mut self, which: ProvinceId, into: ProvinceId) {
+    let connection_data_map = Arc::make_mut(&mut self.base.connection_data_map);
+    let moved = connection_data_map.keys()
+      .filter(|rel| rel.contains(&which))
+      .copied()
+      .collect::<Vec<UOrd<ProvinceId>>>();
+    for rel in moved {
+      let mut connection_data = connection_data_map.remove(&rel).expect("infallible");
+      let rel = rel.replace(&which, &into);
+      if rel.is_distinct() && !connection_data_map.contains_key(&rel) {
+        let connection = Arc::make_mut(&mut connection_data);
+        if let Some(source) = connection.source.as_mut().filter(|source| source.from == which) {
+          source.from = into;
+        };
+
+        connection_data_map.insert(rel, connection_data);
+      };
+    };
+
+    for (rel, connection_data) in connection_data_map.iter_mut() {
+      if connection_data.through == Some(which) {
+        let through = (!rel.contains(&into)).then_some(into);
+        Arc::make_mut(connection_data).through = through;
+      };
     };
   }
 
-  fn erase_province_data(&mut self, color: Color) {
-    Arc::make_mut(&mut self.base.province_data_map).remove(&color);
-    self.remove_related_connections(color);
+  /// Replaces the given province with another in the required provinces and icons of all adjacency rules,
+  /// or removes it from them if there is no replacement
+  fn repoint_adjacency_rules(&mut self, which: ProvinceId, into: Option<ProvinceId>) {
+    let is_related = |rule: &AdjacencyRuleData| rule.icon == Some(which) || rule.required_provinces.contains(&which);
+    if !self.base.adjacency_rules.iter().any(is_related) {
+      return;
+    };
+
+    for rule in Arc::make_mut(&mut self.base.adjacency_rules).iter_mut() {
+      if rule.icon == Some(which) {
+        rule.icon = into;
+      };
+
+      if let Some(index) = rule.required_provinces.iter().position(|&id| id == which) {
+        match into {
+          Some(into) if !rule.required_provinces.contains(&into) => rule.required_provinces[index] = into,
+          _ => {
+            rule.required_provinces.remove(index);
+          }
+        };
+      };
+    };
   }
 
-  /// Removes all connections which contain the given color
-  fn remove_related_connections(&mut self, which: Color) {
+  /// Removes all connections which contain the given province
+  fn remove_related_connections(&mut self, which: ProvinceId) {
     Arc::make_mut(&mut self.base.connection_data_map).retain(|rel, conn| {
       if conn.through == Some(which) {
         Arc::make_mut(conn).through = None;
@@ -303,22 +427,17 @@ impl Map {
     });
   }
 
-  /// Copies another image buffer into the image without any checks
-  #[allow(unused)]
-  fn put_selective_raw(&mut self, buffer: &RgbImage, offset: Vector2<u32>) {
-    use image::GenericImage;
-    Arc::make_mut(&mut self.base.color_buffer)
-      .copy_from(buffer, offset[0], offset[1]).expect("error");
-  }
-
   pub fn validate_pixel_counts(&self) -> bool {
-    let mut pixel_counts = AHashMap::<Color, u64>::default();
-    for &Rgb(pixel) in self.base.color_buffer.pixels() {
-      *pixel_counts.entry(pixel).or_insert(0) += 1;
+    let mut pixel_counts = vec![0u64; self.base.provinces.slots_len()];
+    for &id in self.base.index_buffer.as_slice() {
+      match pixel_counts.get_mut(id.index()) {
+        Some(pixel_count) => *pixel_count += 1,
+        None => return false
+      };
     };
 
-    for (color, province_data) in self.base.province_data_map.iter() {
-      if pixel_counts.get(color) != Some(&province_data.pixel_count) {
+    for (id, province_data) in self.base.provinces.iter() {
+      if pixel_counts[id.index()] != province_data.pixel_count {
         return false;
       };
     };
@@ -326,17 +445,16 @@ impl Map {
     true
   }
 
-  pub fn calculate_coastal_provinces(&self) -> AHashMap<Color, Option<bool>> {
-    let mut coastal_provinces = self.base.province_data_map.keys()
-      .map(|&color| (color, Some(false)))
-      .collect::<AHashMap<Color, Option<bool>>>();
+  /// Returns the new coastal status of each province, indexed by handle
+  pub fn calculate_coastal_provinces(&self) -> Vec<Option<bool>> {
+    let mut coastal_provinces = self.base.provinces.palette(|_| Some(false));
 
     let coastal_neighbors = UOrd::new([ProvinceKind::Land, ProvinceKind::Sea]);
     for neighboring in self.calculate_neighbors() {
-      if neighboring.map(|n| self.get_province(n).kind) == coastal_neighbors {
+      if neighboring.map(|n| self.get_province_by_id(n).kind) == coastal_neighbors {
         let [a, b] = neighboring.into_array();
-        coastal_provinces.insert(a, Some(true));
-        coastal_provinces.insert(b, Some(true));
+        coastal_provinces[a.index()] = Some(true);
+        coastal_provinces[b.index()] = Some(true);
       };
     };
 
@@ -344,63 +462,31 @@ impl Map {
   }
 
   /// Returns a hashset of uords describing which provinces are touching each other
-  fn calculate_neighbors(&self) -> AHashSet<UOrd<Color>> {
+  fn calculate_neighbors(&self) -> AHashSet<UOrd<ProvinceId>> {
     let mut neighbors = AHashSet::default();
     for (pos_a, pos_b) in self.iter_pixel_pairs() {
-      let color_a = self.get_color_at(pos_a);
-      let color_b = self.get_color_at(pos_b);
-      if color_a != color_b {
-        neighbors.insert(UOrd::new([color_a, color_b]));
+      let id_a = self.get_id_at(pos_a);
+      let id_b = self.get_id_at(pos_b);
+      if id_a != id_b {
+        neighbors.insert(UOrd::new([id_a, id_b]));
       };
     };
 
     neighbors
   }
 
-  pub fn recalculate_all_boundaries(&mut self) {
-    self.boundaries = AHashMap::default();
-    for (pos_a, pos_b) in self.iter_pixel_pairs() {
-      let b = UOrd::new([pos_a, pos_b]);
-      let rel = b.map(|pos| self.get_color_at(pos));
-      if rel.is_distinct() {
-        let is_special = self.has_connection(rel);
-        self.boundaries.insert(b, is_special);
-      };
-    };
+  /// Recalculates any boundary tiles that have been changed since the last update
+  pub fn update_boundaries(&mut self) {
+    self.boundaries.update(&self.base.index_buffer);
   }
 
-  pub fn recalculate_boundaries_extents(&mut self, extents: Extents) {
-    for (pos_a, pos_b) in self.iter_pixel_pairs_extents(extents) {
-      let b = UOrd::new([pos_a, pos_b]);
-      let rel = b.map(|pos| self.get_color_at(pos));
-      if rel.is_distinct() {
-        let is_special = self.has_connection(rel);
-        self.boundaries.insert(b, is_special);
-      } else {
-        self.boundaries.remove(&b);
-      };
+  /// Replaces the map's state, marking the boundaries of any changed areas of the map as dirty
+  fn replace_base(&mut self, base: MapBase) {
+    if !Arc::ptr_eq(&self.base.index_buffer, &base.index_buffer) {
+      self.boundaries.mark_changed(&self.base.index_buffer, &base.index_buffer);
     };
-  }
 
-  pub fn recalculate_boundaries_at(&mut self, pos: Vector2<u32>) {
-    for other in self.iter_pixels_adjacent(pos) {
-      let b = UOrd::new([pos, other]);
-      let rel = b.map(|pos| self.get_color_at(pos));
-      if rel.is_distinct() {
-        let is_special = self.has_connection(rel);
-        self.boundaries.insert(b, is_special);
-      } else {
-        self.boundaries.remove(&b);
-      };
-    };
-  }
-
-  // Loops through every boundary, and determines whether or not they are special (have a connection) or not
-  pub fn recalculate_specialness(&mut self) {
-    for (b, _) in std::mem::take(&mut self.boundaries) {
-      let rel = b.map(|pos| self.get_color_at(pos));
-      self.boundaries.insert(b, self.has_connection(rel));
-    };
+    self.base = base;
   }
 
   fn iter_pixels_adjacent(&self, pos: Vector2<u32>) -> impl Iterator<Item = Vector2<u32>> + 'static {
@@ -427,87 +513,335 @@ impl Map {
       })
   }
 
-  fn iter_pixel_pairs_extents(&self, mut extents: Extents) -> impl Iterator<Item = (Vector2<u32>, Vector2<u32>)> + 'static {
-    let [width, height] = self.dimensions();
-    extents.lower[0] = extents.lower[0].saturating_sub(1);
-    extents.lower[1] = extents.lower[1].saturating_sub(1);
-    extents.upper[0] = (extents.upper[0] + 1).min(width - 1);
-    extents.upper[1] = (extents.upper[1] + 1).min(height - 1);
-    let [width, height] = extents.upper;
-    XYIter::from_extents(extents)
-      .flat_map(move |pos| {
-        let a = (pos[0] < width).then(|| (pos, [pos[0] + 1, pos[1]]));
-        let b = (pos[1] < height).then(|| (pos, [pos[0], pos[1] + 1]));
-        Iterator::chain(a.into_iter(), b.into_iter())
-      })
-  }
-
   /// If the map has any provinces where the type is `Unknown`
   pub fn has_unknown_provinces(&self) -> bool {
-    self.base.province_data_map.values()
-      .any(|province_data| province_data.kind == ProvinceKind::Unknown)
+    self.base.provinces.iter()
+      .any(|(_, province_data)| province_data.kind == ProvinceKind::Unknown)
   }
 
-  pub fn has_connection(&self, rel: UOrd<Color>) -> bool {
+  pub fn has_connection(&self, rel: UOrd<ProvinceId>) -> bool {
     self.base.connection_data_map.contains_key(&rel)
   }
 
-  /// Replaces all of one color in `color_buffer`
-  fn replace_color_raw(&mut self, which: Color, color: Color) -> Extents {
-    Arc::make_mut(&mut self.base.color_buffer)
-      .enumerate_pixels_mut()
-      .fold(None, |out: Option<Extents>, (x, y, Rgb(pixel))| {
-        if *pixel == which {
-          *pixel = color;
+  /// Changes the color of a province, this does not touch the pixels of the map
+  pub fn recolor_province(&mut self, which: Color, color: Color) -> Extents {
+    assert_ne!(which, color, "Attempted to recolor a province when it is already the desired color");
+    let id = self.get_province_id(which).expect("province not found with color");
+    Arc::make_mut(&mut self.base.provinces).set_color(id, color);
+    self.get_province_by_id(id).extents.expect("province has no pixels")
+  }
+
+  /// Moves every pixel of one province into another existing province, erasing the first.
+  /// Connections involving the first province are moved over to the second, unless they would be redundant.
+  pub fn merge_province(&mut self, which: Color, into: Color) -> Extents {
+    let which = self.get_province_id(which).expect("province not found with color");
+    let into = self.get_province_id(into).expect("province not found with color");
+    assert_ne!(which, into, "Attempted to merge a province into itself");
+    let extents = self.get_province_extents(which);
+
+    let source = self.get_province_by_id(which);
+    let merged_ids = source.preserved_id.into_iter()
+      .chain(source.merged_ids.iter().copied())
+      .collect::<Vec<u32>>();
+    let source_extras = source.extras.clone();
+    let target = self.get_province_by_id_mut(into);
+    target.merged_ids.extend(merged_ids);
+    // The comments above the merged province's row are kept above the target's row, along with its extra fields if the target has none
+    if let Some(source_extras) = source_extras {
+      match &mut target.extras {
+        Some(extras) => {
+          let extras = Arc::make_mut(extras);
+          extras.comments.extend(source_extras.comments.iter().cloned());
+          if extras.fields.is_empty() {
+            extras.fields = source_extras.fields.clone();
+          };
+        },
+        None => target.extras = Some(source_extras)
+      };
+    };
 
-          Some(match out {
-            Some(extents) => extents.join_point([x, y]),
-            None => Extents::new_point([x, y])
-          })
-        } else {
-          out
-        }
-      })
-      .expect("color not found in map")
-  }
-
-  /// Replaces the key of one province with a new color in `province_data_map`
-  fn rekey_province_raw(&mut self, which: Color, color: Color) {
-    let province_data_map = Arc::make_mut(&mut self.base.province_data_map);
-    let province_data = province_data_map.remove(&which)
-      .expect("province not found with color");
-    let result = province_data_map.insert(color, province_data);
-    debug_assert_eq!(result, None);
-  }
-
-  /// Replaces the keys of all connections containing one color with another color
-  fn rekey_connections_raw(&mut self, which: Color, color: Color) {
-    if !self.base.connection_data_map.is_empty() {
-      let mut new_connection_data_map = AHashMap::with_capacity(self.connections_count());
-      for (&rel, connection_data) in self.base.connection_data_map.iter() {
-        // `through` does not get replaced here because it should
-        // not be one of the colors that compose `rel`
-        debug_assert_ne!(connection_data.through, Some(which));
-        new_connection_data_map.insert(rel.replace(&which, &color), connection_data.clone());
+    self.repoint_connections(which, into);
+    self.repoint_adjacency_rules(which, Some(into));
+
+    let pixels = XYIter::from_extents(extents)
+      .filter(|&pos| self.get_id_at(pos) == which)
+      .collect::<Vec<Vector2<u32>>>();
+    for pos in pixels {
+      self.put_pixel_id(pos, into);
+    };
+
+    extents
+  }
+
+  /// Splits a province into the separate pieces left on either side of the given cut, giving every piece a new color.
+  /// The largest piece keeps the identity of the original province, the rest become new provinces with the same metadata.
+  /// Pieces that do not touch the cut, and pixels of the cut itself that do not border any piece, stay with the largest piece.
+  /// Returns `None` if the cut does not separate the province.
+  pub fn split_province(&mut self, which: Color, cut: &[Vector2<u32>]) -> Option<Extents> {
+    let which = self.get_province_id(which).expect("province not found with color");
+    let extents = self.get_province_extents(which);
+    let (offset, [width, height]) = extents.to_offset_size();
+    let local = |[x, y]: Vector2<u32>| ((y - offset[1]) * width + (x - offset[0])) as usize;
+
+    let mut is_cut = vec![false; (width * height) as usize];
+    for &pos in cut {
+      if extents.contains(pos) && self.get_id_at(pos) == which {
+        is_cut[local(pos)] = true;
+      };
+    };
+
+    // Label every 4-connected piece of the province that remains after removing the cut
+    let mut labels: Vec<Option<usize>> = vec![None; (width * height) as usize];
+    let mut pieces: Vec<Vec<Vector2<u32>>> = Vec::new();
+    for pos in XYIter::from_extents(extents) {
+      if self.get_id_at(pos) != which || is_cut[local(pos)] || labels[local(pos)].is_some() {
+        continue;
       };
 
-      self.base.connection_data_map = Arc::new(new_connection_data_map);
+      let label = pieces.len();
+      let mut piece = vec![pos];
+      labels[local(pos)] = Some(label);
+      let mut i = 0;
+      while let Some(&current) = piece.get(i) {
+        i += 1;
+        for next in self.iter_pixels_adjacent(current) {
+          if extents.contains(next) && self.get_id_at(next) == which && !is_cut[local(next)] && labels[local(next)].is_none() {
+            labels[local(next)] = Some(label);
+            piece.push(next);
+          };
+        };
+      };
+
+      pieces.push(piece);
+    };
+
+    let mut touches_cut = vec![false; pieces.len()];
+    for (label, piece) in pieces.iter().enumerate() {
+      touches_cut[label] = piece.iter().any(|&pos| {
+        self.iter_pixels_adjacent(pos).any(|next| extents.contains(next) && is_cut[local(next)])
+      });
+    };
+
+    if touches_cut.iter().filter(|&&touches| touches).count() < 2 {
+      return None;
+    };
+
+    // Pixels of the cut are handed out to whichever piece reaches them first
+    let mut frontier = pieces.iter().flatten().copied().collect::<VecDeque<Vector2<u32>>>();
+    while let Some(current) = frontier.pop_front() {
+      let label = labels[local(current)];
+      for next in self.iter_pixels_adjacent(current) {
+        if extents.contains(next) && is_cut[local(next)] && labels[local(next)].is_none() {
+          labels[local(next)] = label;
+          pieces[label.expect("infallible")].push(next);
+          frontier.push_back(next);
+        };
+      };
     };
+
+    let keep = (0..pieces.len())
+      .filter(|&label| touches_cut[label])
+      .max_by_key(|&label| pieces[label].len())
+      .expect("infallible");
+
+    let original = self.get_province_by_id(which).clone();
+    let color = random_color_pure(&*self.base.provinces, original.kind);
+    Arc::make_mut(&mut self.base.provinces).set_color(which, color);
+    self.boundaries.mark_dirty_extents(extents);
+
+    for (label, piece) in pieces.into_iter().enumerate() {
+      if label == keep || !touches_cut[label] {
+        continue;
+      };
+
+      let id = self.insert_split_province(&original);
+      for pos in piece {
+        self.put_pixel_id(pos, id);
+      };
+    };
+
+    Some(extents)
   }
 
-  /// Completely replace all of one color in the map with another
-  pub fn recolor_province(&mut self, which: Color, color: Color) -> Extents {
-    assert_ne!(which, color, "Attempted to recolor a province when it is already the desired color");
-    self.rekey_province_raw(which, color);
-    self.rekey_connections_raw(which, color);
-    self.replace_color_raw(which, color)
+  /// Divides a province into the given number of new provinces of roughly equal size, using Voronoi cells around
+  /// seed points that are spread out with Lloyd's algorithm. Every cell is clipped to the province and kept 4-connected,
+  /// and every separate part of the province gets at least one cell, even if that makes more cells than were asked for.
+  /// Like when splitting, every cell gets a new color, so that the original province cannot be mistaken for one left untouched.
+  /// The largest cell keeps the identity of the original province, the rest become new provinces with the same metadata.
+  /// Returns `None` if the province cannot be divided into more than one province.
+  pub fn subdivide_province(&mut self, which: Color, count: usize) -> Option<Extents> {
+    self.subdivide_province_with_progress(which, count, &Progress::new()).expect("infallible")
+  }
+
+  /// The same as `subdivide_province`, but stops early with `Error::Cancelled` if the given task is cancelled,
+  /// in which case the map is left untouched
+  pub fn subdivide_province_with_progress(&mut self, which: Color, count: usize, progress: &Progress) -> Result<Option<Extents>, Error> {
+    const RELAXATION_STEPS: usize = 8;
+
+    let which = self.get_province_id(which).expect("province not found with color");
+    let extents = self.get_province_extents(which);
+    let (offset, [width, height]) = extents.to_offset_size();
+    let local = |[x, y]: Vector2<u32>| ((y - offset[1]) * width + (x - offset[0])) as usize;
+    let distance = |a: Vector2<u32>, b: Vector2<u32>| {
+      let (dx, dy) = (a[0].abs_diff(b[0]) as u64, a[1].abs_diff(b[1]) as u64);
+      dx * dx + dy * dy
+    };
+
+    let pixels = XYIter::from_extents(extents)
+      .filter(|&pos| self.get_id_at(pos) == which)
+      .collect::<Vec<Vector2<u32>>>();
+    let count = count.min(pixels.len());
+    if count < 2 {
+      return Ok(None);
+    };
+
+    let mut inside = vec![false; (width * height) as usize];
+    for &pos in &pixels {
+      inside[local(pos)] = true;
+    };
+
+    let is_inside = |pos: Vector2<u32>| extents.contains(pos) && inside[local(pos)];
+
+    // The first seed is the pixel closest to the center of the province, every following seed is the pixel furthest from the others.
+    // Distances are only spread through the province itself, so a part of the province that no seed can reach is always seeded next.
+    let center = self.get_province_by_id(which).center_of_mass();
+    let center_distance = |[x, y]: Vector2<u32>| (x as f64 - center[0]).powi(2) + (y as f64 - center[1]).powi(2);
+    let mut next_seed = pixels.iter().copied().min_by(|&a, &b| f64::total_cmp(&center_distance(a), &center_distance(b)));
+    let mut seeds = Vec::new();
+    let mut nearest = vec![u64::MAX; (width * height) as usize];
+    let mut farthest = BinaryHeap::new();
+    let mut unreached = 0;
+    while let Some(seed) = next_seed {
+      progress.check()?;
+      seeds.push(seed);
+      nearest[local(seed)] = 0;
+      let mut stack = vec![seed];
+      while let Some(pos) = stack.pop() {
+        for next in self.iter_pixels_adjacent(pos) {
+          let d = distance(next, seed);
+          if is_inside(next) && d < nearest[local(next)] {
+            nearest[local(next)] = d;
+            farthest.push((d, Reverse(next)));
+            stack.push(next);
+          };
+        };
+      };
+
+      while unreached < pixels.len() && nearest[local(pixels[unreached])] != u64::MAX {
+        unreached += 1;
+      };
+
+      next_seed = if let Some(&pos) = pixels.get(unreached) {
+        Some(pos)
+      } else if seeds.len() < count {
+        // Entries whose pixel has since been reached by a closer seed are out of date and skipped
+        std::iter::from_fn(|| farthest.pop())
+          .find(|&(d, Reverse(pos))| nearest[local(pos)] == d && d > 0)
+          .map(|(_, Reverse(pos))| pos)
+      } else {
+        None
+      };
+    };
+
+    // Cells grow outwards from their seeds one pixel at a time, closest pixels first, so every cell is connected to its seed
+    let grow = |seeds: &[Vector2<u32>]| -> Vec<Option<usize>> {
+      let mut cells = vec![None; (width * height) as usize];
+      let mut queue = seeds.iter().enumerate()
+        .map(|(cell, &seed)| Reverse((0, seed, cell)))
+        .collect::<BinaryHeap<Reverse<(u64, Vector2<u32>, usize)>>>();
+      while let Some(Reverse((_, pos, cell))) = queue.pop() {
+        if cells[local(pos)].is_some() {
+          continue;
+        };
+
+        cells[local(pos)] = Some(cell);
+        for next in self.iter_pixels_adjacent(pos) {
+          if is_inside(next) && cells[local(next)].is_none() {
+            queue.push(Reverse((distance(next, seeds[cell]), next, cell)));
+          };
+        };
+      };
+
+      cells
+    };
+
+    // Move each seed to the pixel of its cell closest to the centroid of that cell, so that seeds never leave their part of the province
+    for _ in 0..RELAXATION_STEPS {
+      progress.check()?;
+      let cells = grow(&seeds);
+      let mut sums = vec![(0u64, [0u64; 2]); seeds.len()];
+      for &pos in &pixels {
+        let (n, sum) = &mut sums[cells[local(pos)].expect("infallible")];
+        *n += 1;
+        sum[0] += pos[0] as u64;
+        sum[1] += pos[1] as u64;
+      };
+
+      let mut closest = vec![(f64::INFINITY, None); seeds.len()];
+      for &pos in &pixels {
+        let cell = cells[local(pos)].expect("infallible");
+        let (n, sum) = sums[cell];
+        let [x, y] = [sum[0] as f64 / n as f64, sum[1] as f64 / n as f64];
+        let d = (pos[0] as f64 - x).powi(2) + (pos[1] as f64 - y).powi(2);
+        if d < closest[cell].0 {
+          closest[cell] = (d, Some(pos));
+        };
+      };
+
+      for (cell, (_, pos)) in closest.into_iter().enumerate() {
+        if let Some(pos) = pos {
+          seeds[cell] = pos;
+        };
+      };
+    };
+
+    progress.check()?;
+    let cells = grow(&seeds);
+    let mut members = vec![Vec::new(); seeds.len()];
+    for &pos in &pixels {
+      members[cells[local(pos)].expect("infallible")].push(pos);
+    };
+
+    let keep = (0..members.len()).max_by_key(|&cell| members[cell].len()).expect("infallible");
+    let original = self.get_province_by_id(which).clone();
+    let color = random_color_pure(&*self.base.provinces, original.kind);
+    Arc::make_mut(&mut self.base.provinces).set_color(which, color);
+    self.boundaries.mark_dirty_extents(extents);
+
+    for (cell, members) in members.into_iter().enumerate() {
+      if cell == keep || members.is_empty() {
+        continue;
+      };
+
+      let id = self.insert_split_province(&original);
+      for pos in members {
+        self.put_pixel_id(pos, id);
+      };
+    };
+
+    Ok(Some(extents))
+  }
+
+  /// Creates a new, empty province with a fresh color and the same metadata as the province it was split off from
+  fn insert_split_province(&mut self, original: &ProvinceData) -> ProvinceId {
+    let color = random_color_pure(&*self.base.provinces, original.kind);
+    Arc::make_mut(&mut self.base.provinces).insert(ProvinceData {
+      color,
+      kind: original.kind,
+      terrain: original.terrain.clone(),
+      continent: original.continent,
+      coastal: original.coastal,
+      split_from: original.preserved_id.or(original.split_from),
+      ..ProvinceData::default()
+    })
   }
 
   pub fn flood_fill_province(&mut self, pos: Vector2<u32>, color: Color) -> Extents {
-    let which = self.get_color_at(pos);
-    assert_ne!(which, color, "Attempted to flood-fill a province when it is already the desired color");
-    let (extents, erased) = self.flood_fill_raw(pos, which, color);
-    self.recalculate_boundaries_extents(extents);
+    let which = self.get_id_at(pos);
+    let id = self.get_or_insert_province(color);
+    assert_ne!(which, id, "Attempted to flood-fill a province when it is already the desired color");
+    let (extents, erased) = self.flood_fill_raw(pos, which, id);
+    self.boundaries.mark_dirty_extents(extents);
 
     if erased {
       self.erase_province_data(which);
@@ -516,25 +850,25 @@ impl Map {
     extents
   }
 
-  /// Recursively attempts to replace the given color with the
-  /// given replacement color at the given position, and then repeats the process
-  /// with each pixel in each cardinal direction
-  fn flood_fill_raw(&mut self, pos: Vector2<u32>, which: Color, color: Color) -> (Extents, bool) {
+  /// Replaces the given province with the given replacement province at the given position, and then at every pixel
+  /// of that province connected to it in the cardinal directions. Pixels are filled as they are found and kept on an
+  /// explicit stack, since a single province may cover the whole map.
+  fn flood_fill_raw(&mut self, pos: Vector2<u32>, which: ProvinceId, id: ProvinceId) -> (Extents, bool) {
     let mut extents = Extents::new_point(pos);
-
-    if let Some(_) = self.put_pixel_raw(pos, color) {
+    if self.put_pixel_raw(pos, id).is_some() {
       return (extents, true);
     };
 
-    let [width, height] = self.dimensions();
-    for diff in CARDINAL {
-      let x = pos[0].wrapping_add(diff[0] as u32);
-      let y = pos[1].wrapping_add(diff[1] as u32);
-      if x < width && y < height && self.get_color_at([x, y]) == which {
-        let (ext, erased) = self.flood_fill_raw([x, y], which, color);
-        extents = extents.join(ext);
-        if erased {
-          return (extents, true)
+    let mut stack = vec![pos];
+    while let Some(pos) = stack.pop() {
+      for next in self.iter_pixels_adjacent(pos) {
+        if self.get_id_at(next) == which {
+          extents = extents.join_point(next);
+          if self.put_pixel_raw(next, id).is_some() {
+            return (extents, true);
+          };
+
+          stack.push(next);
         };
       };
     };
@@ -543,49 +877,111 @@ impl Map {
   }
 
   pub fn get_color_extents(&self, which: Color) -> Extents {
-    let mut out: Option<Extents> = None;
-    for (x, y, &Rgb(pixel)) in self.base.color_buffer.enumerate_pixels() {
-      if pixel == which {
-        out = Some(match out {
-          Some(extents) => extents.join_point([x, y]),
-          None => Extents::new_point([x, y])
-        });
+    let id = self.get_province_id(which).expect("province not found with color");
+    self.get_province_extents(id)
+  }
+
+  /// The exact bounds of a province, only the bounds the province has kept track of need to be searched
+  pub fn get_province_extents(&self, which: ProvinceId) -> Extents {
+    let bounds = self.get_province_by_id(which).extents.expect("province not found in map");
+    XYIter::from_extents(bounds)
+      .filter(|&pos| self.get_id_at(pos) == which)
+      .map(Extents::new_point)
+      .reduce(Extents::join)
+      .expect("province not found in map")
+  }
+
+  /// Finds every province inside of a polygon, which is every province with its center inside of the polygon,
+  /// or if a coverage is given, every province with at least that share of its pixels inside of the polygon
+  #[allow(deprecated)]
+  pub fn provinces_within(&self, shape: Vec<Vector2<f64>>, coverage: Option<f64>) -> AHashSet<Color> {
+    use geo::{Coordinate, LineString, Polygon};
+    use geo::algorithm::contains::Contains;
+
+    let mut extents = Extents::from_points(&shape);
+    extents.upper[0] = extents.upper[0].min(self.width() - 1);
+    extents.upper[1] = extents.upper[1].min(self.height() - 1);
+    let shape = Polygon::new(LineString::from(shape), Vec::new());
+    if let Some(coverage) = coverage {
+      let mut counts: AHashMap<Color, u64> = AHashMap::default();
+      for [x, y] in XYIter::from_extents(extents) {
+        if shape.contains(&Coordinate::from([x as f64 + 0.5, y as f64 + 0.5])) {
+          *counts.entry(self.get_color_at([x, y])).or_default() += 1;
+        };
       };
-    };
 
-    out.expect("color not found in map")
+      counts.into_iter()
+        .filter(|&(color, count)| count as f64 >= coverage * self.get_province(color).pixel_count as f64)
+        .map(|(color, _)| color)
+        .collect()
+    } else {
+      self.iter_province_data()
+        .filter(|(_, province_data)| {
+          let center_of_mass = vecmath::vec2_add([0.5, 0.5], province_data.center_of_mass());
+          shape.contains(&Coordinate::from(center_of_mass))
+        })
+        .map(|(_, province_data)| province_data.color)
+        .collect()
+    }
   }
 
   pub fn get_color_at(&self, pos: Vector2<u32>) -> Color {
-    self.base.color_buffer.get_pixel(pos[0], pos[1]).0
+    self.get_province_at(pos).color
+  }
+
+  pub fn get_id_at(&self, pos: Vector2<u32>) -> ProvinceId {
+    self.base.index_buffer.get(pos)
+  }
+
+  pub fn get_province_id(&self, color: Color) -> Option<ProvinceId> {
+    self.base.provinces.id_of(color)
+  }
+
+  /// Whether the province with the given color exists and is locked
+  pub fn is_locked(&self, color: Color) -> bool {
+    self.get_province_id(color)
+      .is_some_and(|id| self.get_province_by_id(id).locked)
   }
 
   pub fn get_province(&self, color: Color) -> &ProvinceData {
-    self.base.province_data_map.get(&color).expect("province not found with color")
+    let id = self.get_province_id(color).expect("province not found with color");
+    self.get_province_by_id(id)
   }
 
   fn get_province_mut(&mut self, color: Color) -> &mut ProvinceData {
-    let province = Arc::make_mut(&mut self.base.province_data_map)
-      .get_mut(&color).expect("province not found with color");
-    Arc::make_mut(province)
+    let id = self.get_province_id(color).expect("province not found with color");
+    self.get_province_by_id_mut(id)
+  }
+
+  pub fn has_province(&self, id: ProvinceId) -> bool {
+    self.base.provinces.get(id).is_some()
+  }
+
+  pub fn get_province_by_id(&self, id: ProvinceId) -> &ProvinceData {
+    self.base.provinces.get(id).expect("province not found with id")
+  }
+
+  fn get_province_by_id_mut(&mut self, id: ProvinceId) -> &mut ProvinceData {
+    Arc::make_mut(&mut self.base.provinces)
+      .get_mut(id).expect("province not found with id")
   }
 
   pub fn get_province_at(&self, pos: Vector2<u32>) -> &ProvinceData {
-    self.get_province(self.get_color_at(pos))
+    self.get_province_by_id(self.get_id_at(pos))
   }
 
-  pub fn get_connection(&self, rel: UOrd<Color>) -> &ConnectionData {
+  pub fn get_connection(&self, rel: UOrd<ProvinceId>) -> &ConnectionData {
     self.base.connection_data_map.get(&rel).expect("connection not found with rel")
   }
 
-  pub fn get_connection_positions(&self, rel: UOrd<Color>) -> (Vector2<f64>, Vector2<f64>) {
+  pub fn get_connection_positions(&self, rel: UOrd<ProvinceId>) -> (Vector2<f64>, Vector2<f64>) {
     let connection_data = self.get_connection(rel);
     if let (Some(start), Some(stop)) = (connection_data.start, connection_data.stop) {
       ([start[0] as f64, start[1] as f64], [stop[0] as f64, stop[1] as f64])
     } else {
       let [start, stop] = rel.into_array();
-      let start = self.get_province(start).center_of_mass();
-      let stop = self.get_province(stop).center_of_mass();
+      let start = self.get_province_by_id(start).center_of_mass();
+      let stop = self.get_province_by_id(stop).center_of_mass();
       (start, stop)
     }
   }
@@ -600,12 +996,12 @@ impl Map {
     self.get_rel_nearest(pos).map(|(rel, _)| self.get_connection(rel))
   }
 
-  pub fn get_rel_nearest(&self, pos: Vector2<f64>) -> Option<(UOrd<Color>, f64)> {
+  pub fn get_rel_nearest(&self, pos: Vector2<f64>) -> Option<(UOrd<ProvinceId>, f64)> {
     use geo::{Point, Line, Closest};
     use geo::algorithm::closest_point::ClosestPoint;
     use geo::algorithm::euclidean_distance::EuclideanDistance;
 
-    fn distance(map: &Map, rel: UOrd<Color>, pos: Vector2<f64>) -> f64 {
+    fn distance(map: &Map, rel: UOrd<ProvinceId>, pos: Vector2<f64>) -> f64 {
       let (a, b) = map.get_connection_positions(rel);
       let line = Line::new(a, b);
       let point = Point::from(pos);
@@ -626,14 +1022,14 @@ impl Map {
 
     let mut pairs = self.base.connection_data_map.keys()
       .map(|&rel| (rel, distance(self, rel, pos)))
-      .collect::<Vec<(UOrd<Color>, f64)>>();
+      .collect::<Vec<(UOrd<ProvinceId>, f64)>>();
     pairs.sort_by_key(|&(_, d)| convert(d));
 
     pairs.first()
       .cloned()
   }
 
-  pub fn add_or_remove_connection(&mut self, rel: UOrd<Color>, kind: ConnectionKind) {
+  pub fn add_or_remove_connection(&mut self, rel: UOrd<ProvinceId>, kind: ConnectionKind) {
     use std::collections::hash_map::Entry;
     match Arc::make_mut(&mut self.base.connection_data_map).entry(rel) {
       Entry::Vacant(entry) => {
@@ -647,16 +1043,58 @@ impl Map {
     }
   }
 
-  pub fn iter_province_data(&self) -> impl Iterator<Item = (Color, &ProvinceData)> {
-    self.base.province_data_map.iter().map(|(i, d)| (*i, &**d))
+  /// Works out new continents for land provinces from the land masses they belong to, see `landmasses::assign_continents`
+  pub fn assign_continents(&self, join_straits: bool, assignment: ContinentAssignment) -> Vec<(Color, u16)> {
+    self::landmasses::assign_continents(self, join_straits, assignment)
   }
 
-  pub fn iter_connection_data(&self) -> impl Iterator<Item = (UOrd<Color>, &ConnectionData)> {
+  /// Suggests straits between land provinces separated by no more than `max_distance` pixels of water, see `straits::suggest_straits`
+  pub fn suggest_straits(&self, max_distance: f64) -> Vec<StraitSuggestion> {
+    self::straits::suggest_straits(self, max_distance)
+  }
+
+  /// The name given to a continent in `continent.txt`, if there is one
+  pub fn continent_name(&self, continent: u16) -> Option<&str> {
+    let index = usize::from(continent).checked_sub(1)?;
+    self.continent_names.get(index).map(String::as_str)
+  }
+
+  /// A continent's number, followed by its name if it has one
+  pub fn continent_label(&self, continent: u16) -> String {
+    match self.continent_name(continent) {
+      Some(name) => format!("{} ({})", continent, name.to_uppercase()),
+      None => continent.to_string()
+    }
+  }
+
+  /// The highest continent that is either named in `continent.txt` or used by a province
+  pub fn defined_continents(&self) -> u16 {
+    let named = u16::try_from(self.continent_names.len()).unwrap_or(u16::MAX);
+    self.base.provinces.iter()
+      .map(|(_, province_data)| province_data.continent)
+      .fold(named, u16::max)
+  }
+
+  pub fn get_adjacency_rule(&self, name: &str) -> Option<&AdjacencyRuleData> {
+    self.base.adjacency_rules.iter().find(|rule| rule.name == name)
+  }
+
+  pub fn iter_adjacency_rules(&self) -> impl Iterator<Item = &AdjacencyRuleData> {
+    self.base.adjacency_rules.iter()
+  }
+
+  pub fn iter_province_data(&self) -> impl Iterator<Item = (ProvinceId, &ProvinceData)> {
+    self.base.provinces.iter()
+  }
+
+  pub fn iter_connection_data(&self) -> impl Iterator<Item = (UOrd<ProvinceId>, &ConnectionData)> {
     self.base.connection_data_map.iter().map(|(i, c)| (*i, &**c))
   }
 
-  pub fn iter_boundaries(&self) -> impl Iterator<Item = (UOrd<Vector2<u32>>, bool)> + '_ {
-    self.boundaries.iter().map(|(b, is_special)| (*b, *is_special))
+  /// Iterates over the province boundaries that may be visible within the given extents,
+  /// `update_boundaries` should be called first for these to be accurate
+  pub fn iter_boundaries_within(&self, extents: Extents) -> impl Iterator<Item = &Polyline> {
+    self.boundaries.iter_within(extents)
   }
 
   pub fn get_rivers_overlay(&self) -> Option<&RgbaImage> {
@@ -745,13 +1183,34 @@ fn vec2_ceil(point: Vector2<f64>) -> Vector2<u32> {
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ProvinceData {
+  pub color: Color,
   pub preserved_id: Option<u32>,
   pub kind: ProvinceKind,
   pub terrain: String,
   pub continent: u16,
   pub coastal: Option<bool>,
   pub pixel_count: u64,
-  pub pixel_sum: Vector2<u64>
+  pub pixel_sum: Vector2<u64>,
+  /// Bounds of every pixel this province has been given, which are never shrunk as pixels are taken away,
+  /// so they may be larger than the province itself
+  pub extents: Option<Extents>,
+  /// Anything from this province's row in `definition.csv` that is not otherwise used
+  pub extras: Option<Arc<DefinitionExtras>>,
+  /// The preserved IDs of any provinces that have been merged into this one
+  pub merged_ids: Vec<u32>,
+  /// The preserved ID of the province this one was split off from
+  pub split_from: Option<u32>,
+  /// Locked provinces are skipped by every painting tool, locks are kept in `locked_provinces.txt`
+  pub locked: bool
+}
+
+/// The parts of a row in `definition.csv` that are kept only so that they can be written back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefinitionExtras {
+  /// The position of this row in the original table
+  pub row: usize,
+  pub fields: Vec<String>,
+  pub comments: Vec<String>
 }
 
 impl ProvinceData {
@@ -765,55 +1224,66 @@ impl ProvinceData {
     self.pixel_count += 1;
     self.pixel_sum[0] += pos[0] as u64;
     self.pixel_sum[1] += pos[1] as u64;
+    self.extents = Some(self.extents.map_or(Extents::new_point(pos), |extents| extents.join_point(pos)));
   }
 
   fn sub_pixel(&mut self, pos: Vector2<u32>) {
     self.pixel_count -= 1;
     self.pixel_sum[0] -= pos[0] as u64;
     self.pixel_sum[1] -= pos[1] as u64;
+    if self.pixel_count == 0 {
+      self.extents = None;
+    };
   }
 
-  pub fn from_definition_config(definition: Definition, config: &Config) -> Self {
+  pub fn from_definition_config(definition: Definition, row: usize, config: &Config) -> Self {
+    let extras = DefinitionExtras {
+      row,
+      fields: definition.extra,
+      comments: definition.comments
+    };
+
     ProvinceData {
+      color: definition.rgb,
       preserved_id: config.preserve_ids.then(|| definition.id),
       kind: definition.kind.into(),
       terrain: definition.terrain,
       continent: definition.continent,
       coastal: Some(definition.coastal),
       pixel_count: 0,
-      pixel_sum: [0, 0]
+      pixel_sum: [0, 0],
+      extents: None,
+      extras: Some(Arc::new(extras)),
+      merged_ids: Vec::new(),
+      split_from: None,
+      locked: false
     }
   }
 
-  pub fn to_definition(&self, color: Color) -> Result<Definition, &'static str> {
-    Ok(Definition {
-      id: self.preserved_id.expect("no id provided for definition"),
-      rgb: color,
-      kind: self.kind.to_definition_kind()
-        .ok_or("province data exists with 'unknown' type")?,
-      coastal: self.coastal
-        .ok_or("province data exists with unknown coastal status")?,
-      terrain: match self.terrain.as_str() {
-        "unknown" => return Err("province data exists with unknown terrain"),
-        terrain => terrain.to_owned()
-      },
-      continent: self.continent
-    })
+  pub fn to_definition(&self) -> Result<Definition, &'static str> {
+    self.to_definition_with_id(self.preserved_id.expect("no id provided for definition"))
   }
 
-  pub fn to_definition_with_id(&self, color: Color, id: u32) -> Result<Definition, &'static str> {
+  pub fn to_definition_with_id(&self, id: u32) -> Result<Definition, &'static str> {
+    let (extra, comments) = match self.extras.as_deref() {
+      Some(extras) => (extras.fields.clone(), extras.comments.clone()),
+      None => (Vec::new(), Vec::new())
+    };
+
     Ok(Definition {
       id,
-      rgb: color,
+      rgb: self.color,
       kind: self.kind.to_definition_kind()
-        .ok_or("Province exists with unknown type")?,
+        .ok_or("province data exists with 'unknown' type")?,
       coastal: self.coastal
-        .ok_or("Province exists with unknown coastal status")?,
+        .ok_or("province data exists with unknown coastal status")?,
       terrain: match self.terrain.as_str() {
-        "unknown" => return Err("Province exists with unknown terrain"),
+        "unknown" => return Err("province data exists with unknown terrain"),
         terrain => terrain.to_owned()
       },
-      continent: self.continent
+      continent: self.continent,
+      extra,
+      comments
     })
   }
 
@@ -827,13 +1297,19 @@ impl ProvinceData {
 impl Default for ProvinceData {
   fn default() -> ProvinceData {
     ProvinceData {
+      color: [0x00; 3],
       preserved_id: None,
       kind: ProvinceKind::Unknown,
       terrain: "unknown".to_owned(),
       continent: 0,
       coastal: None,
       pixel_count: 0,
-      pixel_sum: [0, 0]
+      pixel_sum: [0, 0],
+      extents: None,
+      extras: None,
+      merged_ids: Vec::new(),
+      split_from: None,
+      locked: false
     }
   }
 }
@@ -964,11 +1440,20 @@ impl From<ProvinceKind> for &'static str {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ConnectionData {
   pub kind: ConnectionKind,
-  pub through: Option<Color>,
+  pub through: Option<ProvinceId>,
   pub start: Option<[u32; 2]>,
   pub stop: Option<[u32; 2]>,
   pub rule_name: String,
-  pub comment: String
+  pub comment: String,
+  /// Where this connection came from in `adjacencies.csv`, if it was loaded from one
+  pub source: Option<AdjacencySource>
+}
+
+/// The row a connection was loaded from, and which of its provinces was listed first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdjacencySource {
+  pub row: usize,
+  pub from: ProvinceId
 }
 
 impl ConnectionData {
@@ -979,33 +1464,41 @@ impl ConnectionData {
       start: None,
       stop: None,
       rule_name: String::new(),
-      comment: String::new()
+      comment: String::new(),
+      source: None
     }
   }
 
-  pub fn from_adjacency<F>(adjacency: Adjacency, through: F) -> Option<Self>
-  where F: Fn(u32) -> Option<Color> {
+  pub fn from_adjacency<F>(adjacency: Adjacency, row: usize, id: F) -> Option<Self>
+  where F: Fn(u32) -> Option<ProvinceId> {
     Some(ConnectionData {
       kind: ConnectionKind::from_adjacency_kind(adjacency.kind)?,
       through: match adjacency.through {
-        Some(id) => Some(through(id)?),
+        Some(through) => Some(id(through)?),
         None => None
       },
       start: adjacency.start,
       stop: adjacency.stop,
       rule_name: adjacency.rule_name,
-      comment: adjacency.comment
+      comment: adjacency.comment,
+      source: Some(AdjacencySource { row, from: id(adjacency.from_id)? })
     })
   }
 
-  pub fn to_adjacency<F>(&self, rel: UOrd<u32>, through: F) -> Adjacency
-  where F: Fn(Color) -> u32 {
-    let [from_id, to_id] = rel.into_array();
+  /// Converts this connection back into an adjacency, listing its provinces in their original order
+  pub fn to_adjacency<F>(&self, rel: UOrd<ProvinceId>, id: F) -> Adjacency
+  where F: Fn(ProvinceId) -> u32 {
+    let [a, b] = rel.into_array();
+    let [from, to] = match self.source {
+      Some(source) if source.from == b => [b, a],
+      _ => [a, b]
+    };
+
     Adjacency {
-      from_id,
-      to_id,
+      from_id: id(from),
+      to_id: id(to),
       kind: self.kind.into_adjacency_kind(),
-      through: self.through.map(through),
+      through: self.through.map(&id),
       start: self.start,
       stop: self.stop,
       rule_name: self.rule_name.clone(),
@@ -1014,6 +1507,52 @@ impl ConnectionData {
   }
 }
 
+/// A rule from `adjacency_rules.txt`, which adjacencies refer to by name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdjacencyRuleData {
+  pub name: String,
+  /// Permissions for each relation, in the order of `RuleRelation::ALL`
+  pub permissions: [RulePermissions; 4],
+  pub required_provinces: Vec<ProvinceId>,
+  pub icon: Option<ProvinceId>,
+  /// Anything else in the rule that is not otherwise used, such as `is_disabled` or `offset`
+  pub extra: Vec<ScriptEntry>
+}
+
+impl AdjacencyRuleData {
+  pub fn new(name: String) -> Self {
+    let AdjacencyRule { name, permissions, .. } = AdjacencyRule::new(name);
+    AdjacencyRuleData { name, permissions, required_provinces: Vec::new(), icon: None, extra: Vec::new() }
+  }
+
+  pub fn permissions(&self, relation: RuleRelation) -> &RulePermissions {
+    &self.permissions[relation as usize]
+  }
+
+  /// Converts a rule loaded from `adjacency_rules.txt`, any provinces not on the map are dropped
+  pub fn from_adjacency_rule<F>(rule: AdjacencyRule, id: F) -> Self
+  where F: Fn(u32) -> Option<ProvinceId> {
+    AdjacencyRuleData {
+      name: rule.name,
+      permissions: rule.permissions,
+      required_provinces: rule.required_provinces.into_iter().filter_map(&id).collect(),
+      icon: rule.icon.and_then(&id),
+      extra: rule.extra
+    }
+  }
+
+  pub fn to_adjacency_rule<F>(&self, id: F) -> AdjacencyRule
+  where F: Fn(ProvinceId) -> u32 {
+    AdjacencyRule {
+      name: self.name.clone(),
+      permissions: self.permissions,
+      required_provinces: self.required_provinces.iter().copied().map(&id).collect(),
+      icon: self.icon.map(&id),
+      extra: self.extra.clone()
+    }
+  }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum ConnectionKind {
   Strait,
@@ -1061,6 +1600,21 @@ fn p4(color: Color) -> [u8; 4] {
   [color[0], color[1], color[2], 0xff]
 }
 
+/// Draws diagonal stripes over locked provinces, each stripe pixel is pushed halfway towards black or white
+fn hatch(pos: Vector2<u32>, color: [u8; 4]) -> [u8; 4] {
+  if (pos[0] + pos[1]) % 8 < 2 {
+    let [r, g, b, a] = color;
+    let lightness = r as u32 + g as u32 + b as u32;
+    if lightness > 0x180 {
+      [r / 2, g / 2, b / 2, a]
+    } else {
+      [r / 2 + 0x80, g / 2 + 0x80, b / 2 + 0x80, a]
+    }
+  } else {
+    color
+  }
+}
+
 fn random_color<R: Rng>(rng: &mut R, kind: ProvinceKind) -> Color {
   use crate::util::hsl::hsl_to_rgb;
 
@@ -1126,10 +1680,8 @@ pub fn boundary_to_line(b: UOrd<Vector2<u32>>) -> UOrd<Vector2<u32>> {
 }
 
 /// Takes the average of the colors of the boundary, and then inverts that
-pub fn boundary_color(map: &Map, b: UOrd<Vector2<u32>>) -> Color {
-  let [b1, b2] = b.into_array();
-  let b1 = map.get_color_at(b1);
-  let b2 = map.get_color_at(b2);
+pub fn boundary_color(map: &Map, rel: UOrd<ProvinceId>) -> Color {
+  let [b1, b2] = rel.map(|id| map.get_province_by_id(id).color).into_array();
 
   [
     0xff - b1[0] / 2 - b2[0] / 2,
@@ -1137,3 +1689,143 @@ pub fn boundary_color(map: &Map, b: UOrd<Vector2<u32>>) -> Color {
     0xff - b1[2] / 2 - b2[2] / 2
   ]
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::bridge::deconstruct_map_data;
+  use super::fixtures::{build_bundle, generate_stripes, id_changes, is_connected};
+  use super::synthetic::SyntheticMap;
+
+  const PROVINCE: Color = [0x80, 0x40, 0x40];
+
+  /// A map made of a single land province
+  fn single_province(width: u32, height: u32) -> Bundle {
+    generate_stripes(width, height, &[(PROVINCE, DefinitionKind::Land, 1, 0..width)], Vec::new())
+  }
+
+  #[test]
+  fn test_merge_provinces() {
+    let adjacency = |from_id, to_id, kind, through| Adjacency {
+      from_id, to_id, kind, through,
+      start: None,
+      stop: None,
+      rule_name: String::new(),
+      comment: String::new()
+    };
+
+    let (province_image, mut definitions, _) = SyntheticMap::new(4, 1, 4).generate_tables();
+    definitions[1].comments = vec!["# merged away".to_owned()];
+    definitions[1].extra = vec!["kept".to_owned()];
+    definitions[2].comments = vec!["# merged into".to_owned()];
+    let adjacencies = vec![
+      adjacency(1, 2, AdjacencyKind::Impassable, None),
+      adjacency(2, 3, AdjacencyKind::Impassable, None),
+      adjacency(4, 1, AdjacencyKind::Sea, Some(2))
+    ];
+
+    let mut bundle = build_bundle(province_image, definitions.clone(), adjacencies);
+    bundle.map.merge_province(definitions[1].rgb, definitions[2].rgb);
+    assert_eq!(id_changes(&bundle), ["Merged ID 2 into 3", "Reassigned ID 4 to 2", "Deleted IDs 4 through 4"]);
+
+    let (definition_table, adjacencies_table, _, _) = deconstruct_map_data(&bundle).unwrap();
+    assert_eq!(definition_table.iter().map(|d| (d.id, d.rgb)).collect::<Vec<_>>(), [
+      (1, definitions[0].rgb),
+      (3, definitions[2].rgb),
+      (2, definitions[3].rgb)
+    ]);
+    // The merged province's comments and extra fields move over to the province it was merged into
+    assert_eq!(definition_table[1].comments, ["# merged into", "# merged away"]);
+    assert_eq!(definition_table[1].extra, ["kept"]);
+
+    // The connection to the merged province is moved over, the one between the two merged provinces is dropped
+    assert_eq!(adjacencies_table, [
+      adjacency(1, 3, AdjacencyKind::Impassable, None),
+      adjacency(2, 1, AdjacencyKind::Sea, Some(3))
+    ]);
+  }
+
+  #[test]
+  fn test_split_province() {
+    let mut bundle = single_province(8, 8);
+
+    // A cut that does not cross the province leaves it alone
+    assert!(bundle.map.split_province(PROVINCE, &[[0, 0], [1, 0]]).is_none());
+
+    let cut = (0..8).map(|y| [3, y]).collect::<Vec<[u32; 2]>>();
+    bundle.map.split_province(PROVINCE, &cut).unwrap();
+    assert!(bundle.map.validate_pixel_counts());
+    assert_eq!(bundle.map.provinces_count(), 2);
+    assert_ne!(bundle.map.get_color_at([0, 0]), bundle.map.get_color_at([7, 0]));
+    assert_eq!(bundle.map.get_color_at([0, 0]), bundle.map.get_color_at([3, 0]));
+    assert_eq!(id_changes(&bundle), ["Assigned ID 2 to new province split from 1", "Created IDs 2 through 2"]);
+
+    let (definition_table, _, _, _) = deconstruct_map_data(&bundle).unwrap();
+    assert!(definition_table.iter().all(|d| d.kind == DefinitionKind::Land && d.terrain == "plains"));
+  }
+
+  #[test]
+  fn test_provinces_within() {
+    let mut bundle = single_province(8, 8);
+    let cut = (0..8).map(|y| [3, y]).collect::<Vec<[u32; 2]>>();
+    bundle.map.split_province(PROVINCE, &cut).unwrap();
+    let (left, right) = (bundle.map.get_color_at([0, 0]), bundle.map.get_color_at([7, 0]));
+
+    // By default only the center of a province has to be inside of the shape
+    let rectangle = |width: f64| vec![[0.0, 0.0], [width, 0.0], [width, 8.0], [0.0, 8.0]];
+    assert_eq!(bundle.map.provinces_within(rectangle(3.0), None), AHashSet::from_iter([left]));
+    assert_eq!(bundle.map.provinces_within(rectangle(8.0), None), AHashSet::from_iter([left, right]));
+
+    // Half of the right province is inside of this rectangle
+    assert_eq!(bundle.map.provinces_within(rectangle(6.0), Some(0.5)), AHashSet::from_iter([left, right]));
+    assert_eq!(bundle.map.provinces_within(rectangle(6.0), Some(0.6)), AHashSet::from_iter([left]));
+  }
+
+  #[test]
+  fn test_subdivide_province() {
+    let mut bundle = single_province(24, 16);
+    bundle.map.subdivide_province(PROVINCE, 4).unwrap();
+    assert!(bundle.map.validate_pixel_counts());
+    assert_eq!(bundle.map.provinces_count(), 4);
+    assert_eq!(bundle.map.get_province_id(PROVINCE), None);
+
+    // Every new province is 4-connected and has a reasonable share of the pixels
+    for (id, province_data) in bundle.map.base.provinces.iter() {
+      assert!(province_data.pixel_count >= 24 * 16 / 8);
+      assert!(is_connected(&bundle.map, id));
+      assert_eq!((province_data.terrain.as_str(), province_data.continent), ("plains", 1));
+    };
+
+    assert_eq!(id_changes(&bundle), [
+      "Assigned ID 4 to new province split from 1",
+      "Assigned ID 3 to new province split from 1",
+      "Assigned ID 2 to new province split from 1",
+      "Created IDs 2 through 4"
+    ]);
+  }
+
+  #[test]
+  fn test_subdivide_province_parts() {
+    const BETWEEN: Color = [0x80, 0x60, 0x40];
+    // The province being divided is made of three separate parts, with the other province between them
+    let mut bundle = generate_stripes(24, 8, &[
+      (PROVINCE, DefinitionKind::Land, 1, 0..6),
+      (BETWEEN, DefinitionKind::Land, 1, 6..9),
+      (PROVINCE, DefinitionKind::Land, 1, 9..15),
+      (BETWEEN, DefinitionKind::Land, 1, 15..18),
+      (PROVINCE, DefinitionKind::Land, 1, 18..24)
+    ], Vec::new());
+    bundle.map.subdivide_province(PROVINCE, 2).unwrap();
+    assert!(bundle.map.validate_pixel_counts());
+
+    // Asking for fewer provinces than there are parts still gives every part a province of its own
+    let between = bundle.map.get_province_id(BETWEEN).unwrap();
+    assert_eq!(bundle.map.provinces_count(), 4);
+    for (id, province_data) in bundle.map.base.provinces.iter() {
+      assert!(is_connected(&bundle.map, id));
+      if id != between {
+        assert_eq!(province_data.pixel_count, 6 * 8);
+      };
+    };
+  }
+}
@@ -0,0 +1,306 @@
+//! Chunked storage for the borders between provinces
+use ahash::AHashMap;
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
+use uord::UOrd2 as UOrd;
+use vecmath::Vector2;
+
+use super::{Extents, IndexBuffer, ProvinceId};
+
+/// The width and height of a single boundary tile, in pixels
+pub const TILE_SIZE: u32 = 64;
+
+/// Province boundaries, split into square tiles so that they may be updated
+/// and drawn piecewise instead of all at once.
+///
+/// Each tile owns the borders between every pixel inside of it and that pixel's
+/// right and bottom neighbors, merged into polylines per pair of provinces.
+#[derive(Debug, Clone)]
+pub struct Boundaries {
+  tiles: Vec<Tile>,
+  tiles_size: Vector2<u32>,
+  dimensions: Vector2<u32>
+}
+
+#[derive(Debug, Clone, Default)]
+struct Tile {
+  polylines: Vec<Polyline>,
+  dirty: bool
+}
+
+/// A continuous line along the border between two provinces, in map space
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Polyline {
+  pub rel: UOrd<ProvinceId>,
+  pub points: Vec<Vector2<u32>>
+}
+
+impl Boundaries {
+  /// Creates a new set of boundaries for a map of the given size, with every tile marked dirty
+  pub fn new(dimensions: Vector2<u32>) -> Self {
+    let tiles_size = [dimensions[0].div_ceil(TILE_SIZE), dimensions[1].div_ceil(TILE_SIZE)];
+    let tile = Tile { polylines: Vec::new(), dirty: true };
+    Boundaries {
+      tiles: vec![tile; (tiles_size[0] * tiles_size[1]) as usize],
+      tiles_size,
+      dimensions
+    }
+  }
+
+  pub fn is_dirty(&self) -> bool {
+    self.tiles.iter().any(|tile| tile.dirty)
+  }
+
+  pub fn mark_all_dirty(&mut self) {
+    for tile in self.tiles.iter_mut() {
+      tile.dirty = true;
+    };
+  }
+
+  /// Marks every tile owning a border of the given pixel as dirty
+  pub fn mark_dirty_at(&mut self, pos: Vector2<u32>) {
+    self.mark_dirty_extents(Extents::new_point(pos));
+  }
+
+  /// Marks every tile owning a border of any pixel within the given extents as dirty
+  pub fn mark_dirty_extents(&mut self, extents: Extents) {
+    // The borders along the top and left of the extents belong to the pixels above and to the left
+    let lower = [extents.lower[0].saturating_sub(1) / TILE_SIZE, extents.lower[1].saturating_sub(1) / TILE_SIZE];
+    let upper = [
+      (extents.upper[0] / TILE_SIZE).min(self.tiles_size[0].saturating_sub(1)),
+      (extents.upper[1] / TILE_SIZE).min(self.tiles_size[1].saturating_sub(1))
+    ];
+
+    for ty in lower[1]..=upper[1] {
+      for tx in lower[0]..=upper[0] {
+        let index = self.tile_index([tx, ty]);
+        self.tiles[index].dirty = true;
+      };
+    };
+  }
+
+  /// Compares two versions of an index buffer, marking the tiles whose pixels differ as dirty
+  pub fn mark_changed(&mut self, previous: &IndexBuffer, current: &IndexBuffer) {
+    if previous.dimensions() != current.dimensions() {
+      *self = Boundaries::new(current.dimensions());
+      return;
+    };
+
+    for ty in 0..self.tiles_size[1] {
+      for tx in 0..self.tiles_size[0] {
+        let extents = self.tile_extents([tx, ty]);
+        let columns = extents.lower[0]..=extents.upper[0];
+        let changed = (extents.lower[1]..=extents.upper[1]).any(|y| {
+          previous.row(y, columns.clone()) != current.row(y, columns.clone())
+        });
+
+        if changed {
+          self.mark_dirty_extents(extents);
+        };
+      };
+    };
+  }
+
+  /// Recalculates the polylines of all tiles that have been marked dirty
+  pub fn update(&mut self, index_buffer: &IndexBuffer) {
+    debug_assert_eq!(self.dimensions, index_buffer.dimensions());
+    let tiles_width = self.tiles_size[0];
+    let dimensions = self.dimensions;
+    self.tiles.par_iter_mut().enumerate()
+      .filter(|(_, tile)| tile.dirty)
+      .for_each(|(index, tile)| {
+        let tile_pos = [index as u32 % tiles_width, index as u32 / tiles_width];
+        tile.polylines = calculate_tile(index_buffer, dimensions, tile_pos);
+        tile.dirty = false;
+      });
+  }
+
+  /// Iterates over the polylines of every tile
+  pub fn iter(&self) -> impl Iterator<Item = &Polyline> {
+    self.tiles.iter().flat_map(|tile| tile.polylines.iter())
+  }
+
+  /// Iterates over the polylines of every tile overlapping the given extents
+  pub fn iter_within(&self, extents: Extents) -> impl Iterator<Item = &Polyline> {
+    let lower = [extents.lower[0].saturating_sub(1) / TILE_SIZE, extents.lower[1].saturating_sub(1) / TILE_SIZE];
+    let upper = [
+      (extents.upper[0] / TILE_SIZE).min(self.tiles_size[0].saturating_sub(1)),
+      (extents.upper[1] / TILE_SIZE).min(self.tiles_size[1].saturating_sub(1))
+    ];
+
+    (lower[1]..=upper[1])
+      .flat_map(move |ty| (lower[0]..=upper[0]).map(move |tx| [tx, ty]))
+      .flat_map(move |tile_pos| self.tiles[self.tile_index(tile_pos)].polylines.iter())
+  }
+
+  fn tile_index(&self, tile_pos: Vector2<u32>) -> usize {
+    (tile_pos[1] * self.tiles_size[0] + tile_pos[0]) as usize
+  }
+
+  fn tile_extents(&self, tile_pos: Vector2<u32>) -> Extents {
+    tile_extents(self.dimensions, tile_pos)
+  }
+}
+
+fn tile_extents(dimensions: Vector2<u32>, tile_pos: Vector2<u32>) -> Extents {
+  let lower = [tile_pos[0] * TILE_SIZE, tile_pos[1] * TILE_SIZE];
+  let upper = [
+    (lower[0] + TILE_SIZE).min(dimensions[0]) - 1,
+    (lower[1] + TILE_SIZE).min(dimensions[1]) - 1
+  ];
+
+  Extents::new(upper, lower)
+}
+
+fn calculate_tile(index_buffer: &IndexBuffer, dimensions: Vector2<u32>, tile_pos: Vector2<u32>) -> Vec<Polyline> {
+  let extents = tile_extents(dimensions, tile_pos);
+  let get_id = |x: u32, y: u32| index_buffer.get([x, y]);
+
+  let mut segments: AHashMap<UOrd<ProvinceId>, Vec<[Vector2<u32>; 2]>> = AHashMap::default();
+  for y in extents.lower[1]..=extents.upper[1] {
+    for x in extents.lower[0]..=extents.upper[0] {
+      let id = get_id(x, y);
+      if x + 1 < dimensions[0] {
+        let other = get_id(x + 1, y);
+        if id != other {
+          let segment = [[x + 1, y], [x + 1, y + 1]];
+          segments.entry(UOrd::new([id, other])).or_default().push(segment);
+        };
+      };
+
+      if y + 1 < dimensions[1] {
+        let other = get_id(x, y + 1);
+        if id != other {
+          let segment = [[x, y + 1], [x + 1, y + 1]];
+          segments.entry(UOrd::new([id, other])).or_default().push(segment);
+        };
+      };
+    };
+  };
+
+  let mut polylines = Vec::new();
+  for (rel, segments) in segments {
+    for points in merge_segments(&segments) {
+      polylines.push(Polyline { rel, points });
+    };
+  };
+
+  polylines
+}
+
+/// Chains unit-length segments sharing endpoints into as few polylines as possible,
+/// dropping any points that lie in the middle of a straight run
+fn merge_segments(segments: &[[Vector2<u32>; 2]]) -> Vec<Vec<Vector2<u32>>> {
+  let mut corners: AHashMap<Vector2<u32>, Vec<usize>> = AHashMap::default();
+  for (i, &[a, b]) in segments.iter().enumerate() {
+    corners.entry(a).or_default().push(i);
+    corners.entry(b).or_default().push(i);
+  };
+
+  // Chains are started from dead ends first, so that open lines are not broken in the middle
+  let mut starts = corners.iter()
+    .filter(|(_, edges)| edges.len() % 2 == 1)
+    .map(|(&corner, _)| corner)
+    .collect::<Vec<Vector2<u32>>>();
+  starts.extend(segments.iter().map(|&[a, _]| a));
+
+  let mut used = vec![false; segments.len()];
+  let mut polylines = Vec::new();
+  for start in starts {
+    let mut points = vec![start];
+    let mut current = start;
+    while let Some(&i) = corners[&current].iter().find(|&&i| !used[i]) {
+      used[i] = true;
+      let [a, b] = segments[i];
+      current = if a == current { b } else { a };
+      push_point(&mut points, current);
+    };
+
+    if points.len() > 1 {
+      polylines.push(points);
+    };
+  };
+
+  polylines
+}
+
+fn push_point(points: &mut Vec<Vector2<u32>>, point: Vector2<u32>) {
+  match points[..] {
+    [.., a, b] if (a[0] == b[0] && b[0] == point[0]) || (a[1] == b[1] && b[1] == point[1]) => {
+      *points.last_mut().unwrap() = point;
+    },
+    _ => points.push(point)
+  };
+}
+
+#[cfg(test)]
+mod tests {
+  use ahash::AHashSet;
+  use vecmath::Vector2;
+
+  use super::{Boundaries, Extents};
+  use crate::app::map::{IndexBuffer, ProvinceData, ProvinceId, ProvinceTable};
+
+  /// Splits every polyline back up into unit-length segments
+  fn unit_segments(boundaries: &Boundaries) -> AHashSet<[Vector2<u32>; 2]> {
+    let mut out = AHashSet::default();
+    for polyline in boundaries.iter() {
+      for pair in polyline.points.windows(2) {
+        let [a, b] = [pair[0], pair[1]];
+        let [lower, upper] = if a <= b { [a, b] } else { [b, a] };
+        for i in 0..(upper[0] - lower[0]).max(upper[1] - lower[1]) {
+          let step = if lower[0] == upper[0] { [0, 1] } else { [1, 0] };
+          let p = [lower[0] + step[0] * i, lower[1] + step[1] * i];
+          assert!(out.insert([p, [p[0] + step[0], p[1] + step[1]]]), "segment was drawn twice");
+        };
+      };
+    };
+
+    out
+  }
+
+  fn naive_segments(index_buffer: &IndexBuffer) -> AHashSet<[Vector2<u32>; 2]> {
+    let mut out = AHashSet::default();
+    let [width, height] = index_buffer.dimensions();
+    for (x, y, id) in index_buffer.enumerate() {
+      if x + 1 < width && index_buffer.get([x + 1, y]) != id {
+        out.insert([[x + 1, y], [x + 1, y + 1]]);
+      };
+
+      if y + 1 < height && index_buffer.get([x, y + 1]) != id {
+        out.insert([[x, y + 1], [x + 1, y + 1]]);
+      };
+    };
+
+    out
+  }
+
+  #[test]
+  fn test_boundaries() {
+    let mut provinces = ProvinceTable::default();
+    let ids = [[255, 0, 0], [0, 0, 0], [0, 1, 0], [0, 2, 0], [0, 0, 255]]
+      .map(|color| provinces.insert(ProvinceData { color, ..ProvinceData::default() }));
+    let pattern = |x: u32, y: u32| -> ProvinceId {
+      let dx = x as i32 - 70;
+      let dy = y as i32 - 50;
+      if dx * dx + dy * dy < 900 { ids[0] } else { ids[1 + ((x / 7 + y / 5) % 3) as usize] }
+    };
+
+    let index_buffer = IndexBuffer::from_fn(150, 100, pattern);
+    let mut boundaries = Boundaries::new([150, 100]);
+    boundaries.update(&index_buffer);
+    assert_eq!(unit_segments(&boundaries), naive_segments(&index_buffer));
+
+    let changed = IndexBuffer::from_fn(150, 100, |x, y| {
+      if y == 64 && (60..80).contains(&x) { ids[4] } else { pattern(x, y) }
+    });
+
+    boundaries.mark_changed(&index_buffer, &changed);
+    assert!(boundaries.is_dirty());
+    boundaries.update(&changed);
+    assert!(!boundaries.is_dirty());
+    assert_eq!(unit_segments(&boundaries), naive_segments(&changed));
+
+    let visible = boundaries.iter_within(Extents::new([10, 10], [0, 0])).count();
+    assert!(visible < boundaries.iter().count());
+  }
+}
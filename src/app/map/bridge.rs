@@ -5,39 +5,103 @@ use image::{Rgb, Rgba, RgbImage, RgbaImage, Pixel, DynamicImage, ColorType};
 use image::codecs::bmp::{BmpDecoder, BmpEncoder};
 use uord::UOrd2 as UOrd;
 
-use super::{Color, Bundle, MapBase, Map, ProvinceData, ConnectionData, random_color_pure};
-use crate::app::format::{Adjacency, Definition, ParseCsv};
+use super::{Color, Boundaries, Bundle, MapBase, Map, ProvinceData, ConnectionData, AdjacencyRuleData, random_color_pure};
+use super::{IndexBuffer, ProvinceId, ProvinceTable};
+use crate::app::format::{read_continent_names, Adjacency, AdjacencyRule, Definition, ParseCsv};
 use crate::config::Config;
 use crate::error::Error;
 use crate::util::files::Location;
+use crate::util::task::Progress;
 
-use std::collections::hash_map::Entry;
 use std::cmp::Ordering;
 use std::io::{self, Cursor, Read, Write};
 use std::sync::Arc;
 
-pub(super) fn load_bundle(location: &Location, config: Config) -> Result<Bundle, Error> {
-  let (province_image, definition_table, adjacencies_table, rivers) = location.clone().manipulate_files(|files| {
-    let province_image = read_rgb_bmp_image(files.open_file("provinces.bmp")?)?;
-    let definition_table = read_definition_table(files.open_file("definition.csv")?)?;
+/// Lists the IDs of provinces that have been locked in the editor, this file is not used by the game
+const LOCKED_PROVINCES_FILE: &str = "locked_provinces.txt";
+/// Lists the rules that adjacencies may refer to by name in their `adjacency_rule_name` column
+const ADJACENCY_RULES_FILE: &str = "adjacency_rules.txt";
+/// Names the continents that `definition.csv` refers to by number, this file is only read
+const CONTINENTS_FILE: &str = "continent.txt";
+
+pub(super) fn load_bundle(location: &Location, config: Config, progress: &Progress) -> Result<Bundle, Error> {
+  progress.set_stage("Reading map files");
+  // Only raw file contents are read here; if the operation is cancelled while inside of
+  // `manipulate_files`, a zip archive would never be written back to disk
+  let (province_image, definition_table, adjacencies_table, adjacency_rules, continents, rivers, locked_provinces) = location.clone().manipulate_files(|files| {
+    let province_image = read_all(files.open_file("provinces.bmp")?).context("failed to read bmp image")?;
+    let definition_table = read_all(files.open_file("definition.csv")?).context("failed to read definition.csv")?;
     let adjacencies_table = files.open_file_maybe_not_found("adjacencies.csv")?
-      .map_or_else(|| Ok(Vec::new()), read_adjacencies_table)?;
+      .map(read_all).transpose().context("failed to read adjacencies.csv")?;
+    let adjacency_rules = files.open_file_maybe_not_found(ADJACENCY_RULES_FILE)?
+      .map(read_all).transpose().context("failed to read adjacency_rules.txt")?;
+    let continents = files.open_file_maybe_not_found(CONTINENTS_FILE)?
+      .map(read_all).transpose().context("failed to read continent.txt")?;
     let rivers = files.open_file_maybe_not_found("rivers.bmp")?
-      .map(read_rgb_bmp_image).transpose()?;
-    Ok((province_image, definition_table, adjacencies_table, rivers))
+      .map(read_all).transpose().context("failed to read bmp image")?;
+    let locked_provinces = files.open_file_maybe_not_found(LOCKED_PROVINCES_FILE)?
+      .map(read_all).transpose().context("failed to read locked provinces")?;
+    Ok((province_image, definition_table, adjacencies_table, adjacency_rules, continents, rivers, locked_provinces))
   })?;
 
-  Ok(construct_map_data(province_image, definition_table, adjacencies_table, rivers, config))
+  progress.check()?;
+  progress.set_stage("Decoding provinces.bmp");
+  let province_image = read_rgb_bmp_image(province_image)?;
+  let rivers = rivers.map(read_rgb_bmp_image).transpose()?;
+
+  progress.check()?;
+  progress.set_stage("Parsing definition.csv");
+  let (definition_table, definition_comments) = read_definition_table(definition_table)?;
+  progress.set_stage("Parsing adjacencies.csv");
+  let adjacencies_table = adjacencies_table
+    .map_or_else(|| Ok(Vec::new()), read_adjacencies_table)?;
+  progress.set_stage("Parsing adjacency_rules.txt");
+  let (adjacency_rules, adjacency_rules_text) = adjacency_rules
+    .map_or_else(|| Ok((Vec::new(), None)), |reader| read_adjacency_rules(reader).map(|(rules, text)| (rules, Some(text))))?;
+  let continent_names = continents
+    .map_or_else(|| Ok(Vec::new()), read_continents)?;
+  let locked_ids = locked_provinces
+    .map_or_else(|| Ok(Vec::new()), read_locked_ids)?;
+
+  progress.check()?;
+  let tables = MapTables {
+    definitions: definition_table,
+    definition_comments,
+    adjacencies: adjacencies_table,
+    adjacency_rules,
+    locked_ids,
+    rivers
+  };
+  let mut bundle = construct_map_data(province_image, tables, config, progress)?;
+  bundle.map.continent_names = continent_names;
+  bundle.map.preserved_adjacency_rules = adjacency_rules_text;
+  Ok(bundle)
 }
 
-fn construct_map_data(
-  province_image: RgbImage,
-  definition_table: Vec<Definition>,
-  adjacencies_table: Vec<Adjacency>,
-  rivers: Option<RgbImage>,
-  config: Config
-) -> Bundle {
-  let mut color_buffer = province_image;
+/// Everything a map is built from besides its province image, as read from the files of a map.
+/// Tables a map does not have, such as the adjacencies of a newly generated map, are left empty.
+#[derive(Debug, Default)]
+pub(super) struct MapTables {
+  pub definitions: Vec<Definition>,
+  /// Comment lines found after the last row of `definition.csv`
+  pub definition_comments: Vec<String>,
+  pub adjacencies: Vec<Adjacency>,
+  pub adjacency_rules: Vec<AdjacencyRule>,
+  pub locked_ids: Vec<u32>,
+  pub rivers: Option<RgbImage>
+}
+
+pub(super) fn construct_map_data(province_image: RgbImage, tables: MapTables, config: Config, progress: &Progress) -> Result<Bundle, Error> {
+  let MapTables {
+    definitions: definition_table,
+    definition_comments,
+    adjacencies: adjacencies_table,
+    adjacency_rules,
+    locked_ids,
+    rivers
+  } = tables;
+  progress.set_stage("Building province data");
+  let [width, height] = [province_image.width(), province_image.height()];
 
   let mut preserved_id_count = definition_table[0].id;
   // Create a sparse array for mapping province ids to colors
@@ -53,130 +117,128 @@ fn construct_map_data(
   assert_eq!(preserved_id_count, definition_table.len() as u32);
 
   // Initially convert the definition table into a province data map
-  let mut definition_map = definition_table.into_iter()
-    .map(|d| (d.rgb, ProvinceData::from_definition_config(d, &config)))
+  let mut definition_map = definition_table.into_iter().enumerate()
+    .map(|(row, d)| (d.rgb, ProvinceData::from_definition_config(d, row, &config)))
     .collect::<AHashMap<Color, ProvinceData>>();
-  // Loop through every pixel in the color buffer, ensuring that the resulting province data map
-  // will be valid and will have no provinces mapping to colors not on the color buffer
-  let mut province_data_map = AHashMap::default();
-  for (x, y, &Rgb(pixel)) in color_buffer.enumerate_pixels() {
-    // If this color isn't in the new province data map, but it is in the definition table,
-    // take it from the former and put it in the latter
-    match province_data_map.entry(pixel) {
-      Entry::Vacant(entry) => {
-        let mut province_data = definition_map.remove(&pixel).unwrap_or_default();
-        province_data.add_pixel([x, y]);
-        entry.insert(Arc::new(province_data));
-      },
-      Entry::Occupied(entry) => {
-        let entry = Arc::make_mut(entry.into_mut());
-        entry.add_pixel([x, y]);
+  // Loop through every pixel in the province image, ensuring that the resulting province table
+  // will be valid and will have no provinces mapping to colors not on the province image
+  let mut provinces = ProvinceTable::default();
+  let mut pixel_provinces = Vec::with_capacity(width as usize * height as usize);
+  for (x, y, &Rgb(pixel)) in province_image.enumerate_pixels() {
+    if x == 0 {
+      progress.check()?;
+      progress.set_fraction(y as f32 / height as f32);
+    };
+
+    // If this color isn't in the province table yet, take its data from the definition table
+    let id = match provinces.id_of(pixel) {
+      Some(id) => id,
+      None => {
+        let province_data = definition_map.remove(&pixel)
+          .unwrap_or(ProvinceData { color: pixel, ..ProvinceData::default() });
+        provinces.insert(province_data)
       }
     };
-  };
 
-  // strip colors from the color index that failed to have province data created for them
-  for color_index_entry in color_index.iter_mut() {
-    *color_index_entry = color_index_entry.filter(|color| province_data_map.contains_key(color));
+    provinces.get_mut(id).expect("infallible").add_pixel([x, y]);
+    pixel_provinces.push(id);
   };
 
-  let get_color_index = |id: u32| get_color_index(&color_index, id);
+  let mut pixel_provinces = pixel_provinces.into_iter();
+  let index_buffer = IndexBuffer::from_fn(width, height, |_, _| {
+    pixel_provinces.next().expect("infallible")
+  });
+
+  // Strip colors from the color index that failed to have province data created for them
+  let id_index = color_index.into_iter()
+    .map(|color| color.and_then(|color| provinces.id_of(color)))
+    .collect::<Vec<Option<ProvinceId>>>();
+
+  let get_id_index = |id: u32| get_id_index(&id_index, id);
 
-  province_data_map.shrink_to_fit();
   let _ = definition_map;
 
-  // Loop through the entries in the adjacencies table, converting ids to colors using `color_index`,
-  // since the adjacencies map is indexed by color instead of id
+  // Loop through the entries in the adjacencies table, converting ids to province handles
   let mut preserved_unsupported_adjacencies = Vec::new();
   let mut connection_data_map = AHashMap::with_capacity(adjacencies_table.len());
-  for a in adjacencies_table.into_iter() {
-    if let Some(rel) = UOrd::new([a.from_id, a.to_id]).try_map_opt(get_color_index) {
-      if let Some(connection_data) = ConnectionData::from_adjacency(a.clone(), get_color_index) {
+  for (row, a) in adjacencies_table.into_iter().enumerate() {
+    if let Some(rel) = UOrd::new([a.from_id, a.to_id]).try_map_opt(get_id_index) {
+      if let Some(connection_data) = ConnectionData::from_adjacency(a.clone(), row, get_id_index) {
         connection_data_map.insert(rel, Arc::new(connection_data));
       } else {
-        preserved_unsupported_adjacencies.push(a);
+        preserved_unsupported_adjacencies.push((row, a));
       };
     };
   };
 
   connection_data_map.shrink_to_fit();
-  let _ = color_index;
+
+  let adjacency_rules = adjacency_rules.into_iter()
+    .map(|rule| AdjacencyRuleData::from_adjacency_rule(rule, get_id_index))
+    .collect::<Vec<AdjacencyRuleData>>();
+
+  for id in locked_ids.into_iter().filter_map(get_id_index) {
+    provinces.get_mut(id).expect("infallible").locked = true;
+  };
 
   // Recolor the entire map if `preserve_ids` is false
   if !config.preserve_ids {
-    recolor_everything(
-      &mut color_buffer,
-      &mut province_data_map,
-      &mut connection_data_map
-    );
+    recolor_everything(&mut provinces);
   };
 
   let id_data = config.preserve_ids.then(|| preserved_id_count);
 
   let rivers_overlay = rivers.as_ref().map(process_and_clear_rivers_image);
+  let boundaries = Boundaries::new([width, height]);
 
   let mut map = Map {
     base: MapBase {
-      color_buffer: Arc::new(color_buffer),
-      province_data_map: Arc::new(province_data_map),
+      index_buffer: Arc::new(index_buffer),
+      provinces: Arc::new(provinces),
       connection_data_map: Arc::new(connection_data_map),
+      adjacency_rules: Arc::new(adjacency_rules),
       rivers_overlay: rivers_overlay.map(Arc::new)
     },
-    boundaries: AHashMap::default(),
+    boundaries,
     preserved_unsupported_adjacencies,
-    preserved_id_count: id_data
+    preserved_definition_comments: definition_comments,
+    preserved_id_count: id_data,
+    continent_names: Vec::new(),
+    preserved_adjacency_rules: None
   };
 
-  map.recalculate_all_boundaries();
+  progress.check()?;
+  progress.set_stage("Computing province boundaries");
+  map.update_boundaries();
 
-  Bundle { map, config }
+  Ok(Bundle { map, config })
 }
 
-pub(super) fn recolor_everything(
-  color_buffer: &mut RgbImage,
-  province_data_map: &mut AHashMap<Color, Arc<ProvinceData>>,
-  connection_data_map: &mut AHashMap<UOrd<Color>, Arc<ConnectionData>>
-) {
-  let mut colors_list = AHashSet::with_capacity(province_data_map.len());
-  let mut replacement_map = AHashMap::with_capacity(province_data_map.len());
-
-  let mut new_province_data_map = AHashMap::with_capacity(province_data_map.len());
-  for (previous_color, province_data) in province_data_map.drain() {
+/// Gives every province in the table that is not locked a new random color
+pub(super) fn recolor_everything(provinces: &mut ProvinceTable) {
+  // The new colors must not clash with the colors that locked provinces keep
+  let mut colors_list = provinces.iter()
+    .filter(|(_, province_data)| province_data.locked)
+    .map(|(_, province_data)| province_data.color)
+    .collect::<AHashSet<Color>>();
+  for (_, province_data) in provinces.iter_mut().filter(|(_, province_data)| !province_data.locked) {
     let color = random_color_pure(&colors_list, province_data.kind);
     let opt = colors_list.insert(color);
     debug_assert!(opt);
-    let opt = replacement_map.insert(previous_color, color);
-    debug_assert_eq!(opt, None);
-    let opt = new_province_data_map.insert(color, province_data);
-    debug_assert_eq!(opt, None);
+    province_data.color = color;
   };
 
-  *province_data_map = new_province_data_map;
-
-  let mut new_connection_data_map = AHashMap::with_capacity(connection_data_map.len());
-  for (previous_rel, mut connection_data) in connection_data_map.drain() {
-    // Replace `through`'s color with the new one
-    let connection_data_mut = Arc::make_mut(&mut connection_data);
-    connection_data_mut.through = connection_data_mut.through
-      .and_then(|t| replacement_map.get(&t).copied());
-    if let Some(rel) = previous_rel.try_map_opt(|color| replacement_map.get(&color).copied()) {
-      new_connection_data_map.insert(rel, connection_data);
-    };
-  };
-
-  *connection_data_map = new_connection_data_map;
-
-  for Rgb(pixel) in color_buffer.pixels_mut() {
-    *pixel = replacement_map[pixel];
-  };
+  provinces.reindex_colors();
 }
 
 #[derive(Debug, Clone)]
-enum IdChange {
+pub(super) enum IdChange {
   DeletedRange(u32, u32),
   CreatedRange(u32, u32),
   Reassigned(u32, u32),
-  AssignedNew(u32)
+  AssignedNew(u32),
+  AssignedSplit(u32, u32),
+  Merged(u32, u32)
 }
 
 impl ToString for IdChange {
@@ -185,40 +247,104 @@ impl ToString for IdChange {
       IdChange::DeletedRange(start, end) => format!("Deleted IDs {} through {}", start, end),
       IdChange::CreatedRange(start, end) => format!("Created IDs {} through {}", start, end),
       IdChange::Reassigned(from, to) => format!("Reassigned ID {} to {}", from, to),
-      IdChange::AssignedNew(id) => format!("Assigned ID {} to new province", id)
+      IdChange::AssignedNew(id) => format!("Assigned ID {} to new province", id),
+      IdChange::AssignedSplit(id, from) => format!("Assigned ID {} to new province split from {}", id, from),
+      IdChange::Merged(from, into) => format!("Merged ID {} into {}", from, into)
     }
   }
 }
 
-type MapData = (Vec<Definition>, Vec<Adjacency>, Option<Vec<IdChange>>);
+pub(super) type MapData = (Vec<Definition>, Vec<Adjacency>, Vec<AdjacencyRule>, Option<Vec<IdChange>>);
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct SaveOperation {
   pub had_id_changes: bool
 }
 
-pub fn save_bundle(location: &Location, bundle: &Bundle) -> Result<SaveOperation, Error> {
-  let (definition_table, adjacencies_table, id_changes) = deconstruct_map_data(bundle)?;
-  location.clone().manipulate_files(|files| {
-    write_rgb_bmp_image(files.create_file("provinces.bmp")?, &bundle.map.base.color_buffer)?;
-    write_definition_table(files.create_file("definition.csv")?, definition_table)?;
+/// All of the data needed to write a map to disk, detached from the bundle it was taken from
+/// so that it may be written from a worker thread
+#[derive(Debug)]
+pub struct SaveJob {
+  index_buffer: Arc<IndexBuffer>,
+  palette: Vec<Color>,
+  definition_table: Vec<Definition>,
+  definition_comments: Vec<String>,
+  adjacencies_table: Vec<Adjacency>,
+  /// The text to write to `adjacency_rules.txt`
+  adjacency_rules: String,
+  locked_ids: Vec<u32>,
+  id_changes: Option<Vec<IdChange>>
+}
+
+impl SaveJob {
+  pub fn write(self, location: &Location, progress: &Progress) -> Result<SaveOperation, Error> {
+    let SaveJob { index_buffer, palette, definition_table, definition_comments, adjacencies_table, adjacency_rules, locked_ids, id_changes } = self;
+    location.clone().manipulate_files(|files| {
+      progress.set_stage("Encoding provinces.bmp");
+      let color_buffer = RgbImage::from_par_fn(index_buffer.width(), index_buffer.height(), |x, y| {
+        Rgb(palette[index_buffer.get([x, y]).index()])
+      });
+
+      write_rgb_bmp_image(files.create_file("provinces.bmp")?, &color_buffer)?;
+      progress.set_stage("Writing definition.csv");
+      write_definition_table(files.create_file("definition.csv")?, definition_table, &definition_comments)?;
+
+      if !adjacencies_table.is_empty() {
+        progress.set_stage("Writing adjacencies.csv");
+        write_adjacencies_table(files.create_file("adjacencies.csv")?, adjacencies_table)?;
+      };
 
-    if !adjacencies_table.is_empty() {
-      write_adjacencies_table(files.create_file("adjacencies.csv")?, adjacencies_table)?;
-    };
+      if !adjacency_rules.is_empty() || files.open_file_maybe_not_found(ADJACENCY_RULES_FILE)?.is_some() {
+        progress.set_stage("Writing adjacency_rules.txt");
+        write_adjacency_rules(files.create_file(ADJACENCY_RULES_FILE)?, &adjacency_rules)?;
+      };
 
-    let had_id_changes = id_changes.is_some();
-    if let Some(id_changes) = id_changes {
-      write_id_changes(files.create_file("id_changes.txt")?, id_changes)?;
-    };
+      // An existing file is overwritten even when nothing is locked anymore, so that old locks are not loaded again
+      if !locked_ids.is_empty() || files.open_file_maybe_not_found(LOCKED_PROVINCES_FILE)?.is_some() {
+        write_locked_ids(files.create_file(LOCKED_PROVINCES_FILE)?, &locked_ids)?;
+      };
 
-    Ok(SaveOperation {
-      had_id_changes
+      let had_id_changes = id_changes.is_some();
+      if let Some(id_changes) = id_changes {
+        write_id_changes(files.create_file("id_changes.txt")?, id_changes)?;
+      };
+
+      progress.set_stage("Writing files");
+      Ok(SaveOperation {
+        had_id_changes
+      })
     })
+  }
+}
+
+pub(super) fn prepare_save_bundle(bundle: &Bundle) -> Result<SaveJob, Error> {
+  let (definition_table, adjacencies_table, adjacency_rules, id_changes) = deconstruct_map_data(bundle)?;
+  let locked_ids = definition_table.iter()
+    .filter(|definition| bundle.map.get_province(definition.rgb).locked)
+    .map(|definition| definition.id)
+    .collect();
+  // The file is written back as it was loaded when none of its rules have changed,
+  // otherwise its rules are rewritten around its comments and anything else in it
+  let adjacency_rules = match &bundle.map.preserved_adjacency_rules {
+    Some(text) if AdjacencyRule::read_rules(text).is_ok_and(|rules| rules == adjacency_rules) => text.clone(),
+    Some(text) => AdjacencyRule::rewrite_rules(text, &adjacency_rules)
+      .map_err(|err| Error::Script(err, ADJACENCY_RULES_FILE))?,
+    None => AdjacencyRule::write_rules(&adjacency_rules)
+  };
+
+  Ok(SaveJob {
+    index_buffer: Arc::clone(&bundle.map.base.index_buffer),
+    palette: bundle.map.base.provinces.palette(|province_data| province_data.color),
+    definition_table,
+    definition_comments: bundle.map.preserved_definition_comments.clone(),
+    adjacencies_table,
+    adjacency_rules,
+    locked_ids,
+    id_changes
   })
 }
 
-fn deconstruct_map_data(bundle: &Bundle) -> Result<MapData, Error> {
+pub(super) fn deconstruct_map_data(bundle: &Bundle) -> Result<MapData, Error> {
   if bundle.config.preserve_ids {
     deconstruct_map_data_preserve_ids(bundle)
   } else {
@@ -233,17 +359,17 @@ fn deconstruct_map_data_preserve_ids(bundle: &Bundle) -> Result<MapData, Error>
   let count = bundle.map.provinces_count();
   let mut outlier_definitions = Vec::new();
   let mut sparse_definitions_table = vec![None; count];
-  for (&color, province_data) in bundle.map.base.province_data_map.iter() {
+  for (_, province_data) in bundle.map.base.provinces.iter() {
     if let Some(preserved_id) = province_data.preserved_id {
-      let definition = province_data.to_definition(color)?;
+      let definition = province_data.to_definition()?;
       let index = (preserved_id - 1) as usize;
       if index < sparse_definitions_table.len() {
         sparse_definitions_table[index] = Some(definition);
       } else {
-        outlier_definitions.push(definition);
+        outlier_definitions.push((definition, None));
       };
     } else {
-      outlier_definitions.push(province_data.to_definition_with_id(color, 0)?);
+      outlier_definitions.push((province_data.to_definition_with_id(0)?, province_data.split_from));
     };
   };
 
@@ -251,14 +377,16 @@ fn deconstruct_map_data_preserve_ids(bundle: &Bundle) -> Result<MapData, Error>
 
   let mut changes = Vec::new();
   // Loop through all of the 'outlier' definitions
-  for mut outlier_definition in outlier_definitions.into_iter().rev() {
+  for (mut outlier_definition, split_from) in outlier_definitions.into_iter().rev() {
     // Loop through the sparse definitions table until you find an empty spot
     for (id, slot) in sparse_definitions_table.iter_mut().enumerate().rev() {
       let id = id as u32 + 1;
       if slot.is_none() {
         // Insert the current definition into the sparse definitions table
         if outlier_definition.id != id {
-          if outlier_definition.id == 0 {
+          if let (0, Some(split_from)) = (outlier_definition.id, split_from) {
+            changes.push(IdChange::AssignedSplit(id, split_from));
+          } else if outlier_definition.id == 0 {
             changes.push(IdChange::AssignedNew(id));
           } else {
             changes.push(IdChange::Reassigned(outlier_definition.id, id));
@@ -286,26 +414,37 @@ fn deconstruct_map_data_preserve_ids(bundle: &Bundle) -> Result<MapData, Error>
     definitions_table.push(definition);
   };
 
-  let mut adjacencies_table = Vec::with_capacity(bundle.map.connections_count());
-  for (&rel, connection_data) in bundle.map.base.connection_data_map.iter() {
-    let rel = rel.map(|color| color_index[&color]);
-    adjacencies_table.push(connection_data.to_adjacency(rel, |t| color_index[&t]));
+  if bundle.config.stable_output {
+    sort_definitions_by_row(bundle, &mut definitions_table);
   };
 
-  adjacencies_table.extend_from_slice(&bundle.map.preserved_unsupported_adjacencies);
-  adjacencies_table.sort();
+  let mut merges = bundle.map.base.provinces.iter()
+    .flat_map(|(_, province_data)| {
+      let into = color_index[&province_data.color];
+      province_data.merged_ids.iter().map(move |&from| (from, into))
+    })
+    .collect::<Vec<(u32, u32)>>();
+  merges.sort();
+  changes.splice(0..0, merges.into_iter().map(|(from, into)| IdChange::Merged(from, into)));
+
+  let id_index = |id| color_index[&bundle.map.get_province_by_id(id).color];
+  let adjacencies_table = deconstruct_adjacencies(bundle, id_index, true);
+  let adjacency_rules = deconstruct_adjacency_rules(bundle, id_index);
 
   let id_changes = if changes.is_empty() { None } else { Some(changes) };
-  Ok((definitions_table, adjacencies_table, id_changes))
+  Ok((definitions_table, adjacencies_table, adjacency_rules, id_changes))
 }
 
 fn deconstruct_map_data_no_preserve_ids(bundle: &Bundle) -> Result<MapData, Error> {
   let mut definitions_table = Vec::with_capacity(bundle.map.provinces_count());
-  for (&color, province_data) in bundle.map.base.province_data_map.iter() {
-    definitions_table.push(province_data.to_definition_with_id(color, 0)?);
+  for (_, province_data) in bundle.map.base.provinces.iter() {
+    definitions_table.push(province_data.to_definition_with_id(0)?);
   };
 
   definitions_table.sort();
+  if bundle.config.stable_output {
+    sort_definitions_by_row(bundle, &mut definitions_table);
+  };
 
   let mut id = 1;
   let mut color_index = AHashMap::with_capacity(definitions_table.len());
@@ -315,15 +454,56 @@ fn deconstruct_map_data_no_preserve_ids(bundle: &Bundle) -> Result<MapData, Erro
     id += 1;
   };
 
-  let mut adjacencies_table = Vec::with_capacity(bundle.map.connections_count());
+  let id_index = |id| color_index[&bundle.map.get_province_by_id(id).color];
+  let adjacencies_table = deconstruct_adjacencies(bundle, id_index, false);
+  let adjacency_rules = deconstruct_adjacency_rules(bundle, id_index);
+
+  Ok((definitions_table, adjacencies_table, adjacency_rules, None))
+}
+
+/// Moves definitions that were loaded from `definition.csv` back into their original order,
+/// new definitions are kept in their current order after them
+fn sort_definitions_by_row(bundle: &Bundle, definitions_table: &mut [Definition]) {
+  definitions_table.sort_by_cached_key(|definition| {
+    let extras = bundle.map.get_province(definition.rgb).extras.as_deref();
+    let row = extras.map(|extras| extras.row);
+    (row.is_none(), row)
+  });
+}
+
+/// Builds the adjacencies table, if `stable-output` is enabled, rows that were loaded from
+/// `adjacencies.csv` keep their original order and new rows are added after them
+fn deconstruct_adjacencies<F>(bundle: &Bundle, id_index: F, include_unsupported: bool) -> Vec<Adjacency>
+where F: Fn(ProvinceId) -> u32 {
+  let mut rows = Vec::with_capacity(bundle.map.connections_count());
   for (&rel, connection_data) in bundle.map.base.connection_data_map.iter() {
-    let rel = rel.map(|color| color_index[&color]);
-    adjacencies_table.push(connection_data.to_adjacency(rel, |t| color_index[&t]));
+    let row = connection_data.source.map(|source| source.row);
+    rows.push((row, connection_data.to_adjacency(rel, &id_index)));
+  };
+
+  if include_unsupported {
+    rows.extend(bundle.map.preserved_unsupported_adjacencies.iter()
+      .map(|(row, adjacency)| (Some(*row), adjacency.clone())));
+  };
+
+  if bundle.config.stable_output {
+    rows.sort_by(|(row1, a1), (row2, a2)| {
+      Ord::cmp(&(row1.is_none(), row1), &(row2.is_none(), row2))
+        .then_with(|| a1.cmp(a2))
+        .then_with(|| [a1.from_id, a1.to_id].cmp(&[a2.from_id, a2.to_id]))
+    });
+  } else {
+    rows.sort_by(|(_, a1), (_, a2)| a1.cmp(a2));
   };
 
-  adjacencies_table.sort();
+  rows.into_iter().map(|(_, adjacency)| adjacency).collect()
+}
 
-  Ok((definitions_table, adjacencies_table, None))
+fn deconstruct_adjacency_rules<F>(bundle: &Bundle, id_index: F) -> Vec<AdjacencyRule>
+where F: Fn(ProvinceId) -> u32 {
+  bundle.map.base.adjacency_rules.iter()
+    .map(|rule| rule.to_adjacency_rule(&id_index))
+    .collect()
 }
 
 fn process_and_clear_rivers_image(img: &RgbImage) -> RgbaImage {
@@ -369,8 +549,8 @@ pub fn read_rgb_bmp_image<R: Read>(reader: R) -> Result<RgbImage, Error> {
   Ok(img.into_rgb8())
 }
 
-fn read_definition_table<R: Read>(reader: R) -> Result<Vec<Definition>, Error> {
-  Definition::read_records(reader).map_err(|err| Error::Csv(err, "definition.csv"))
+fn read_definition_table<R: Read>(reader: R) -> Result<(Vec<Definition>, Vec<String>), Error> {
+  Definition::read_records_commented(reader).map_err(|err| Error::Csv(err, "definition.csv"))
 }
 
 fn read_adjacencies_table<R: Read>(reader: R) -> Result<Vec<Adjacency>, Error> {
@@ -383,14 +563,37 @@ pub fn write_rgb_bmp_image<W: Write>(mut writer: W, province_image: &RgbImage) -
   encoder.encode(province_image.as_raw(), width, height, ColorType::Rgb8).map_err(From::from)
 }
 
-fn write_definition_table<W: Write>(writer: W, definition_table: Vec<Definition>) -> Result<(), Error> {
-  Definition::write_records(&definition_table, writer).map_err(|err| Error::Csv(err, "definition.csv"))
+fn write_definition_table<W: Write>(writer: W, definition_table: Vec<Definition>, comments: &[String]) -> Result<(), Error> {
+  Definition::write_records_commented(&definition_table, comments, writer).map_err(|err| Error::Csv(err, "definition.csv"))
 }
 
 fn write_adjacencies_table<W: Write>(writer: W, adjacencies_table: Vec<Adjacency>) -> Result<(), Error> {
   Adjacency::write_records(&adjacencies_table, writer).map_err(|err| Error::Csv(err, "adjacencies.csv"))
 }
 
+/// Reads the rules from `adjacency_rules.txt`, along with the text they were read from
+fn read_adjacency_rules<R: Read>(mut reader: R) -> Result<(Vec<AdjacencyRule>, String), Error> {
+  let mut buf = Vec::new();
+  reader.read_to_end(&mut buf).context("failed to read adjacency_rules.txt")?;
+  let text = String::from_utf8_lossy(&buf).into_owned();
+  let rules = AdjacencyRule::read_rules(&text)
+    .map_err(|err| Error::Script(err, ADJACENCY_RULES_FILE))?;
+  Ok((rules, text))
+}
+
+fn read_continents<R: Read>(mut reader: R) -> Result<Vec<String>, Error> {
+  let mut buf = Vec::new();
+  reader.read_to_end(&mut buf).context("failed to read continent.txt")?;
+  read_continent_names(&String::from_utf8_lossy(&buf))
+    .map_err(|err| Error::Script(err, CONTINENTS_FILE))
+}
+
+fn write_adjacency_rules<W: Write>(mut writer: W, adjacency_rules: &str) -> Result<(), Error> {
+  writer.write_all(adjacency_rules.as_bytes())
+    .context("failed to write adjacency rules to file")?;
+  Ok(())
+}
+
 fn write_id_changes<W: Write>(mut writer: W, id_changes: Vec<IdChange>) -> Result<(), Error> {
   writeln!(writer, "ID Changes {}", crate::util::now())
     .context("failed to write id changes to file")?;
@@ -402,12 +605,287 @@ fn write_id_changes<W: Write>(mut writer: W, id_changes: Vec<IdChange>) -> Resul
   Ok(())
 }
 
+fn read_locked_ids<R: Read>(mut reader: R) -> Result<Vec<u32>, Error> {
+  let mut buf = String::new();
+  reader.read_to_string(&mut buf).context("failed to read locked provinces")?;
+  buf.lines()
+    .map(|line| line.split('#').next().unwrap_or_default().trim())
+    .filter(|line| !line.is_empty())
+    .map(|line| line.parse::<u32>().map_err(|_| Error::from(format!("invalid province ID in {}: {}", LOCKED_PROVINCES_FILE, line))))
+    .collect()
+}
+
+fn write_locked_ids<W: Write>(mut writer: W, locked_ids: &[u32]) -> Result<(), Error> {
+  writeln!(writer, "# Provinces locked in the editor, by their ID in definition.csv")
+    .context("failed to write locked provinces to file")?;
+  for id in locked_ids {
+    writeln!(writer, "{}", id)
+      .context("failed to write locked provinces to file")?;
+  };
+
+  Ok(())
+}
+
 fn read_all<R: Read>(mut reader: R) -> io::Result<Cursor<Vec<u8>>> {
   let mut buf = Vec::new();
   reader.read_to_end(&mut buf)?;
   Ok(Cursor::new(buf))
 }
 
-fn get_color_index(color_index: &[Option<Color>], id: u32) -> Option<Color> {
-  color_index.get(id as usize).and_then(Clone::clone)
+fn get_id_index(id_index: &[Option<ProvinceId>], id: u32) -> Option<ProvinceId> {
+  id_index.get(id as usize).and_then(Clone::clone)
+}
+
+#[cfg(test)]
+mod tests {
+  use image::{Rgb, RgbImage};
+
+  use super::*;
+  use crate::app::format::{DefinitionKind, RuleRelation, RuleUnit};
+  use crate::app::map::{AdjacencyRuleEdit, History};
+  use crate::app::map::synthetic::SyntheticMap;
+
+  use std::fs::File;
+  use std::path::PathBuf;
+
+  struct TempDir(PathBuf);
+
+  impl TempDir {
+    fn new(name: &str) -> Self {
+      let path = std::env::temp_dir().join(format!("hoi4pe_test_{}_{}", name, std::process::id()));
+      let _ = std::fs::remove_dir_all(&path);
+      std::fs::create_dir_all(&path).unwrap();
+      TempDir(path)
+    }
+
+    fn location(&self) -> Location {
+      Location::Directory(self.0.clone())
+    }
+  }
+
+  impl Drop for TempDir {
+    fn drop(&mut self) {
+      let _ = std::fs::remove_dir_all(&self.0);
+    }
+  }
+
+  #[derive(Debug, Clone, PartialEq)]
+  struct Tables {
+    province_image: RgbImage,
+    definitions: Vec<Definition>,
+    definition_comments: Vec<String>,
+    adjacencies: Vec<Adjacency>
+  }
+
+  fn write_tables(dir: &TempDir, tables: &Tables) {
+    write_rgb_bmp_image(File::create(dir.0.join("provinces.bmp")).unwrap(), &tables.province_image).unwrap();
+    let definition_file = File::create(dir.0.join("definition.csv")).unwrap();
+    write_definition_table(definition_file, tables.definitions.clone(), &tables.definition_comments).unwrap();
+    write_adjacencies_table(File::create(dir.0.join("adjacencies.csv")).unwrap(), tables.adjacencies.clone()).unwrap();
+  }
+
+  fn read_tables(dir: &TempDir) -> Tables {
+    let province_image = read_rgb_bmp_image(File::open(dir.0.join("provinces.bmp")).unwrap()).unwrap();
+    let (definitions, definition_comments) = read_definition_table(File::open(dir.0.join("definition.csv")).unwrap()).unwrap();
+    let adjacencies = read_adjacencies_table(File::open(dir.0.join("adjacencies.csv")).unwrap()).unwrap();
+    Tables { province_image, definitions, definition_comments, adjacencies }
+  }
+
+  /// Writes the tables to disk, then loads and saves them without making any changes
+  fn round_trip(name: &str, tables: &Tables, stable_output: bool) -> Tables {
+    let input = TempDir::new(&format!("{name}_in"));
+    let output = TempDir::new(&format!("{name}_out"));
+    write_tables(&input, tables);
+
+    let config = Config { preserve_ids: true, stable_output, ..Config::default() };
+    let bundle = Bundle::load(&input.location(), config, &Progress::new()).unwrap();
+    let save_operation = bundle.save(&output.location()).unwrap();
+    assert!(!save_operation.had_id_changes);
+    read_tables(&output)
+  }
+
+  fn assert_round_trip(name: &str, tables: Tables) {
+    assert_eq!(round_trip(name, &tables, true), tables);
+
+    // Without stable output, rows may be reordered but must otherwise be unchanged
+    let mut output = round_trip(name, &tables, false);
+    let mut expected = tables;
+    for tables in [&mut output, &mut expected] {
+      tables.definitions.sort_by_key(|d| d.id);
+      tables.adjacencies.sort_by_key(|a| (a.from_id, a.to_id));
+    };
+
+    assert_eq!(output, expected);
+  }
+
+  #[test]
+  fn test_round_trip_synthetic() {
+    for seed in 0..4 {
+      let synthetic_map = SyntheticMap { seed, connections: 24, ..SyntheticMap::new(160, 96, 60) };
+      let (province_image, mut definitions, mut adjacencies) = synthetic_map.generate_tables();
+      // Rows are shuffled and some are flipped, so that neither table is already in canonical order
+      definitions.swap(0, 5);
+      definitions.swap(2, 9);
+      for definition in definitions.iter_mut().step_by(4) {
+        definition.extra = vec![format!("{}", definition.id * 3), String::new()];
+        definition.comments = vec![format!("# province {}", definition.id)];
+      };
+
+      adjacencies.reverse();
+      for adjacency in adjacencies.iter_mut().step_by(3) {
+        std::mem::swap(&mut adjacency.from_id, &mut adjacency.to_id);
+        adjacency.start = Some([1, 2]);
+        adjacency.stop = Some([3, 4]);
+        adjacency.comment = format!("seed {seed}");
+      };
+
+      let definition_comments = vec!["# generated;by a tool".to_owned(), "#".to_owned()];
+      let tables = Tables { province_image, definitions, definition_comments, adjacencies };
+      assert_round_trip(&format!("synthetic_{seed}"), tables);
+    };
+  }
+
+  #[test]
+  fn test_round_trip_samples() {
+    let definitions = Definition::read_records(include_str!("../format/samples/definition.csv").as_bytes()).unwrap();
+    let adjacencies = Adjacency::read_records(include_str!("../format/samples/adjacencies.csv").as_bytes()).unwrap();
+
+    // Give every province in the sample a single pixel
+    let width = 125;
+    let height = (definitions.len() as u32).div_ceil(width);
+    let province_image = RgbImage::from_fn(width, height, |x, y| {
+      let index = ((y * width + x) as usize).min(definitions.len() - 1);
+      Rgb(definitions[index].rgb)
+    });
+
+    let definition_comments = Vec::new();
+    assert_round_trip("samples", Tables { province_image, definitions, definition_comments, adjacencies });
+  }
+
+  #[test]
+  fn test_new_blank() {
+    let config = Config { preserve_ids: true, ..Config::default() };
+    let bundle = Bundle::new_blank(128, 64, DefinitionKind::Land, config).unwrap();
+    assert_eq!(bundle.map.dimensions(), [128, 64]);
+    assert_eq!(bundle.map.provinces_count(), 1);
+    assert_eq!(bundle.map.preserved_id_count, Some(1));
+
+    let (definition_table, _, _, id_changes) = deconstruct_map_data(&bundle).unwrap();
+    // The blank province keeps ID 1, so there are no ID changes to report
+    assert!(id_changes.is_none());
+    assert_eq!(definition_table.len(), 1);
+    let definition = &definition_table[0];
+    assert_eq!((definition.id, definition.kind, definition.terrain.as_str(), definition.continent), (1, DefinitionKind::Land, "plains", 1));
+  }
+
+  #[test]
+  fn test_locked_provinces() {
+    let (province_image, definitions, adjacencies) = SyntheticMap::new(4, 1, 4).generate_tables();
+    let tables = Tables { province_image, definitions: definitions.clone(), definition_comments: Vec::new(), adjacencies };
+    let input = TempDir::new("locked_in");
+    let output = TempDir::new("locked_out");
+    write_tables(&input, &tables);
+    std::fs::write(input.0.join(LOCKED_PROVINCES_FILE), "# locked\n2\n4 # last province\n").unwrap();
+
+    let config = Config { preserve_ids: true, ..Config::default() };
+    let mut bundle = Bundle::load(&input.location(), config, &Progress::new()).unwrap();
+    let locked = definitions.iter()
+      .map(|definition| bundle.map.get_province(definition.rgb).locked)
+      .collect::<Vec<bool>>();
+    assert_eq!(locked, [false, true, false, true]);
+
+    bundle.save(&output.location()).unwrap();
+    let locked_ids = read_locked_ids(File::open(output.0.join(LOCKED_PROVINCES_FILE)).unwrap()).unwrap();
+    assert_eq!(locked_ids, [2, 4]);
+
+    // Unlocking every province must still overwrite the file that was there before
+    for definition in definitions.iter() {
+      bundle.map.get_province_mut(definition.rgb).locked = false;
+    };
+
+    bundle.save(&output.location()).unwrap();
+    let locked_ids = read_locked_ids(File::open(output.0.join(LOCKED_PROVINCES_FILE)).unwrap()).unwrap();
+    assert!(locked_ids.is_empty());
+  }
+
+  #[test]
+  fn test_adjacency_rules() {
+    let (province_image, definitions, adjacencies) = SyntheticMap::new(4, 1, 4).generate_tables();
+    let tables = Tables { province_image, definitions: definitions.clone(), definition_comments: Vec::new(), adjacencies };
+    let input = TempDir::new("rules_in");
+    let output = TempDir::new("rules_out");
+    write_tables(&input, &tables);
+    let rules = "adjacency_rule = {\n\tname = \"STRAIT\"\n\trequired_provinces = { 2 4 }\n\ticon = 4\n\tis_disabled = { tooltip = \"Closed\" }\n}\n";
+    std::fs::write(input.0.join(ADJACENCY_RULES_FILE), rules).unwrap();
+
+    let config = Config { preserve_ids: true, ..Config::default() };
+    let mut bundle = Bundle::load(&input.location(), config, &Progress::new()).unwrap();
+    let rule = bundle.map.get_adjacency_rule("STRAIT").unwrap();
+    assert_eq!(rule.required_provinces.len(), 2);
+    assert_eq!(rule.extra.len(), 1);
+    let extra = rule.extra.clone();
+
+    // Merging a required province moves the rule over to the province it was merged into,
+    // and the province that takes over the last ID keeps its place in the rule
+    bundle.map.merge_province(definitions[1].rgb, definitions[2].rgb);
+    bundle.save(&output.location()).unwrap();
+    let (rules, _) = read_adjacency_rules(File::open(output.0.join(ADJACENCY_RULES_FILE)).unwrap()).unwrap();
+    assert_eq!(rules.len(), 1);
+    assert_eq!(rules[0].required_provinces, [3, 2]);
+    assert_eq!(rules[0].icon, Some(2));
+    assert_eq!(rules[0].extra, extra);
+  }
+
+  #[test]
+  fn test_adjacency_rules_unchanged() {
+    let (province_image, definitions, adjacencies) = SyntheticMap::new(4, 1, 4).generate_tables();
+    let tables = Tables { province_image, definitions, definition_comments: Vec::new(), adjacencies };
+    let input = TempDir::new("rules_unchanged_in");
+    let output = TempDir::new("rules_unchanged_out");
+    write_tables(&input, &tables);
+    let rules = "# Rules for the straits of the test map\n\
+      adjacency_rule = {\n\
+      \tname = \"STRAIT\" # the only rule\n\
+      \trequired_provinces = { 2 4 }\n\
+      }\n\
+      \n\
+      some_other_block = { value = 1 }\n";
+    std::fs::write(input.0.join(ADJACENCY_RULES_FILE), rules).unwrap();
+
+    // Nothing changes the rules, so the file is written back with its comments and its other block
+    let config = Config { preserve_ids: true, ..Config::default() };
+    let bundle = Bundle::load(&input.location(), config, &Progress::new()).unwrap();
+    bundle.save(&output.location()).unwrap();
+    assert_eq!(std::fs::read_to_string(output.0.join(ADJACENCY_RULES_FILE)).unwrap(), rules);
+  }
+
+  #[test]
+  fn test_adjacency_rules_changed() {
+    let (province_image, definitions, adjacencies) = SyntheticMap::new(4, 1, 4).generate_tables();
+    let tables = Tables { province_image, definitions, definition_comments: Vec::new(), adjacencies };
+    let input = TempDir::new("rules_changed_in");
+    let output = TempDir::new("rules_changed_out");
+    write_tables(&input, &tables);
+    let rules = "# Rules for the straits of the test map\n\
+      adjacency_rule = {\n\
+      \tname = \"STRAIT\"\n\
+      \trequired_provinces = { 2 4 }\n\
+      }\n\
+      \n\
+      some_other_block = { value = 1 }\n";
+    std::fs::write(input.0.join(ADJACENCY_RULES_FILE), rules).unwrap();
+
+    // Editing the rule rewrites it in its place, the comment before it and the block after it are kept
+    let config = Config { preserve_ids: true, ..Config::default() };
+    let mut bundle = Bundle::load(&input.location(), config, &Progress::new()).unwrap();
+    let mut history = History::new(8, &bundle.map);
+    assert!(history.edit_adjacency_rule(&mut bundle, "STRAIT", AdjacencyRuleEdit::Permission(RuleRelation::Enemy, RuleUnit::Army)));
+    bundle.save(&output.location()).unwrap();
+    let text = std::fs::read_to_string(output.0.join(ADJACENCY_RULES_FILE)).unwrap();
+    assert!(text.starts_with("# Rules for the straits of the test map\nadjacency_rule = {\n"));
+    assert!(text.ends_with("}\nsome_other_block = {\n\tvalue = 1\n}\n"));
+    let (rules, _) = read_adjacency_rules(text.as_bytes()).unwrap();
+    assert_eq!(rules.len(), 1);
+    assert!(rules[0].permissions(RuleRelation::Enemy).army);
+  }
 }
@@ -0,0 +1,70 @@
+//! Maps laid out by hand for tests, built as if they had been loaded from disk
+use ahash::AHashSet;
+use image::{Rgb, RgbImage};
+
+use super::{Bundle, Color, Map, ProvinceId, ProvinceKind};
+use super::bridge::{construct_map_data, deconstruct_map_data, MapTables};
+use crate::app::format::{Adjacency, Definition, DefinitionKind};
+use crate::config::Config;
+use crate::util::XYIter;
+use crate::util::task::Progress;
+
+use std::ops::Range;
+
+/// Builds a map from its tables with province IDs preserved and stable output,
+/// so that every province keeps the color and ID it is given and saves in a predictable order
+pub fn build_bundle(province_image: RgbImage, definitions: Vec<Definition>, adjacencies: Vec<Adjacency>) -> Bundle {
+  let config = Config { preserve_ids: true, stable_output: true, ..Config::default() };
+  construct_map_data(province_image, MapTables { definitions, adjacencies, ..MapTables::default() }, config, &Progress::new())
+    .expect("test map data was invalid")
+}
+
+/// Builds a map of vertical stripes, each given as the color, kind and continent of its province and the columns it covers.
+/// Each column belongs to the first stripe covering it, so a last stripe covering every column fills the rest.
+/// Stripes of the same color are parts of the same province, which takes its kind and continent from the first of them.
+pub fn generate_stripes(width: u32, height: u32, stripes: &[(Color, DefinitionKind, u16, Range<u32>)], adjacencies: Vec<Adjacency>) -> Bundle {
+  let province_image = RgbImage::from_fn(width, height, |x, _| {
+    let &(color, ..) = stripes.iter().find(|(.., columns)| columns.contains(&x)).expect("column not covered by any stripe");
+    Rgb(color)
+  });
+  let mut seen = AHashSet::new();
+  let definitions = stripes.iter()
+    .filter(|&&(rgb, ..)| seen.insert(rgb))
+    .enumerate()
+    .map(|(i, &(rgb, kind, continent, _))| Definition {
+      id: i as u32 + 1,
+      rgb,
+      kind,
+      coastal: true,
+      terrain: ProvinceKind::from(kind).default_terrain(),
+      continent,
+      extra: Vec::new(),
+      comments: Vec::new()
+    })
+    .collect::<Vec<Definition>>();
+
+  build_bundle(province_image, definitions, adjacencies)
+}
+
+/// The ID changes that saving the map would report, as they would be written to the log
+pub fn id_changes(bundle: &Bundle) -> Vec<String> {
+  let (_, _, _, id_changes) = deconstruct_map_data(bundle).expect("test map could not be saved");
+  id_changes.unwrap_or_default().iter().map(ToString::to_string).collect()
+}
+
+/// Whether every pixel of the province can be reached from every other through the province itself
+pub fn is_connected(map: &Map, id: ProvinceId) -> bool {
+  let extents = map.get_province_extents(id);
+  let start = XYIter::from_extents(extents).find(|&pos| map.get_id_at(pos) == id).unwrap();
+  let mut visited = AHashSet::from_iter([start]);
+  let mut stack = vec![start];
+  while let Some(pos) = stack.pop() {
+    for next in map.iter_pixels_adjacent(pos) {
+      if map.get_id_at(next) == id && visited.insert(next) {
+        stack.push(next);
+      };
+    };
+  };
+
+  visited.len() as u64 == map.get_province_by_id(id).pixel_count
+}
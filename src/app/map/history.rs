@@ -1,9 +1,11 @@
 //! Structures for managing the history state and abstracting changes applied to the map
+use ahash::AHashSet;
 use uord::UOrd2 as UOrd;
 use vecmath::Vector2;
 
-use crate::app::canvas::{ViewMode, BrushMask};
-use crate::app::map::{Bundle, Color, Extents, Map, MapBase, ProvinceKind, ConnectionData, ConnectionKind};
+use crate::app::canvas::{ViewMode, BrushMask, SubdivideTarget};
+use crate::app::format::{RuleRelation, RuleUnit};
+use crate::app::map::{Bundle, Color, Extents, Map, MapBase, ProvinceId, ProvinceKind, ConnectionData, ConnectionKind, AdjacencyRuleData, StraitSuggestion};
 use crate::app::map::bridge::recolor_everything;
 use crate::util::XYIter;
 
@@ -86,12 +88,11 @@ impl History {
 
   pub fn calculate_coastal_provinces(&mut self, bundle: &mut Bundle) -> bool {
     let coastal_provinces = bundle.map.calculate_coastal_provinces();
-    let is_not_pointless = coastal_provinces.iter()
-      .any(|(&which, &coastal)| bundle.map.get_province(which).coastal != coastal);
+    let is_not_pointless = bundle.map.iter_province_data()
+      .any(|(id, province_data)| province_data.coastal != coastal_provinces[id.index()]);
     if is_not_pointless {
-      for (&color, province_data) in Arc::make_mut(&mut bundle.map.base.province_data_map).iter_mut() {
-        let province_data = Arc::make_mut(province_data);
-        province_data.coastal = coastal_provinces[&color];
+      for (id, province_data) in Arc::make_mut(&mut bundle.map.base.provinces).iter_mut() {
+        province_data.coastal = coastal_provinces[id.index()];
       };
 
       self.push_map_state(&bundle.map, StepOrigin::CalculateCoastalProvinces, ViewMode::Coastal);
@@ -103,88 +104,216 @@ impl History {
   }
 
   pub fn calculate_recolor_map(&mut self, bundle: &mut Bundle) {
-    recolor_everything(
-      Arc::make_mut(&mut bundle.map.base.color_buffer),
-      Arc::make_mut(&mut bundle.map.base.province_data_map),
-      Arc::make_mut(&mut bundle.map.base.connection_data_map)
-    );
+    recolor_everything(Arc::make_mut(&mut bundle.map.base.provinces));
 
     self.push_map_state(&bundle.map, StepOrigin::CalculateRecolorMap, ViewMode::Color);
   }
 
   pub fn paint_province_kind(&mut self, bundle: &mut Bundle, pos: Vector2<u32>, kind: impl Into<ProvinceKind>) -> Option<Extents> {
-    let kind = kind.into();
     let which = bundle.map.get_color_at(pos);
-    let province_data = bundle.map.get_province(which);
-    if province_data.kind != kind && kind != ProvinceKind::Unknown {
-      let terrain = kind.default_terrain();
-      let continent = kind.correct_continent_id(province_data.continent);
-      // Because the type changed, a repaint is always necessary
-      let repaint = bundle.random_color_pure(kind);
+    let (extents, _) = set_province_kind(bundle, which, kind.into())?;
+    self.push_map_state(&bundle.map, StepOrigin::PaintProvinceKind, ViewMode::Kind);
+    Some(extents)
+  }
 
-      let province_data = bundle.map.get_province_mut(which);
-      province_data.set_meta(kind, terrain.clone(), continent);
-      let extents = bundle.map.recolor_province(which, repaint);
+  pub fn paint_province_terrain(&mut self, bundle: &mut Bundle, pos: Vector2<u32>, terrain: String) -> Option<Extents> {
+    let which = bundle.map.get_color_at(pos);
+    let (extents, _) = set_province_terrain(bundle, which, &terrain)?;
+    self.push_map_state(&bundle.map, StepOrigin::PaintProvinceTerrain, ViewMode::Terrain);
+    Some(extents)
+  }
 
-      self.push_map_state(&bundle.map, StepOrigin::PaintProvinceKind, ViewMode::Kind);
-      Some(extents)
+  pub fn paint_province_continent(&mut self, bundle: &mut Bundle, pos: Vector2<u32>, continent: u16) -> Option<Extents> {
+    let which = bundle.map.get_color_at(pos);
+    let extents = set_province_continent(bundle, which, continent)?;
+    self.push_map_state(&bundle.map, StepOrigin::PaintProvinceContinent, ViewMode::Continent);
+    Some(extents)
+  }
+
+  /// Sets the type of many provinces at once, provinces that get repainted are replaced in the given set
+  pub fn paint_provinces_kind(&mut self, bundle: &mut Bundle, provinces: &mut AHashSet<Color>, kind: impl Into<ProvinceKind>) -> Option<Extents> {
+    let kind = kind.into();
+    let extents = update_provinces(bundle, provinces, |bundle, which| set_province_kind(bundle, which, kind))?;
+    self.push_map_state(&bundle.map, StepOrigin::PaintProvincesKind, ViewMode::Kind);
+    Some(extents)
+  }
+
+  /// Sets the terrain of many provinces at once, provinces that get repainted are replaced in the given set
+  pub fn paint_provinces_terrain(&mut self, bundle: &mut Bundle, provinces: &mut AHashSet<Color>, terrain: String) -> Option<Extents> {
+    let extents = update_provinces(bundle, provinces, |bundle, which| set_province_terrain(bundle, which, &terrain))?;
+    self.push_map_state(&bundle.map, StepOrigin::PaintProvincesTerrain, ViewMode::Terrain);
+    Some(extents)
+  }
+
+  /// Sets the continent of every one of the given provinces that may have that continent
+  pub fn paint_provinces_continent(&mut self, bundle: &mut Bundle, provinces: &mut AHashSet<Color>, continent: u16) -> Option<Extents> {
+    let extents = update_provinces(bundle, provinces, |bundle, which| {
+      set_province_continent(bundle, which, continent).map(|extents| (extents, which))
+    })?;
+    self.push_map_state(&bundle.map, StepOrigin::PaintProvincesContinent, ViewMode::Continent);
+    Some(extents)
+  }
+
+  /// Sets the continent of each province as a single step, skipping provinces that no longer exist or may not have that continent,
+  /// and returns how many were changed
+  pub fn assign_continents(&mut self, bundle: &mut Bundle, continents: &[(Color, u16)]) -> usize {
+    let changed = continents.iter()
+      .filter(|&&(which, continent)| {
+        bundle.map.get_province_id(which).is_some() && set_province_continent(bundle, which, continent).is_some()
+      })
+      .count();
+    if changed > 0 {
+      self.push_map_state(&bundle.map, StepOrigin::AssignContinents, ViewMode::Continent);
+    };
+
+    changed
+  }
+
+  /// Gives every selected province that is not locked a new random color, the selection is updated with the new colors
+  pub fn recolor_selection(&mut self, bundle: &mut Bundle, selection: &mut AHashSet<Color>) -> Option<Extents> {
+    let extents = update_provinces(bundle, selection, |bundle, which| {
+      if bundle.map.is_locked(which) {
+        return None;
+      };
+
+      let repaint = bundle.random_color_pure(bundle.map.get_province(which).kind);
+      Some((bundle.map.recolor_province(which, repaint), repaint))
+    })?;
+    self.push_map_state(&bundle.map, StepOrigin::RecolorSelection, ViewMode::Color);
+    Some(extents)
+  }
+
+  /// Removes every adjacency that has a selected province at either end
+  pub fn remove_selection_connections(&mut self, bundle: &mut Bundle, selection: &AHashSet<Color>) -> bool {
+    let ids = selection.iter()
+      .filter_map(|&color| bundle.map.get_province_id(color))
+      .collect::<AHashSet<ProvinceId>>();
+    let is_not_pointless = bundle.map.iter_connection_data()
+      .any(|(rel, _)| rel.into_iter().any(|id| ids.contains(&id)));
+    if is_not_pointless {
+      Arc::make_mut(&mut bundle.map.base.connection_data_map)
+        .retain(|rel, _| !rel.into_iter().any(|id| ids.contains(&id)));
+
+      self.push_map_state(&bundle.map, StepOrigin::RemoveSelectionConnections, ViewMode::Adjacencies);
+      true
     } else {
-      None
+      false
     }
   }
 
-  pub fn paint_province_terrain(&mut self, bundle: &mut Bundle, pos: Vector2<u32>, terrain: String) -> Option<Extents> {
+  /// Applies a single edit made from the inspector, returning the extents to redraw and the color of the province afterwards
+  pub fn edit_province(&mut self, bundle: &mut Bundle, which: Color, edit: ProvinceEdit) -> Option<(Extents, Color)> {
+    if bundle.map.get_province_id(which).is_none() || bundle.map.is_locked(which) {
+      return None;
+    };
+
+    let view_mode = edit.view_mode();
+    let (extents, color) = match edit {
+      ProvinceEdit::Color(color) => {
+        // Taking the color of another province would merge the two, that must be done with the merge tool
+        if which == color || bundle.map.get_province_id(color).is_some() {
+          return None;
+        };
+
+        (bundle.map.recolor_province(which, color), color)
+      },
+      ProvinceEdit::Kind(kind) => set_province_kind(bundle, which, kind)?,
+      ProvinceEdit::Terrain(terrain) => set_province_terrain(bundle, which, &terrain)?,
+      ProvinceEdit::Continent(continent) => (set_province_continent(bundle, which, continent)?, which),
+      ProvinceEdit::Coastal(coastal) => {
+        if bundle.map.get_province(which).coastal == Some(coastal) {
+          return None;
+        };
+
+        bundle.map.get_province_mut(which).coastal = Some(coastal);
+        (bundle.map.get_color_extents(which), which)
+      }
+    };
+
+    self.push_map_state(&bundle.map, StepOrigin::EditProvince, view_mode);
+    Some((extents, color))
+  }
+
+  /// Locks or unlocks every one of the given provinces that still exists
+  pub fn set_provinces_locked(&mut self, bundle: &mut Bundle, provinces: &AHashSet<Color>, locked: bool, view_mode: ViewMode) -> Option<Extents> {
+    let mut extents: Option<Extents> = None;
+    for &which in provinces {
+      if bundle.map.get_province_id(which).is_some() && bundle.map.is_locked(which) != locked {
+        let province_extents = bundle.map.get_color_extents(which);
+        extents = Some(extents.map_or(province_extents, |extents| extents.join(province_extents)));
+        bundle.map.get_province_mut(which).locked = locked;
+      };
+    };
+
+    let extents = extents?;
+    self.push_map_state(&bundle.map, StepOrigin::SetProvincesLocked, view_mode);
+    Some(extents)
+  }
+
+  pub fn paint_entire_province(&mut self, bundle: &mut Bundle, pos: Vector2<u32>, fill_color: Color) -> Option<Extents> {
     let which = bundle.map.get_color_at(pos);
-    let province_data = bundle.map.get_province(which);
-    if province_data.terrain != terrain {
-      let kind = bundle.config.terrain_kind(&terrain)
-        .unwrap_or(ProvinceKind::Unknown);
-      let continent = kind.correct_continent_id(province_data.continent);
-      // If the type changed, generate a new color for it
-      let repaint = (province_data.kind != kind)
-        .then(|| bundle.random_color_pure(kind));
-
-      let province_data = bundle.map.get_province_mut(which);
-      province_data.set_meta(kind, terrain.clone(), continent);
-      let extents = if let Some(repaint) = repaint {
-        bundle.map.recolor_province(which, repaint)
+    if which != fill_color && !bundle.map.is_locked(which) && !bundle.map.is_locked(fill_color) {
+      // Filling with the color of another province merges the two provinces
+      let extents = if bundle.map.get_province_id(fill_color).is_some() {
+        bundle.map.merge_province(which, fill_color)
       } else {
-        bundle.map.get_color_extents(which)
+        bundle.map.recolor_province(which, fill_color)
       };
 
-      self.push_map_state(&bundle.map, StepOrigin::PaintProvinceTerrain, ViewMode::Terrain);
+      self.push_map_state(&bundle.map, StepOrigin::PaintEntireProvince, ViewMode::Color);
       Some(extents)
     } else {
       None
     }
   }
 
-  pub fn paint_province_continent(&mut self, bundle: &mut Bundle, pos: Vector2<u32>, continent: u16) -> Option<Extents> {
+  /// Merges the province at the given position into the target province
+  pub fn merge_provinces(&mut self, bundle: &mut Bundle, target: Color, pos: Vector2<u32>) -> Option<Extents> {
     let which = bundle.map.get_color_at(pos);
-    let province_data = bundle.map.get_province(which);
-    let valid_continent = province_data.kind.valid_continent_id(continent);
-    if province_data.continent != continent && valid_continent {
-      let extents = bundle.map.get_color_extents(which);
-
-      let province_data = bundle.map.get_province_mut(which);
-      province_data.continent = continent;
-
-      self.push_map_state(&bundle.map, StepOrigin::PaintProvinceContinent, ViewMode::Continent);
+    let locked = bundle.map.is_locked(which) || bundle.map.is_locked(target);
+    if which != target && bundle.map.get_province_id(target).is_some() && !locked {
+      let extents = bundle.map.merge_province(which, target);
+      self.push_map_state(&bundle.map, StepOrigin::MergeProvinces, ViewMode::Color);
       Some(extents)
     } else {
       None
     }
   }
 
-  pub fn paint_entire_province(&mut self, bundle: &mut Bundle, pos: Vector2<u32>, fill_color: Color) -> Option<Extents> {
+  /// Splits the province crossed the most by the given cut line
+  pub fn split_province(&mut self, bundle: &mut Bundle, line: Vec<Vector2<f64>>) -> Option<Extents> {
+    let cut = pixel_line(&bundle.map, &line);
+    let mut crossed: Vec<(Color, usize)> = Vec::new();
+    for &pos in &cut {
+      let color = bundle.map.get_color_at(pos);
+      match crossed.iter_mut().find(|(c, _)| *c == color) {
+        Some((_, count)) => *count += 1,
+        None => crossed.push((color, 1))
+      };
+    };
+
+    let (which, _) = crossed.into_iter().max_by_key(|&(_, count)| count)?;
+    if bundle.map.is_locked(which) {
+      return None;
+    };
+
+    let extents = bundle.map.split_province(which, &cut)?;
+    self.push_map_state(&bundle.map, StepOrigin::SplitProvince, ViewMode::Color);
+    Some(extents)
+  }
+
+  /// Divides the province at the given position into roughly equal parts
+  pub fn subdivide_province(&mut self, bundle: &mut Bundle, pos: Vector2<u32>, target: SubdivideTarget) -> Option<Extents> {
     let which = bundle.map.get_color_at(pos);
-    if which != fill_color {
-      let extents = bundle.map.recolor_province(which, fill_color);
-      self.push_map_state(&bundle.map, StepOrigin::PaintEntireProvince, ViewMode::Color);
-      Some(extents)
-    } else {
-      None
-    }
+    let province_data = bundle.map.get_province_at(pos);
+    if province_data.locked {
+      return None;
+    };
+
+    let pixel_count = province_data.pixel_count;
+    let extents = bundle.map.subdivide_province(which, target.count(pixel_count))?;
+    self.push_map_state(&bundle.map, StepOrigin::SubdivideProvince, ViewMode::Color);
+    Some(extents)
   }
 
   pub fn paint_pixel_lasso(
@@ -192,7 +321,7 @@ impl History {
     bundle: &mut Bundle,
     lasso: Vec<Vector2<f64>>,
     color: Color,
-    mask: Option<BrushMask>
+    mask: Option<&BrushMask>
   ) -> Option<Extents> {
     let (extents, pixels) = pixel_lasso(&bundle.map, lasso, color, mask);
     if !pixels.is_empty() {
@@ -209,12 +338,13 @@ impl History {
     bundle: &mut Bundle,
     pos: Vector2<u32>,
     color: Color,
-    mask: Option<BrushMask>
+    mask: Option<&BrushMask>
   ) -> Option<Extents> {
     let which = bundle.map.get_color_at(pos);
-    let previous_kind = bundle.map.get_province(which).kind;
-    let masked = mask.map_or(true, |mask| mask.includes(previous_kind));
-    if masked && which != color {
+    let province_data = bundle.map.get_province(which);
+    let masked = mask.map_or(true, |mask| mask.includes(bundle.map.get_id_at(pos), province_data));
+    let locked = province_data.locked || bundle.map.is_locked(color);
+    if masked && !locked && which != color {
       let extents = bundle.map.flood_fill_province(pos, color);
       self.push_map_state(&bundle.map, StepOrigin::PaintPixelBucket, ViewMode::Color);
       Some(extents)
@@ -229,7 +359,7 @@ impl History {
     pos: Vector2<f64>,
     radius: f64,
     color: Color,
-    mask: Option<BrushMask>,
+    mask: Option<&BrushMask>,
     id: u32
   ) -> Option<Extents> {
     let (extents, pixels) = pixel_area(&bundle.map, pos, radius, color, mask);
@@ -249,7 +379,8 @@ impl History {
     color: Color,
     id: u32
   ) -> Option<Extents> {
-    if bundle.map.get_color_at(pos) != color {
+    let locked = bundle.map.get_province_at(pos).locked || bundle.map.is_locked(color);
+    if bundle.map.get_color_at(pos) != color && !locked {
       bundle.map.put_pixel(pos, color);
       let extents = Extents::new_point(pos);
       self.push_map_state(&bundle.map, StepOrigin::PaintPixel(id), ViewMode::Color);
@@ -262,6 +393,7 @@ impl History {
   pub fn add_or_remove_connection(&mut self, bundle: &mut Bundle, rel: UOrd<Color>, kind: ConnectionKind) -> bool {
     use std::collections::hash_map::Entry;
     if rel.is_distinct() {
+      let rel = rel.map(|color| bundle.map.get_province_id(color).expect("province not found with color"));
       match Arc::make_mut(&mut bundle.map.base.connection_data_map).entry(rel) {
         Entry::Vacant(entry) => {
           entry.insert(Arc::new(ConnectionData::new(kind)));
@@ -273,13 +405,178 @@ impl History {
         }
       };
 
-      bundle.map.recalculate_specialness();
       self.push_map_state(&bundle.map, StepOrigin::AddOrRemoveConnection, ViewMode::Adjacencies);
       true
     } else {
       false
     }
   }
+
+  /// Applies a single edit to an existing adjacency, returning false if nothing was changed
+  pub fn edit_connection(&mut self, bundle: &mut Bundle, rel: UOrd<Color>, edit: ConnectionEdit) -> bool {
+    let [a, b] = rel.into_array().map(|color| bundle.map.get_province_id(color));
+    let Some(rel) = Option::zip(a, b).map(|(a, b)| UOrd::new([a, b])) else { return false };
+    if !bundle.map.has_connection(rel) {
+      return false;
+    };
+
+    // The through province must exist, and cannot be either end of the adjacency
+    let through = match edit {
+      ConnectionEdit::Through(Some(color)) => match bundle.map.get_province_id(color) {
+        Some(id) if !rel.contains(&id) => Some(id),
+        _ => return false
+      },
+      _ => None
+    };
+
+    let endpoints = |endpoints: Option<[Vector2<u32>; 2]>| endpoints.map_or([None; 2], |endpoints| endpoints.map(Some));
+    let connection_data = bundle.map.get_connection(rel);
+    let unchanged = match &edit {
+      ConnectionEdit::Through(_) => connection_data.through == through,
+      ConnectionEdit::Endpoints(new) => [connection_data.start, connection_data.stop] == endpoints(*new),
+      ConnectionEdit::RuleName(rule_name) => &connection_data.rule_name == rule_name,
+      ConnectionEdit::Comment(comment) => &connection_data.comment == comment
+    };
+
+    if unchanged {
+      return false;
+    };
+
+    let connection_data_map = Arc::make_mut(&mut bundle.map.base.connection_data_map);
+    let connection_data = Arc::make_mut(connection_data_map.get_mut(&rel).expect("infallible"));
+    match edit {
+      ConnectionEdit::Through(_) => connection_data.through = through,
+      ConnectionEdit::Endpoints(new) => [connection_data.start, connection_data.stop] = endpoints(new),
+      ConnectionEdit::RuleName(rule_name) => connection_data.rule_name = rule_name,
+      ConnectionEdit::Comment(comment) => connection_data.comment = comment
+    };
+
+    self.push_map_state(&bundle.map, StepOrigin::EditConnection, ViewMode::Adjacencies);
+    true
+  }
+
+  /// Adds a strait for each suggestion as a single step, skipping any that no longer make sense, and returns how many were added
+  pub fn add_suggested_straits(&mut self, bundle: &mut Bundle, suggestions: &[StraitSuggestion]) -> usize {
+    let mut added = 0;
+    for suggestion in suggestions {
+      let [a, b] = suggestion.rel.into_array().map(|color| bundle.map.get_province_id(color));
+      let through = bundle.map.get_province_id(suggestion.through);
+      let (Some(a), Some(b), Some(through)) = (a, b, through) else { continue };
+      let rel = UOrd::new([a, b]);
+      if !rel.is_distinct() || rel.contains(&through) || bundle.map.has_connection(rel) {
+        continue;
+      };
+
+      // The endpoints are kept in the same order as the provinces of the adjacency
+      let [start, stop] = if rel.into_array()[0] == a { suggestion.endpoints } else { [suggestion.endpoints[1], suggestion.endpoints[0]] };
+      let connection_data = ConnectionData {
+        through: Some(through),
+        start: Some(start),
+        stop: Some(stop),
+        ..ConnectionData::new(ConnectionKind::Strait)
+      };
+
+      Arc::make_mut(&mut bundle.map.base.connection_data_map).insert(rel, Arc::new(connection_data));
+      added += 1;
+    };
+
+    if added > 0 {
+      self.push_map_state(&bundle.map, StepOrigin::AddSuggestedStraits, ViewMode::Adjacencies);
+    };
+
+    added
+  }
+
+  /// Applies a single edit to the adjacency rule with the given name, returning false if nothing was changed
+  pub fn edit_adjacency_rule(&mut self, bundle: &mut Bundle, name: &str, edit: AdjacencyRuleEdit) -> bool {
+    let index = bundle.map.base.adjacency_rules.iter().position(|rule| rule.name == name);
+    // Provinces must exist before they can be added to a rule
+    let province = match edit {
+      AdjacencyRuleEdit::RequiredProvince(color) | AdjacencyRuleEdit::Icon(Some(color)) => match bundle.map.get_province_id(color) {
+        Some(id) => Some(id),
+        None => return false
+      },
+      _ => None
+    };
+
+    match (index, edit) {
+      (None, AdjacencyRuleEdit::Create) if !name.is_empty() => {
+        Arc::make_mut(&mut bundle.map.base.adjacency_rules).push(AdjacencyRuleData::new(name.to_owned()));
+      },
+      (None, _) | (Some(_), AdjacencyRuleEdit::Create) => return false,
+      (Some(index), AdjacencyRuleEdit::Permission(relation, unit)) => {
+        let rule = &mut Arc::make_mut(&mut bundle.map.base.adjacency_rules)[index];
+        let allowed = rule.permissions[relation as usize].get_mut(unit);
+        *allowed = !*allowed;
+      },
+      (Some(index), AdjacencyRuleEdit::RequiredProvince(_)) => {
+        let id = province.expect("infallible");
+        let rule = &mut Arc::make_mut(&mut bundle.map.base.adjacency_rules)[index];
+        match rule.required_provinces.iter().position(|&required| required == id) {
+          Some(position) => {
+            rule.required_provinces.remove(position);
+          },
+          None => rule.required_provinces.push(id)
+        };
+      },
+      (Some(index), AdjacencyRuleEdit::Icon(_)) => {
+        if bundle.map.base.adjacency_rules[index].icon == province {
+          return false;
+        };
+
+        Arc::make_mut(&mut bundle.map.base.adjacency_rules)[index].icon = province;
+      }
+    };
+
+    self.push_map_state(&bundle.map, StepOrigin::EditAdjacencyRule, ViewMode::Adjacencies);
+    true
+  }
+}
+
+/// A change to a single adjacency rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdjacencyRuleEdit {
+  /// Creates a rule with the given name, if there is not one already
+  Create,
+  /// Toggles whether a kind of unit may pass through for a relation
+  Permission(RuleRelation, RuleUnit),
+  /// Adds a province to the rule's required provinces, or removes it if it was already required
+  RequiredProvince(Color),
+  Icon(Option<Color>)
+}
+
+/// A change to a single field of an adjacency
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEdit {
+  /// The province a strait passes through, which HOI4 requires for `sea` and `land` adjacencies
+  Through(Option<Color>),
+  /// Where the adjacency starts and stops in map pixels, without these it is drawn between the centers of its provinces
+  Endpoints(Option<[Vector2<u32>; 2]>),
+  RuleName(String),
+  Comment(String)
+}
+
+/// A change to a single field of a province
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProvinceEdit {
+  Color(Color),
+  Kind(ProvinceKind),
+  Terrain(String),
+  Continent(u16),
+  Coastal(bool)
+}
+
+impl ProvinceEdit {
+  /// The view mode in which this edit is visible
+  fn view_mode(&self) -> ViewMode {
+    match self {
+      ProvinceEdit::Color(..) => ViewMode::Color,
+      ProvinceEdit::Kind(..) => ViewMode::Kind,
+      ProvinceEdit::Terrain(..) => ViewMode::Terrain,
+      ProvinceEdit::Continent(..) => ViewMode::Continent,
+      ProvinceEdit::Coastal(..) => ViewMode::Coastal
+    }
+  }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -296,7 +593,7 @@ struct Step {
 
 impl Step {
   fn apply(&self, map: &mut Map) -> Commit {
-    map.base = self.map_base.clone();
+    map.replace_base(self.map_base.clone());
     Commit { view_mode: self.view_mode }
   }
 
@@ -319,11 +616,25 @@ enum StepOrigin {
   PaintProvinceTerrain,
   PaintProvinceContinent,
   PaintEntireProvince,
+  MergeProvinces,
+  SplitProvince,
+  SubdivideProvince,
   PaintPixelLasso,
   PaintPixelBucket,
   PaintPixelArea(u32),
   PaintPixel(u32),
-  AddOrRemoveConnection
+  PaintProvincesKind,
+  PaintProvincesTerrain,
+  PaintProvincesContinent,
+  SetProvincesLocked,
+  EditProvince,
+  RecolorSelection,
+  RemoveSelectionConnections,
+  AddOrRemoveConnection,
+  EditConnection,
+  AddSuggestedStraits,
+  EditAdjacencyRule,
+  AssignContinents
 }
 
 impl StepOrigin {
@@ -336,22 +647,107 @@ impl StepOrigin {
   }
 }
 
+/// Changes the type of a single province, returning the extents to redraw and its new color
+fn set_province_kind(bundle: &mut Bundle, which: Color, kind: ProvinceKind) -> Option<(Extents, Color)> {
+  let province_data = bundle.map.get_province(which);
+  if province_data.kind != kind && kind != ProvinceKind::Unknown && !province_data.locked {
+    let terrain = kind.default_terrain();
+    let continent = kind.correct_continent_id(province_data.continent);
+    // Because the type changed, a repaint is always necessary
+    let repaint = bundle.random_color_pure(kind);
+
+    let province_data = bundle.map.get_province_mut(which);
+    province_data.set_meta(kind, terrain, continent);
+    Some((bundle.map.recolor_province(which, repaint), repaint))
+  } else {
+    None
+  }
+}
+
+/// Changes the terrain of a single province, returning the extents to redraw and its new color
+fn set_province_terrain(bundle: &mut Bundle, which: Color, terrain: &str) -> Option<(Extents, Color)> {
+  let province_data = bundle.map.get_province(which);
+  if province_data.terrain != terrain && !province_data.locked {
+    let kind = bundle.config.terrain_kind(terrain)
+      .unwrap_or(ProvinceKind::Unknown);
+    let continent = kind.correct_continent_id(province_data.continent);
+    // If the type changed, generate a new color for it
+    let repaint = (province_data.kind != kind)
+      .then(|| bundle.random_color_pure(kind));
+
+    let province_data = bundle.map.get_province_mut(which);
+    province_data.set_meta(kind, terrain.to_owned(), continent);
+    Some(if let Some(repaint) = repaint {
+      (bundle.map.recolor_province(which, repaint), repaint)
+    } else {
+      (bundle.map.get_color_extents(which), which)
+    })
+  } else {
+    None
+  }
+}
+
+fn set_province_continent(bundle: &mut Bundle, which: Color, continent: u16) -> Option<Extents> {
+  let province_data = bundle.map.get_province(which);
+  let valid_continent = province_data.kind.valid_continent_id(continent);
+  if province_data.continent != continent && valid_continent && !province_data.locked {
+    let extents = bundle.map.get_color_extents(which);
+
+    let province_data = bundle.map.get_province_mut(which);
+    province_data.continent = continent;
+    Some(extents)
+  } else {
+    None
+  }
+}
+
+/// Applies a change to every one of the given provinces that still exists, keeping track of provinces that changed color.
+/// Returns the combined extents of every province that was changed, if any were.
+fn update_provinces<F>(bundle: &mut Bundle, provinces: &mut AHashSet<Color>, mut f: F) -> Option<Extents>
+where F: FnMut(&mut Bundle, Color) -> Option<(Extents, Color)> {
+  let mut extents: Option<Extents> = None;
+  let mut updated = AHashSet::with_capacity(provinces.len());
+  for which in provinces.drain() {
+    if bundle.map.get_province_id(which).is_none() {
+      continue;
+    };
+
+    match f(bundle, which) {
+      Some((province_extents, color)) => {
+        extents = Some(extents.map_or(province_extents, |extents| extents.join(province_extents)));
+        updated.insert(color);
+      },
+      None => {
+        updated.insert(which);
+      }
+    };
+  };
+
+  *provinces = updated;
+  extents
+}
+
 #[allow(deprecated)]
-fn pixel_lasso(map: &Map, lasso: Vec<Vector2<f64>>, color: Color, mask: Option<BrushMask>) -> (Extents, Vec<Vector2<u32>>) {
+fn pixel_lasso(map: &Map, lasso: Vec<Vector2<f64>>, color: Color, mask: Option<&BrushMask>) -> (Extents, Vec<Vector2<u32>>) {
   use geo::{Coordinate, LineString, Polygon};
   use geo::algorithm::contains::Contains;
 
   let mut pixels = Vec::new();
   let mut extents = Extents::from_points(&lasso);
+  if map.is_locked(color) {
+    return (extents, pixels);
+  };
+
   extents.upper[0] = extents.upper[0].min(map.width() - 1);
   extents.upper[1] = extents.upper[1].min(map.height() - 1);
   let lasso = Polygon::new(LineString::from(lasso), Vec::new());
   for [x, y] in XYIter::from_extents(extents) {
     let coord = Coordinate::from([x as f64 + 0.5, y as f64 + 0.5]);
-    let previous_color = map.get_color_at([x, y]);
-    let previous_kind = map.get_province(previous_color).kind;
-    let masked = mask.map_or(true, |mask| mask.includes(previous_kind));
-    if masked && color != previous_color && lasso.contains(&coord) {
+    let previous_id = map.get_id_at([x, y]);
+    let previous_province = map.get_province_by_id(previous_id);
+    let previous_color = previous_province.color;
+    let masked = mask.map_or(true, |mask| mask.includes(previous_id, previous_province));
+    if masked && !previous_province.locked && color != previous_color && lasso.contains(&coord) {
       pixels.push([x, y]);
     };
   };
@@ -359,18 +755,568 @@ fn pixel_lasso(map: &Map, lasso: Vec<Vector2<f64>>, color: Color, mask: Option<B
   (extents, pixels)
 }
 
-fn pixel_area(map: &Map, pos: Vector2<f64>, radius: f64, color: Color, mask: Option<BrushMask>) -> (Extents, Vec<Vector2<u32>>) {
+/// Every pixel along a polyline, connected diagonally so that the line separates 4-connected areas
+fn pixel_line(map: &Map, line: &[Vector2<f64>]) -> Vec<Vector2<u32>> {
+  let [width, height] = map.dimensions();
+  let mut pixels = Vec::new();
+  for pair in line.windows(2) {
+    let [x0, y0] = [pair[0][0].floor() as i64, pair[0][1].floor() as i64];
+    let [x1, y1] = [pair[1][0].floor() as i64, pair[1][1].floor() as i64];
+    let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+    let (sx, sy) = ((x1 - x0).signum(), (y1 - y0).signum());
+    let (mut x, mut y, mut error) = (x0, y0, dx + dy);
+    loop {
+      if (0..width as i64).contains(&x) && (0..height as i64).contains(&y) {
+        pixels.push([x as u32, y as u32]);
+      };
+
+      if x == x1 && y == y1 {
+        break;
+      };
+
+      let e2 = 2 * error;
+      if e2 >= dy {
+        error += dy;
+        x += sx;
+      };
+
+      if e2 <= dx {
+        error += dx;
+        y += sy;
+      };
+    };
+  };
+
+  pixels
+}
+
+fn pixel_area(map: &Map, pos: Vector2<f64>, radius: f64, color: Color, mask: Option<&BrushMask>) -> (Extents, Vec<Vector2<u32>>) {
   let mut pixels = Vec::new();
   let extents = Extents::from_pos_radius(pos, radius, map.dimensions());
+  if map.is_locked(color) {
+    return (extents, pixels);
+  };
+
   for [x, y] in XYIter::from_extents(extents) {
     let distance = f64::hypot(x as f64 + 0.5 - pos[0], y as f64 + 0.5 - pos[1]);
-    let previous_color = map.get_color_at([x, y]);
-    let previous_kind = map.get_province(previous_color).kind;
-    let masked = mask.map_or(true, |mask| mask.includes(previous_kind));
-    if masked && distance < radius && color != previous_color {
+    let previous_id = map.get_id_at([x, y]);
+    let previous_province = map.get_province_by_id(previous_id);
+    let previous_color = previous_province.color;
+    let masked = mask.map_or(true, |mask| mask.includes(previous_id, previous_province));
+    if masked && !previous_province.locked && distance < radius && color != previous_color {
       pixels.push([x, y]);
     };
   };
 
   (extents, pixels)
 }
+
+#[cfg(test)]
+mod tests {
+  use ahash::{AHashMap, AHashSet};
+  use proptest::prelude::*;
+  use uord::UOrd2 as UOrd;
+  use vecmath::Vector2;
+
+  use super::{AdjacencyRuleEdit, ConnectionEdit, History, ProvinceEdit};
+  use crate::app::canvas::{BrushMask, SubdivideTarget, ViewMode};
+  use crate::app::format::{RuleRelation, RuleUnit};
+  use crate::app::map::{Boundaries, Bundle, Color, ConnectionKind, ContinentAssignment, ProvinceId, ProvinceKind};
+  use crate::app::map::synthetic::SyntheticMap;
+
+  const WIDTH: u32 = 96;
+  const HEIGHT: u32 = 72;
+
+  #[derive(Debug, Clone)]
+  enum Paint {
+    New(Color),
+    /// The color of whichever province is at the given position
+    Existing(Vector2<u32>)
+  }
+
+  #[derive(Debug, Clone)]
+  enum Op {
+    PaintArea(Vector2<f64>, f64, Paint, Option<BrushMask>, u32),
+    PaintPixel(Vector2<u32>, Paint, u32),
+    PaintLasso(Vec<Vector2<f64>>, Paint, Option<BrushMask>),
+    PaintBucket(Vector2<u32>, Paint, Option<BrushMask>),
+    PaintEntire(Vector2<u32>, Paint),
+    /// Merges the province at the second position into the one at the first
+    Merge(Vector2<u32>, Vector2<u32>),
+    Split(Vec<Vector2<f64>>),
+    Subdivide(Vector2<u32>, usize),
+    PaintKind(Vector2<u32>, ProvinceKind),
+    PaintTerrain(Vector2<u32>, usize),
+    PaintContinent(Vector2<u32>, u16),
+    ToggleConnection(Vector2<u32>, Vector2<u32>, ConnectionKind),
+    /// Edits the adjacency between the provinces at the first two positions, if there is one
+    EditConnection(Vector2<u32>, Vector2<u32>, EditConnection),
+    /// Adds every strait suggested within the given distance
+    AddSuggestedStraits(f64),
+    /// Assigns continents by land mass, optionally joined by straits and canals
+    AssignContinents(bool, ContinentAssignment),
+    /// Edits the adjacency rule with one of the names in `RULE_NAMES`
+    EditAdjacencyRule(usize, EditRule),
+    /// Applies a bulk operation to the provinces at each of the given positions
+    Selection(Vec<Vector2<u32>>, Bulk),
+    Edit(Vector2<u32>, Edit),
+    RecolorMap,
+    CalculateCoastal,
+    Undo,
+    Redo
+  }
+
+  #[derive(Debug, Clone)]
+  enum Bulk {
+    Kind(ProvinceKind),
+    Terrain(usize),
+    Continent(u16),
+    Recolor,
+    RemoveConnections,
+    Lock(bool)
+  }
+
+  /// Like `ProvinceEdit`, but with terrains and colors resolved when the edit is applied
+  #[derive(Debug, Clone)]
+  enum Edit {
+    Color(Paint),
+    Kind(ProvinceKind),
+    Terrain(usize),
+    Continent(u16),
+    Coastal(bool)
+  }
+
+  /// Like `ConnectionEdit`, but with the through province resolved when the edit is applied
+  #[derive(Debug, Clone)]
+  enum EditConnection {
+    Through(Option<Vector2<u32>>),
+    Endpoints(Option<[Vector2<u32>; 2]>),
+    RuleName(String)
+  }
+
+  const RULE_NAMES: [&str; 2] = ["STRAIT", "CANAL"];
+
+  /// Like `AdjacencyRuleEdit`, but with provinces resolved when the edit is applied
+  #[derive(Debug, Clone)]
+  enum EditRule {
+    Create,
+    Permission(RuleRelation, RuleUnit),
+    RequiredProvince(Vector2<u32>),
+    Icon(Option<Vector2<u32>>)
+  }
+
+  fn edit_rule() -> impl Strategy<Value = EditRule> {
+    prop_oneof![
+      Just(EditRule::Create),
+      (0..4usize, 0..4usize).prop_map(|(relation, unit)| EditRule::Permission(RuleRelation::ALL[relation], RuleUnit::ALL[unit])),
+      pos().prop_map(EditRule::RequiredProvince),
+      proptest::option::of(pos()).prop_map(EditRule::Icon)
+    ]
+  }
+
+  fn edit_connection() -> impl Strategy<Value = EditConnection> {
+    prop_oneof![
+      proptest::option::of(pos()).prop_map(EditConnection::Through),
+      proptest::option::of((pos(), pos())).prop_map(|endpoints| EditConnection::Endpoints(endpoints.map(|(a, b)| [a, b]))),
+      "[a-z_]{0,8}".prop_map(EditConnection::RuleName)
+    ]
+  }
+
+  fn edit() -> impl Strategy<Value = Edit> {
+    prop_oneof![
+      paint().prop_map(Edit::Color),
+      kind().prop_map(Edit::Kind),
+      any::<usize>().prop_map(Edit::Terrain),
+      (0..6u16).prop_map(Edit::Continent),
+      any::<bool>().prop_map(Edit::Coastal)
+    ]
+  }
+
+  fn kind() -> impl Strategy<Value = ProvinceKind> {
+    prop_oneof![Just(ProvinceKind::Land), Just(ProvinceKind::Sea), Just(ProvinceKind::Lake)]
+  }
+
+  fn bulk() -> impl Strategy<Value = Bulk> {
+    prop_oneof![
+      kind().prop_map(Bulk::Kind),
+      any::<usize>().prop_map(Bulk::Terrain),
+      (0..6u16).prop_map(Bulk::Continent),
+      Just(Bulk::Recolor),
+      Just(Bulk::RemoveConnections),
+      any::<bool>().prop_map(Bulk::Lock)
+    ]
+  }
+
+  fn terrain(bundle: &Bundle, terrain: usize) -> String {
+    let mut terrains = bundle.config.terrains.keys().cloned().collect::<Vec<String>>();
+    terrains.sort();
+    terrains[terrain % terrains.len()].clone()
+  }
+
+  fn pos() -> impl Strategy<Value = Vector2<u32>> {
+    (0..WIDTH, 0..HEIGHT).prop_map(|(x, y)| [x, y])
+  }
+
+  fn pos_f64() -> impl Strategy<Value = Vector2<f64>> {
+    (0.0..WIDTH as f64, 0.0..HEIGHT as f64).prop_map(|(x, y)| [x, y])
+  }
+
+  fn paint() -> impl Strategy<Value = Paint> {
+    prop_oneof![
+      any::<Color>().prop_map(Paint::New),
+      pos().prop_map(Paint::Existing)
+    ]
+  }
+
+  fn mask() -> impl Strategy<Value = Option<BrushMask>> {
+    let mask = prop_oneof![
+      Just(BrushMask::LandLakes),
+      Just(BrushMask::Sea),
+      Just(BrushMask::Terrain("plains".to_owned())),
+      (0..6u16).prop_map(BrushMask::Continent),
+      prop::collection::hash_set(0..24usize, 1..4).prop_map(|ids| BrushMask::Provinces(ids.into_iter().map(ProvinceId::from_index).collect()))
+    ];
+
+    prop_oneof![
+      2 => Just(None),
+      3 => mask.clone().prop_map(Some),
+      1 => mask.prop_map(|mask| Some(BrushMask::Inverse(Box::new(mask))))
+    ]
+  }
+
+  fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+      3 => (pos_f64(), 1.0..12.0, paint(), mask(), 0..3u32)
+        .prop_map(|(pos, radius, paint, mask, id)| Op::PaintArea(pos, radius, paint, mask, id)),
+      2 => (pos(), paint(), 0..3u32).prop_map(|(pos, paint, id)| Op::PaintPixel(pos, paint, id)),
+      2 => (prop::collection::vec(pos_f64(), 3..7), paint(), mask())
+        .prop_map(|(lasso, paint, mask)| Op::PaintLasso(lasso, paint, mask)),
+      2 => (pos(), paint(), mask()).prop_map(|(pos, paint, mask)| Op::PaintBucket(pos, paint, mask)),
+      1 => (pos(), paint()).prop_map(|(pos, paint)| Op::PaintEntire(pos, paint)),
+      1 => (pos(), pos()).prop_map(|(target, pos)| Op::Merge(target, pos)),
+      1 => proptest::collection::vec(pos_f64(), 2..5).prop_map(Op::Split),
+      1 => (pos(), 2..6usize).prop_map(|(pos, count)| Op::Subdivide(pos, count)),
+      1 => (pos(), kind()).prop_map(|(pos, kind)| Op::PaintKind(pos, kind)),
+      1 => (pos(), any::<usize>()).prop_map(|(pos, terrain)| Op::PaintTerrain(pos, terrain)),
+      1 => (pos(), 0..6u16).prop_map(|(pos, continent)| Op::PaintContinent(pos, continent)),
+      2 => (pos(), pos(), prop_oneof![Just(ConnectionKind::Strait), Just(ConnectionKind::Canal), Just(ConnectionKind::Impassable)])
+        .prop_map(|(a, b, kind)| Op::ToggleConnection(a, b, kind)),
+      2 => (pos(), pos(), edit_connection()).prop_map(|(a, b, edit)| Op::EditConnection(a, b, edit)),
+      1 => (2.0..24.0f64).prop_map(Op::AddSuggestedStraits),
+      1 => (any::<bool>(), prop_oneof![Just(ContinentAssignment::PerLandmass), Just(ContinentAssignment::Majority)])
+        .prop_map(|(join_straits, assignment)| Op::AssignContinents(join_straits, assignment)),
+      2 => (0..RULE_NAMES.len(), edit_rule()).prop_map(|(name, edit)| Op::EditAdjacencyRule(name, edit)),
+      2 => (prop::collection::vec(pos(), 1..6), bulk()).prop_map(|(selection, bulk)| Op::Selection(selection, bulk)),
+      1 => (pos(), edit()).prop_map(|(pos, edit)| Op::Edit(pos, edit)),
+      1 => Just(Op::RecolorMap),
+      1 => Just(Op::CalculateCoastal),
+      2 => Just(Op::Undo),
+      1 => Just(Op::Redo)
+    ]
+  }
+
+  fn resolve(bundle: &Bundle, paint: &Paint) -> Color {
+    match *paint {
+      Paint::New(color) => color,
+      Paint::Existing(pos) => bundle.map.get_color_at(pos)
+    }
+  }
+
+  fn apply(history: &mut History, bundle: &mut Bundle, op: &Op) {
+    match op {
+      Op::PaintArea(pos, radius, paint, mask, id) => {
+        let color = resolve(bundle, paint);
+        history.paint_pixel_area(bundle, *pos, *radius, color, mask.as_ref(), *id);
+      },
+      Op::PaintPixel(pos, paint, id) => {
+        let color = resolve(bundle, paint);
+        history.paint_pixel(bundle, *pos, color, *id);
+      },
+      Op::PaintLasso(lasso, paint, mask) => {
+        let color = resolve(bundle, paint);
+        history.paint_pixel_lasso(bundle, lasso.clone(), color, mask.as_ref());
+      },
+      Op::PaintBucket(pos, paint, mask) => {
+        let color = resolve(bundle, paint);
+        history.paint_pixel_bucket(bundle, *pos, color, mask.as_ref());
+      },
+      Op::PaintEntire(pos, paint) => {
+        let color = resolve(bundle, paint);
+        history.paint_entire_province(bundle, *pos, color);
+      },
+      Op::Merge(target, pos) => {
+        let target = bundle.map.get_color_at(*target);
+        history.merge_provinces(bundle, target, *pos);
+      },
+      Op::Split(line) => {
+        history.split_province(bundle, line.clone());
+      },
+      Op::Subdivide(pos, count) => {
+        history.subdivide_province(bundle, *pos, SubdivideTarget::Count(*count));
+      },
+      Op::PaintKind(pos, kind) => {
+        history.paint_province_kind(bundle, *pos, *kind);
+      },
+      Op::PaintTerrain(pos, terrain) => {
+        let terrain = self::terrain(bundle, *terrain);
+        history.paint_province_terrain(bundle, *pos, terrain);
+      },
+      Op::PaintContinent(pos, continent) => {
+        history.paint_province_continent(bundle, *pos, *continent);
+      },
+      Op::ToggleConnection(a, b, kind) => {
+        let rel = UOrd::new([bundle.map.get_color_at(*a), bundle.map.get_color_at(*b)]);
+        history.add_or_remove_connection(bundle, rel, *kind);
+      },
+      Op::EditConnection(a, b, edit) => {
+        let rel = UOrd::new([bundle.map.get_color_at(*a), bundle.map.get_color_at(*b)]);
+        let edit = match edit {
+          EditConnection::Through(through) => ConnectionEdit::Through(through.map(|pos| bundle.map.get_color_at(pos))),
+          EditConnection::Endpoints(endpoints) => ConnectionEdit::Endpoints(*endpoints),
+          EditConnection::RuleName(rule_name) => ConnectionEdit::RuleName(rule_name.clone())
+        };
+
+        history.edit_connection(bundle, rel, edit);
+      },
+      Op::AddSuggestedStraits(max_distance) => {
+        let suggestions = bundle.map.suggest_straits(*max_distance);
+        let added = history.add_suggested_straits(bundle, &suggestions);
+        assert_eq!(added, suggestions.len(), "fresh suggestions should all be added");
+        assert!(bundle.map.suggest_straits(*max_distance).is_empty(), "accepted suggestions should not be suggested again");
+      },
+      Op::AssignContinents(join_straits, assignment) => {
+        let continents = bundle.map.assign_continents(*join_straits, *assignment);
+        history.assign_continents(bundle, &continents);
+        // Locked provinces may keep a land mass from getting a continent of its own, but never from agreeing with itself
+        if *assignment == ContinentAssignment::Majority {
+          assert!(bundle.map.assign_continents(*join_straits, *assignment).is_empty(), "assigned continents should not need assigning again");
+        };
+      },
+      Op::EditAdjacencyRule(name, edit) => {
+        let edit = match *edit {
+          EditRule::Create => AdjacencyRuleEdit::Create,
+          EditRule::Permission(relation, unit) => AdjacencyRuleEdit::Permission(relation, unit),
+          EditRule::RequiredProvince(pos) => AdjacencyRuleEdit::RequiredProvince(bundle.map.get_color_at(pos)),
+          EditRule::Icon(pos) => AdjacencyRuleEdit::Icon(pos.map(|pos| bundle.map.get_color_at(pos)))
+        };
+
+        history.edit_adjacency_rule(bundle, RULE_NAMES[*name], edit);
+      },
+      Op::Selection(positions, bulk) => {
+        let mut selection = positions.iter()
+          .map(|&pos| bundle.map.get_color_at(pos))
+          .collect::<AHashSet<Color>>();
+        match bulk {
+          Bulk::Kind(kind) => {
+            history.paint_provinces_kind(bundle, &mut selection, *kind);
+          },
+          Bulk::Terrain(terrain) => {
+            let terrain = self::terrain(bundle, *terrain);
+            history.paint_provinces_terrain(bundle, &mut selection, terrain);
+          },
+          Bulk::Continent(continent) => {
+            history.paint_provinces_continent(bundle, &mut selection, *continent);
+          },
+          Bulk::Recolor => {
+            history.recolor_selection(bundle, &mut selection);
+          },
+          Bulk::RemoveConnections => {
+            history.remove_selection_connections(bundle, &selection);
+          },
+          Bulk::Lock(locked) => {
+            history.set_provinces_locked(bundle, &selection, *locked, ViewMode::Color);
+          }
+        };
+
+        // The selection must keep referring to the same provinces, even after they are repainted
+        for &pos in positions.iter() {
+          assert!(selection.contains(&bundle.map.get_color_at(pos)), "selection lost track of a province");
+        };
+      },
+      Op::Edit(pos, edit) => {
+        let which = bundle.map.get_color_at(*pos);
+        let edit = match edit {
+          Edit::Color(paint) => ProvinceEdit::Color(resolve(bundle, paint)),
+          Edit::Kind(kind) => ProvinceEdit::Kind(*kind),
+          Edit::Terrain(terrain) => ProvinceEdit::Terrain(self::terrain(bundle, *terrain)),
+          Edit::Continent(continent) => ProvinceEdit::Continent(*continent),
+          Edit::Coastal(coastal) => ProvinceEdit::Coastal(*coastal)
+        };
+
+        if let Some((_, color)) = history.edit_province(bundle, which, edit) {
+          assert_eq!(bundle.map.get_color_at(*pos), color, "edited province lost track of its color");
+        };
+      },
+      Op::RecolorMap => history.calculate_recolor_map(bundle),
+      Op::CalculateCoastal => {
+        history.calculate_coastal_provinces(bundle);
+      },
+      Op::Undo => {
+        history.undo(&mut bundle.map);
+      },
+      Op::Redo => {
+        history.redo(&mut bundle.map);
+      }
+    };
+  }
+
+  /// Every border between two provinces, as unit-length segments
+  fn boundary_segments(boundaries: &Boundaries) -> AHashSet<(UOrd<ProvinceId>, [Vector2<u32>; 2])> {
+    let mut out = AHashSet::default();
+    for polyline in boundaries.iter() {
+      for pair in polyline.points.windows(2) {
+        let [lower, upper] = if pair[0] <= pair[1] { [pair[0], pair[1]] } else { [pair[1], pair[0]] };
+        let step = if lower[0] == upper[0] { [0, 1] } else { [1, 0] };
+        for i in 0..(upper[0] - lower[0]).max(upper[1] - lower[1]) {
+          let p = [lower[0] + step[0] * i, lower[1] + step[1] * i];
+          out.insert((polyline.rel, [p, [p[0] + step[0], p[1] + step[1]]]));
+        };
+      };
+    };
+
+    out
+  }
+
+  fn check_invariants(bundle: &mut Bundle) -> Result<(), TestCaseError> {
+    let map = &mut bundle.map;
+
+    let mut pixel_sums: AHashMap<ProvinceId, (u64, Vector2<u64>)> = AHashMap::default();
+    for (x, y, id) in map.base.index_buffer.enumerate() {
+      prop_assert!(map.base.provinces.get(id).is_some(), "pixel belongs to a missing province");
+      let extents = map.get_province_by_id(id).extents;
+      prop_assert!(extents.is_some_and(|extents| extents.contains([x, y])), "pixel is outside of its province's extents");
+      let (count, sum) = pixel_sums.entry(id).or_default();
+      *count += 1;
+      sum[0] += x as u64;
+      sum[1] += y as u64;
+    };
+
+    prop_assert!(map.validate_pixel_counts());
+    prop_assert_eq!(pixel_sums.len(), map.provinces_count(), "province exists without pixels");
+    for (id, province_data) in map.iter_province_data() {
+      prop_assert_eq!(Some(&(province_data.pixel_count, province_data.pixel_sum)), pixel_sums.get(&id));
+      prop_assert_eq!(map.get_province_id(province_data.color), Some(id));
+      prop_assert!(province_data.kind.valid_continent_id(province_data.continent), "invalid continent for kind");
+    };
+
+    for (rel, connection_data) in map.iter_connection_data() {
+      prop_assert!(rel.is_distinct());
+      for id in rel.into_iter().chain(connection_data.through) {
+        prop_assert!(map.base.provinces.get(id).is_some(), "connection refers to a missing province");
+      };
+    };
+
+    for rule in map.iter_adjacency_rules() {
+      let required = rule.required_provinces.iter().copied().collect::<AHashSet<ProvinceId>>();
+      prop_assert_eq!(required.len(), rule.required_provinces.len(), "rule requires a province twice");
+      for id in rule.required_provinces.iter().copied().chain(rule.icon) {
+        prop_assert!(map.base.provinces.get(id).is_some(), "rule refers to a missing province");
+      };
+    };
+
+    map.update_boundaries();
+    let mut recalculated = Boundaries::new(map.dimensions());
+    recalculated.update(&map.base.index_buffer);
+    prop_assert!(boundary_segments(&map.boundaries) == boundary_segments(&recalculated), "boundaries are out of date");
+
+    Ok(())
+  }
+
+  #[test]
+  fn test_selection_single_step() {
+    let synthetic_map = SyntheticMap { seed: 7, ..SyntheticMap::new(WIDTH, HEIGHT, 24) };
+    let mut bundle = synthetic_map.generate();
+    let mut history = History::new(8, &bundle.map);
+    let positions = [[0, 0], [WIDTH - 1, 0], [0, HEIGHT - 1], [WIDTH - 1, HEIGHT - 1]];
+    let kinds_before = positions.map(|pos| bundle.map.get_province_at(pos).kind);
+
+    // Turning every selected province into a lake repaints them all as a single step
+    let mut selection = positions.iter().map(|&pos| bundle.map.get_color_at(pos)).collect::<AHashSet<Color>>();
+    assert!(history.paint_provinces_kind(&mut bundle, &mut selection, ProvinceKind::Lake).is_some());
+    for pos in positions {
+      assert_eq!(bundle.map.get_province_at(pos).kind, ProvinceKind::Lake);
+      assert!(selection.contains(&bundle.map.get_color_at(pos)));
+    };
+
+    history.undo(&mut bundle.map).unwrap();
+    assert_eq!(positions.map(|pos| bundle.map.get_province_at(pos).kind), kinds_before);
+    assert!(history.undo(&mut bundle.map).is_none());
+  }
+
+  #[test]
+  fn test_brush_mask_provinces() {
+    let synthetic_map = SyntheticMap { seed: 3, ..SyntheticMap::new(WIDTH, HEIGHT, 24) };
+    let original = synthetic_map.generate();
+    let which = original.map.get_color_at([WIDTH / 2, HEIGHT / 2]);
+    let paint: Color = [0x01, 0x02, 0x03];
+    let only_which = BrushMask::Provinces(AHashSet::from_iter([original.map.get_province_id(which).unwrap()]));
+    let masks = [(only_which.clone(), true), (BrushMask::Inverse(Box::new(only_which)), false)];
+    for (mask, inside) in masks {
+      // The brush covers the entire map, so only the mask decides what gets painted
+      let mut bundle = synthetic_map.generate();
+      let mut history = History::new(8, &bundle.map);
+      let center = [WIDTH as f64 / 2.0, HEIGHT as f64 / 2.0];
+      history.paint_pixel_area(&mut bundle, center, WIDTH as f64, paint, Some(&mask), 0).unwrap();
+      for (x, y, _) in original.map.base.index_buffer.enumerate() {
+        let was_which = original.map.get_color_at([x, y]) == which;
+        assert_eq!(bundle.map.get_color_at([x, y]) == paint, was_which == inside);
+      };
+    };
+  }
+
+  #[test]
+  fn test_locked_province() {
+    let synthetic_map = SyntheticMap { seed: 5, ..SyntheticMap::new(WIDTH, HEIGHT, 24) };
+    let mut bundle = synthetic_map.generate();
+    let mut history = History::new(8, &bundle.map);
+    let center = [WIDTH / 2, HEIGHT / 2];
+    let which = bundle.map.get_color_at(center);
+    let before = bundle.map.get_province(which).clone();
+    history.set_provinces_locked(&mut bundle, &AHashSet::from_iter([which]), true, ViewMode::Color).unwrap();
+
+    let paint: Color = [0x01, 0x02, 0x03];
+    let center_f64 = [WIDTH as f64 / 2.0, HEIGHT as f64 / 2.0];
+    let everything = vec![[0.0, 0.0], [WIDTH as f64, 0.0], [WIDTH as f64, HEIGHT as f64], [0.0, HEIGHT as f64]];
+    history.paint_pixel_area(&mut bundle, center_f64, WIDTH as f64, paint, None, 0).unwrap();
+    assert!(history.paint_pixel_lasso(&mut bundle, everything.clone(), [0x04, 0x05, 0x06], None).is_some());
+    assert!(history.paint_pixel_bucket(&mut bundle, center, paint, None).is_none());
+    assert!(history.paint_province_kind(&mut bundle, center, ProvinceKind::Lake).is_none());
+    assert!(history.paint_province_continent(&mut bundle, center, 5).is_none());
+    assert!(history.subdivide_province(&mut bundle, center, SubdivideTarget::Count(4)).is_none());
+    // Painting with the color of a locked province would grow it, so that is not allowed either
+    assert!(history.paint_pixel_lasso(&mut bundle, everything, which, None).is_none());
+
+    let province_data = bundle.map.get_province(which);
+    assert_eq!((province_data.pixel_count, province_data.pixel_sum), (before.pixel_count, before.pixel_sum));
+    assert_eq!((province_data.kind, province_data.continent), (before.kind, before.continent));
+    assert_eq!(bundle.map.provinces_count(), 2);
+
+    // Recoloring skips the locked province, whether it is selected or the whole map is recolored
+    let mut selection = AHashSet::from_iter([which]);
+    assert!(history.recolor_selection(&mut bundle, &mut selection).is_none());
+    assert_eq!(selection, AHashSet::from_iter([which]));
+    history.calculate_recolor_map(&mut bundle);
+    assert_eq!(bundle.map.get_color_at(center), which);
+
+    // Once unlocked, the province can be painted over again
+    history.set_provinces_locked(&mut bundle, &AHashSet::from_iter([which]), false, ViewMode::Color).unwrap();
+    history.paint_pixel_bucket(&mut bundle, center, paint, None).unwrap();
+    assert_eq!(bundle.map.get_color_at(center), paint);
+  }
+
+  proptest! {
+    #![proptest_config(ProptestConfig::with_cases(48))]
+
+    #[test]
+    fn test_history_invariants(seed in any::<u64>(), ops in prop::collection::vec(op(), 1..24)) {
+      let synthetic_map = SyntheticMap { seed, connections: 6, ..SyntheticMap::new(WIDTH, HEIGHT, 24) };
+      let mut bundle = synthetic_map.generate();
+      let mut history = History::new(8, &bundle.map);
+      check_invariants(&mut bundle)?;
+      for op in ops.iter() {
+        apply(&mut history, &mut bundle, op);
+        check_invariants(&mut bundle)?;
+      };
+    }
+  }
+}
@@ -0,0 +1,175 @@
+//! Finding land masses, groups of land provinces connected by land, and assigning continents by them
+use ahash::{AHashMap, AHashSet};
+
+use super::{Color, ConnectionKind, Map, ProvinceId, ProvinceKind};
+
+/// How continents are assigned to the land masses of a map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContinentAssignment {
+  /// Every land mass gets a continent of its own, keeping the continent most of it already has where possible
+  PerLandmass,
+  /// Provinces whose continent differs from most of their land mass are moved to the land mass's continent
+  Majority
+}
+
+/// A group of land provinces that are connected to each other
+#[derive(Debug, Clone)]
+struct Landmass {
+  provinces: Vec<ProvinceId>,
+  pixel_count: u64,
+  /// The continent covering the most pixels of this land mass, if any of it has a valid continent
+  majority: Option<u16>
+}
+
+/// Groups land provinces into land masses, through the provinces they border and,
+/// if `join_straits` is set, through straits and canals between them
+fn find_landmasses(map: &Map, join_straits: bool) -> Vec<Landmass> {
+  let is_land = |id: ProvinceId| map.get_province_by_id(id).kind == ProvinceKind::Land;
+  let mut parents = (0..map.base.provinces.slots_len()).collect::<Vec<usize>>();
+  let mut join = |a: ProvinceId, b: ProvinceId| {
+    let [a, b] = [a, b].map(|id| find_root(&mut parents, id.index()));
+    parents[a.max(b)] = a.min(b);
+  };
+
+  for rel in map.calculate_neighbors() {
+    let [a, b] = rel.into_array();
+    if is_land(a) && is_land(b) {
+      join(a, b);
+    };
+  };
+
+  if join_straits {
+    for (rel, connection_data) in map.iter_connection_data() {
+      let [a, b] = rel.into_array();
+      let joins = matches!(connection_data.kind, ConnectionKind::Strait | ConnectionKind::Canal);
+      if joins && is_land(a) && is_land(b) {
+        join(a, b);
+      };
+    };
+  };
+
+  let mut landmasses: AHashMap<usize, Vec<ProvinceId>> = AHashMap::default();
+  for (id, province_data) in map.iter_province_data() {
+    if province_data.kind == ProvinceKind::Land {
+      let root = find_root(&mut parents, id.index());
+      landmasses.entry(root).or_default().push(id);
+    };
+  };
+
+  let mut landmasses = landmasses.into_values()
+    .map(|provinces| {
+      let mut continents: AHashMap<u16, u64> = AHashMap::default();
+      for &id in provinces.iter() {
+        let province_data = map.get_province_by_id(id);
+        if province_data.kind.valid_continent_id(province_data.continent) {
+          *continents.entry(province_data.continent).or_default() += province_data.pixel_count;
+        };
+      };
+
+      // Ties go to the lowest continent, so that the same map always gives the same result
+      let majority = continents.into_iter()
+        .max_by_key(|&(continent, pixels)| (pixels, std::cmp::Reverse(continent)))
+        .map(|(continent, _)| continent);
+      let pixel_count = provinces.iter().map(|&id| map.get_province_by_id(id).pixel_count).sum();
+      Landmass { provinces, pixel_count, majority }
+    })
+    .collect::<Vec<Landmass>>();
+  // Larger land masses pick their continent first, equally large ones go in order of their lowest province
+  landmasses.sort_by_key(|landmass| (std::cmp::Reverse(landmass.pixel_count), landmass.provinces.iter().map(|id| id.index()).min()));
+  landmasses
+}
+
+fn find_root(parents: &mut [usize], mut index: usize) -> usize {
+  while parents[index] != index {
+    parents[index] = parents[parents[index]];
+    index = parents[index];
+  };
+
+  index
+}
+
+/// Works out the continent each land province should have, returning only the provinces that would change.
+/// Locked provinces are left alone.
+pub fn assign_continents(map: &Map, join_straits: bool, assignment: ContinentAssignment) -> Vec<(Color, u16)> {
+  let landmasses = find_landmasses(map, join_straits);
+  let continents = match assignment {
+    ContinentAssignment::Majority => landmasses.iter()
+      .map(|landmass| landmass.majority.unwrap_or(1))
+      .collect::<Vec<u16>>(),
+    ContinentAssignment::PerLandmass => {
+      // Larger land masses pick first, the rest take the lowest continent no other land mass would keep
+      let reserved = landmasses.iter().filter_map(|landmass| landmass.majority).collect::<AHashSet<u16>>();
+      let mut taken = AHashSet::default();
+      let mut next = 1..;
+      landmasses.iter()
+        .map(|landmass| {
+          let continent = match landmass.majority {
+            Some(majority) if !taken.contains(&majority) => majority,
+            _ => next.find(|continent| !reserved.contains(continent) && !taken.contains(continent)).expect("infallible")
+          };
+
+          taken.insert(continent);
+          continent
+        })
+        .collect::<Vec<u16>>()
+    }
+  };
+
+  let mut changes = Vec::new();
+  for (landmass, continent) in landmasses.iter().zip(continents) {
+    for &id in landmass.provinces.iter() {
+      let province_data = map.get_province_by_id(id);
+      if province_data.continent != continent && !province_data.locked {
+        changes.push((province_data.color, continent));
+      };
+    };
+  };
+
+  changes.sort();
+  changes
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::app::format::{Adjacency, AdjacencyKind, DefinitionKind};
+  use crate::app::map::fixtures::generate_stripes;
+
+  #[test]
+  fn test_assign_continents() {
+    const WEST: Color = [0x80, 0x40, 0x40];
+    const EAST: Color = [0x80, 0x60, 0x40];
+    const FAR_EAST: Color = [0x80, 0x80, 0x40];
+    const NARROWS: Color = [0x00, 0x00, 0x80];
+    const OCEAN: Color = [0x00, 0x00, 0x60];
+
+    // A small land mass on continent 2 in the west, and a larger one in the east split between continents 2 and 1
+    let stripes = [
+      (WEST, DefinitionKind::Land, 2, 0..8),
+      (EAST, DefinitionKind::Land, 2, 12..20),
+      (FAR_EAST, DefinitionKind::Land, 1, 20..24),
+      (NARROWS, DefinitionKind::Sea, 0, 8..12),
+      (OCEAN, DefinitionKind::Sea, 0, 0..64)
+    ];
+    let strait = Adjacency {
+      from_id: 1,
+      to_id: 2,
+      kind: AdjacencyKind::Sea,
+      through: Some(4),
+      start: None,
+      stop: None,
+      rule_name: String::new(),
+      comment: String::new()
+    };
+
+    let bundle = generate_stripes(64, 16, &stripes, Vec::new());
+    assert_eq!(assign_continents(&bundle.map, true, ContinentAssignment::Majority), [(FAR_EAST, 2)]);
+    // The east is larger and keeps continent 2, so the west needs a continent of its own
+    assert_eq!(assign_continents(&bundle.map, true, ContinentAssignment::PerLandmass), [(WEST, 1), (FAR_EAST, 2)]);
+
+    // Once the strait joins them, the whole map is a single land mass, unless straits are not allowed to join land masses
+    let bundle = generate_stripes(64, 16, &stripes, vec![strait]);
+    assert_eq!(assign_continents(&bundle.map, true, ContinentAssignment::PerLandmass), [(FAR_EAST, 2)]);
+    assert_eq!(assign_continents(&bundle.map, false, ContinentAssignment::PerLandmass), [(WEST, 1), (FAR_EAST, 2)]);
+  }
+}
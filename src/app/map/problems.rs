@@ -9,10 +9,9 @@ use vecmath::Vector2;
 
 use crate::app::colors;
 use crate::app::canvas::CameraCombo;
-use super::{Bundle, Color, Map, Extents, boundary_to_line};
+use super::{Bundle, Color, Map, Extents, ProvinceId, boundary_to_line};
 use crate::util::{stringify_color, XYIter};
 
-use std::collections::hash_map::Entry;
 use std::fmt;
 
 
@@ -25,7 +24,8 @@ pub enum Problem {
   InvalidWidth,
   InvalidHeight,
   LonePixel(Vector2<u32>),
-  FewSharedBorders(UOrd<Color>, Vec<UOrd<Vector2<u32>>>)
+  FewSharedBorders(UOrd<Color>, Vec<UOrd<Vector2<u32>>>),
+  UnknownAdjacencyRule(UOrd<Color>, Vector2<f64>, String)
 }
 
 impl Problem {
@@ -45,6 +45,9 @@ impl Problem {
         let pos = vecmath::vec2_add(pos, [0.5; 2]);
         draw_dot(pos, ctx, camera_combo, colors::PROBLEM, gl);
       },
+      Problem::UnknownAdjacencyRule(_, pos, _) => {
+        draw_cross(pos, ctx, camera_combo, colors::PROBLEM, gl);
+      },
       Problem::LonePixel(pos) if extras => {
         let pos = [pos[0] as f64 + 0.5, pos[1] as f64 + 0.5];
         draw_dot(pos, ctx, camera_combo, colors::WARNING, gl);
@@ -98,6 +101,10 @@ impl fmt::Display for Problem {
         let [a, b] = boundary.map(|which| stringify_color(which)).into_array();
         write!(f, "Only {} shared borders between provinces {} and {}", borders.len(), a, b)
       },
+      Problem::UnknownAdjacencyRule(rel, _, ref rule_name) => {
+        let [a, b] = rel.map(|which| stringify_color(which)).into_array();
+        write!(f, "Adjacency between provinces {} and {} refers to unknown rule {:?}", a, b, rule_name)
+      },
     }
   }
 }
@@ -133,8 +140,8 @@ pub fn analyze(bundle: &Bundle) -> Vec<Problem> {
   let extras = bundle.config.extra_warnings.enabled;
   let [width, height] = bundle.map.dimensions();
   let mut problems = Vec::new();
-  let mut province_extents = AHashMap::<Color, Extents>::with_capacity(bundle.map.provinces_count());
-  let mut borders: AHashMap<UOrd<Color>, Vec<UOrd<Vector2<u32>>>> = AHashMap::default();
+  let mut province_extents: Vec<Option<Extents>> = vec![None; bundle.map.base.provinces.slots_len()];
+  let mut borders: AHashMap<UOrd<ProvinceId>, Vec<UOrd<Vector2<u32>>>> = AHashMap::default();
 
   for pos in XYIter::new(0..width, 0..height) {
     if pos[1] != height - 1 && is_crossing_at(&bundle.map, pos) {
@@ -142,20 +149,16 @@ pub fn analyze(bundle: &Bundle) -> Vec<Problem> {
       problems.push(Problem::InvalidXCrossing(pos));
     };
 
-    match province_extents.entry(bundle.map.get_color_at(pos)) {
-      Entry::Vacant(entry) => {
-        entry.insert(Extents::new_point(pos));
-      },
-      Entry::Occupied(entry) => {
-        let entry = entry.into_mut();
-        *entry = entry.join_point(pos);
-      }
-    };
+    let extents = &mut province_extents[bundle.map.get_id_at(pos).index()];
+    *extents = Some(match *extents {
+      Some(extents) => extents.join_point(pos),
+      None => Extents::new_point(pos)
+    });
 
     if extras && bundle.config.extra_warnings.lone_pixels {
-      let color = bundle.map.get_color_at(pos);
+      let id = bundle.map.get_id_at(pos);
       let alone = bundle.map.iter_pixels_adjacent(pos)
-        .all(|p| bundle.map.get_color_at(p) != color);
+        .all(|p| bundle.map.get_id_at(p) != id);
       if alone {
         problems.push(Problem::LonePixel(pos));
       };
@@ -164,8 +167,8 @@ pub fn analyze(bundle: &Bundle) -> Vec<Problem> {
     if extras && bundle.config.extra_warnings.few_shared_borders {
       if pos[0] + 1 < width {
         let other = [pos[0] + 1, pos[1]];
-        let a = bundle.map.get_color_at(pos);
-        let b = bundle.map.get_color_at(other);
+        let a = bundle.map.get_id_at(pos);
+        let b = bundle.map.get_id_at(other);
         let borders = borders.entry(UOrd::new([a, b]))
           .or_insert_with(Vec::new);
         borders.push(UOrd::new([pos, other]));
@@ -173,8 +176,8 @@ pub fn analyze(bundle: &Bundle) -> Vec<Problem> {
 
       if pos[1] + 1 < height {
         let other = [pos[0], pos[1] + 1];
-        let a = bundle.map.get_color_at(pos);
-        let b = bundle.map.get_color_at(other);
+        let a = bundle.map.get_id_at(pos);
+        let b = bundle.map.get_id_at(other);
         let borders = borders.entry(UOrd::new([a, b]))
           .or_insert_with(Vec::new);
         borders.push(UOrd::new([pos, other]));
@@ -185,13 +188,14 @@ pub fn analyze(bundle: &Bundle) -> Vec<Problem> {
   if extras && bundle.config.extra_warnings.few_shared_borders {
     for (boundary, borders) in borders {
       if borders.len() <= bundle.config.extra_warnings.few_shared_borders_threshold {
+        let boundary = boundary.map(|id| bundle.map.get_province_by_id(id).color);
         problems.push(Problem::FewSharedBorders(boundary, borders));
       };
     };
   };
 
-  for (color, extents) in province_extents {
-    let province_data = bundle.map.get_province(color);
+  for (id, province_data) in bundle.map.iter_province_data() {
+    let extents = province_extents[id.index()].expect("province has no pixels");
     if province_data.pixel_count <= 8 {
       let center_of_mass = province_data.center_of_mass();
       problems.push(Problem::TooFewPixels(province_data.pixel_count, center_of_mass));
@@ -203,6 +207,15 @@ pub fn analyze(bundle: &Bundle) -> Vec<Problem> {
     };
   };
 
+  for (rel, connection_data) in bundle.map.iter_connection_data() {
+    if !connection_data.rule_name.is_empty() && bundle.map.get_adjacency_rule(&connection_data.rule_name).is_none() {
+      let [a, b] = rel.map(|id| bundle.map.get_province_by_id(id).center_of_mass()).into_array();
+      let pos = vecmath::vec2_add(vecmath::vec2_scale(vecmath::vec2_add(a, b), 0.5), [0.5; 2]);
+      let rel = rel.map(|id| bundle.map.get_province_by_id(id).color);
+      problems.push(Problem::UnknownAdjacencyRule(rel, pos, connection_data.rule_name.clone()));
+    };
+  };
+
   if width % 64 != 0 {
     problems.push(Problem::InvalidWidth);
   };
@@ -217,10 +230,10 @@ pub fn analyze(bundle: &Bundle) -> Vec<Problem> {
 fn is_crossing_at(map: &Map, [x0, y0]: Vector2<u32>) -> bool {
   #![allow(clippy::many_single_char_names)]
   let [x1, y1] = [if x0 + 1 == map.width() { 0 } else { x0 + 1 }, y0 + 1];
-  let a = map.get_color_at([x0, y0]);
-  let b = map.get_color_at([x1, y0]);
-  let c = map.get_color_at([x0, y1]);
-  let d = map.get_color_at([x1, y1]);
+  let a = map.get_id_at([x0, y0]);
+  let b = map.get_id_at([x1, y0]);
+  let c = map.get_id_at([x0, y1]);
+  let d = map.get_id_at([x1, y1]);
   a != b && c != d && b != d && a != c && a != d && b != c
 }
 
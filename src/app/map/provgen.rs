@@ -0,0 +1,221 @@
+//! Generation of provinces from a land image and an optional terrain image, much like the MapGen tool
+use ahash::{AHashMap, AHashSet};
+use image::{Rgb, RgbImage};
+use rand::SeedableRng;
+use rand::rngs::SmallRng;
+
+use super::{Bundle, Color, ProvinceKind, random_color};
+use super::bridge::{construct_map_data, MapTables};
+use crate::app::format::Definition;
+use crate::config::Config;
+use crate::error::Error;
+use crate::util::stringify_color;
+use crate::util::task::Progress;
+
+use std::cmp::Reverse;
+
+/// Generates a new map from a land image in the colors of `ProvinceKind::color_mapgen`, and optionally a terrain image
+/// in the colors of the configured terrains. Every landmass, ocean and lake is divided into provinces of roughly the size
+/// given in the config, and each province takes the terrain that covers most of it in the terrain image.
+pub fn generate_bundle(land_image: &RgbImage, terrain_image: Option<&RgbImage>, config: Config, progress: &Progress) -> Result<Bundle, Error> {
+  let (width, height) = land_image.dimensions();
+  if width == 0 || height == 0 {
+    return Err("the land image must not be empty".into());
+  };
+
+  if terrain_image.is_some_and(|terrain_image| terrain_image.dimensions() != (width, height)) {
+    return Err("the terrain image must be the same size as the land image".into());
+  };
+
+  progress.set_stage("Reading land image");
+  let mut kinds = Vec::with_capacity(width as usize * height as usize);
+  for (x, y, &Rgb(color)) in land_image.enumerate_pixels() {
+    let kind = kind_from_mapgen(color)
+      .ok_or_else(|| format!("unexpected color {} in land image at {}, {}", stringify_color(color), x, y))?;
+    kinds.push(kind);
+  };
+
+  // Find every 4-connected area of a single kind, these become the initial provinces
+  progress.check()?;
+  progress.set_stage("Finding landmasses");
+  let index = |[x, y]: [u32; 2]| (y * width + x) as usize;
+  let mut labels: Vec<Option<usize>> = vec![None; kinds.len()];
+  let mut areas = Vec::new();
+  for y in 0..height {
+    progress.check()?;
+    progress.set_fraction(y as f32 / height as f32);
+    for x in 0..width {
+      if labels[index([x, y])].is_some() {
+        continue;
+      };
+
+      let label = areas.len();
+      let kind = kinds[index([x, y])];
+      let mut stack = vec![[x, y]];
+      labels[index([x, y])] = Some(label);
+      while let Some([x, y]) = stack.pop() {
+        let neighbors = [
+          x.checked_sub(1).map(|x| [x, y]),
+          y.checked_sub(1).map(|y| [x, y]),
+          (x + 1 < width).then(|| [x + 1, y]),
+          (y + 1 < height).then(|| [x, y + 1])
+        ];
+
+        for next in neighbors.into_iter().flatten() {
+          if kinds[index(next)] == kind && labels[index(next)].is_none() {
+            labels[index(next)] = Some(label);
+            stack.push(next);
+          };
+        };
+      };
+
+      areas.push(kind);
+    };
+  };
+
+  let mut rng = SmallRng::seed_from_u64(0);
+  let mut colors_list = AHashSet::with_capacity(areas.len());
+  let definitions = areas.iter().enumerate()
+    .map(|(i, &kind)| {
+      let color = loop {
+        let color: Color = random_color(&mut rng, kind);
+        if color != [0x00; 3] && colors_list.insert(color) {
+          break color;
+        };
+      };
+
+      Definition {
+        id: i as u32 + 1,
+        rgb: color,
+        kind: kind.to_definition_kind().expect("infallible"),
+        coastal: false,
+        terrain: kind.default_terrain(),
+        continent: kind.correct_continent_id(0),
+        extra: Vec::new(),
+        comments: Vec::new()
+      }
+    })
+    .collect::<Vec<Definition>>();
+
+  let province_image = RgbImage::from_fn(width, height, |x, y| {
+    Rgb(definitions[labels[index([x, y])].expect("infallible")].rgb)
+  });
+
+  progress.check()?;
+  let intermediate_config = Config { preserve_ids: true, ..config.clone() };
+  let mut bundle = construct_map_data(province_image, MapTables { definitions, ..MapTables::default() }, intermediate_config, progress)?;
+
+  progress.check()?;
+  progress.set_stage("Dividing provinces");
+  let areas = bundle.map.iter_province_data()
+    .map(|(_, province_data)| (province_data.color, province_data.kind, province_data.pixel_count))
+    .collect::<Vec<(Color, ProvinceKind, u64)>>();
+  for (i, &(color, kind, pixel_count)) in areas.iter().enumerate() {
+    progress.check()?;
+    progress.set_fraction(i as f32 / areas.len() as f32);
+    let size = config.province_generation.province_size(kind).max(1);
+    bundle.map.subdivide_province_with_progress(color, pixel_count.div_ceil(size) as usize, progress)?;
+  };
+
+  progress.check()?;
+  progress.set_stage("Assigning terrain");
+  let terrain_index = config.terrains.iter()
+    .map(|(name, terrain)| (terrain.color, name.as_str()))
+    .collect::<AHashMap<Color, &str>>();
+  let mut terrain_counts = bundle.map.base.provinces.palette(|_| AHashMap::<&str, u64>::default());
+  if let Some(terrain_image) = terrain_image {
+    for (x, y, id) in bundle.map.base.index_buffer.enumerate() {
+      let &Rgb(color) = terrain_image.get_pixel(x, y);
+      if let Some(&terrain) = terrain_index.get(&color) {
+        *terrain_counts[id.index()].entry(terrain).or_default() += 1;
+      };
+    };
+  };
+
+  let coastal_provinces = bundle.map.calculate_coastal_provinces();
+  let definitions = bundle.map.iter_province_data().enumerate()
+    .map(|(i, (id, province_data))| {
+      // Terrains of the wrong kind for the province are ignored, ties go to the terrain that sorts first
+      let terrain = terrain_counts[id.index()].iter()
+        .filter(|&(terrain, _)| config.terrain_kind(terrain) == Some(province_data.kind))
+        .max_by_key(|&(&terrain, &count)| (count, Reverse(terrain)))
+        .map_or_else(|| province_data.kind.default_terrain(), |(terrain, _)| terrain.to_string());
+
+      Definition {
+        id: i as u32 + 1,
+        rgb: province_data.color,
+        kind: province_data.kind.to_definition_kind().expect("infallible"),
+        coastal: coastal_provinces[id.index()].unwrap_or(false),
+        terrain,
+        continent: province_data.continent,
+        extra: Vec::new(),
+        comments: Vec::new()
+      }
+    })
+    .collect::<Vec<Definition>>();
+
+  // The map is built once more so that province IDs start out in order, as if it had been loaded from disk
+  let province_image = bundle.map.gen_color_buffer();
+  construct_map_data(province_image, MapTables { definitions, ..MapTables::default() }, config, progress)
+}
+
+fn kind_from_mapgen(color: Color) -> Option<ProvinceKind> {
+  [ProvinceKind::Land, ProvinceKind::Sea, ProvinceKind::Lake].into_iter()
+    .find(|kind| kind.color_mapgen() == Some(color))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_generate_bundle() {
+    let land = ProvinceKind::Land.color_mapgen().unwrap();
+    let sea = ProvinceKind::Sea.color_mapgen().unwrap();
+    let lake = ProvinceKind::Lake.color_mapgen().unwrap();
+    let forest = [0x59, 0xc7, 0x55];
+
+    // Land on the left with a lake in it, sea on the right, and forest covering most of the land
+    let land_image = RgbImage::from_fn(128, 64, |x, y| match (x, y) {
+      (8..16, 8..16) => Rgb(lake),
+      (0..64, _) => Rgb(land),
+      _ => Rgb(sea)
+    });
+    let terrain_image = RgbImage::from_fn(128, 64, |_, y| {
+      Rgb(if y < 40 { forest } else { [0xff, 0x81, 0x42] })
+    });
+
+    let mut config = Config { preserve_ids: true, ..Config::default() };
+    config.province_generation.land_province_size = 4096;
+    config.province_generation.sea_province_size = 1000;
+    config.province_generation.lake_province_size = 4096;
+    let bundle = generate_bundle(&land_image, Some(&terrain_image), config, &Progress::new()).unwrap();
+    assert!(bundle.map.validate_pixel_counts());
+    assert_eq!(bundle.map.preserved_id_count, Some(bundle.map.provinces_count() as u32));
+
+    // The 4096 pixels of sea are divided into 5 provinces, the land and the lake are small enough to be left alone
+    let count = |kind| bundle.map.iter_province_data().filter(|(_, p)| p.kind == kind).count();
+    assert_eq!((count(ProvinceKind::Land), count(ProvinceKind::Sea), count(ProvinceKind::Lake)), (1, 5, 1));
+
+    for (x, y, _) in bundle.map.base.index_buffer.enumerate() {
+      let province_data = bundle.map.get_province_at([x, y]);
+      assert_eq!(province_data.kind.color_mapgen(), Some(land_image.get_pixel(x, y).0));
+    };
+
+    let land_province = bundle.map.get_province_at([0, 0]);
+    assert_eq!((land_province.terrain.as_str(), land_province.coastal, land_province.continent), ("forest", Some(true), 1));
+    let sea_province = bundle.map.get_province_at([64, 0]);
+    assert_eq!((sea_province.terrain.as_str(), sea_province.coastal, sea_province.continent), ("ocean", Some(true), 0));
+    let lake_province = bundle.map.get_province_at([8, 8]);
+    assert_eq!((lake_province.terrain.as_str(), lake_province.coastal), ("lakes", Some(false)));
+
+    // Dividing a province stops once the task is cancelled, leaving the map as it was
+    let mut bundle = bundle;
+    let color = bundle.map.get_color_at([64, 0]);
+    let progress = Progress::new();
+    progress.cancel();
+    let count = bundle.map.provinces_count();
+    assert!(matches!(bundle.map.subdivide_province_with_progress(color, 4, &progress), Err(Error::Cancelled)));
+    assert_eq!(bundle.map.provinces_count(), count);
+  }
+}
@@ -0,0 +1,220 @@
+//! Compact storage for which province each pixel of the map belongs to
+use ahash::AHashMap;
+use vecmath::Vector2;
+
+use super::{Color, ColorKeyable, ProvinceData};
+
+use std::fmt;
+use std::sync::Arc;
+
+/// A compact handle to a province within a map's `ProvinceTable`.
+///
+/// This is not the same as the ID a province is assigned in `definition.csv`,
+/// handles are reused as provinces are created and destroyed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProvinceId(u32);
+
+impl ProvinceId {
+  #[inline]
+  pub fn index(self) -> usize {
+    self.0 as usize
+  }
+
+  /// The handle of the given slot, which may or may not hold a province
+  #[cfg(test)]
+  pub(super) fn from_index(index: usize) -> Self {
+    ProvinceId(index as u32)
+  }
+}
+
+/// A buffer the size of the map, storing which province each pixel belongs to
+#[derive(Clone, PartialEq, Eq)]
+pub struct IndexBuffer {
+  width: u32,
+  height: u32,
+  data: Vec<ProvinceId>
+}
+
+impl IndexBuffer {
+  pub fn from_fn<F>(width: u32, height: u32, mut f: F) -> Self
+  where F: FnMut(u32, u32) -> ProvinceId {
+    let mut data = Vec::with_capacity(width as usize * height as usize);
+    for y in 0..height {
+      for x in 0..width {
+        data.push(f(x, y));
+      };
+    };
+
+    IndexBuffer { width, height, data }
+  }
+
+  #[inline]
+  pub fn width(&self) -> u32 {
+    self.width
+  }
+
+  #[inline]
+  pub fn height(&self) -> u32 {
+    self.height
+  }
+
+  #[inline]
+  pub fn dimensions(&self) -> Vector2<u32> {
+    [self.width, self.height]
+  }
+
+  #[inline]
+  pub fn get(&self, pos: Vector2<u32>) -> ProvinceId {
+    self.data[self.offset(pos)]
+  }
+
+  /// Sets the province of a single pixel, returning the province it previously belonged to
+  #[inline]
+  pub fn replace(&mut self, pos: Vector2<u32>, id: ProvinceId) -> ProvinceId {
+    let offset = self.offset(pos);
+    std::mem::replace(&mut self.data[offset], id)
+  }
+
+  /// The handles of each pixel in the buffer, row by row
+  pub fn as_slice(&self) -> &[ProvinceId] {
+    &self.data
+  }
+
+  /// A single row of the buffer, limited to the given range of columns
+  pub fn row(&self, y: u32, columns: std::ops::RangeInclusive<u32>) -> &[ProvinceId] {
+    let start = self.offset([*columns.start(), y]);
+    let end = self.offset([*columns.end(), y]);
+    &self.data[start..=end]
+  }
+
+  pub fn enumerate(&self) -> impl Iterator<Item = (u32, u32, ProvinceId)> + '_ {
+    let width = self.width as usize;
+    self.data.iter().enumerate()
+      .map(move |(i, &id)| ((i % width) as u32, (i / width) as u32, id))
+  }
+
+  #[inline]
+  fn offset(&self, [x, y]: Vector2<u32>) -> usize {
+    debug_assert!(x < self.width && y < self.height, "position out of bounds");
+    y as usize * self.width as usize + x as usize
+  }
+}
+
+impl fmt::Debug for IndexBuffer {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("IndexBuffer")
+      .field("width", &self.width)
+      .field("height", &self.height)
+      .finish_non_exhaustive()
+  }
+}
+
+/// Every province in a map, indexed by handle and by color
+#[derive(Debug, Clone, Default)]
+pub struct ProvinceTable {
+  slots: Vec<Option<Arc<ProvinceData>>>,
+  color_index: AHashMap<Color, ProvinceId>,
+  vacant: Vec<ProvinceId>
+}
+
+impl ProvinceTable {
+  /// The number of provinces in the table
+  pub fn len(&self) -> usize {
+    self.color_index.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.color_index.is_empty()
+  }
+
+  /// One more than the highest handle that may be in use, for sizing arrays indexed by handle
+  pub fn slots_len(&self) -> usize {
+    self.slots.len()
+  }
+
+  pub fn get(&self, id: ProvinceId) -> Option<&ProvinceData> {
+    self.slots.get(id.index())?.as_deref()
+  }
+
+  pub fn get_mut(&mut self, id: ProvinceId) -> Option<&mut ProvinceData> {
+    self.slots.get_mut(id.index())?.as_mut().map(Arc::make_mut)
+  }
+
+  pub fn id_of(&self, color: Color) -> Option<ProvinceId> {
+    self.color_index.get(&color).copied()
+  }
+
+  /// Adds a province to the table, the province's color must not already be in use
+  pub fn insert(&mut self, province_data: ProvinceData) -> ProvinceId {
+    let color = province_data.color;
+    let province_data = Some(Arc::new(province_data));
+    let id = match self.vacant.pop() {
+      Some(id) => {
+        self.slots[id.index()] = province_data;
+        id
+      },
+      None => {
+        self.slots.push(province_data);
+        ProvinceId(self.slots.len() as u32 - 1)
+      }
+    };
+
+    let previous = self.color_index.insert(color, id);
+    assert_eq!(previous, None, "a province already exists with this color");
+    id
+  }
+
+  pub fn remove(&mut self, id: ProvinceId) -> Option<Arc<ProvinceData>> {
+    let province_data = self.slots.get_mut(id.index())?.take()?;
+    self.color_index.remove(&province_data.color);
+    self.vacant.push(id);
+    Some(province_data)
+  }
+
+  /// Changes the color of a province, the new color must not already be in use
+  pub fn set_color(&mut self, id: ProvinceId, color: Color) {
+    let province_data = self.get_mut(id).expect("province not found with id");
+    let previous_color = std::mem::replace(&mut province_data.color, color);
+    self.color_index.remove(&previous_color);
+    let previous = self.color_index.insert(color, id);
+    assert_eq!(previous, None, "a province already exists with this color");
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (ProvinceId, &ProvinceData)> {
+    self.slots.iter().enumerate().filter_map(|(i, slot)| {
+      slot.as_deref().map(|province_data| (ProvinceId(i as u32), province_data))
+    })
+  }
+
+  pub fn iter_mut(&mut self) -> impl Iterator<Item = (ProvinceId, &mut ProvinceData)> {
+    self.slots.iter_mut().enumerate().filter_map(|(i, slot)| {
+      slot.as_mut().map(|province_data| (ProvinceId(i as u32), Arc::make_mut(province_data)))
+    })
+  }
+
+  /// Builds an array indexed by handle from the data of every province
+  pub fn palette<T, F>(&self, f: F) -> Vec<T>
+  where T: Default + Clone, F: Fn(&ProvinceData) -> T {
+    let mut palette = vec![T::default(); self.slots.len()];
+    for (id, province_data) in self.iter() {
+      palette[id.index()] = f(province_data);
+    };
+
+    palette
+  }
+
+  /// Rebuilds the color index, for after the colors of provinces have been modified through `iter_mut`
+  pub fn reindex_colors(&mut self) {
+    let color_index = self.iter()
+      .map(|(id, province_data)| (province_data.color, id))
+      .collect::<AHashMap<Color, ProvinceId>>();
+    assert_eq!(color_index.len(), self.len(), "multiple provinces exist with the same color");
+    self.color_index = color_index;
+  }
+}
+
+impl ColorKeyable for ProvinceTable {
+  fn contains_color(&self, color: Color) -> bool {
+    self.color_index.contains_key(&color)
+  }
+}
@@ -0,0 +1,174 @@
+//! Finding land provinces separated only by narrow water, which could be joined by a strait
+use ahash::AHashMap;
+use uord::UOrd2 as UOrd;
+use vecmath::Vector2;
+
+use super::{Color, Map, ProvinceId, ProvinceKind};
+use crate::util::XYIter;
+
+use std::collections::VecDeque;
+
+/// The most sea provinces a suggested strait may cross
+const MAX_CROSSED_SEAS: usize = 2;
+
+/// A strait that could be added between two land provinces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StraitSuggestion {
+  pub rel: UOrd<Color>,
+  /// The sea province the strait mostly crosses
+  pub through: Color,
+  /// The coastal pixels the strait runs between, in the same order as `rel.into_array()`
+  pub endpoints: [Vector2<u32>; 2]
+}
+
+impl StraitSuggestion {
+  pub fn positions(&self) -> [Vector2<f64>; 2] {
+    self.endpoints.map(|[x, y]| [x as f64 + 0.5, y as f64 + 0.5])
+  }
+}
+
+/// Suggests straits between pairs of land provinces whose coasts are no more than `max_distance` pixels apart,
+/// which do not already border each other or have an adjacency, and where the water between them spans one or two sea provinces.
+///
+/// Sea pixels are flooded outwards from every coast at once, each remembering the coastal pixel it was reached from,
+/// and wherever two floods from different land provinces meet, the closest pair of coastal pixels is kept.
+pub fn suggest_straits(map: &Map, max_distance: f64) -> Vec<StraitSuggestion> {
+  const NONE: u32 = u32::MAX;
+
+  let [width, height] = map.dimensions();
+  let offset = |[x, y]: Vector2<u32>| y as usize * width as usize + x as usize;
+  let position = |offset: u32| [offset % width, offset / width];
+  let distance = |a: Vector2<u32>, b: Vector2<u32>| {
+    let [dx, dy] = [a[0] as f64 - b[0] as f64, a[1] as f64 - b[1] as f64];
+    dx.hypot(dy)
+  };
+
+  let kinds = map.base.provinces.palette(|province_data| Some(province_data.kind));
+  let kind_at = |pos: Vector2<u32>| kinds[map.get_id_at(pos).index()];
+
+  // The offset of the coastal land pixel each sea pixel was first reached from
+  let mut origins = vec![NONE; width as usize * height as usize];
+  let mut queue = VecDeque::new();
+  for pos in XYIter::new(0..width, 0..height) {
+    if kind_at(pos) == Some(ProvinceKind::Land) {
+      for adjacent in map.iter_pixels_adjacent(pos) {
+        if kind_at(adjacent) == Some(ProvinceKind::Sea) && origins[offset(adjacent)] == NONE {
+          origins[offset(adjacent)] = offset(pos) as u32;
+          queue.push_back(adjacent);
+        };
+      };
+    };
+  };
+
+  let mut closest: AHashMap<UOrd<ProvinceId>, (f64, [Vector2<u32>; 2])> = AHashMap::default();
+  while let Some(pos) = queue.pop_front() {
+    let origin = position(origins[offset(pos)]);
+    let origin_id = map.get_id_at(origin);
+    for adjacent in map.iter_pixels_adjacent(pos) {
+      let other_origin = match kind_at(adjacent) {
+        Some(ProvinceKind::Land) => adjacent,
+        Some(ProvinceKind::Sea) => match origins[offset(adjacent)] {
+          NONE => {
+            if distance(origin, adjacent) <= max_distance {
+              origins[offset(adjacent)] = offset(origin) as u32;
+              queue.push_back(adjacent);
+            };
+
+            continue;
+          },
+          other_origin => position(other_origin)
+        },
+        _ => continue
+      };
+
+      let other_id = map.get_id_at(other_origin);
+      let gap = distance(origin, other_origin);
+      if other_id != origin_id && gap <= max_distance {
+        let entry = closest.entry(UOrd::new([origin_id, other_id]))
+          .or_insert((f64::INFINITY, [origin, other_origin]));
+        if gap < entry.0 {
+          *entry = (gap, [origin, other_origin]);
+        };
+      };
+    };
+  };
+
+  let neighbors = map.calculate_neighbors();
+  let mut suggestions = closest.into_iter()
+    .filter(|(rel, _)| !neighbors.contains(rel) && !map.has_connection(*rel))
+    .filter_map(|(rel, (distance, [a, b]))| {
+      let through = find_through(map, rel, [a, b])?;
+      let endpoints = if map.get_id_at(a) == rel.into_array()[0] { [a, b] } else { [b, a] };
+      let rel = rel.map(|id| map.get_province_by_id(id).color);
+      Some((distance, StraitSuggestion { rel, through, endpoints }))
+    })
+    .collect::<Vec<_>>();
+  // Closest first, with ties broken so that the same map always gives the same order
+  suggestions.sort_by(|(d1, s1), (d2, s2)| f64::total_cmp(d1, d2).then_with(|| s1.endpoints.cmp(&s2.endpoints)));
+  suggestions.into_iter().map(|(_, suggestion)| suggestion).collect()
+}
+
+/// Walks the straight line between two coastal pixels, returning the sea province it spends the most time in,
+/// or nothing if it crosses too many sea provinces or anything other than sea between its ends
+fn find_through(map: &Map, rel: UOrd<ProvinceId>, [a, b]: [Vector2<u32>; 2]) -> Option<Color> {
+  let [a, b] = [a, b].map(|[x, y]| [x as f64 + 0.5, y as f64 + 0.5]);
+  let steps = (vecmath::vec2_len(vecmath::vec2_sub(b, a)) * 2.0).ceil().max(1.0) as usize;
+  let mut crossed: Vec<(ProvinceId, usize)> = Vec::new();
+  for i in 0..=steps {
+    let t = i as f64 / steps as f64;
+    let [x, y] = [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t];
+    let id = map.get_id_at([x as u32, y as u32]);
+    if rel.contains(&id) {
+      continue;
+    } else if map.get_province_by_id(id).kind != ProvinceKind::Sea {
+      return None;
+    };
+
+    match crossed.iter_mut().find(|(crossed_id, _)| *crossed_id == id) {
+      Some((_, count)) => *count += 1,
+      None => crossed.push((id, 1))
+    };
+  };
+
+  if crossed.len() > MAX_CROSSED_SEAS {
+    return None;
+  };
+
+  crossed.into_iter()
+    .max_by_key(|&(_, count)| count)
+    .map(|(id, _)| map.get_province_by_id(id).color)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::app::format::DefinitionKind;
+  use crate::app::map::fixtures::generate_stripes;
+
+  #[test]
+  fn test_suggest_straits() {
+    const WEST: Color = [0x80, 0x40, 0x40];
+    const EAST: Color = [0x80, 0x60, 0x40];
+    const ISLAND: Color = [0x80, 0x80, 0x40];
+    const NARROWS: Color = [0x00, 0x00, 0x80];
+    const OCEAN: Color = [0x00, 0x00, 0x60];
+
+    // Two coasts 4 pixels apart across a narrow sea, and an island far out in the ocean
+    let bundle = generate_stripes(64, 16, &[
+      (WEST, DefinitionKind::Land, 1, 0..8),
+      (EAST, DefinitionKind::Land, 1, 12..20),
+      (ISLAND, DefinitionKind::Land, 1, 56..64),
+      (NARROWS, DefinitionKind::Sea, 0, 8..12),
+      (OCEAN, DefinitionKind::Sea, 0, 0..64)
+    ], Vec::new());
+
+    let suggestions = suggest_straits(&bundle.map, 8.0);
+    assert_eq!(suggestions.len(), 1);
+    assert_eq!((suggestions[0].rel, suggestions[0].through), (UOrd::new([WEST, EAST]), NARROWS));
+    let [a, b] = suggestions[0].endpoints;
+    assert_eq!([a[0], b[0]], if suggestions[0].rel.into_array()[0] == WEST { [7, 12] } else { [12, 7] });
+
+    assert!(suggest_straits(&bundle.map, 4.0).is_empty());
+    assert_eq!(suggest_straits(&bundle.map, 64.0).iter().filter(|s| s.rel.contains(&ISLAND)).count(), 1);
+  }
+}
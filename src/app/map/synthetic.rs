@@ -0,0 +1,169 @@
+//! Generation of randomized maps, for benchmarking and testing
+use ahash::AHashSet;
+use image::{Rgb, RgbImage};
+use rand::{Rng, SeedableRng};
+use rand::rngs::SmallRng;
+
+use super::{Bundle, Color, ProvinceKind, random_color};
+use super::bridge::{construct_map_data, MapTables};
+use crate::app::format::{Adjacency, AdjacencyKind, Definition, DefinitionKind};
+use crate::config::Config;
+use crate::util::task::Progress;
+
+const LAND_TERRAINS: &[&str] = &["plains", "forest", "hills", "mountain", "desert", "jungle", "marsh", "urban"];
+
+/// Describes a randomly generated map. The same description will always generate the same map.
+///
+/// Provinces are the cells of a jittered grid, so the actual number of provinces
+/// will be close to, but not necessarily exactly, the number requested.
+#[derive(Debug, Clone)]
+pub struct SyntheticMap {
+  pub width: u32,
+  pub height: u32,
+  pub provinces: u32,
+  /// The fraction of provinces that should be land, the rest will be sea
+  pub land_fraction: f64,
+  pub continents: u16,
+  /// The number of impassable adjacencies to create between neighboring provinces
+  pub connections: usize,
+  pub seed: u64
+}
+
+impl SyntheticMap {
+  pub fn new(width: u32, height: u32, provinces: u32) -> Self {
+    SyntheticMap { width, height, provinces, ..SyntheticMap::default() }
+  }
+
+  /// Generates the contents of `provinces.bmp`, `definition.csv` and `adjacencies.csv`
+  pub fn generate_tables(&self) -> (RgbImage, Vec<Definition>, Vec<Adjacency>) {
+    let mut rng = SmallRng::seed_from_u64(self.seed);
+    let area = self.width as f64 * self.height as f64;
+    let cell_size = (area / self.provinces.max(1) as f64).sqrt().max(1.0);
+    let columns = (self.width as f64 / cell_size).ceil() as u32;
+    let rows = (self.height as f64 / cell_size).ceil() as u32;
+
+    // Place one seed point within each cell of the grid
+    let mut seeds = Vec::with_capacity((columns * rows) as usize);
+    for row in 0..rows {
+      for column in 0..columns {
+        let x0 = (column as f64 * cell_size) as u32;
+        let y0 = (row as f64 * cell_size) as u32;
+        let x1 = (((column + 1) as f64 * cell_size) as u32).clamp(x0 + 1, self.width);
+        let y1 = (((row + 1) as f64 * cell_size) as u32).clamp(y0 + 1, self.height);
+        seeds.push([rng.gen_range(x0..x1), rng.gen_range(y0..y1)]);
+      };
+    };
+
+    // Provinces are made land or sea based on a smooth function of their position,
+    // so that land forms a few large masses
+    let phase: [f64; 2] = [rng.gen_range(0.0..std::f64::consts::TAU), rng.gen_range(0.0..std::f64::consts::TAU)];
+    let mut elevations = seeds.iter().enumerate()
+      .map(|(i, &[x, y])| {
+        let u = x as f64 / self.width as f64 * std::f64::consts::TAU * 2.0 + phase[0];
+        let v = y as f64 / self.height as f64 * std::f64::consts::TAU * 1.5 + phase[1];
+        (i, u.sin() * v.cos() + rng.gen_range(-0.25..0.25))
+      })
+      .collect::<Vec<(usize, f64)>>();
+    elevations.sort_by(|a, b| f64::total_cmp(&b.1, &a.1));
+    let land_count = (seeds.len() as f64 * self.land_fraction).round() as usize;
+    let mut kinds = vec![DefinitionKind::Sea; seeds.len()];
+    for &(i, _) in &elevations[..land_count.min(seeds.len())] {
+      kinds[i] = DefinitionKind::Land;
+    };
+
+    let mut colors_list = AHashSet::with_capacity(seeds.len());
+    let mut definitions = Vec::with_capacity(seeds.len());
+    for (i, (&[x, _], &kind)) in seeds.iter().zip(kinds.iter()).enumerate() {
+      let color = loop {
+        let color: Color = random_color(&mut rng, ProvinceKind::from(kind));
+        if color != [0x00; 3] && colors_list.insert(color) {
+          break color;
+        };
+      };
+
+      let (terrain, continent) = match kind {
+        DefinitionKind::Land => {
+          let terrain = LAND_TERRAINS[rng.gen_range(0..LAND_TERRAINS.len())];
+          let continent = 1 + (x as u64 * self.continents.max(1) as u64 / self.width as u64) as u16;
+          (terrain, continent)
+        },
+        _ => ("ocean", 0)
+      };
+
+      definitions.push(Definition {
+        id: i as u32 + 1,
+        rgb: color,
+        kind,
+        coastal: false,
+        terrain: terrain.to_owned(),
+        continent,
+        extra: Vec::new(),
+        comments: Vec::new()
+      });
+    };
+
+    // Each pixel belongs to the nearest seed point in its own or any neighboring cell
+    let province_image = RgbImage::from_fn(self.width, self.height, |x, y| {
+      let column = ((x as f64 / cell_size) as u32).min(columns - 1);
+      let row = ((y as f64 / cell_size) as u32).min(rows - 1);
+      let mut nearest = (u64::MAX, 0);
+      for r in row.saturating_sub(1)..=(row + 1).min(rows - 1) {
+        for c in column.saturating_sub(1)..=(column + 1).min(columns - 1) {
+          let i = (r * columns + c) as usize;
+          let [sx, sy] = seeds[i];
+          let distance = (sx as i64 - x as i64).pow(2) as u64 + (sy as i64 - y as i64).pow(2) as u64;
+          if distance < nearest.0 {
+            nearest = (distance, i);
+          };
+        };
+      };
+
+      Rgb(definitions[nearest.1].rgb)
+    });
+
+    // Connect random pairs of horizontally neighboring cells
+    let mut adjacencies = Vec::with_capacity(self.connections);
+    let mut connected = AHashSet::with_capacity(self.connections);
+    let mut attempts = 0;
+    while adjacencies.len() < self.connections && columns > 1 && attempts < self.connections * 16 {
+      attempts += 1;
+      let i = rng.gen_range(0..seeds.len() as u32);
+      if i % columns + 1 < columns && connected.insert(i) {
+        adjacencies.push(Adjacency {
+          from_id: i + 1,
+          to_id: i + 2,
+          kind: AdjacencyKind::Impassable,
+          through: None,
+          start: None,
+          stop: None,
+          rule_name: String::new(),
+          comment: String::new()
+        });
+      };
+    };
+
+    (province_image, definitions, adjacencies)
+  }
+
+  /// Generates a bundle with province IDs preserved, as if it had been loaded from disk
+  pub fn generate(&self) -> Bundle {
+    let (province_image, definitions, adjacencies) = self.generate_tables();
+    let config = Config { preserve_ids: true, ..Config::default() };
+    construct_map_data(province_image, MapTables { definitions, adjacencies, ..MapTables::default() }, config, &Progress::new())
+      .expect("synthetic map data was invalid")
+  }
+}
+
+impl Default for SyntheticMap {
+  fn default() -> SyntheticMap {
+    SyntheticMap {
+      width: 512,
+      height: 256,
+      provinces: 400,
+      land_fraction: 0.6,
+      continents: 4,
+      connections: 16,
+      seed: 0
+    }
+  }
+}
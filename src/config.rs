@@ -16,9 +16,18 @@ pub struct Config {
   pub preserve_ids: bool,
   pub change_view_mode_on_undo: bool,
   pub generate_coastal_on_save: bool,
+  pub stable_output: bool,
+  /// The share of a province's pixels that must be inside of a lasso or rectangle for it to count as inside,
+  /// if this is not set, a province counts as inside when its center is
+  pub shape_coverage: Option<f64>,
+  /// How many pixels of water may separate two land provinces for a strait between them to be suggested
+  pub strait_suggestion_distance: f64,
+  /// Whether straits and canals join the land provinces at either end into one land mass when assigning continents
+  pub landmasses_join_straits: bool,
   #[serde(alias = "terrain")]
   pub terrains: AHashMap<String, Terrain>,
-  pub extra_warnings: ExtraWarnings
+  pub extra_warnings: ExtraWarnings,
+  pub province_generation: ProvinceGeneration
 }
 
 impl Config {
@@ -76,13 +85,18 @@ impl Default for Config {
       preserve_ids: false,
       change_view_mode_on_undo: true,
       generate_coastal_on_save: false,
+      stable_output: false,
+      shape_coverage: None,
+      strait_suggestion_distance: 24.0,
+      landmasses_join_straits: true,
       terrains: default_terrains(),
       extra_warnings: ExtraWarnings {
         enabled: false,
         lone_pixels: false,
         few_shared_borders: false,
         few_shared_borders_threshold: 4
-      }
+      },
+      province_generation: ProvinceGeneration::default()
     }
   }
 }
@@ -116,6 +130,35 @@ impl Default for ExtraWarnings {
   }
 }
 
+/// The average size in pixels of each kind of province, when generating provinces from a land image
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ProvinceGeneration {
+  pub land_province_size: u64,
+  pub sea_province_size: u64,
+  pub lake_province_size: u64
+}
+
+impl ProvinceGeneration {
+  pub fn province_size(&self, kind: ProvinceKind) -> u64 {
+    match kind {
+      ProvinceKind::Land | ProvinceKind::Unknown => self.land_province_size,
+      ProvinceKind::Sea => self.sea_province_size,
+      ProvinceKind::Lake => self.lake_province_size
+    }
+  }
+}
+
+impl Default for ProvinceGeneration {
+  fn default() -> ProvinceGeneration {
+    ProvinceGeneration {
+      land_province_size: 600,
+      sea_province_size: 4000,
+      lake_province_size: 2000
+    }
+  }
+}
+
 #[derive(Error, Debug)]
 pub enum LoadConfigError {
   #[error(transparent)]
@@ -1,7 +1,7 @@
 use defy::ContextualError;
 use thiserror::Error;
 
-use crate::app::format::CsvError;
+use crate::app::format::{CsvError, ParseError};
 use crate::config::LoadConfigError;
 use crate::util::files::FilesError;
 
@@ -17,8 +17,12 @@ pub enum Error {
   Image(#[from] image::ImageError),
   #[error("csv parse error ({1}): {0}")]
   Csv(CsvError, &'static str),
+  #[error("script parse error ({1}): {0}")]
+  Script(ParseError, &'static str),
   #[error("config error: {0}")]
   ConfigError(#[from] LoadConfigError),
+  #[error("operation was cancelled")]
+  Cancelled,
   #[error("{0}")]
   Custom(String)
 }
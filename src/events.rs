@@ -15,6 +15,8 @@ pub trait EventHandler: Sized {
   fn on_render(&mut self, ctx: Context, cursor_pos: Option<Vector2<f64>>, gl: &mut GlGraphics);
   fn on_update(&mut self, dt: f32);
   fn on_key(&mut self, _key: Key, _state: bool, _mods: KeyMods, _pos: Option<Vector2<f64>>) {}
+  /// Called with typed text, after the key presses that produced it
+  fn on_text(&mut self, _text: String, _mods: KeyMods) {}
   fn on_mouse(&mut self, _button: MouseButton, _state: bool, _mods: KeyMods, _pos: Vector2<f64>) {}
   fn on_mouse_position(&mut self, _pos: Vector2<f64>, _mods: KeyMods) {}
   fn on_mouse_relative(&mut self, _rel: Vector2<f64>) {}
@@ -63,6 +65,7 @@ pub fn launch<H: EventHandler>(window: &mut GlutinWindow, gl: &mut GlGraphics) {
           },
           _ => ()
         },
+        Input::Text(text) => event_handler.on_text(text, mods),
         Input::Move(Motion::MouseCursor(pos)) => {
           cursor_pos = Some(pos);
           event_handler.on_mouse_position(pos, mods);
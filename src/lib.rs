@@ -0,0 +1,10 @@
+#![warn(missing_debug_implementations)]
+#[macro_use]
+pub mod util;
+pub mod app;
+pub mod config;
+pub mod error;
+pub mod events;
+pub mod font;
+
+pub const APPNAME: &str = concat!("HOI4 Province Map Editor v", env!("CARGO_PKG_VERSION"));
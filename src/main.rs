@@ -1,20 +1,13 @@
 #![warn(missing_debug_implementations)]
 #![cfg_attr(not(any(debug_assertions, feature = "debug-mode")), windows_subsystem = "windows")]
-#[macro_use]
-pub mod util;
-pub mod app;
-pub mod config;
-pub mod error;
-pub mod events;
-pub mod font;
-
 use glutin_window::GlutinWindow;
 use glutin::dpi::LogicalSize;
 use opengl_graphics::{GlGraphics, OpenGL};
 use piston::window::WindowSettings;
 
-use crate::app::App;
-use crate::events::launch;
+use hoi4_province_editor::APPNAME;
+use hoi4_province_editor::app::App;
+use hoi4_province_editor::events::launch;
 
 use std::path::PathBuf;
 use std::env;
@@ -26,8 +19,6 @@ const WINDOW_HEIGHT_DEFAULT: u32 = 720;
 const WINDOW_WIDTH_MIN: u32 = 384;
 const WINDOW_HEIGHT_MIN: u32 = 256;
 
-pub const APPNAME: &str = concat!("HOI4 Province Map Editor v", env!("CARGO_PKG_VERSION"));
-
 fn main() {
   install_handler();
 
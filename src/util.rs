@@ -1,6 +1,7 @@
 pub mod files;
 pub mod hsl;
 pub mod random;
+pub mod task;
 
 use chrono::Local;
 use vecmath::Vector2;
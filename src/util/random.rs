@@ -5,22 +5,31 @@ use rand::rngs::SmallRng;
 use crate::app::map::Color;
 use crate::util::hsl::hsl_to_rgb;
 
+/// How many colors are generated ahead of time, colors past this are generated as they are needed
 const SEQUENCE_LENGTH: usize = 4096;
+const SEQUENCE_SEED: u64 = 0x938b902e4f56bf5b;
 
-pub fn sequence_color(index: usize) -> Option<Color> {
+/// A distinct color for each index, the same index always gives the same color, and index 0 is always black
+pub fn sequence_color(index: usize) -> Color {
   static SEQUENCE: Lazy<Vec<Color>> = Lazy::new(|| {
-    let mut rng = SmallRng::seed_from_u64(0x938b902e4f56bf5b);
+    let mut rng = SmallRng::seed_from_u64(SEQUENCE_SEED);
     let mut sequence = Vec::with_capacity(SEQUENCE_LENGTH);
     sequence.push([0; 3]);
     while sequence.len() < SEQUENCE_LENGTH {
-      let h = rng.gen_range(0.0..360.0);
-      let l = rng.gen_range(0.25..0.75);
-      let color = hsl_to_rgb([h, 1.0, l]);
-      sequence.push(color);
+      sequence.push(random_sequence_color(&mut rng));
     };
 
     sequence
   });
 
-  SEQUENCE.get(index).cloned()
+  match SEQUENCE.get(index) {
+    Some(&color) => color,
+    None => random_sequence_color(&mut SmallRng::seed_from_u64(SEQUENCE_SEED ^ index as u64))
+  }
+}
+
+fn random_sequence_color(rng: &mut SmallRng) -> Color {
+  let h = rng.gen_range(0.0..360.0);
+  let l = rng.gen_range(0.25..0.75);
+  hsl_to_rgb([h, 1.0, l])
 }
@@ -0,0 +1,132 @@
+//! Utilities for running long operations on a worker thread
+use crate::error::Error;
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::fmt;
+
+/// A handle for reporting the progress of a task, and for requesting that it be cancelled
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+  inner: Arc<ProgressInner>
+}
+
+#[derive(Debug, Default)]
+struct ProgressInner {
+  state: Mutex<ProgressState>,
+  cancelled: AtomicBool
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct ProgressState {
+  stage: &'static str,
+  fraction: Option<f32>
+}
+
+impl Progress {
+  pub fn new() -> Self {
+    Progress::default()
+  }
+
+  /// Begins a new stage, clearing the fraction of the previous stage
+  pub fn set_stage(&self, stage: &'static str) {
+    let mut state = self.inner.state.lock().unwrap();
+    *state = ProgressState { stage, fraction: None };
+  }
+
+  /// Sets how much of the current stage has been completed, from 0 to 1
+  pub fn set_fraction(&self, fraction: f32) {
+    let mut state = self.inner.state.lock().unwrap();
+    state.fraction = Some(fraction.clamp(0.0, 1.0));
+  }
+
+  pub fn stage(&self) -> (&'static str, Option<f32>) {
+    let state = self.inner.state.lock().unwrap();
+    (state.stage, state.fraction)
+  }
+
+  pub fn cancel(&self) {
+    self.inner.cancelled.store(true, Ordering::Relaxed);
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.inner.cancelled.load(Ordering::Relaxed)
+  }
+
+  /// Returns `Err(Error::Cancelled)` if cancellation has been requested
+  pub fn check(&self) -> Result<(), Error> {
+    if self.is_cancelled() { Err(Error::Cancelled) } else { Ok(()) }
+  }
+}
+
+/// An operation running on a worker thread
+pub struct Task<T> {
+  handle: Option<JoinHandle<Result<T, Error>>>,
+  progress: Progress,
+  description: String
+}
+
+impl<T: Send + 'static> Task<T> {
+  pub fn spawn<F>(description: impl Into<String>, operation: F) -> Self
+  where F: FnOnce(&Progress) -> Result<T, Error> + Send + 'static {
+    let progress = Progress::new();
+    let handle = {
+      let progress = progress.clone();
+      thread::spawn(move || operation(&progress))
+    };
+
+    Task {
+      handle: Some(handle),
+      progress,
+      description: description.into()
+    }
+  }
+
+  /// Returns the result of the task if it has finished, without blocking
+  pub fn poll(&mut self) -> Option<Result<T, Error>> {
+    if self.handle.as_ref()?.is_finished() {
+      self.join()
+    } else {
+      None
+    }
+  }
+
+  /// Blocks until the task has finished
+  pub fn join(&mut self) -> Option<Result<T, Error>> {
+    let handle = self.handle.take()?;
+    Some(match handle.join() {
+      Ok(result) => result,
+      // Re-raise the panic on this thread so that it gets reported
+      Err(payload) => std::panic::resume_unwind(payload)
+    })
+  }
+
+  pub fn progress(&self) -> &Progress {
+    &self.progress
+  }
+
+  pub fn description(&self) -> &str {
+    &self.description
+  }
+
+  /// Returns a line of text describing the state of the task
+  pub fn status_text(&self) -> String {
+    let (stage, fraction) = self.progress.stage();
+    match fraction {
+      Some(fraction) => format!("{} ({}, {:.0}%)", self.description, stage, fraction * 100.0),
+      None if stage.is_empty() => self.description.clone(),
+      None => format!("{} ({})", self.description, stage)
+    }
+  }
+}
+
+impl<T> fmt::Debug for Task<T> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("Task")
+      .field("handle", &self.handle)
+      .field("progress", &self.progress)
+      .field("description", &self.description)
+      .finish()
+  }
+}